@@ -0,0 +1,44 @@
+//! Latency of uncontended `MCSLock::lock` and unlocking by a single thread.
+//!
+//! Without contention, `lock` takes the lock by a swap on `last`,
+//! and unlocking resets `last` by a CAS unless a successor is enqueued.
+//! Compare the results with a build of another fast path, e.g. a CAS from null before the swap.
+//!
+//! `cargo bench --bench mcs`
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+use synctools::mcs::{MCSLock, MCSNode};
+
+const NUM_LOOP: u64 = 10_000_000;
+const NUM_ROUNDS: usize = 5;
+
+fn main() {
+    let lock = MCSLock::new(0u64);
+    let mut node = MCSNode::new();
+
+    // warm up
+    for _ in 0..NUM_LOOP / 10 {
+        *lock.lock(&mut node) += 1;
+    }
+
+    let mut best = Duration::MAX;
+    for _ in 0..NUM_ROUNDS {
+        let start = Instant::now();
+        for _ in 0..NUM_LOOP {
+            let mut guard = lock.lock(black_box(&mut node));
+            *guard += 1;
+        }
+        best = best.min(start.elapsed());
+    }
+
+    println!(
+        "lock/unlock: {:.2} ns/op (best of {} rounds of {} ops)",
+        best.as_nanos() as f64 / NUM_LOOP as f64,
+        NUM_ROUNDS,
+        NUM_LOOP
+    );
+    black_box(*lock.lock(&mut node));
+}
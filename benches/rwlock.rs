@@ -0,0 +1,197 @@
+//! Throughput of `RwLock` with concurrent readers and writers.
+//!
+//! `state` and `writer_wake_counter` are placed on different cache lines,
+//! so writer wake-ups do not invalidate the cache line readers spin on.
+//! The layout of the build is printed first,
+//! so compare the results with a build by `--features compact`, in which they share a cache line.
+//! The last runs show the delta of the padding in a single build:
+//! readers load a word while a writer increments another word,
+//! which is on the same cache line or on its own line by `CachePadded`.
+//!
+//! A writer waiting for another writer spins on `state`,
+//! so only the last reader increments `writer_wake_counter`.
+//! The runs with several writers show the cost of waking writers,
+//! which is compared with a build incrementing the counter on every writer unlock.
+//!
+//! Readers copy the data by `*lock.read()`, or by `lock.load()`,
+//! which reads a word-sized value optimistically and never writes the lock,
+//! so the readers do not bounce the cache line of `state` between them.
+//!
+//! `cargo bench --bench rwlock` and `cargo bench --bench rwlock --features compact`
+
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use synctools::{rwlock::RwLock, util::CachePadded};
+
+const NUM_READERS: usize = 3;
+const NUM_WRITERS: [usize; 2] = [1, 2];
+const DURATION: Duration = Duration::from_secs(2);
+
+fn main() {
+    let layout = if cfg!(feature = "compact") {
+        "compact"
+    } else {
+        "padded"
+    };
+    println!(
+        "size_of::<RwLock<usize>>() = {} ({})",
+        size_of::<RwLock<usize>>(),
+        layout
+    );
+
+    for load in [false, true] {
+        for num_writers in NUM_WRITERS {
+            bench(num_writers, load);
+        }
+    }
+
+    let shared = bench_layout::<Shared>();
+    let padded = bench_layout::<Padded>();
+    println!("padding delta of the words readers load and writers increment");
+    println!("  reads on a shared line:    {:.0} ops/s", shared);
+    println!("  reads on separate lines:   {:.0} ops/s", padded);
+    println!("  ratio:                     {:.2}", padded / shared);
+}
+
+fn bench(num_writers: usize, load: bool) {
+    let lock = Arc::new(RwLock::new(0usize));
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut readers = Vec::new();
+
+    for _ in 0..NUM_READERS {
+        let lock0 = lock.clone();
+        let stop0 = stop.clone();
+        let t = std::thread::spawn(move || {
+            let mut n: u64 = 0;
+            while !stop0.load(Ordering::Relaxed) {
+                if load {
+                    black_box(lock0.load());
+                } else {
+                    black_box(*lock0.read());
+                }
+                n += 1;
+            }
+            n
+        });
+        readers.push(t);
+    }
+
+    let mut writers = Vec::new();
+    for _ in 0..num_writers {
+        let lock0 = lock.clone();
+        let stop0 = stop.clone();
+        let t = std::thread::spawn(move || {
+            let mut n: u64 = 0;
+            while !stop0.load(Ordering::Relaxed) {
+                *lock0.write() += 1;
+                n += 1;
+
+                // let readers in
+                for _ in 0..100 {
+                    std::hint::spin_loop();
+                }
+            }
+            n
+        });
+        writers.push(t);
+    }
+
+    let start = Instant::now();
+    std::thread::sleep(DURATION);
+    stop.store(true, Ordering::Relaxed);
+
+    let reads: u64 = readers.into_iter().map(|t| t.join().unwrap()).sum();
+    let writes: u64 = writers.into_iter().map(|t| t.join().unwrap()).sum();
+    let secs = start.elapsed().as_secs_f64();
+
+    let read = if load { "lock.load()" } else { "*lock.read()" };
+    println!(
+        "{} readers by {}, {} writers",
+        NUM_READERS, read, num_writers
+    );
+    println!("  reads:  {:.0} ops/s", reads as f64 / secs);
+    println!("  writes: {:.0} ops/s", writes as f64 / secs);
+}
+
+/// `state` readers load, and `writer_wake_counter` a writer increments
+trait Words: Default + Send + Sync + 'static {
+    fn state(&self) -> &AtomicUsize;
+    fn counter(&self) -> &AtomicUsize;
+}
+
+/// the words on the same cache line, as the `compact` feature
+#[derive(Default)]
+struct Shared {
+    state: AtomicUsize,
+    counter: AtomicUsize,
+}
+
+impl Words for Shared {
+    fn state(&self) -> &AtomicUsize {
+        &self.state
+    }
+
+    fn counter(&self) -> &AtomicUsize {
+        &self.counter
+    }
+}
+
+/// the words on their own cache lines, as `RwLock`
+#[derive(Default)]
+struct Padded {
+    state: CachePadded<AtomicUsize>,
+    counter: CachePadded<AtomicUsize>,
+}
+
+impl Words for Padded {
+    fn state(&self) -> &AtomicUsize {
+        &self.state
+    }
+
+    fn counter(&self) -> &AtomicUsize {
+        &self.counter
+    }
+}
+
+/// return the loads of the readers per second
+fn bench_layout<W: Words>() -> f64 {
+    let words = Arc::new(W::default());
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut readers = Vec::new();
+
+    for _ in 0..NUM_READERS {
+        let words0 = words.clone();
+        let stop0 = stop.clone();
+        let t = std::thread::spawn(move || {
+            let mut n: u64 = 0;
+            while !stop0.load(Ordering::Relaxed) {
+                black_box(words0.state().load(Ordering::Acquire));
+                n += 1;
+            }
+            n
+        });
+        readers.push(t);
+    }
+
+    let words0 = words.clone();
+    let stop0 = stop.clone();
+    let writer = std::thread::spawn(move || {
+        while !stop0.load(Ordering::Relaxed) {
+            words0.counter().fetch_add(1, Ordering::Release);
+        }
+    });
+
+    let start = Instant::now();
+    std::thread::sleep(DURATION);
+    stop.store(true, Ordering::Relaxed);
+
+    let reads: u64 = readers.into_iter().map(|t| t.join().unwrap()).sum();
+    writer.join().unwrap();
+    reads as f64 / start.elapsed().as_secs_f64()
+}
@@ -0,0 +1,214 @@
+use crate::wait_queue::{SpinLocked, WaitList, Waiter, WaiterState};
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    marker::{PhantomData, PhantomPinned},
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// asynchronous mutex for no_std
+///
+/// Like [`crate::mcs::MCSLock`], tasks waiting for the lock are queued in FIFO order,
+/// and each task owns its node of the queue.
+/// The node is embedded in the future returned by [`AsyncMutex::lock`],
+/// so no allocator is needed.
+/// A waiting task is parked by `Poll::Pending` instead of spinning,
+/// and woken by its `Waker` when the lock is handed over to it.
+///
+/// The queue is protected by a spin lock held only while it is modified.
+///
+/// ```rust
+/// use synctools::async_mcs::AsyncMutex;
+///
+/// static COUNTER: AsyncMutex<u64> = AsyncMutex::new(0);
+///
+/// async fn increment() {
+///     let mut guard = COUNTER.lock().await;
+///     *guard += 1;
+/// }
+/// ```
+pub struct AsyncMutex<T> {
+    queue: SpinLocked<Queue>,
+    data: UnsafeCell<T>,
+}
+
+struct Queue {
+    locked: bool,
+    waiters: WaitList,
+}
+
+impl Queue {
+    /// hand over the lock to the first waiter, or unlock if there is no waiter
+    ///
+    /// The lock is kept locked if it is handed over,
+    /// and the waker of the waiter is returned.
+    fn hand_over(&mut self) -> Option<core::task::Waker> {
+        let waker = self.waiters.notify_one();
+        if waker.is_none() {
+            self.locked = false;
+        }
+        waker
+    }
+}
+
+impl<T> AsyncMutex<T> {
+    pub const fn new(v: T) -> AsyncMutex<T> {
+        AsyncMutex {
+            queue: SpinLocked::new(Queue {
+                locked: false,
+                waiters: WaitList::new(),
+            }),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    /// acquire lock
+    ///
+    /// The returned future is resolved when the lock is acquired.
+    /// If it is dropped before that, it leaves the queue,
+    /// or passes the lock to the next task if the lock is already handed over.
+    pub fn lock(&self) -> LockFuture<'_, T> {
+        LockFuture {
+            mutex: self,
+            waiter: UnsafeCell::new(Waiter::new()),
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// consume the mutex and return the data
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// get the data without locking, which is safe because of `&mut self`
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn unlock(&self) {
+        let waker = self.queue.with(|queue| queue.hand_over());
+
+        // wake the next task outside the spin lock
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+unsafe impl<T: Send> Sync for AsyncMutex<T> {}
+unsafe impl<T: Send> Send for AsyncMutex<T> {}
+
+/// future returned by [`AsyncMutex::lock`]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct LockFuture<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+    waiter: UnsafeCell<Waiter>,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T> Future for LockFuture<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // the waiter is not moved, because self is pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let mutex = this.mutex;
+        let waiter = this.waiter.get();
+
+        let acquired = mutex.queue.with(|queue| unsafe {
+            match (*waiter).state {
+                WaiterState::Idle => {
+                    if !queue.locked {
+                        queue.locked = true;
+                        return true;
+                    }
+
+                    queue.waiters.push_back(waiter, cx.waker());
+                    false
+                }
+                WaiterState::Waiting => {
+                    (*waiter).register(cx.waker());
+                    false
+                }
+                // the lock is handed over
+                WaiterState::Notified => true,
+                WaiterState::Done => panic!("LockFuture: polled after completion"),
+            }
+        });
+
+        if acquired {
+            unsafe { (*waiter).state = WaiterState::Done };
+            Poll::Ready(AsyncMutexGuard {
+                mutex,
+                _phantom: PhantomData,
+            })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// leave the queue, or pass the lock to the next task
+impl<'a, T> Drop for LockFuture<'a, T> {
+    fn drop(&mut self) {
+        let waiter = self.waiter.get();
+        let waker = self.mutex.queue.with(|queue| unsafe {
+            match (*waiter).state {
+                WaiterState::Waiting => {
+                    queue.waiters.remove(waiter);
+                    None
+                }
+                WaiterState::Notified => queue.hand_over(),
+                WaiterState::Idle | WaiterState::Done => None,
+            }
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// the waiter is accessed only while the spin lock is held
+unsafe impl<'a, T: Send> Send for LockFuture<'a, T> {}
+unsafe impl<'a, T: Send> Sync for LockFuture<'a, T> {}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct AsyncMutexGuard<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+    _phantom: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> AsyncMutexGuard<'a, T> {
+    /// unlock the mutex
+    pub fn unlock(self) {}
+}
+
+impl<'a, T> Deref for AsyncMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for AsyncMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+/// release the lock and wake the next task
+impl<'a, T> Drop for AsyncMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
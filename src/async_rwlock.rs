@@ -0,0 +1,414 @@
+use crate::wait_queue::{SpinLocked, WaitList, Waiter, WaiterState};
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    marker::{PhantomData, PhantomPinned},
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+/// asynchronous readers writer lock for no_std
+///
+/// The lock is taken by the same `state` as [`crate::rwlock::RwLock`],
+/// but waiting tasks are parked by `Poll::Pending` instead of spinning.
+/// A waiting writer blocks new readers as `RwLock`,
+/// and the queue of waiting writers replaces `writer_wake_counter`.
+/// The nodes of the queues are embedded in the futures, so no allocator is needed.
+///
+/// ```rust
+/// use synctools::async_rwlock::AsyncRwLock;
+///
+/// static CONFIG: AsyncRwLock<u64> = AsyncRwLock::new(0);
+///
+/// async fn update() {
+///     *CONFIG.write().await += 1;
+/// }
+///
+/// async fn get() -> u64 {
+///     *CONFIG.read().await
+/// }
+/// ```
+pub struct AsyncRwLock<T> {
+    state: AtomicUsize,
+    waiters: SpinLocked<Waiters>,
+    data: UnsafeCell<T>,
+}
+
+// # State
+//
+// `state` is the same as `RwLock`, that is,
+// `usize::MAX` if a writer holds the lock,
+// otherwise (the number of readers) * 2 + (1 if a writer is waiting).
+//
+// # Wake-ups
+//
+// A task which failed to acquire the lock retries it while holding the spin lock
+// of the queues, and it is queued only if the retry fails.
+// A task releasing the lock changes `state` before it wakes the queued tasks
+// while holding the spin lock.
+// Therefore, either the retry observes the release, or the release observes the queued task.
+//
+// - A writer releasing the lock clears the writer waiting bit,
+//   so it wakes all readers and one writer.
+// - The last reader wakes one writer if the writer waiting bit is set.
+// - A writer which is woken but dropped before it is polled passes the wake-up to the next writer.
+// - If no writer is queued any longer, the writer waiting bit is cleared
+//   and all readers are woken, because the bit blocks them.
+struct Waiters {
+    readers: WaitList,
+    writers: WaitList,
+}
+
+impl Waiters {
+    /// A writer left the queue without acquiring the lock.
+    /// Pass the wake-up to the next writer if `notified`,
+    /// or unblock readers if there is no writer.
+    fn writer_gone(&mut self, state: &AtomicUsize, notified: bool) -> Option<Waker> {
+        if !self.writers.is_empty() {
+            return if notified {
+                self.writers.notify_one()
+            } else {
+                None
+            };
+        }
+
+        let mut s = state.load(Ordering::Relaxed);
+        while s != usize::MAX && s & 1 == 1 {
+            match state.compare_exchange_weak(s, s - 1, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(e) => s = e,
+            }
+        }
+
+        self.readers.notify_all();
+        None
+    }
+}
+
+impl<T> AsyncRwLock<T> {
+    pub const fn new(v: T) -> AsyncRwLock<T> {
+        AsyncRwLock {
+            state: AtomicUsize::new(0),
+            waiters: SpinLocked::new(Waiters {
+                readers: WaitList::new(),
+                writers: WaitList::new(),
+            }),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    /// acquire reader lock
+    ///
+    /// If the returned future is dropped before it is resolved, it leaves the queue.
+    pub fn read(&self) -> ReadFuture<'_, T> {
+        ReadFuture {
+            rwlock: self,
+            waiter: UnsafeCell::new(Waiter::new()),
+            queued: false,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// acquire writer lock
+    ///
+    /// If the returned future is dropped before it is resolved,
+    /// it leaves the queue and passes its wake-up to the next writer.
+    pub fn write(&self) -> WriteFuture<'_, T> {
+        WriteFuture {
+            rwlock: self,
+            waiter: UnsafeCell::new(Waiter::new()),
+            queued: false,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// consume the lock and return the data
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// get the data without locking, which is safe because of `&mut self`
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn try_read(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s & 1 == 0 {
+            match self
+                .state
+                .compare_exchange_weak(s, s + 2, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    /// If this fails, the writer waiting bit is set to block new readers.
+    fn try_write(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s <= 1 {
+                match self.state.compare_exchange(
+                    s,
+                    usize::MAX,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return true,
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+
+            if s & 1 == 1 {
+                return false;
+            }
+
+            match self
+                .state
+                .compare_exchange(s, s + 1, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return false,
+                Err(e) => s = e,
+            }
+        }
+    }
+}
+
+impl<T: Default> Default for AsyncRwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for AsyncRwLock<T> {}
+unsafe impl<T: Send> Send for AsyncRwLock<T> {}
+
+/// future returned by [`AsyncRwLock::read`]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ReadFuture<'a, T> {
+    rwlock: &'a AsyncRwLock<T>,
+    waiter: UnsafeCell<Waiter>,
+    queued: bool,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T> Future for ReadFuture<'a, T> {
+    type Output = AsyncRwLockReadGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // the waiter is not moved, because self is pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let rwlock = this.rwlock;
+        let waiter = this.waiter.get();
+
+        // the state of the waiter is read only while holding the spin lock once it is queued
+        let acquired = (!this.queued && rwlock.try_read())
+            || rwlock.waiters.with(|waiters| unsafe {
+                if rwlock.try_read() {
+                    if (*waiter).state == WaiterState::Waiting {
+                        waiters.readers.remove(waiter);
+                    }
+                    (*waiter).state = WaiterState::Done;
+                    true
+                } else {
+                    if (*waiter).state == WaiterState::Waiting {
+                        (*waiter).register(cx.waker());
+                    } else {
+                        waiters.readers.push_back(waiter, cx.waker());
+                    }
+                    false
+                }
+            });
+
+        if acquired {
+            Poll::Ready(AsyncRwLockReadGuard {
+                rwlock,
+                _phantom: PhantomData,
+            })
+        } else {
+            this.queued = true;
+            Poll::Pending
+        }
+    }
+}
+
+/// leave the queue
+impl<'a, T> Drop for ReadFuture<'a, T> {
+    fn drop(&mut self) {
+        if !self.queued {
+            return;
+        }
+
+        let waiter = self.waiter.get();
+        self.rwlock.waiters.with(|waiters| unsafe {
+            if (*waiter).state == WaiterState::Waiting {
+                waiters.readers.remove(waiter);
+            }
+        });
+    }
+}
+
+/// future returned by [`AsyncRwLock::write`]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WriteFuture<'a, T> {
+    rwlock: &'a AsyncRwLock<T>,
+    waiter: UnsafeCell<Waiter>,
+    queued: bool,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T> Future for WriteFuture<'a, T> {
+    type Output = AsyncRwLockWriteGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // the waiter is not moved, because self is pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let rwlock = this.rwlock;
+        let waiter = this.waiter.get();
+
+        // the state of the waiter is read only while holding the spin lock once it is queued
+        let acquired = (!this.queued && rwlock.try_write())
+            || rwlock.waiters.with(|waiters| unsafe {
+                if rwlock.try_write() {
+                    if (*waiter).state == WaiterState::Waiting {
+                        waiters.writers.remove(waiter);
+                    }
+                    (*waiter).state = WaiterState::Done;
+                    true
+                } else {
+                    if (*waiter).state == WaiterState::Waiting {
+                        (*waiter).register(cx.waker());
+                    } else {
+                        waiters.writers.push_back(waiter, cx.waker());
+                    }
+                    false
+                }
+            });
+
+        if acquired {
+            Poll::Ready(AsyncRwLockWriteGuard {
+                rwlock,
+                _phantom: PhantomData,
+            })
+        } else {
+            this.queued = true;
+            Poll::Pending
+        }
+    }
+}
+
+/// leave the queue, and pass the wake-up to the next writer
+impl<'a, T> Drop for WriteFuture<'a, T> {
+    fn drop(&mut self) {
+        if !self.queued {
+            return;
+        }
+
+        let waiter = self.waiter.get();
+        let state = &self.rwlock.state;
+        let waker = self.rwlock.waiters.with(|waiters| unsafe {
+            match (*waiter).state {
+                WaiterState::Waiting => {
+                    waiters.writers.remove(waiter);
+                    waiters.writer_gone(state, false)
+                }
+                WaiterState::Notified => waiters.writer_gone(state, true),
+                WaiterState::Idle | WaiterState::Done => None,
+            }
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// the waiters are accessed only while the spin lock is held
+unsafe impl<'a, T: Send + Sync> Send for ReadFuture<'a, T> {}
+unsafe impl<'a, T: Send + Sync> Sync for ReadFuture<'a, T> {}
+unsafe impl<'a, T: Send + Sync> Send for WriteFuture<'a, T> {}
+unsafe impl<'a, T: Send + Sync> Sync for WriteFuture<'a, T> {}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct AsyncRwLockReadGuard<'a, T> {
+    rwlock: &'a AsyncRwLock<T>,
+    _phantom: PhantomData<&'a T>,
+}
+
+impl<'a, T> AsyncRwLockReadGuard<'a, T> {
+    /// unlock read lock
+    pub fn unlock(self) {}
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct AsyncRwLockWriteGuard<'a, T> {
+    rwlock: &'a AsyncRwLock<T>,
+    _phantom: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> AsyncRwLockWriteGuard<'a, T> {
+    /// unlock write lock
+    pub fn unlock(self) {}
+}
+
+impl<'a, T> Deref for AsyncRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<'a, T> Deref for AsyncRwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for AsyncRwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+/// release read lock, and wake a writer if this is the last reader
+impl<'a, T> Drop for AsyncRwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        if self.rwlock.state.fetch_sub(2, Ordering::Release) == 3 {
+            let waker = self
+                .rwlock
+                .waiters
+                .with(|waiters| waiters.writers.notify_one());
+
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// release write lock, and wake all readers and a writer
+impl<'a, T> Drop for AsyncRwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.store(0, Ordering::Release);
+
+        let waker = self.rwlock.waiters.with(|waiters| {
+            waiters.readers.notify_all();
+            waiters.writers.notify_one()
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,235 @@
+#[cfg(not(loom))]
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[cfg(loom)]
+use loom::{
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const SPIN_LIMIT: u32 = 6;
+const SNOOZE_LIMIT: u32 = 10;
+
+/// exponential backoff for spin loops
+///
+/// `spin` is for retrying an atomic operation which failed by contention,
+/// and `snooze` is for waiting until another thread changes a state.
+/// Under loom, `snooze` yields the current thread instead of spinning, and `spin` does nothing.
+///
+/// ```rust
+/// use synctools::backoff::Backoff;
+/// use core::sync::atomic::{AtomicBool, Ordering};
+///
+/// fn wait(ready: &AtomicBool) {
+///     let mut backoff = Backoff::new();
+///     while !ready.load(Ordering::Acquire) {
+///         backoff.snooze();
+///     }
+/// }
+/// ```
+pub struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    pub const fn new() -> Backoff {
+        Backoff { step: 0 }
+    }
+
+    /// reset the backoff to the initial state
+    pub fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    /// spin for a short time, doubling the time up to 2^6 iterations of `spin_loop`
+    ///
+    /// This does nothing under loom,
+    /// because a failed atomic operation means that another thread made progress,
+    /// and yielding there only multiplies the interleavings to explore.
+    pub fn spin(&mut self) {
+        #[cfg(not(loom))]
+        for _ in 0..1 << self.step.min(SPIN_LIMIT) {
+            spin_loop();
+        }
+
+        if self.step <= SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// spin for a longer time than `spin`, doubling the time up to 2^10 iterations of `spin_loop`
+    ///
+    /// There is no way to yield the CPU in no_std,
+    /// so use [`Backoff::is_completed`] to switch to the blocking
+    /// mechanism of the environment for long waits.
+    pub fn snooze(&mut self) {
+        #[cfg(not(loom))]
+        for _ in 0..1 << self.step.min(SNOOZE_LIMIT) {
+            spin_loop();
+        }
+
+        #[cfg(loom)]
+        {
+            spin_loop();
+            loom::thread::yield_now();
+        }
+
+        if self.step <= SNOOZE_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// true if the backoff reached the maximum
+    /// and the caller should block instead of spinning
+    pub fn is_completed(&self) -> bool {
+        self.step > SNOOZE_LIMIT
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// hooks to give up the CPU during long waits
+///
+/// A lock waits by [`Backoff::snooze`] for the first `SPINS` waits,
+/// and calls `park` instead for each of the following waits.
+/// This is distinct from the hint of `spin_loop`:
+/// on a cooperative scheduler, `park` should yield the core to another task.
+/// `unpark` is called once by the waiting thread when it stops waiting after `park` was called,
+/// e.g. to restore the priority of the task lowered by `park`.
+///
+/// ```rust
+/// use synctools::{backoff::Park, rwlock::RwLock};
+///
+/// struct Yield;
+///
+/// impl Park for Yield {
+///     const SPINS: usize = 16;
+///
+///     fn park() {
+///         // yield to the scheduler here
+///     }
+/// }
+///
+/// let lock = RwLock::new(0);
+/// *lock.write_with_park::<Yield>() += 1;
+/// assert_eq!(*lock.read_with_park::<Yield>(), 1);
+/// ```
+pub trait Park {
+    /// the number of waits by [`Backoff::snooze`] before `park` is called
+    const SPINS: usize = 1 << 10;
+
+    /// give up the CPU for a while
+    fn park() {
+        spin_loop();
+    }
+
+    /// called after the last `park` when the wait ends
+    fn unpark() {}
+}
+
+/// the default hooks, which never park and only spin,
+/// as the locks without the hooks
+pub struct SpinPark;
+
+impl Park for SpinPark {
+    const SPINS: usize = usize::MAX;
+}
+
+/// wait until `done` returns true for the value of `atomic`,
+/// and return the value and the number of waits
+///
+/// Each iteration loads `atomic` once by `order`, which is `Relaxed` or `Acquire`,
+/// and waits for a change before reloading it.
+/// With the `wfe` feature on AArch64, the load arms the exclusive monitor,
+/// and the core sleeps by `wfe` until another core writes the cache line of `atomic`,
+/// which clears the monitor and wakes the core, so the releasing side needs no `sev`.
+/// The event stream of the OS, if any, also wakes the core periodically.
+/// Otherwise, it waits by [`Backoff::snooze`].
+///
+/// `done` may read other variables,
+/// but they must be written only before `atomic` is written,
+/// because only a write to `atomic` wakes the core.
+pub(crate) fn wait_until(
+    atomic: &AtomicUsize,
+    order: Ordering,
+    done: impl FnMut(usize) -> bool,
+) -> (usize, usize) {
+    wait_until_park::<SpinPark>(atomic, order, done)
+}
+
+/// [`wait_until`], but call the hooks of `K` after `K::SPINS` waits
+pub(crate) fn wait_until_park<K: Park>(
+    atomic: &AtomicUsize,
+    order: Ordering,
+    mut done: impl FnMut(usize) -> bool,
+) -> (usize, usize) {
+    #[cfg(not(all(feature = "wfe", target_arch = "aarch64", not(loom))))]
+    let mut backoff = Backoff::new();
+
+    let mut waits = 0;
+    loop {
+        #[cfg(all(feature = "wfe", target_arch = "aarch64", not(loom)))]
+        let v = load_exclusive(atomic, order);
+
+        #[cfg(not(all(feature = "wfe", target_arch = "aarch64", not(loom))))]
+        let v = atomic.load(order);
+
+        if done(v) {
+            if waits > K::SPINS {
+                K::unpark();
+            }
+            return (v, waits);
+        }
+
+        if waits >= K::SPINS {
+            K::park();
+
+            #[cfg(loom)]
+            loom::thread::yield_now();
+        } else {
+            #[cfg(all(feature = "wfe", target_arch = "aarch64", not(loom)))]
+            unsafe {
+                core::arch::asm!("wfe", options(nomem, nostack, preserves_flags));
+            }
+
+            #[cfg(not(all(feature = "wfe", target_arch = "aarch64", not(loom))))]
+            backoff.snooze();
+        }
+
+        waits += 1;
+    }
+}
+
+/// load `atomic` and arm the exclusive monitor for its cache line
+#[cfg(all(feature = "wfe", target_arch = "aarch64", not(loom)))]
+#[inline]
+fn load_exclusive(atomic: &AtomicUsize, order: Ordering) -> usize {
+    let ptr = atomic.as_ptr();
+    let v: usize;
+    unsafe {
+        if order == Ordering::Relaxed {
+            core::arch::asm!(
+                "ldxr {v}, [{ptr}]",
+                ptr = in(reg) ptr,
+                v = out(reg) v,
+                options(nostack, readonly, preserves_flags),
+            );
+        } else {
+            // `ldaxr` is an `Acquire` load
+            core::arch::asm!(
+                "ldaxr {v}, [{ptr}]",
+                ptr = in(reg) ptr,
+                v = out(reg) v,
+                options(nostack, readonly, preserves_flags),
+            );
+        }
+    }
+    v
+}
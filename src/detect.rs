@@ -0,0 +1,152 @@
+//! detection of self-deadlocks for debugging
+//!
+//! A thread holding a reader lock of [`crate::rwlock::RwLock`] and acquiring its writer lock
+//! waits for itself forever.
+//! With the `debug-detect` feature, each `RwLock` records the owners of its reader guards
+//! in a small table, and [`crate::rwlock::RwLock::write`] panics
+//! if the current owner is in the table, instead of deadlocking.
+//!
+//! The identity of a thread depends on the scheduler,
+//! so it is supplied by the user by [`set_owner_id`].
+//! Nothing is detected until then.
+//!
+//! ```rust,should_panic
+//! use synctools::{detect::{set_owner_id, OwnerId}, rwlock::RwLock};
+//!
+//! struct Thread;
+//!
+//! impl OwnerId for Thread {
+//!     fn current() -> usize {
+//!         // e.g. the task ID of the RTOS, which must not be 0
+//!         1
+//!     }
+//! }
+//!
+//! set_owner_id::<Thread>();
+//!
+//! let lock = RwLock::new(0);
+//! let _r = lock.read();
+//!
+//! // panics instead of deadlocking
+//! *lock.write() += 1;
+//! ```
+//!
+//! # Limitations
+//!
+//! - Up to [`SLOTS`] reader guards of a lock are recorded at the same time,
+//!   and the others are not checked.
+//! - A reader guard is recorded for the owner which acquired it,
+//!   so if it is sent to another thread, the acquiring thread still panics in `write`
+//!   until the guard is dropped.
+//! - [`crate::rwlock::RwLock::force_unlock_read`] forgets a reader of the current owner.
+
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// the number of reader guards recorded per lock
+pub const SLOTS: usize = 8;
+
+/// provider of the identity of the current thread
+pub trait OwnerId {
+    /// the identity of the current thread, which must not be 0
+    fn current() -> usize;
+}
+
+/// `OwnerId::current` of the provider, or null
+static OWNER_ID: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// set the provider of the identity of threads, which is shared by all locks
+///
+/// This should be called before any lock is used,
+/// because the guards acquired before are not recorded.
+pub fn set_owner_id<O: OwnerId>() {
+    let f: fn() -> usize = O::current;
+    OWNER_ID.store(f as *mut (), Ordering::Release);
+}
+
+/// the identity of the current thread, or 0 if no provider is set
+pub(crate) fn current() -> usize {
+    let f = OWNER_ID.load(Ordering::Acquire);
+    if f.is_null() {
+        return 0;
+    }
+
+    // `f` is stored only by `set_owner_id`
+    let f = unsafe { core::mem::transmute::<*mut (), fn() -> usize>(f) };
+    let owner = f();
+    debug_assert_ne!(owner, 0, "OwnerId::current must not return 0");
+    owner
+}
+
+/// the owners of the reader guards of a lock
+///
+/// An empty slot is 0.
+/// The slots are only hints for debugging, so they are accessed by `Relaxed`.
+pub(crate) struct Readers {
+    slots: [AtomicUsize; SLOTS],
+}
+
+impl Readers {
+    pub(crate) const fn new() -> Readers {
+        Readers {
+            slots: [const { AtomicUsize::new(0) }; SLOTS],
+        }
+    }
+
+    /// record the current owner, and return it, or 0 if it is not recorded
+    pub(crate) fn insert(&self) -> usize {
+        let owner = current();
+        if owner == 0 {
+            return 0;
+        }
+
+        for slot in &self.slots {
+            if slot
+                .compare_exchange(0, owner, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                return owner;
+            }
+        }
+
+        // the table is full
+        0
+    }
+
+    /// remove an owner returned by `insert`
+    pub(crate) fn remove(&self, owner: usize) {
+        if owner == 0 {
+            return;
+        }
+
+        for slot in &self.slots {
+            if slot
+                .compare_exchange(owner, 0, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+
+    /// panic if the current owner holds a reader guard
+    pub(crate) fn check_write(&self) {
+        let owner = current();
+        if owner != 0
+            && self
+                .slots
+                .iter()
+                .any(|s| s.load(Ordering::Relaxed) == owner)
+        {
+            deadlock(owner);
+        }
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn deadlock(owner: usize) -> ! {
+    panic!(
+        "RwLock: deadlock, the owner {} requests the writer lock while holding a reader lock of it",
+        owner
+    );
+}
@@ -0,0 +1,208 @@
+//! epoch-based reclamation
+//!
+//! A thread reading shared nodes pins the global epoch by [`pin`],
+//! and a thread unlinking a node defers freeing it by [`defer_destroy`]
+//! until every guard pinned before the unlink is dropped.
+//! So a node read under an [`EpochGuard`] is never freed while the guard is held,
+//! e.g. for [`crate::lfstack::LFStack::peek`].
+//!
+//! The global epoch advances by one when every pinned guard has observed the current epoch,
+//! and a node deferred in an epoch is freed when the global epoch is ahead of it by two.
+//! The guards are pinned in a fixed table of [`MAX_GUARDS`] slots,
+//! so this needs no thread-local storage, and works in `no_std` with `alloc`.
+//!
+//! ```rust
+//! use synctools::epoch;
+//!
+//! let ptr = Box::into_raw(Box::new(1));
+//! {
+//!     let _guard = epoch::pin();
+//!     // `ptr` is valid while the guard is held
+//!     assert_eq!(unsafe { *ptr }, 1);
+//! }
+//!
+//! // nobody reads `ptr` after this
+//! unsafe { epoch::defer_destroy(ptr) };
+//! epoch::collect();
+//! ```
+
+use crate::backoff::Backoff;
+use alloc::boxed::Box;
+use core::{
+    marker::PhantomData,
+    ptr::null_mut,
+    sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering},
+};
+
+/// the maximum number of guards pinned at the same time
+///
+/// [`pin`] spins while all the slots are in use.
+pub const MAX_GUARDS: usize = 64;
+
+/// the number of deferred nodes between collections by [`defer_destroy`]
+const COLLECT_INTERVAL: usize = 64;
+
+/// the global epoch
+///
+/// Every access is `SeqCst`, so that the epoch read by [`defer_destroy`] after an unlink
+/// is not older than the epoch pinned by a guard which read the node before the unlink.
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// the slots of pinned guards, which are 0 if free, or `epoch << 1 | 1` if pinned
+static SLOTS: [AtomicUsize; MAX_GUARDS] = [const { AtomicUsize::new(0) }; MAX_GUARDS];
+
+/// the deferred nodes
+static GARBAGE: AtomicPtr<Garbage> = AtomicPtr::new(null_mut());
+
+/// the number of deferred nodes, to collect them periodically
+static DEFERRED: AtomicUsize = AtomicUsize::new(0);
+
+struct Garbage {
+    next: *mut Garbage,
+    /// the global epoch when the node was deferred
+    epoch: usize,
+    ptr: *mut u8,
+    free: unsafe fn(*mut u8),
+}
+
+const fn pinned(epoch: usize) -> usize {
+    epoch << 1 | 1
+}
+
+/// guard of a pinned epoch
+///
+/// Nodes read while the guard is held are not freed until it is dropped.
+/// The guard cannot be sent to another thread, as lock guards.
+#[must_use = "the epoch is unpinned when the guard is dropped"]
+pub struct EpochGuard {
+    /// the index of the slot taken from the table
+    slot: usize,
+    _phantom: PhantomData<*mut ()>,
+}
+
+/// pin the current epoch
+///
+/// This spins while [`MAX_GUARDS`] guards are pinned.
+pub fn pin() -> EpochGuard {
+    let mut backoff = Backoff::new();
+    let mut e = EPOCH.load(Ordering::SeqCst);
+    let slot = loop {
+        let slot = SLOTS.iter().position(|s| {
+            s.load(Ordering::Relaxed) == 0
+                && s.compare_exchange(0, pinned(e), Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+        });
+
+        if let Some(slot) = slot {
+            break slot;
+        }
+
+        backoff.snooze();
+        e = EPOCH.load(Ordering::SeqCst);
+    };
+
+    // the slot must be visible to a thread advancing the epoch before nodes are read,
+    // and the epoch must not advance meanwhile, so that the slot is not stale
+    loop {
+        fence(Ordering::SeqCst);
+        let now = EPOCH.load(Ordering::SeqCst);
+        if now == e {
+            break;
+        }
+
+        e = now;
+        SLOTS[slot].swap(pinned(e), Ordering::Relaxed);
+    }
+
+    EpochGuard {
+        slot,
+        _phantom: PhantomData,
+    }
+}
+
+impl Drop for EpochGuard {
+    fn drop(&mut self) {
+        // the reads under the guard happen before the nodes are freed
+        SLOTS[self.slot].store(0, Ordering::Release);
+    }
+}
+
+/// free `ptr` by `Box::from_raw` after every guard pinned before this call is dropped
+///
+/// This also collects the deferred nodes once in a while.
+///
+/// # Safety
+///
+/// `ptr` must be allocated by `Box::into_raw`, and already unlinked,
+/// so that no thread can read it without a guard pinned before this call.
+/// It is dropped later by any thread calling [`collect`], so `T` must be safe to drop there.
+pub unsafe fn defer_destroy<T>(ptr: *mut T) {
+    unsafe fn free<T>(ptr: *mut u8) {
+        drop(Box::from_raw(ptr as *mut T));
+    }
+
+    // the unlink happens before the epoch is read
+    fence(Ordering::SeqCst);
+    let garbage = Box::into_raw(Box::new(Garbage {
+        next: null_mut(),
+        epoch: EPOCH.load(Ordering::SeqCst),
+        ptr: ptr as *mut u8,
+        free: free::<T>,
+    }));
+    push(garbage);
+
+    if DEFERRED.fetch_add(1, Ordering::Relaxed) % COLLECT_INTERVAL == COLLECT_INTERVAL - 1 {
+        collect();
+    }
+}
+
+/// try to advance the epoch, and free the deferred nodes which no guard can read
+///
+/// A node is freed after the epoch advances twice since it was deferred,
+/// so this must be called at least twice to free a node if nobody else calls it.
+pub fn collect() {
+    try_advance();
+
+    // the nodes are taken by a single swap, so that concurrent collectors free them only once
+    let mut garbage = GARBAGE.swap(null_mut(), Ordering::Acquire);
+    let now = EPOCH.load(Ordering::SeqCst);
+
+    while !garbage.is_null() {
+        let next = unsafe { (*garbage).next };
+        if now.wrapping_sub(unsafe { (*garbage).epoch }) >= 2 {
+            let g = unsafe { Box::from_raw(garbage) };
+            unsafe { (g.free)(g.ptr) };
+        } else {
+            push(garbage);
+        }
+        garbage = next;
+    }
+}
+
+/// advance the epoch if every pinned guard has observed it
+fn try_advance() {
+    let e = EPOCH.load(Ordering::SeqCst);
+
+    // a guard pinned before the fence is visible, or observes the advanced epoch
+    fence(Ordering::SeqCst);
+    for s in SLOTS.iter() {
+        // `Acquire` so that the reads under an unpinned guard happen before
+        let v = s.load(Ordering::Acquire);
+        if v != 0 && v != pinned(e) {
+            return;
+        }
+    }
+
+    let _ = EPOCH.compare_exchange(e, e.wrapping_add(1), Ordering::SeqCst, Ordering::SeqCst);
+}
+
+fn push(garbage: *mut Garbage) {
+    let mut head = GARBAGE.load(Ordering::Relaxed);
+    loop {
+        unsafe { (*garbage).next = head };
+        match GARBAGE.compare_exchange_weak(head, garbage, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(e) => head = e,
+        }
+    }
+}
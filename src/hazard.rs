@@ -0,0 +1,221 @@
+//! hazard pointers for safe reclamation of shared nodes
+//!
+//! A thread reading a shared node publishes its address by [`HazardPointer::protect`],
+//! and a thread unlinking the node frees it by [`retire`] only when no hazard pointer holds it.
+//! Otherwise, the node is kept in a list of retired nodes, and freed by a later [`retire`].
+//! So a protected node is neither freed nor reused for another node,
+//! which also prevents the ABA problem of compare-and-swap on the address.
+//!
+//! The hazard pointers are taken from a fixed table of [`MAX_HAZARDS`] slots,
+//! so this needs no thread-local storage, and works in `no_std` with `alloc`.
+//!
+//! ```rust
+//! use synctools::hazard::{self, HazardPointer};
+//! use core::sync::atomic::{AtomicPtr, Ordering};
+//!
+//! let shared = AtomicPtr::new(Box::into_raw(Box::new(1)));
+//!
+//! let hp = HazardPointer::new();
+//! let ptr = hp.protect(&shared);
+//!
+//! // unlink and retire the node, which is not freed while `hp` protects it
+//! shared.store(core::ptr::null_mut(), Ordering::Relaxed);
+//! unsafe { hazard::retire(ptr) };
+//! assert_eq!(unsafe { *ptr }, 1);
+//!
+//! // freed by a later `retire`
+//! drop(hp);
+//! ```
+
+use crate::backoff::Backoff;
+use alloc::boxed::Box;
+use core::{marker::PhantomData, ptr::null_mut};
+
+#[cfg(not(loom))]
+use core::sync::atomic::{fence, AtomicBool, AtomicPtr, Ordering};
+
+#[cfg(loom)]
+use loom::sync::atomic::{fence, AtomicBool, AtomicPtr, Ordering};
+
+/// the maximum number of hazard pointers at the same time
+///
+/// [`HazardPointer::new`] spins while all the slots are in use.
+#[cfg(not(loom))]
+pub const MAX_HAZARDS: usize = 64;
+
+/// the maximum number of hazard pointers at the same time
+///
+/// This is small under loom, because every slot is read by [`retire`].
+#[cfg(loom)]
+pub const MAX_HAZARDS: usize = 4;
+
+struct Slot {
+    /// true if the slot is taken by a hazard pointer
+    used: AtomicBool,
+    /// the protected address, or null
+    ptr: AtomicPtr<u8>,
+}
+
+impl Slot {
+    #[cfg(not(loom))]
+    const fn new() -> Slot {
+        Slot {
+            used: AtomicBool::new(false),
+            ptr: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    #[cfg(loom)]
+    fn new() -> Slot {
+        Slot {
+            used: AtomicBool::new(false),
+            ptr: AtomicPtr::new(null_mut()),
+        }
+    }
+}
+
+struct Retired {
+    next: *mut Retired,
+    ptr: *mut u8,
+    free: unsafe fn(*mut u8),
+}
+
+#[cfg(not(loom))]
+static SLOTS: [Slot; MAX_HAZARDS] = [const { Slot::new() }; MAX_HAZARDS];
+
+/// the retired nodes which were protected when they were retired
+#[cfg(not(loom))]
+static RETIRED: AtomicPtr<Retired> = AtomicPtr::new(null_mut());
+
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref SLOTS: [Slot; MAX_HAZARDS] = core::array::from_fn(|_| Slot::new());
+    static ref RETIRED: AtomicPtr<Retired> = AtomicPtr::new(null_mut());
+}
+
+/// hazard pointer, which protects a node from being freed by [`retire`]
+///
+/// The hazard pointer cannot be sent to another thread, as lock guards.
+pub struct HazardPointer {
+    /// the index of the slot taken from the table
+    slot: usize,
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl HazardPointer {
+    /// take a hazard pointer protecting nothing
+    ///
+    /// This spins while [`MAX_HAZARDS`] hazard pointers are in use.
+    pub fn new() -> HazardPointer {
+        let mut backoff = Backoff::new();
+        loop {
+            for (i, s) in SLOTS.iter().enumerate() {
+                if !s.used.load(Ordering::Relaxed)
+                    && s.used
+                        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    return HazardPointer {
+                        slot: i,
+                        _phantom: PhantomData,
+                    };
+                }
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// load `src`, and protect the loaded node until the next call or `reset`
+    ///
+    /// This retries until `src` holds the same address after it is published,
+    /// so the returned node was not retired before it is protected.
+    pub fn protect<T>(&self, src: &AtomicPtr<T>) -> *mut T {
+        let slot = &SLOTS[self.slot];
+        let mut ptr = src.load(Ordering::Relaxed);
+        loop {
+            slot.ptr.store(ptr as *mut u8, Ordering::Relaxed);
+
+            // the hazard pointer is visible to `retire` before `src` is read again
+            fence(Ordering::SeqCst);
+
+            let now = src.load(Ordering::Acquire);
+            if now == ptr {
+                return ptr;
+            }
+            ptr = now;
+        }
+    }
+
+    /// stop protecting the node
+    pub fn reset(&self) {
+        // the reads of the node happen before it is freed
+        SLOTS[self.slot].ptr.store(null_mut(), Ordering::Release);
+    }
+}
+
+impl Default for HazardPointer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for HazardPointer {
+    fn drop(&mut self) {
+        self.reset();
+        SLOTS[self.slot].used.store(false, Ordering::Release);
+    }
+}
+
+/// free `ptr` by `Box::from_raw` if no hazard pointer protects it,
+/// or when a later call finds it unprotected
+///
+/// This also frees the nodes retired before, which are no longer protected.
+///
+/// # Safety
+///
+/// `ptr` must be allocated by `Box::into_raw`, and already unlinked,
+/// so that no thread can load it from the shared pointer after this call.
+/// It may be dropped later by another thread calling `retire`, so `T` must be safe to drop there.
+pub unsafe fn retire<T>(ptr: *mut T) {
+    unsafe fn free<T>(ptr: *mut u8) {
+        drop(Box::from_raw(ptr as *mut T));
+    }
+
+    let retired = Box::into_raw(Box::new(Retired {
+        next: RETIRED.swap(null_mut(), Ordering::Acquire),
+        ptr: ptr as *mut u8,
+        free: free::<T>,
+    }));
+
+    // the unlink happens before the hazard pointers are read,
+    // so a thread protecting the node after this observes the unlink, and retries
+    fence(Ordering::SeqCst);
+
+    // the nodes are taken by a single swap, so that concurrent callers free them only once
+    let mut node = retired;
+    while !node.is_null() {
+        let next = (*node).next;
+        let protected = SLOTS
+            .iter()
+            .any(|s| s.ptr.load(Ordering::Acquire) == (*node).ptr);
+
+        if protected {
+            push(node);
+        } else {
+            let r = Box::from_raw(node);
+            (r.free)(r.ptr);
+        }
+        node = next;
+    }
+}
+
+fn push(retired: *mut Retired) {
+    let mut head = RETIRED.load(Ordering::Relaxed);
+    loop {
+        unsafe { (*retired).next = head };
+        match RETIRED.compare_exchange_weak(head, retired, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(e) => head = e,
+        }
+    }
+}
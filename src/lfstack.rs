@@ -1,30 +1,253 @@
+use crate::backoff::Backoff;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
-use core::{arch::asm, ptr::null_mut};
+use alloc::vec::Vec;
+use core::{mem::ManuallyDrop, ptr::null_mut};
+
+#[cfg(all(
+    not(loom),
+    any(
+        feature = "epoch",
+        feature = "force-locked-stack",
+        not(target_has_atomic = "ptr")
+    )
+))]
+use core::marker::PhantomData;
+
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(loom, miri, feature = "force-locked-stack"))
+))]
+use core::arch::asm;
+
+#[cfg(all(
+    feature = "epoch",
+    target_arch = "aarch64",
+    not(any(loom, miri, feature = "force-locked-stack"))
+))]
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(all(feature = "epoch", not(loom)))]
+use crate::epoch::{self, EpochGuard};
+
+#[cfg(all(
+    any(loom, miri, not(target_arch = "aarch64")),
+    any(
+        loom,
+        all(target_has_atomic = "ptr", not(feature = "force-locked-stack"))
+    ),
+    not(all(feature = "epoch", not(loom)))
+))]
+use crate::hazard::HazardPointer;
+
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(all(
+    any(miri, not(target_arch = "aarch64")),
+    target_has_atomic = "ptr",
+    not(any(loom, feature = "force-locked-stack"))
+))]
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(all(
+    any(feature = "force-locked-stack", not(target_has_atomic = "ptr")),
+    not(loom)
+))]
+use crate::wait_queue::SpinLocked;
 
 #[repr(C)]
 struct Node<T> {
     next: *mut Node<T>,
+    /// the number of `LFStack::peek_clone` reading the data, see `wait_claims`
+    #[cfg(all(feature = "epoch", not(loom)))]
+    claims: core::sync::atomic::AtomicUsize,
     data: T,
 }
 
+impl<T> Node<T> {
+    fn new(data: T) -> Node<T> {
+        Node {
+            next: null_mut(),
+            #[cfg(all(feature = "epoch", not(loom)))]
+            claims: core::sync::atomic::AtomicUsize::new(0),
+            data,
+        }
+    }
+}
+
+/// the implementation of [`StackHead`] in this build,
+/// `"llsc"` for the LL/SC instructions of AArch64
+///
+/// It is `"cas"` for compare-and-swap on the other architectures and under loom and Miri,
+/// and `"locked"` for a spin lock on targets without compare-and-swap of pointers
+/// or with the `force-locked-stack` feature.
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(loom, miri, feature = "force-locked-stack"))
+))]
+pub const BACKEND: &str = "llsc";
+
+/// the implementation of [`StackHead`] in this build, see the AArch64 version
+#[cfg(all(
+    any(loom, miri, not(target_arch = "aarch64")),
+    any(
+        loom,
+        all(target_has_atomic = "ptr", not(feature = "force-locked-stack"))
+    )
+))]
+pub const BACKEND: &str = "cas";
+
+/// the implementation of [`StackHead`] in this build, see the AArch64 version
+#[cfg(all(
+    any(feature = "force-locked-stack", not(target_has_atomic = "ptr")),
+    not(loom)
+))]
+pub const BACKEND: &str = "locked";
+
+/// move the data out of a popped node, and free the node
+///
+/// With the `epoch` feature, the node is freed after the guards pinned before the pop are dropped,
+/// because [`LFStack::peek`] may be reading it,
+/// and the data is moved out after [`LFStack::peek_clone`] finishes cloning it.
+/// Otherwise, the CAS version frees the node when no hazard pointer of `pop` protects it.
+unsafe fn take_data<T>(ptr: *mut Node<T>) -> T {
+    #[cfg(all(
+        any(
+            all(target_arch = "aarch64", not(miri)),
+            feature = "force-locked-stack",
+            not(target_has_atomic = "ptr")
+        ),
+        not(any(loom, feature = "epoch"))
+    ))]
+    {
+        Box::from_raw(ptr).data
+    }
+
+    #[cfg(all(
+        any(loom, miri, not(target_arch = "aarch64")),
+        any(
+            loom,
+            all(target_has_atomic = "ptr", not(feature = "force-locked-stack"))
+        ),
+        not(all(feature = "epoch", not(loom)))
+    ))]
+    {
+        let data = core::ptr::read(&(*ptr).data);
+        crate::hazard::retire(ptr as *mut ManuallyDrop<Node<T>>);
+        data
+    }
+
+    #[cfg(all(feature = "epoch", not(loom)))]
+    {
+        wait_claims(ptr);
+        let data = core::ptr::read(&(*ptr).data);
+        epoch::defer_destroy(ptr as *mut ManuallyDrop<Node<T>>);
+        data
+    }
+}
+
+/// wait until no [`LFStack::peek_clone`] reads the data of a node detached from the stack
+///
+/// `peek_clone` increments `claims`, and then checks that the node is still the head,
+/// and the caller detached the node, and then loads `claims`.
+/// Both are separated by `SeqCst` fences,
+/// so either `peek_clone` observes the detachment and gives up the node,
+/// or this observes the claim and waits for the decrement after the clone.
+#[cfg(all(feature = "epoch", not(loom)))]
+unsafe fn wait_claims<T>(ptr: *mut Node<T>) {
+    use core::sync::atomic::{fence, Ordering};
+
+    fence(Ordering::SeqCst);
+    while (*ptr).claims.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// drop the data of a node, free the node, and return the next node
+unsafe fn free_node<T>(ptr: *mut Node<T>) -> *mut Node<T> {
+    let next = (*ptr).next;
+    drop(take_data(ptr));
+    next
+}
+
+/// follow up to `n - 1` nexts of `first`, and return the last node and the number of the nodes
+#[cfg(all(
+    not(loom),
+    any(
+        feature = "epoch",
+        feature = "force-locked-stack",
+        not(target_has_atomic = "ptr")
+    )
+))]
+unsafe fn walk<T>(first: *mut Node<T>, n: usize) -> (*mut Node<T>, usize) {
+    let mut last = first;
+    let mut count = 1;
+    while count < n {
+        let next = (*last).next;
+        if next.is_null() {
+            break;
+        }
+        last = next;
+        count += 1;
+    }
+    (last, count)
+}
+
+/// detach up to `n` nodes from the top by a single CAS of `head`,
+/// and return the first node and the number of the nodes
+///
+/// The nodes reachable from the head are not freed while the epoch is pinned,
+/// so the nexts can be followed.
+/// If the head is not changed until the CAS, no node is popped meanwhile,
+/// because a node popped is never pushed again and its memory is not reused while pinned.
+#[cfg(all(
+    feature = "epoch",
+    target_has_atomic = "ptr",
+    not(any(loom, feature = "force-locked-stack"))
+))]
+fn detach_n_pinned<T>(head: &AtomicPtr<Node<T>>, n: usize) -> (*mut Node<T>, usize) {
+    let _guard = epoch::pin();
+    let mut first = head.load(Ordering::Acquire);
+    loop {
+        if first.is_null() || n == 0 {
+            return (null_mut(), 0);
+        }
+
+        let (last, count) = unsafe { walk(first, n) };
+        let rest = unsafe { (*last).next };
+        match head.compare_exchange(first, rest, Ordering::Acquire, Ordering::Acquire) {
+            Ok(_) => return (first, count),
+            Err(e) => first = e,
+        }
+    }
+}
+
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(loom, miri, feature = "force-locked-stack"))
+))]
 #[repr(C)]
 pub struct StackHead<T> {
-    head: *mut Node<T>,
+    head: UnsafeCell<*mut Node<T>>,
 }
 
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(loom, miri, feature = "force-locked-stack"))
+))]
 impl<T> StackHead<T> {
-    const fn new() -> StackHead<T> {
-        StackHead { head: null_mut() }
+    /// create an empty stack, e.g. to build a chain for [`StackHead::swap`]
+    pub const fn new() -> StackHead<T> {
+        StackHead {
+            head: UnsafeCell::new(null_mut()),
+        }
     }
 
-    pub fn push(&mut self, v: T) {
-        let node = Box::new(Node {
-            next: null_mut(),
-            data: v,
-        });
+    fn push_shared(&self, v: T) {
+        let node = Box::new(Node::new(v));
         let ptr = Box::into_raw(node) as *mut u8 as usize;
-        let head = &mut self.head as *mut *mut Node<T> as *mut u8 as usize;
+        let head = self.head.get() as *mut u8 as usize;
 
         unsafe {
             asm!("1:
@@ -39,9 +262,9 @@ impl<T> StackHead<T> {
         };
     }
 
-    pub fn pop(&mut self) -> Option<T> {
+    fn pop_shared(&self) -> Option<T> {
         unsafe {
-            let head = &mut self.head as *mut *mut Node<T> as *mut u8 as usize;
+            let head = self.head.get() as *mut u8 as usize;
             let mut result: usize;
 
             asm!("1:
@@ -68,23 +291,641 @@ impl<T> StackHead<T> {
                 None
             } else {
                 let ptr = result as *mut u8 as *mut Node<T>;
-                let head = Box::from_raw(ptr);
-                Some((*head).data)
+                Some(take_data(ptr))
             }
         }
     }
+
+    fn swap_shared(&self, new_head: StackHead<T>) -> StackHead<T> {
+        let ptr = new_head.into_raw() as *mut u8 as usize;
+        let head = self.head.get() as *mut u8 as usize;
+        let mut result: usize;
+
+        unsafe {
+            asm!("1:
+                  ldaxr {result}, [{head}]     // result = *head
+                  stlxr w10, {ptr}, [{head}]   // *head = ptr
+                  cbnz w10, 1b                 // if tmp != 0 then goto 1",
+                result = out(reg) result,
+                ptr = in(reg) ptr,
+                head = in(reg) head,
+                out("w10") _)
+        };
+
+        StackHead {
+            head: UnsafeCell::new(result as *mut u8 as *mut Node<T>),
+        }
+    }
+
+    /// the head, which is not popped concurrently because of `&mut self`
+    fn top_mut(&mut self) -> *mut Node<T> {
+        *self.head.get_mut()
+    }
+
+    /// take the nodes out of the owned stack without freeing them
+    fn into_raw(self) -> *mut Node<T> {
+        // no other thread accesses the owned stack
+        unsafe { *ManuallyDrop::new(self).head.get() }
+    }
+
+    /// detach up to `n` nodes from the top, see `detach_n_pinned`
+    #[cfg(feature = "epoch")]
+    fn detach_n(&self, n: usize) -> (*mut Node<T>, usize) {
+        // a pointer has the same layout as `AtomicPtr`
+        let head = self.head.get() as *const AtomicPtr<Node<T>>;
+        unsafe { detach_n_pinned(&*head, n) }
+    }
+
+    /// load the head, which may be popped concurrently
+    #[cfg(feature = "epoch")]
+    fn top(&self) -> *mut Node<T> {
+        // a pointer has the same layout as `AtomicPtr`
+        let head = self.head.get() as *const AtomicPtr<Node<T>>;
+        unsafe { (*head).load(Ordering::Acquire) }
+    }
 }
 
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(loom, miri, feature = "force-locked-stack"))
+))]
 impl<T> Drop for StackHead<T> {
     fn drop(&mut self) {
-        let mut node = self.head;
+        let mut node = *self.head.get_mut();
         while !node.is_null() {
-            let n = unsafe { Box::from_raw(node) };
-            node = n.next;
+            node = unsafe { free_node(node) };
         }
     }
 }
 
+/// stack head implemented by CAS of `AtomicPtr` for architectures other than AArch64,
+/// and for loom and Miri, because they cannot execute the LL/SC instructions
+///
+/// `pop` reads the next of the head before the CAS,
+/// so it protects the head by a hazard pointer,
+/// which prevents the head from being freed or reused by another `pop` meanwhile.
+/// With the `epoch` feature, it pins the epoch instead.
+///
+/// This also prevents the ABA problem without a counter packed next to the head.
+/// `push` always allocates a new node, so the head has the same address again
+/// only if the node is freed and its memory reused,
+/// which the hazard pointer, or the pinned epoch, forbids until the CAS is done.
+#[cfg(all(
+    any(loom, miri, not(target_arch = "aarch64")),
+    any(
+        loom,
+        all(target_has_atomic = "ptr", not(feature = "force-locked-stack"))
+    )
+))]
+pub struct StackHead<T> {
+    head: AtomicPtr<Node<T>>,
+}
+
+#[cfg(all(
+    any(loom, miri, not(target_arch = "aarch64")),
+    any(
+        loom,
+        all(target_has_atomic = "ptr", not(feature = "force-locked-stack"))
+    )
+))]
+impl<T> StackHead<T> {
+    /// create an empty stack, e.g. to build a chain for [`StackHead::swap`]
+    #[cfg(loom)]
+    pub fn new() -> StackHead<T> {
+        StackHead {
+            head: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    /// create an empty stack, e.g. to build a chain for [`StackHead::swap`]
+    #[cfg(not(loom))]
+    pub const fn new() -> StackHead<T> {
+        StackHead {
+            head: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    fn push_shared(&self, v: T) {
+        let ptr = Box::into_raw(Box::new(Node::new(v)));
+
+        let mut next = self.head.load(Ordering::Relaxed);
+        loop {
+            // the node is not published yet
+            unsafe { (*ptr).next = next };
+
+            // publish the node and its next
+            match self
+                .head
+                .compare_exchange(next, ptr, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(e) => next = e,
+            }
+        }
+    }
+
+    fn pop_shared(&self) -> Option<T> {
+        #[cfg(all(feature = "epoch", not(loom)))]
+        let _guard = epoch::pin();
+
+        #[cfg(not(all(feature = "epoch", not(loom))))]
+        let hazard = HazardPointer::new();
+
+        loop {
+            #[cfg(all(feature = "epoch", not(loom)))]
+            let result = self.head.load(Ordering::Acquire);
+
+            // the head is not freed until the hazard pointer is dropped
+            #[cfg(not(all(feature = "epoch", not(loom))))]
+            let result = hazard.protect(&self.head);
+
+            if result.is_null() {
+                return None;
+            }
+
+            let next = unsafe { (*result).next };
+            if self
+                .head
+                .compare_exchange(result, next, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(unsafe { take_data(result) });
+            }
+        }
+    }
+
+    fn swap_shared(&self, new_head: StackHead<T>) -> StackHead<T> {
+        let new_head = ManuallyDrop::new(new_head);
+
+        // `new_head` is owned, so no other thread accesses it
+        let ptr = new_head.head.load(Ordering::Relaxed);
+
+        // acquire the old nodes, and publish the new nodes
+        let old = self.head.swap(ptr, Ordering::AcqRel);
+        StackHead {
+            head: AtomicPtr::new(old),
+        }
+    }
+
+    /// the head, which is not popped concurrently because of `&mut self`
+    fn top_mut(&mut self) -> *mut Node<T> {
+        self.head.load(Ordering::Relaxed)
+    }
+
+    /// take the nodes out of the owned stack without freeing them
+    fn into_raw(self) -> *mut Node<T> {
+        // no other thread accesses the owned stack
+        ManuallyDrop::new(self).head.load(Ordering::Relaxed)
+    }
+
+    /// detach up to `n` nodes from the top, see `detach_n_pinned`
+    #[cfg(all(feature = "epoch", not(loom)))]
+    fn detach_n(&self, n: usize) -> (*mut Node<T>, usize) {
+        detach_n_pinned(&self.head, n)
+    }
+
+    /// load the head, which may be popped concurrently
+    #[cfg(all(feature = "epoch", not(loom)))]
+    fn top(&self) -> *mut Node<T> {
+        self.head.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(all(
+    any(loom, miri, not(target_arch = "aarch64")),
+    any(
+        loom,
+        all(target_has_atomic = "ptr", not(feature = "force-locked-stack"))
+    )
+))]
+impl<T> Drop for StackHead<T> {
+    fn drop(&mut self) {
+        let mut node = self.head.load(Ordering::Relaxed);
+        while !node.is_null() {
+            node = unsafe { free_node(node) };
+        }
+    }
+}
+
+/// stack head protected by a spin lock, for targets without compare-and-swap of pointers,
+/// and with the `force-locked-stack` feature, e.g. to compare the lock-free versions under Miri
+///
+/// The API is the same as the lock-free versions,
+/// but a thread preempted while holding the lock blocks the others, so it is not lock-free.
+/// `pop` reads the next of the head while holding the lock,
+/// so the head is neither freed nor reused meanwhile, and the ABA problem does not occur.
+#[cfg(all(
+    any(feature = "force-locked-stack", not(target_has_atomic = "ptr")),
+    not(loom)
+))]
+pub struct StackHead<T> {
+    head: SpinLocked<*mut Node<T>>,
+}
+
+#[cfg(all(
+    any(feature = "force-locked-stack", not(target_has_atomic = "ptr")),
+    not(loom)
+))]
+impl<T> StackHead<T> {
+    /// create an empty stack, e.g. to build a chain for [`StackHead::swap`]
+    pub const fn new() -> StackHead<T> {
+        StackHead {
+            head: SpinLocked::new(null_mut()),
+        }
+    }
+
+    fn push_shared(&self, v: T) {
+        let ptr = Box::into_raw(Box::new(Node::new(v)));
+
+        self.head.with(|head| {
+            // the node is not published yet
+            unsafe { (*ptr).next = *head };
+            *head = ptr;
+        });
+    }
+
+    fn pop_shared(&self) -> Option<T> {
+        let result = self.head.with(|head| {
+            let result = *head;
+            if !result.is_null() {
+                // the head is not freed while the lock is held
+                *head = unsafe { (*result).next };
+            }
+            result
+        });
+
+        if result.is_null() {
+            None
+        } else {
+            Some(unsafe { take_data(result) })
+        }
+    }
+
+    fn swap_shared(&self, new_head: StackHead<T>) -> StackHead<T> {
+        let ptr = new_head.into_raw();
+        let old = self.head.with(|head| core::mem::replace(head, ptr));
+        StackHead {
+            head: SpinLocked::new(old),
+        }
+    }
+
+    /// the head, which is not popped concurrently because of `&mut self`
+    fn top_mut(&mut self) -> *mut Node<T> {
+        self.head.with(|head| *head)
+    }
+
+    /// take the nodes out of the owned stack without freeing them
+    fn into_raw(self) -> *mut Node<T> {
+        ManuallyDrop::new(self).head.with(|head| *head)
+    }
+
+    /// detach up to `n` nodes from the top,
+    /// and return the first node and the number of the nodes
+    fn detach_n(&self, n: usize) -> (*mut Node<T>, usize) {
+        self.head.with(|head| {
+            let first = *head;
+            if first.is_null() || n == 0 {
+                return (null_mut(), 0);
+            }
+
+            // the nodes are not freed while the lock is held
+            let (last, count) = unsafe { walk(first, n) };
+            *head = unsafe { (*last).next };
+            (first, count)
+        })
+    }
+
+    /// load the head, which may be popped concurrently
+    #[cfg(feature = "epoch")]
+    fn top(&self) -> *mut Node<T> {
+        self.head.with(|head| *head)
+    }
+}
+
+#[cfg(all(
+    any(feature = "force-locked-stack", not(target_has_atomic = "ptr")),
+    not(loom)
+))]
+impl<T> Drop for StackHead<T> {
+    fn drop(&mut self) {
+        let mut node = self.head.with(|head| *head);
+        while !node.is_null() {
+            node = unsafe { free_node(node) };
+        }
+    }
+}
+
+impl<T> StackHead<T> {
+    pub fn push(&mut self, v: T) {
+        self.push_shared(v)
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.pop_shared()
+    }
+
+    /// replace the whole stack by `new_head` atomically, and return the old stack
+    ///
+    /// This is a single exchange of the head,
+    /// so it is cheaper than popping the values one by one.
+    /// The returned stack owns the old nodes, which are popped or dropped as usual.
+    pub fn swap(&mut self, new_head: StackHead<T>) -> StackHead<T> {
+        self.swap_shared(new_head)
+    }
+
+    /// call `f` with the top value without popping it, and return its result
+    ///
+    /// `&mut self` guarantees that no other thread pops the value meanwhile,
+    /// so this needs no synchronization.
+    /// Use [`LFStack::peek_clone`] while other threads push and pop.
+    ///
+    /// ```rust
+    /// use synctools::lfstack::StackHead;
+    ///
+    /// let mut stack = StackHead::new();
+    /// assert_eq!(stack.peek_with(|v: &String| v.len()), None);
+    ///
+    /// stack.push("top".to_string());
+    /// assert_eq!(stack.peek_with(|v| v.len()), Some(3));
+    /// assert_eq!(stack.pop().as_deref(), Some("top"));
+    /// ```
+    pub fn peek_with<R>(&mut self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let head = self.top_mut();
+        if head.is_null() {
+            None
+        } else {
+            Some(f(unsafe { &(*head).data }))
+        }
+    }
+
+    /// pop a value, spinning until a value is pushed
+    ///
+    /// This spins forever if the stack stays empty,
+    /// so use it only if a producer is known to push values, e.g. in producer/consumer pipelines.
+    /// Use [`StackHead::pop_with_backoff`] to give up spinning.
+    pub fn pop_blocking(&mut self) -> T {
+        self.pop_blocking_shared()
+    }
+
+    fn pop_blocking_shared(&self) -> T {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(v) = self.pop_shared() {
+                return v;
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// pop a value, spinning by `backoff` while the stack is empty
+    ///
+    /// This returns `None` when `backoff` is completed,
+    /// so that the caller can switch to the blocking mechanism of the environment,
+    /// and then retry with the same backoff, which returns after a single try,
+    /// or with a reset one.
+    ///
+    /// ```rust
+    /// use synctools::{backoff::Backoff, lfstack::LFStack};
+    /// use std::sync::Arc;
+    ///
+    /// let stack = Arc::new(LFStack::new());
+    /// stack.push(1);
+    ///
+    /// let mut backoff = Backoff::new();
+    /// let v = loop {
+    ///     match stack.pop_with_backoff(&mut backoff) {
+    ///         Some(v) => break v,
+    ///         None => std::thread::yield_now(),
+    ///     }
+    /// };
+    /// assert_eq!(v, 1);
+    /// ```
+    pub fn pop_with_backoff(&mut self, backoff: &mut Backoff) -> Option<T> {
+        self.pop_with_backoff_shared(backoff)
+    }
+
+    fn pop_with_backoff_shared(&self, backoff: &mut Backoff) -> Option<T> {
+        loop {
+            if let Some(v) = self.pop_shared() {
+                return Some(v);
+            }
+
+            if backoff.is_completed() {
+                return None;
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// pop up to `n` values from the top, which are returned from the top by the iterator
+    ///
+    /// If the stack has fewer than `n` values, the iterator returns all of them.
+    /// The values which are not iterated are dropped with the iterator.
+    ///
+    /// With the `epoch` feature, or the spin lock version,
+    /// the values are detached at once by a single CAS or a single critical section,
+    /// so they are consecutive in the stack and the iterator does not synchronize any longer.
+    /// Otherwise, `pop` protects only the head,
+    /// so the following nodes may be freed by other threads and cannot be detached with it,
+    /// and the iterator pops the values one by one,
+    /// which may be interleaved with other threads.
+    ///
+    /// ```rust
+    /// use synctools::lfstack::LFStack;
+    ///
+    /// let stack = LFStack::new();
+    /// for i in 0..5 {
+    ///     stack.push(i);
+    /// }
+    ///
+    /// assert!(stack.pop_n(2).eq([4, 3]));
+    /// assert!(stack.pop_n(10).eq([2, 1, 0]));
+    /// assert_eq!(stack.pop_n(10).next(), None);
+    /// ```
+    pub fn pop_n(&mut self, n: usize) -> PopN<'_, T> {
+        self.pop_n_shared(n)
+    }
+
+    fn pop_n_shared(&self, n: usize) -> PopN<'_, T> {
+        #[cfg(all(
+            not(loom),
+            any(
+                feature = "epoch",
+                feature = "force-locked-stack",
+                not(target_has_atomic = "ptr")
+            )
+        ))]
+        {
+            let (node, remaining) = self.detach_n(n);
+            PopN {
+                node,
+                remaining,
+                _stack: PhantomData,
+            }
+        }
+
+        #[cfg(not(all(
+            not(loom),
+            any(
+                feature = "epoch",
+                feature = "force-locked-stack",
+                not(target_has_atomic = "ptr")
+            )
+        )))]
+        PopN {
+            stack: self,
+            remaining: n,
+        }
+    }
+
+    /// pop all the values of the owned stack from the top, without atomic operations
+    ///
+    /// The values left in the iterator are dropped with it.
+    ///
+    /// ```rust
+    /// use synctools::lfstack::LFStack;
+    /// use std::sync::Arc;
+    ///
+    /// let stack = Arc::new(LFStack::new());
+    /// for i in 0..3 {
+    ///     stack.push(i);
+    /// }
+    ///
+    /// // no other thread has the stack any longer
+    /// let stack = Arc::into_inner(stack).unwrap();
+    /// let v: Vec<_> = stack.into_inner().iter_consume().collect();
+    /// assert_eq!(v, [2, 1, 0]);
+    /// ```
+    pub fn iter_consume(self) -> IterConsume<T> {
+        IterConsume {
+            node: self.into_raw(),
+        }
+    }
+}
+
+/// iterator returned by [`StackHead::iter_consume`]
+pub struct IterConsume<T> {
+    node: *mut Node<T>,
+}
+
+impl<T> Iterator for IterConsume<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.node.is_null() {
+            return None;
+        }
+
+        // the nodes are owned by the iterator
+        unsafe {
+            let node = self.node;
+            self.node = (*node).next;
+            Some(take_data(node))
+        }
+    }
+}
+
+impl<T> Drop for IterConsume<T> {
+    fn drop(&mut self) {
+        while !self.node.is_null() {
+            self.node = unsafe { free_node(self.node) };
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for IterConsume<T> {}
+
+/// iterator returned by [`LFStack::pop_all`], which owns the values detached from the stack
+pub type StackDrain<T> = IterConsume<T>;
+
+/// iterator returned by [`StackHead::pop_n`]
+pub struct PopN<'a, T> {
+    /// the first of the `remaining` nodes detached from the stack
+    #[cfg(all(
+        not(loom),
+        any(
+            feature = "epoch",
+            feature = "force-locked-stack",
+            not(target_has_atomic = "ptr")
+        )
+    ))]
+    node: *mut Node<T>,
+    #[cfg(all(
+        not(loom),
+        any(
+            feature = "epoch",
+            feature = "force-locked-stack",
+            not(target_has_atomic = "ptr")
+        )
+    ))]
+    _stack: PhantomData<&'a StackHead<T>>,
+    #[cfg(not(all(
+        not(loom),
+        any(
+            feature = "epoch",
+            feature = "force-locked-stack",
+            not(target_has_atomic = "ptr")
+        )
+    )))]
+    stack: &'a StackHead<T>,
+    remaining: usize,
+}
+
+impl<T> Iterator for PopN<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        #[cfg(all(
+            not(loom),
+            any(
+                feature = "epoch",
+                feature = "force-locked-stack",
+                not(target_has_atomic = "ptr")
+            )
+        ))]
+        // the detached nodes are owned by the iterator,
+        // and the next of the last one is not followed
+        unsafe {
+            let node = self.node;
+            self.node = (*node).next;
+            Some(take_data(node))
+        }
+
+        #[cfg(not(all(
+            not(loom),
+            any(
+                feature = "epoch",
+                feature = "force-locked-stack",
+                not(target_has_atomic = "ptr")
+            )
+        )))]
+        {
+            let v = self.stack.pop_shared();
+            if v.is_none() {
+                self.remaining = 0;
+            }
+            v
+        }
+    }
+}
+
+impl<T> Drop for PopN<'_, T> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
+impl<T> Default for StackHead<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 //-----------------------------------------------------------------------------
 
 use core::cell::UnsafeCell;
@@ -94,16 +935,207 @@ pub struct LFStack<T> {
 }
 
 impl<T> LFStack<T> {
+    #[cfg(not(loom))]
     pub const fn new() -> LFStack<T> {
         LFStack {
             data: UnsafeCell::new(StackHead::new()),
         }
     }
 
-    pub fn get_mut<'a>(self: &'a mut Arc<Self>) -> &'a mut StackHead<T> {
+    #[cfg(loom)]
+    pub fn new() -> LFStack<T> {
+        LFStack {
+            data: UnsafeCell::new(StackHead::new()),
+        }
+    }
+
+    /// get the stack, which is shared by the clones of `self`
+    ///
+    /// The clones get aliasing `&mut` references, which is undefined behavior
+    /// even though the operations are atomic.
+    #[deprecated(note = "use `LFStack::push` and `LFStack::pop` through a shared reference")]
+    pub fn get_mut(self: &mut Arc<Self>) -> &mut StackHead<T> {
         unsafe { &mut *self.data.get() }
     }
+
+    /// the stack shared by the threads, whose operations take `&self`
+    fn head(&self) -> &StackHead<T> {
+        unsafe { &*self.data.get() }
+    }
+
+    pub fn push(&self, v: T) {
+        self.head().push_shared(v)
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.head().pop_shared()
+    }
+
+    /// pop a value, spinning until a value is pushed, see [`StackHead::pop_blocking`]
+    pub fn pop_blocking(&self) -> T {
+        self.head().pop_blocking_shared()
+    }
+
+    /// pop a value, spinning by `backoff` while the stack is empty,
+    /// see [`StackHead::pop_with_backoff`]
+    pub fn pop_with_backoff(&self, backoff: &mut Backoff) -> Option<T> {
+        self.head().pop_with_backoff_shared(backoff)
+    }
+
+    /// pop up to `n` values from the top, see [`StackHead::pop_n`]
+    pub fn pop_n(&self, n: usize) -> PopN<'_, T> {
+        self.head().pop_n_shared(n)
+    }
+
+    /// pop all the values at once by a single exchange of the head,
+    /// which are returned from the top by the iterator
+    ///
+    /// Values pushed after the exchange are left in the stack.
+    /// The values which are not iterated are dropped with the iterator.
+    ///
+    /// ```rust
+    /// use synctools::lfstack::LFStack;
+    ///
+    /// let stack = LFStack::new();
+    /// for i in 0..3 {
+    ///     stack.push(i);
+    /// }
+    ///
+    /// assert!(stack.pop_all().eq([2, 1, 0]));
+    /// assert_eq!(stack.pop(), None);
+    /// ```
+    pub fn pop_all(&self) -> StackDrain<T> {
+        self.swap(StackHead::new()).iter_consume()
+    }
+
+    /// pop all the values at once as [`LFStack::pop_all`], and return them in the pushed order
+    ///
+    /// Threads which loaded the head before the exchange may still read the nexts of the nodes,
+    /// so the chain is not reversed in place, and the values are collected into a `Vec`.
+    ///
+    /// ```rust
+    /// use synctools::lfstack::LFStack;
+    ///
+    /// let stack = LFStack::new();
+    /// for i in 0..3 {
+    ///     stack.push(i);
+    /// }
+    ///
+    /// assert!(stack.pop_all_reversed().eq([0, 1, 2]));
+    /// ```
+    pub fn pop_all_reversed(&self) -> alloc::vec::IntoIter<T> {
+        let mut v: Vec<T> = self.pop_all().collect();
+        v.reverse();
+        v.into_iter()
+    }
+
+    /// replace the whole stack by `new_head` atomically, and return the old stack,
+    /// see [`StackHead::swap`]
+    pub fn swap(&self, new_head: StackHead<T>) -> StackHead<T> {
+        self.head().swap_shared(new_head)
+    }
+
+    /// take the stack out, e.g. to pop the remaining values by [`StackHead::iter_consume`]
+    pub fn into_inner(self) -> StackHead<T> {
+        self.data.into_inner()
+    }
+
+    /// read the top value without popping it
+    ///
+    /// The node is freed after `guard` is dropped even if another thread pops it meanwhile,
+    /// so the reference is valid as long as `guard`.
+    /// The popping thread moves the value out and may drop it,
+    /// so `T` must be `Copy`, which has no drop glue.
+    /// `T` must also be `Sync`, because other threads may read the same value at the same time.
+    ///
+    /// ```rust
+    /// use synctools::{epoch, lfstack::LFStack};
+    /// use std::sync::Arc;
+    ///
+    /// let stack = Arc::new(LFStack::new());
+    /// stack.push(1);
+    ///
+    /// let guard = epoch::pin();
+    /// assert_eq!(stack.peek(&guard), Some(&1));
+    /// ```
+    #[cfg(all(feature = "epoch", not(loom)))]
+    pub fn peek<'a>(&'a self, _guard: &'a EpochGuard) -> Option<&'a T>
+    where
+        T: Copy + Sync,
+    {
+        let head = self.head().top();
+        if head.is_null() {
+            None
+        } else {
+            Some(unsafe { &(*head).data })
+        }
+    }
+
+    /// clone the top value without popping it
+    ///
+    /// The value may be popped by another thread immediately after this returns,
+    /// so the returned value is only a snapshot.
+    /// While the value is being cloned, a thread popping it waits for the clone
+    /// before moving it out, so `clone` should be cheap.
+    /// `T` must be `Sync`, because other threads may clone the same value at the same time.
+    ///
+    /// ```rust
+    /// use synctools::lfstack::LFStack;
+    ///
+    /// let stack = LFStack::new();
+    /// assert_eq!(stack.peek_clone(), None);
+    ///
+    /// stack.push("top".to_string());
+    /// assert_eq!(stack.peek_clone().as_deref(), Some("top"));
+    /// assert_eq!(stack.pop().as_deref(), Some("top"));
+    /// ```
+    #[cfg(all(feature = "epoch", not(loom)))]
+    pub fn peek_clone(&self) -> Option<T>
+    where
+        T: Clone + Sync,
+    {
+        use core::sync::atomic::{fence, Ordering};
+
+        // the head is not freed while pinned
+        let _guard = epoch::pin();
+        loop {
+            let head = self.head().top();
+            if head.is_null() {
+                return None;
+            }
+
+            // see `wait_claims`
+            let claims = unsafe { &(*head).claims };
+            claims.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+
+            // a popped node is never pushed again, so it is not popped yet if it is still the head
+            let v = if self.head().top() == head {
+                Some(unsafe { (*head).data.clone() })
+            } else {
+                None
+            };
+
+            // the data is not moved out until the clone happens before this
+            claims.fetch_sub(1, Ordering::Release);
+
+            if v.is_some() {
+                return v;
+            }
+        }
+    }
+
+    /// call `f` with the top value without popping it, see [`StackHead::peek_with`]
+    pub fn peek_with<R>(&mut self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.data.get_mut().peek_with(f)
+    }
+}
+
+impl<T> Default for LFStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-unsafe impl<T> Sync for LFStack<T> {}
-unsafe impl<T> Send for LFStack<T> {}
+unsafe impl<T: Send> Sync for LFStack<T> {}
+unsafe impl<T: Send> Send for LFStack<T> {}
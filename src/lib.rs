@@ -44,6 +44,10 @@
 //! }
 //! ```
 //!
+//! With the `tme` feature, `lock()` first tries to elide the lock by the Transactional Memory Extension of AArch64,
+//! and falls back to the queue if TME is not available or transactions are aborted.
+//! Critical sections which can be elided must not perform irrevocable operations, e.g. I/O.
+//!
 //! ## Readers Writer Lock
 //!
 //! Spin lock based readers writer lock can be used as std::sync:RwLock.
@@ -96,11 +100,20 @@
 //! }
 //! ```
 //!
-//! ## Lock Free Stack (AArch64 only)
+//! ## Lock Free Stack
 //!
 //! Lock free stack is a concurrent data structure.
-//! This can be used only AArch64 and nightly because this
-//! uses LL/SC instructions in inline assembly internally.
+//! On AArch64, this uses LL/SC instructions in inline assembly internally.
+//! On other architectures, the `alloc` feature provides a portable version by compare-and-swap,
+//! whose `pop` protects the head by a hazard pointer of the `hazard` module,
+//! so that a popped node is neither freed nor reused while another `pop` reads it.
+//! On targets without compare-and-swap of pointers, or with the `force-locked-stack` feature,
+//! a spin lock protects the stack instead, with the same API but without the lock-freedom.
+//! `lfstack::BACKEND` tells which version a build uses.
+//!
+//! `stack::Stack` has the same `push`, `pop` and `len` on every architecture.
+//! It is lock-free by `LFStack` on AArch64, and a `Vec` protected by `MCSLock` on the others,
+//! which blocks while a preempted thread holds the lock.
 //!
 //! ```rust
 //! #[cfg(target_arch = "aarch64")]
@@ -114,23 +127,23 @@
 //! #[cfg(target_arch = "aarch64")]
 //! fn main() {
 //!     // create a stack
-//!     let mut stack = Arc::new(lfstack::LFStack::<usize>::new());
+//!     let stack = Arc::new(lfstack::LFStack::<usize>::new());
 //!     let mut v = Vec::new();
 //!
 //!     for i in 0..NUM_THREADS {
-//!         let mut stack0 = stack.clone();
+//!         let stack0 = stack.clone();
 //!         let t = std::thread::spawn(move || {
 //!             if i & 1 == 0 { // even thread
 //!                 for j in 0..NUM_LOOP {
 //!                     let k = i * NUM_LOOP + j;
 //!                     // push k to the stack
-//!                     stack0.get_mut().push(k);
+//!                     stack0.push(k);
 //!                 }
 //!             } else { // odd thread
 //!                 for _ in 0..NUM_LOOP {
 //!                     loop {
 //!                         // pop from the stack
-//!                         if let Some(k) = stack0.get_mut().pop() {
+//!                         if let Some(k) = stack0.pop() {
 //!                             break;
 //!                         }
 //!                     }
@@ -144,38 +157,159 @@
 //!         t.join().unwrap();
 //!     }
 //!
-//!     assert_eq!(stack.get_mut().pop(), None);
+//!     assert_eq!(stack.pop(), None);
 //! }
 //!
 //! #[cfg(not(target_arch = "aarch64"))]
 //! fn main() {}
 //! ```
+//!
+//! ## OnceCell
+//!
+//! OnceCell is a cell which can be written only once.
+//!
+//! ```rust
+//! use crate::synctools::once::OnceCell;
+//!
+//! static CONFIG: OnceCell<usize> = OnceCell::new();
+//!
+//! fn main() {
+//!     assert_eq!(CONFIG.get(), None);
+//!
+//!     // initialize the value only once
+//!     assert_eq!(*CONFIG.get_or_init(|| 10), 10);
+//!     assert_eq!(*CONFIG.get_or_init(|| 20), 10);
+//!     assert_eq!(CONFIG.set(30), Err(30));
+//! }
+//! ```
+//!
+//! ## WaitGroup
+//!
+//! WaitGroup waits for a set of workers to finish without `std`.
+//!
+//! ```rust
+//! use crate::synctools::waitgroup::WaitGroup;
+//!
+//! const NUM_THREADS: usize = 4;
+//!
+//! static WG: WaitGroup = WaitGroup::new();
+//!
+//! fn main() {
+//!     let worker = WG.worker();
+//!     for _ in 0..NUM_THREADS {
+//!         // the counter is incremented by clone
+//!         let w = worker.clone();
+//!         std::thread::spawn(move || {
+//!             // do something
+//!
+//!             // the counter is decremented by drop
+//!             drop(w);
+//!         });
+//!     }
+//!     drop(worker);
+//!
+//!     // wait until all workers finish
+//!     WG.wait();
+//! }
+//! ```
+//!
+//! ## Async Mutex
+//!
+//! AsyncMutex is a FIFO mutex for async code, which parks waiting tasks instead of spinning.
+//! It needs neither `std` nor an allocator.
+//!
+//! ```rust
+//! use crate::synctools::async_mcs::AsyncMutex;
+//!
+//! static COUNTER: AsyncMutex<u64> = AsyncMutex::new(0);
+//!
+//! async fn increment() {
+//!     // wait for the lock without spinning
+//!     let mut guard = COUNTER.lock().await;
+//!     *guard += 1;
+//! }
+//!
+//! fn main() {}
+//! ```
+//!
+//! ## Async Readers Writer Lock
+//!
+//! AsyncRwLock is a readers writer lock for async code, which parks waiting tasks instead of spinning.
+//!
+//! ```rust
+//! use crate::synctools::async_rwlock::AsyncRwLock;
+//!
+//! static CONFIG: AsyncRwLock<u64> = AsyncRwLock::new(0);
+//!
+//! async fn update() {
+//!     // writers exclude each other and readers
+//!     *CONFIG.write().await += 1;
+//! }
+//!
+//! async fn get() -> u64 {
+//!     // readers share the lock
+//!     *CONFIG.read().await
+//! }
+//!
+//! fn main() {}
+//! ```
 
 #![no_std]
 
-#[cfg(target_arch = "aarch64")]
+#[cfg(any(target_arch = "aarch64", feature = "alloc", loom, miri))]
 extern crate alloc;
 
-#[cfg(target_arch = "aarch64")]
+#[cfg(any(target_arch = "aarch64", feature = "alloc", loom, miri))]
 pub mod lfstack;
 
+pub mod async_mcs;
+pub mod async_rwlock;
+pub mod backoff;
+#[cfg(feature = "debug-detect")]
+pub mod detect;
+#[cfg(all(feature = "epoch", not(loom)))]
+pub mod epoch;
+#[cfg(any(feature = "alloc", loom, miri))]
+pub mod hazard;
 pub mod mcs;
+pub mod once;
+#[cfg(feature = "poison")]
+pub mod poison;
 pub mod rwlock;
+pub mod shm;
+#[cfg(all(any(target_arch = "aarch64", feature = "alloc", miri), not(loom)))]
+pub mod stack;
+#[cfg(feature = "tme")]
+pub mod tme;
+#[cfg(not(loom))]
+pub mod traits;
+pub mod util;
+mod wait_queue;
+pub mod waitgroup;
 
 #[cfg(test)]
 #[macro_use]
 extern crate std;
 
-#[cfg(test)]
+#[cfg(all(feature = "std", not(test)))]
+extern crate std;
+
+// the loom models are in `tests/`
+#[cfg(all(test, not(loom)))]
 mod tests {
     use crate::mcs;
     use crate::rwlock;
     use std::sync::Arc;
     use std::vec::Vec;
 
-    const NUM_LOOP: usize = 10000000;
+    // Miri is too slow to run the loops of the native tests
+    const NUM_LOOP: usize = if cfg!(miri) { 100 } else { 10000000 };
     const NUM_THREADS: usize = 8;
 
+    /// for tests checking behavior rather than throughput
+    const NUM_SMALL_LOOP: usize = if cfg!(miri) { 10 } else { 1000 };
+    const NUM_SMALL_THREADS: usize = 4;
+
     #[test]
     fn test_mcs() {
         let n = Arc::new(mcs::MCSLock::new(0));
@@ -203,25 +337,25 @@ mod tests {
         assert_eq!(NUM_LOOP * NUM_THREADS, *r);
     }
 
-    #[cfg(target_arch = "aarch64")]
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
     #[test]
     fn test_lfstack() {
         use crate::lfstack;
-        let mut stack = Arc::new(lfstack::LFStack::<usize>::new());
+        let stack = Arc::new(lfstack::LFStack::<usize>::new());
         let mut v = Vec::new();
 
         for i in 0..NUM_THREADS {
-            let mut stack0 = stack.clone();
+            let stack0 = stack.clone();
             let t = std::thread::spawn(move || {
                 if i & 1 == 0 {
                     for j in 0..NUM_LOOP {
                         let k = i * NUM_LOOP + j;
-                        stack0.get_mut().push(k);
+                        stack0.push(k);
                     }
                 } else {
                     for _ in 0..NUM_LOOP {
                         loop {
-                            if let Some(_) = stack0.get_mut().pop() {
+                            if stack0.pop().is_some() {
                                 break;
                             }
                         }
@@ -235,7 +369,306 @@ mod tests {
             t.join().unwrap();
         }
 
-        assert_eq!(stack.get_mut().pop(), None);
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_drop() {
+        use crate::lfstack;
+        let stack = Arc::new(lfstack::LFStack::new());
+        let counter = Arc::new(());
+
+        for _ in 0..NUM_SMALL_LOOP {
+            stack.push(counter.clone());
+        }
+        for _ in 0..NUM_SMALL_LOOP / 2 {
+            assert!(stack.pop().is_some());
+        }
+        assert_eq!(
+            Arc::strong_count(&counter),
+            NUM_SMALL_LOOP - NUM_SMALL_LOOP / 2 + 1
+        );
+
+        // the remaining nodes are freed with the data
+        drop(stack);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_pop_blocking() {
+        use crate::backoff::Backoff;
+        use crate::lfstack;
+        let stack = Arc::new(lfstack::LFStack::new());
+
+        // an empty stack gives up when the backoff is completed
+        let mut backoff = Backoff::new();
+        assert_eq!(stack.pop_with_backoff(&mut backoff), None);
+        assert!(backoff.is_completed());
+
+        for i in 0..NUM_SMALL_LOOP {
+            stack.push(i);
+        }
+        let mut sum = 0;
+        for _ in 0..NUM_SMALL_LOOP / 2 {
+            sum += stack.pop_blocking();
+        }
+        while let Some(v) = stack.pop_with_backoff(&mut backoff) {
+            sum += v;
+        }
+        assert_eq!(sum, NUM_SMALL_LOOP * (NUM_SMALL_LOOP - 1) / 2);
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_swap() {
+        use crate::lfstack;
+        let stack = Arc::new(lfstack::LFStack::new());
+        for i in 0..3 {
+            stack.push(i);
+        }
+
+        // a prebuilt chain replaces the whole stack
+        let mut new_head = lfstack::StackHead::new();
+        new_head.push(10);
+        let mut old = stack.swap(new_head);
+        assert_eq!(old.pop(), Some(2));
+
+        assert_eq!(stack.pop(), Some(10));
+        assert_eq!(stack.pop(), None);
+
+        // an empty stack detaches the whole stack
+        stack.push(20);
+        let mut taken = stack.swap(lfstack::StackHead::default());
+        assert_eq!(stack.pop(), None);
+        assert_eq!(taken.pop(), Some(20));
+        assert_eq!(taken.pop(), None);
+
+        // the nodes are owned by the stack swapped in, and freed with it
+        let counter = Arc::new(());
+        let mut head = lfstack::StackHead::new();
+        head.push(counter.clone());
+        let stack = Arc::new(lfstack::LFStack::new());
+        drop(stack.swap(head));
+        assert_eq!(Arc::strong_count(&counter), 2);
+        drop(stack);
+        assert_eq!(Arc::strong_count(&counter), 1);
+        assert_eq!(old.pop(), Some(1));
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_into_inner() {
+        use crate::lfstack;
+        let counter = Arc::new(());
+        let stack = Arc::new(lfstack::LFStack::new());
+        for _ in 0..NUM_SMALL_LOOP {
+            stack.push(counter.clone());
+        }
+
+        let stack = Arc::into_inner(stack).unwrap().into_inner();
+        let mut iter = stack.iter_consume();
+        for _ in 0..NUM_SMALL_LOOP / 2 {
+            assert!(iter.next().is_some());
+        }
+        assert_eq!(
+            Arc::strong_count(&counter),
+            NUM_SMALL_LOOP - NUM_SMALL_LOOP / 2 + 1
+        );
+
+        // the values left are dropped with the iterator
+        drop(iter);
+        assert_eq!(Arc::strong_count(&counter), 1);
+
+        let mut head = lfstack::StackHead::new();
+        for i in 0..3 {
+            head.push(i);
+        }
+        assert!(head.iter_consume().eq([2, 1, 0]));
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_peek_with() {
+        use crate::lfstack;
+        use std::string::{String, ToString};
+        let mut stack = lfstack::LFStack::new();
+        assert_eq!(stack.peek_with(|v: &String| v.clone()), None);
+
+        stack.push("a".to_string());
+        stack.push("b".to_string());
+        assert_eq!(stack.peek_with(|v| v.clone()).as_deref(), Some("b"));
+
+        // the value is not popped
+        assert_eq!(stack.pop().as_deref(), Some("b"));
+        assert_eq!(stack.peek_with(|v| v.len()), Some(1));
+
+        let mut head = stack.into_inner();
+        assert_eq!(head.peek_with(|v| v.clone()).as_deref(), Some("a"));
+        assert_eq!(head.pop().as_deref(), Some("a"));
+        assert_eq!(head.peek_with(|v| v.len()), None);
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_pop_n() {
+        use crate::lfstack;
+        let stack = Arc::new(lfstack::LFStack::new());
+        assert_eq!(stack.pop_n(3).next(), None);
+
+        let counter = Arc::new(());
+        for i in 0..10 {
+            stack.push((i, counter.clone()));
+        }
+        assert_eq!(stack.pop_n(0).next(), None);
+        assert!(stack.pop_n(3).map(|(i, _)| i).eq([9, 8, 7]));
+
+        // the values not iterated are dropped with the iterator
+        let mut popped = stack.pop_n(3);
+        assert_eq!(popped.next().map(|(i, _)| i), Some(6));
+        drop(popped);
+        assert_eq!(Arc::strong_count(&counter), 5);
+
+        // fewer than `n` values are left
+        assert!(stack.pop_n(10).map(|(i, _)| i).eq([3, 2, 1, 0]));
+        assert!(stack.pop().is_none());
+        assert_eq!(Arc::strong_count(&counter), 1);
+
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let stack0 = stack.clone();
+            let counter0 = counter.clone();
+            let t = std::thread::spawn(move || {
+                let mut sum = 0;
+                for j in 0..NUM_SMALL_LOOP {
+                    stack0.push((i * NUM_SMALL_LOOP + j, counter0.clone()));
+                    if j % 4 == 3 {
+                        sum += stack0.pop_n(4).map(|(k, _)| k).sum::<usize>();
+                    }
+                }
+                sum
+            });
+            v.push(t);
+        }
+
+        let mut sum = 0;
+        for t in v {
+            sum += t.join().unwrap();
+        }
+        sum += stack.pop_n(usize::MAX).map(|(k, _)| k).sum::<usize>();
+
+        // every value is popped exactly once, and dropped
+        let n = NUM_SMALL_THREADS * NUM_SMALL_LOOP;
+        assert_eq!(sum, n * (n - 1) / 2);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_pop_all() {
+        use crate::lfstack;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let stack = Arc::new(lfstack::LFStack::new());
+        assert_eq!(stack.pop_all().next(), None);
+
+        for i in 0..3 {
+            stack.push(i);
+        }
+        assert!(stack.pop_all_reversed().eq([0, 1, 2]));
+        assert_eq!(stack.pop(), None);
+
+        // producers push while a consumer drains repeatedly
+        let producers = NUM_SMALL_THREADS - 1;
+        let finished = Arc::new(AtomicUsize::new(0));
+        let mut v = Vec::new();
+        for i in 0..producers {
+            let stack0 = stack.clone();
+            let finished0 = finished.clone();
+            let t = std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    stack0.push(i * NUM_SMALL_LOOP + j);
+                }
+                finished0.fetch_add(1, Ordering::Release);
+            });
+            v.push(t);
+        }
+
+        let mut popped = Vec::new();
+        loop {
+            let done = finished.load(Ordering::Acquire) == producers;
+            let drained: Vec<_> = stack.pop_all().collect();
+
+            // the values of a producer are popped in the reverse order of the pushes
+            for w in drained.windows(2) {
+                assert!(w[0] / NUM_SMALL_LOOP != w[1] / NUM_SMALL_LOOP || w[0] > w[1]);
+            }
+            popped.extend(drained);
+
+            if done {
+                break;
+            }
+            std::thread::yield_now();
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        // no value is lost or duplicated
+        popped.sort();
+        assert!(popped.into_iter().eq(0..producers * NUM_SMALL_LOOP));
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_backend() {
+        use crate::lfstack;
+        let expected = if cfg!(feature = "force-locked-stack") {
+            "locked"
+        } else if cfg!(all(target_arch = "aarch64", not(miri))) {
+            "llsc"
+        } else {
+            "cas"
+        };
+        assert_eq!(lfstack::BACKEND, expected);
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_stack() {
+        use crate::stack::Stack;
+        let stack = Arc::new(Stack::new());
+        assert!(stack.is_empty());
+        assert_eq!(stack.pop(), None);
+
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let stack0 = stack.clone();
+            let t = std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    stack0.push(i * NUM_SMALL_LOOP + j);
+                }
+                for _ in 0..NUM_SMALL_LOOP / 2 {
+                    // at least the values pushed by this thread are left
+                    assert!(stack0.pop().is_some());
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let rest = NUM_SMALL_THREADS * (NUM_SMALL_LOOP - NUM_SMALL_LOOP / 2);
+        assert_eq!(stack.len(), rest);
+        let mut n = 0;
+        while stack.pop().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, rest);
+        assert!(stack.is_empty());
     }
 
     #[test]
@@ -272,4 +705,3762 @@ mod tests {
             t.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_mcs_lock_both() {
+        const TOTAL: isize = 1000;
+
+        let a = Arc::new(mcs::MCSLock::new(TOTAL));
+        let b = Arc::new(mcs::MCSLock::new(0isize));
+        let mut v = Vec::new();
+
+        for i in 0..NUM_SMALL_THREADS {
+            let a0 = a.clone();
+            let b0 = b.clone();
+            let t = std::thread::spawn(move || {
+                let mut na = mcs::MCSNode::new();
+                let mut nb = mcs::MCSNode::new();
+                for j in 0..NUM_SMALL_LOOP {
+                    let amount = (j % 7) as isize;
+
+                    // transfer in both directions
+                    let (mut from, mut to) = if i & 1 == 0 {
+                        mcs::lock_both(&a0, &b0, &mut na, &mut nb)
+                    } else {
+                        let (gb, ga) = mcs::lock_both(&b0, &a0, &mut nb, &mut na);
+                        (gb, ga)
+                    };
+
+                    *from -= amount;
+                    *to += amount;
+                    assert_eq!(*from + *to, TOTAL);
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let mut na = mcs::MCSNode::new();
+        let mut nb = mcs::MCSNode::new();
+        let (ga, gb) = mcs::lock_both(&a, &b, &mut na, &mut nb);
+        assert_eq!(*ga + *gb, TOTAL);
+    }
+
+    #[test]
+    fn test_mcs_lock_both_or_one() {
+        let a = mcs::MCSLock::new(1);
+        let b = mcs::MCSLock::new(2);
+        let mut na = mcs::MCSNode::new();
+        let mut nb = mcs::MCSNode::new();
+
+        match mcs::lock_both_or_one(&a, &a, &mut na, &mut nb) {
+            mcs::MCSLockBoth::One(mut g) => *g += 10,
+            mcs::MCSLockBoth::Two(..) => panic!("a and a are the same lock"),
+        }
+
+        match mcs::lock_both_or_one(&a, &b, &mut na, &mut nb) {
+            mcs::MCSLockBoth::One(_) => panic!("a and b are different locks"),
+            mcs::MCSLockBoth::Two(ga, gb) => {
+                assert_eq!(*ga, 11);
+                assert_eq!(*gb, 2);
+            }
+        };
+    }
+
+    #[test]
+    #[should_panic(expected = "same lock")]
+    fn test_mcs_lock_both_alias() {
+        let a = mcs::MCSLock::new(0);
+        let mut na = mcs::MCSNode::new();
+        let mut nb = mcs::MCSNode::new();
+        let _ = mcs::lock_both(&a, &a, &mut na, &mut nb);
+    }
+
+    #[test]
+    fn test_once_cell() {
+        use crate::once::OnceCell;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        let cell = Arc::new(OnceCell::new());
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut v = Vec::new();
+
+        for i in 0..NUM_SMALL_THREADS {
+            let cell0 = cell.clone();
+            let count0 = count.clone();
+            let t = std::thread::spawn(move || {
+                let r = cell0.get_or_init(|| {
+                    count0.fetch_add(1, Ordering::Relaxed);
+                    i
+                });
+                assert_eq!(Some(r), cell0.get());
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        // initialized exactly once
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        assert!(cell.get().is_some());
+
+        let cell = OnceCell::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(vec![1]), Ok(()));
+        assert_eq!(cell.set(vec![2]), Err(vec![2]));
+        assert_eq!(cell.into_inner(), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_once_cell_panic() {
+        use crate::once::OnceCell;
+
+        let cell = OnceCell::new();
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cell.get_or_init(|| panic!("init failed"));
+        }));
+        assert!(r.is_err());
+
+        // the cell can be initialized after the panic
+        assert_eq!(cell.get(), None);
+        assert_eq!(*cell.get_or_init(|| 1), 1);
+    }
+
+    #[test]
+    fn test_mcs_lock_all() {
+        const NUM_LOCKS: usize = 4;
+
+        let locks: Arc<Vec<mcs::MCSLock<usize>>> =
+            Arc::new((0..NUM_LOCKS).map(|_| mcs::MCSLock::new(0)).collect());
+        let mut v = Vec::new();
+
+        // each thread locks an overlapping range
+        for i in 0..2 {
+            let locks0 = locks.clone();
+            let t = std::thread::spawn(move || {
+                let range = &locks0[i..i + NUM_LOCKS - 1];
+                let mut nodes: Vec<_> = (0..range.len()).map(|_| mcs::MCSNode::new()).collect();
+                for _ in 0..NUM_SMALL_LOOP {
+                    let mut guard = mcs::lock_all(range, &mut nodes);
+                    for n in guard.iter_mut() {
+                        *n += 1;
+                    }
+                    guard[0] += 1;
+                    guard[0] -= 1;
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let mut nodes: Vec<_> = (0..NUM_LOCKS).map(|_| mcs::MCSNode::new()).collect();
+        let guard = mcs::lock_all(&locks, &mut nodes);
+        assert_eq!(guard.len(), NUM_LOCKS);
+        assert_eq!(guard[0], NUM_SMALL_LOOP);
+        assert_eq!(guard[1], NUM_SMALL_LOOP * 2);
+        assert_eq!(guard[2], NUM_SMALL_LOOP * 2);
+        assert_eq!(guard[NUM_LOCKS - 1], NUM_SMALL_LOOP);
+        guard.unlock();
+
+        // empty slice
+        let guard = mcs::lock_all::<usize>(&[], &mut []);
+        assert!(guard.is_empty());
+        assert_eq!(guard.iter().count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "nodes.len()")]
+    fn test_mcs_lock_all_few_nodes() {
+        let locks = [mcs::MCSLock::new(0), mcs::MCSLock::new(0)];
+        let mut nodes = [mcs::MCSNode::new()];
+        let _ = mcs::lock_all(&locks, &mut nodes);
+    }
+
+    #[test]
+    fn test_waitgroup() {
+        use crate::waitgroup::WaitGroup;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static WG: WaitGroup = WaitGroup::new();
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        // Worker
+        let worker = WG.worker();
+        for _ in 0..NUM_SMALL_THREADS {
+            let w = worker.clone();
+            std::thread::spawn(move || {
+                COUNT.fetch_add(1, Ordering::Relaxed);
+                w.done();
+            });
+        }
+        drop(worker);
+
+        WG.wait();
+        assert_eq!(COUNT.load(Ordering::Relaxed), NUM_SMALL_THREADS);
+        assert_eq!(WG.count(), 0);
+
+        // add and done
+        WG.add(NUM_SMALL_THREADS);
+        for _ in 0..NUM_SMALL_THREADS {
+            std::thread::spawn(|| {
+                COUNT.fetch_add(1, Ordering::Relaxed);
+                WG.done();
+            });
+        }
+
+        WG.wait();
+        assert_eq!(COUNT.load(Ordering::Relaxed), NUM_SMALL_THREADS * 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "already zero")]
+    fn test_waitgroup_done_underflow() {
+        let wg = crate::waitgroup::WaitGroup::new();
+        wg.done();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_waitgroup_done_underflow_unwind() {
+        let wg = crate::waitgroup::WaitGroup::new();
+        let r = std::panic::catch_unwind(|| wg.done());
+        assert!(r.is_err());
+
+        // the counter is not wrapped around
+        assert_eq!(wg.count(), 0);
+        wg.wait();
+    }
+
+    #[test]
+    fn test_mcs_take_replace_swap() {
+        // not Clone
+        #[derive(Debug, Default, PartialEq)]
+        struct NoClone(usize);
+
+        let lock = mcs::MCSLock::new(NoClone(1));
+        let mut node = mcs::MCSNode::new();
+
+        assert_eq!(lock.replace(&mut node, NoClone(2)), NoClone(1));
+        assert_eq!(lock.take(&mut node), NoClone(2));
+
+        let mut other = NoClone(3);
+        lock.swap(&mut node, &mut other);
+        assert_eq!(other, NoClone(0));
+        assert_eq!(lock.take(&mut node), NoClone(3));
+    }
+
+    #[test]
+    fn test_mcs_take_concurrent() {
+        let lock = Arc::new(mcs::MCSLock::new(Vec::new()));
+        let mut v = Vec::new();
+
+        // producers
+        for _ in 0..(NUM_SMALL_THREADS - 1) {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = mcs::MCSNode::new();
+                for i in 0..NUM_SMALL_LOOP {
+                    lock0.lock(&mut node).push(i);
+                }
+            });
+            v.push(t);
+        }
+
+        // consumer
+        let lock0 = lock.clone();
+        let consumer = std::thread::spawn(move || {
+            let mut node = mcs::MCSNode::new();
+            let mut n = 0;
+            for _ in 0..NUM_SMALL_LOOP {
+                n += lock0.take(&mut node).len();
+            }
+            n
+        });
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let mut node = mcs::MCSNode::new();
+        let n = consumer.join().unwrap() + lock.take(&mut node).len();
+        assert_eq!(n, NUM_SMALL_LOOP * (NUM_SMALL_THREADS - 1));
+    }
+
+    #[test]
+    fn test_backoff() {
+        use crate::backoff::Backoff;
+
+        let mut backoff = Backoff::new();
+        for _ in 0..20 {
+            backoff.spin();
+        }
+        assert!(!backoff.is_completed());
+
+        while !backoff.is_completed() {
+            backoff.snooze();
+        }
+
+        backoff.reset();
+        assert!(!backoff.is_completed());
+    }
+
+    #[test]
+    fn test_rwlock_writers() {
+        let n = Arc::new(rwlock::RwLock::new(0));
+        let mut v = Vec::new();
+
+        for i in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i & 1 == 0 {
+                        *n0.write() += 1;
+                    } else {
+                        let r = n0.read();
+                        assert!(*r <= NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(*n.read(), NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+    }
+
+    #[test]
+    fn test_cache_padded() {
+        use crate::util::CachePadded;
+        use core::mem::{align_of, size_of};
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        assert!(align_of::<CachePadded<u8>>() >= 64);
+        assert_eq!(size_of::<CachePadded<u8>>(), align_of::<CachePadded<u8>>());
+
+        // adjacent elements never share a cache line
+        let locks = [
+            CachePadded::new(mcs::MCSLock::new(0)),
+            CachePadded::new(mcs::MCSLock::new(0)),
+        ];
+        let a0 = &*locks[0] as *const _ as usize;
+        let a1 = &*locks[1] as *const _ as usize;
+        assert!(a1 - a0 >= 64);
+
+        let mut node = mcs::MCSNode::new();
+        *locks[1].lock(&mut node) += 1;
+        assert_eq!(*locks[1].lock(&mut node), 1);
+
+        let mut n = CachePadded::new(AtomicUsize::new(0));
+        n.fetch_add(1, Ordering::Relaxed);
+        *n.get_mut() += 1;
+        assert_eq!(n.into_inner().into_inner(), 2);
+    }
+
+    #[test]
+    fn test_mcs_guard_raw() {
+        fn acquire(
+            lock: &mcs::MCSLock<usize>,
+            node: &mut mcs::MCSNode<usize>,
+        ) -> (*const mcs::MCSLock<usize>, *mut mcs::MCSNode<usize>) {
+            let mut guard = lock.lock(node);
+            *guard += 1;
+            guard.into_raw()
+        }
+
+        fn release(raw: (*const mcs::MCSLock<usize>, *mut mcs::MCSNode<usize>)) {
+            let mut guard = unsafe { mcs::MCSLockGuard::from_raw(raw.0, raw.1) };
+            *guard += 1;
+        }
+
+        let lock = Arc::new(mcs::MCSLock::new(0));
+        let mut node = std::boxed::Box::new(mcs::MCSNode::new());
+        let raw = acquire(&lock, &mut node);
+
+        // other threads wait for the lock held by the raw parts
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = mcs::MCSNode::new();
+                let mut guard = lock0.lock(&mut node);
+
+                // the holder has already finished
+                assert_eq!(*guard % 2, 0);
+                *guard += 2;
+            });
+            v.push(t);
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        release(raw);
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(*lock.lock(&mut node), 2 + 2 * NUM_SMALL_THREADS);
+    }
+
+    #[test]
+    fn test_raw_mcs() {
+        use crate::mcs::{RawMCSLock, RawMCSNode};
+        use core::ptr::addr_of_mut;
+
+        static LOCK: RawMCSLock = RawMCSLock::new();
+        static mut RESOURCE: usize = 0;
+
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let t = std::thread::spawn(|| {
+                let mut node = RawMCSNode::new();
+                for _ in 0..NUM_SMALL_LOOP {
+                    let guard = LOCK.lock(&mut node);
+                    let ptr = addr_of_mut!(RESOURCE);
+                    unsafe {
+                        let n = ptr.read_volatile();
+                        ptr.write_volatile(n + 1);
+                    }
+                    guard.unlock();
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let mut node = RawMCSNode::new();
+        let _guard = LOCK.lock(&mut node);
+        let n = unsafe { addr_of_mut!(RESOURCE).read_volatile() };
+        assert_eq!(n, NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_mcs_parallel_fold() {
+        let lock = mcs::MCSLock::new(Vec::new());
+        mcs::parallel_fold(&lock, 0..NUM_SMALL_LOOP, |v, i| v.push(i));
+
+        let mut node = mcs::MCSNode::new();
+        let mut v = core::mem::take(&mut *lock.lock(&mut node));
+        v.sort_unstable();
+        assert!(v.into_iter().eq(0..NUM_SMALL_LOOP));
+
+        // no thread is spawned for no items
+        mcs::parallel_fold(&lock, [], |v, i: usize| v.push(i));
+        assert!(lock.lock(&mut node).is_empty());
+    }
+
+    #[test]
+    fn test_shm_mcs() {
+        use crate::shm::{ShmMCSLock, ShmMCSNode};
+        use core::mem::size_of;
+
+        assert_eq!(ShmMCSLock::<u64>::DATA_OFFSET, 8);
+        assert_eq!(size_of::<ShmMCSNode>(), size_of::<isize>() * 2);
+
+        const NODE_OFFSET: usize = 64;
+        const NODE_SIZE: usize = 16;
+
+        // shared memory region
+        let mut region = vec![0u64; 64];
+        let base = region.as_mut_ptr() as usize;
+
+        // the first process initializes the lock
+        unsafe { ShmMCSLock::init_in_place(base as *mut ShmMCSLock<u64>, 0) };
+
+        // each process uses the lock and its node through raw pointers
+        let mut v = Vec::new();
+        for i in 0..2 {
+            let t = std::thread::spawn(move || {
+                let lock = unsafe { ShmMCSLock::<u64>::from_raw(base as *mut _) };
+                let node_ptr = (base + NODE_OFFSET + i * NODE_SIZE) as *mut ShmMCSNode;
+                let node = unsafe { ShmMCSNode::init_in_place(node_ptr) };
+                for _ in 0..NUM_SMALL_LOOP {
+                    let mut guard = lock.lock(node);
+                    let n = *guard;
+                    *guard = n + 1;
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let lock = unsafe { ShmMCSLock::<u64>::from_raw(base as *mut _) };
+        let node = unsafe { ShmMCSNode::init_in_place((base + NODE_OFFSET) as *mut _) };
+        assert_eq!(*lock.lock(node), NUM_SMALL_LOOP as u64 * 2);
+
+        // the data is in the region
+        assert_eq!(region[1], NUM_SMALL_LOOP as u64 * 2);
+    }
+
+    #[test]
+    fn test_shm_rwlock() {
+        use crate::shm::ShmRwLock;
+        use core::mem::size_of;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        assert_eq!(ShmRwLock::<u64>::STATE_OFFSET, 0);
+        assert_eq!(
+            ShmRwLock::<u64>::WRITER_WAKE_COUNTER_OFFSET,
+            size_of::<usize>()
+        );
+        assert_eq!(ShmRwLock::<u64>::DATA_OFFSET, size_of::<usize>() * 2);
+
+        // shared memory region
+        let mut region = vec![0u64; 8];
+        let base = region.as_mut_ptr() as usize;
+
+        // the first process initializes the lock
+        unsafe { ShmRwLock::init_in_place(base as *mut ShmRwLock<u64>, 0) };
+
+        let mut v = Vec::new();
+
+        // the second process uses the lock through a raw pointer
+        v.push(std::thread::spawn(move || {
+            let lock = unsafe { ShmRwLock::<u64>::from_raw(base as *mut _) };
+            for _ in 0..NUM_SMALL_LOOP {
+                *lock.write() += 1;
+            }
+        }));
+
+        // the third process implements the protocol by the words, as C code does
+        v.push(std::thread::spawn(move || {
+            let state = unsafe {
+                AtomicUsize::from_ptr((base + ShmRwLock::<u64>::STATE_OFFSET) as *mut usize)
+            };
+            let data = (base + ShmRwLock::<u64>::DATA_OFFSET) as *mut u64;
+            for _ in 0..NUM_SMALL_LOOP {
+                loop {
+                    let s = state.load(Ordering::Relaxed);
+                    if s <= 1
+                        && state
+                            .compare_exchange(s, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+                            .is_ok()
+                    {
+                        break;
+                    }
+                    core::hint::spin_loop();
+                }
+                unsafe { data.write_volatile(data.read_volatile() + 1) };
+                state.store(0, Ordering::Release);
+            }
+        }));
+
+        // readers never observe the count decreasing
+        for _ in 0..2 {
+            v.push(std::thread::spawn(move || {
+                let lock = unsafe { ShmRwLock::<u64>::from_raw(base as *mut _) };
+                let mut prev = 0;
+                for _ in 0..NUM_SMALL_LOOP {
+                    let n = *lock.read();
+                    assert!(n >= prev);
+                    prev = n;
+                }
+            }));
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let lock = unsafe { ShmRwLock::<u64>::from_raw(base as *mut _) };
+        assert_eq!(*lock.read(), NUM_SMALL_LOOP as u64 * 2);
+        assert!(lock.try_write().is_some());
+
+        // the data is in the region
+        assert_eq!(region[2], NUM_SMALL_LOOP as u64 * 2);
+    }
+
+    #[cfg(feature = "tme")]
+    #[test]
+    fn test_mcs_tme_fallback() {
+        let n = Arc::new(mcs::MCSLock::new(0));
+
+        // without TME, every lock is acquired by the queue
+        if !crate::tme::is_available() {
+            let mut node = mcs::MCSNode::new();
+            let guard = n.lock(&mut node);
+            assert!(!guard.is_elided());
+        }
+
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = mcs::MCSNode::new();
+                for _ in 0..NUM_SMALL_LOOP {
+                    *n0.lock(&mut node) += 1;
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let mut node = mcs::MCSNode::new();
+        assert_eq!(*n.lock(&mut node), NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+    }
+
+    /// minimal executor which parks the thread until the future is woken
+    fn block_on<F: core::future::Future>(fut: F) -> F::Output {
+        use core::task::{Context, Poll};
+        use std::task::Wake;
+
+        struct ThreadWaker(std::thread::Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = core::pin::pin!(fut);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    /// waker counting the number of wake-ups
+    fn counting_waker() -> (Arc<core::sync::atomic::AtomicUsize>, core::task::Waker) {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use std::task::Wake;
+
+        struct CountingWaker(Arc<AtomicUsize>);
+
+        impl Wake for CountingWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let waker = Arc::new(CountingWaker(count.clone())).into();
+        (count, waker)
+    }
+
+    #[test]
+    fn test_async_mutex() {
+        let n = Arc::new(crate::async_mcs::AsyncMutex::new(0));
+        let mut v = Vec::new();
+
+        for _ in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                block_on(async {
+                    for _ in 0..NUM_SMALL_LOOP {
+                        *n0.lock().await += 1;
+                    }
+                })
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(
+            block_on(async { *n.lock().await }),
+            NUM_SMALL_LOOP * NUM_SMALL_THREADS
+        );
+    }
+
+    #[test]
+    fn test_async_mutex_handover() {
+        use core::future::Future;
+        use core::sync::atomic::Ordering;
+        use core::task::{Context, Poll};
+
+        let m = crate::async_mcs::AsyncMutex::new(0);
+        let (count1, waker1) = counting_waker();
+        let (count2, waker2) = counting_waker();
+        let (count3, waker3) = counting_waker();
+        let mut cx1 = Context::from_waker(&waker1);
+        let mut cx2 = Context::from_waker(&waker2);
+        let mut cx3 = Context::from_waker(&waker3);
+
+        let guard = block_on(m.lock());
+
+        let mut f1 = std::boxed::Box::pin(m.lock());
+        let mut f2 = std::boxed::Box::pin(m.lock());
+        let mut f3 = std::boxed::Box::pin(m.lock());
+        assert!(f1.as_mut().poll(&mut cx1).is_pending());
+        assert!(f2.as_mut().poll(&mut cx2).is_pending());
+        assert!(f3.as_mut().poll(&mut cx3).is_pending());
+
+        // a canceled waiter leaves the queue
+        drop(f1);
+        drop(guard);
+        assert_eq!(count1.load(Ordering::Relaxed), 0);
+        assert_eq!(count2.load(Ordering::Relaxed), 1);
+
+        // a waiter which is handed over the lock but dropped passes it to the next
+        drop(f2);
+        assert_eq!(count3.load(Ordering::Relaxed), 1);
+
+        let Poll::Ready(mut guard) = f3.as_mut().poll(&mut cx3) else {
+            panic!("the lock must be handed over");
+        };
+        *guard += 1;
+        drop(guard);
+        drop(f3);
+
+        // unlocked
+        let mut f4 = std::boxed::Box::pin(m.lock());
+        assert!(matches!(f4.as_mut().poll(&mut cx1), Poll::Ready(ref g) if **g == 1));
+    }
+
+    #[test]
+    fn test_async_rwlock() {
+        let n = Arc::new(crate::async_rwlock::AsyncRwLock::new(0));
+        let mut v = Vec::new();
+
+        for i in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                block_on(async {
+                    for _ in 0..NUM_SMALL_LOOP {
+                        if i % 2 == 0 {
+                            *n0.write().await += 1;
+                        } else {
+                            let r = n0.read().await;
+                            assert!(*r <= NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+                        }
+                    }
+                })
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(
+            block_on(async { *n.read().await }),
+            NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2
+        );
+    }
+
+    #[test]
+    fn test_async_rwlock_wake() {
+        use core::future::Future;
+        use core::sync::atomic::Ordering;
+        use core::task::Context;
+
+        let lock = crate::async_rwlock::AsyncRwLock::new(0);
+        let (count_r1, waker_r1) = counting_waker();
+        let (count_r2, waker_r2) = counting_waker();
+        let (count_w, waker_w) = counting_waker();
+        let mut cx_r1 = Context::from_waker(&waker_r1);
+        let mut cx_r2 = Context::from_waker(&waker_r2);
+        let mut cx_w = Context::from_waker(&waker_w);
+
+        // the writer wakes all readers and a writer
+        let guard = block_on(lock.write());
+        let mut r1 = std::boxed::Box::pin(lock.read());
+        let mut r2 = std::boxed::Box::pin(lock.read());
+        let mut w = std::boxed::Box::pin(lock.write());
+        assert!(r1.as_mut().poll(&mut cx_r1).is_pending());
+        assert!(r2.as_mut().poll(&mut cx_r2).is_pending());
+        assert!(w.as_mut().poll(&mut cx_w).is_pending());
+        drop(guard);
+        assert_eq!(count_r1.load(Ordering::Relaxed), 1);
+        assert_eq!(count_r2.load(Ordering::Relaxed), 1);
+        assert_eq!(count_w.load(Ordering::Relaxed), 1);
+
+        // readers share the lock, and the writer waits for them
+        let g1 = r1.as_mut().poll(&mut cx_r1);
+        let g2 = r2.as_mut().poll(&mut cx_r2);
+        assert!(g1.is_ready() && g2.is_ready());
+        assert!(w.as_mut().poll(&mut cx_w).is_pending());
+
+        // the waiting writer blocks new readers
+        let mut r3 = std::boxed::Box::pin(lock.read());
+        assert!(r3.as_mut().poll(&mut cx_r1).is_pending());
+
+        // the last reader wakes the writer
+        drop(g1);
+        assert_eq!(count_w.load(Ordering::Relaxed), 1);
+        drop(g2);
+        assert_eq!(count_w.load(Ordering::Relaxed), 2);
+
+        // the canceled writer unblocks readers
+        drop(w);
+        assert_eq!(count_r1.load(Ordering::Relaxed), 2);
+        assert!(r3.as_mut().poll(&mut cx_r1).is_ready());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let lock = mcs::MCSLock::new(vec![1, 2, 3]);
+        let json = serde_json::to_string(&lock).unwrap();
+        assert_eq!(json, "[1,2,3]");
+
+        let lock: mcs::MCSLock<Vec<u32>> = serde_json::from_str(&json).unwrap();
+        let mut node = mcs::MCSNode::new();
+        assert_eq!(*lock.lock(&mut node), [1, 2, 3]);
+
+        let lock = rwlock::RwLock::new((10, true));
+        let json = serde_json::to_string(&lock).unwrap();
+        assert_eq!(json, "[10,true]");
+
+        let lock: rwlock::RwLock<(u32, bool)> = serde_json::from_str(&json).unwrap();
+        assert_eq!(*lock.read(), (10, true));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_mcs_unwind() {
+        let n = Arc::new(mcs::MCSLock::new(0));
+        let n0 = n.clone();
+        let mut waiter = None;
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut node = mcs::MCSNode::new();
+            let mut guard = n.lock(&mut node);
+            *guard += 1;
+
+            // a waiter is queued behind the holder
+            waiter = Some(std::thread::spawn(move || {
+                let mut node = mcs::MCSNode::new();
+                *n0.lock(&mut node) += 1;
+            }));
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            panic!("unwind with the lock held");
+        }));
+        assert!(r.is_err());
+
+        // the guard dropped while unwinding hands over the lock to the waiter
+        waiter.unwrap().join().unwrap();
+        let mut node = mcs::MCSNode::new();
+        assert_eq!(*n.lock(&mut node), 2);
+    }
+
+    #[test]
+    fn test_cancelable_mcs() {
+        let n = Arc::new(mcs::MCSLockCancelable::new(0));
+        let mut v = Vec::new();
+
+        for _ in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = mcs::MCSNodeCancelable::new();
+                let mut acquired = 0;
+                for i in 0..NUM_SMALL_LOOP {
+                    // waiters leave the queue between the others
+                    let guard = if i & 1 == 0 {
+                        Some(n0.lock(&mut node))
+                    } else {
+                        n0.try_lock_for(&mut node, 4)
+                    };
+                    if let Some(mut guard) = guard {
+                        *guard += 1;
+                        acquired += 1;
+                    }
+                }
+                acquired
+            });
+            v.push(t);
+        }
+
+        let acquired: usize = v.into_iter().map(|t| t.join().unwrap()).sum();
+        assert!(acquired >= NUM_SMALL_THREADS * NUM_SMALL_LOOP / 2);
+
+        let mut node = mcs::MCSNodeCancelable::new();
+        assert_eq!(*n.lock(&mut node), acquired);
+
+        // the lock is still held while a waiter leaves
+        let guard = n.lock(&mut node);
+        let mut node0 = mcs::MCSNodeCancelable::new();
+        assert!(n.try_lock_until(&mut node0, || true).is_none());
+        drop(guard);
+        assert!(n.try_lock_until(&mut node0, || true).is_some());
+    }
+
+    #[cfg(feature = "poison")]
+    #[test]
+    fn test_poison() {
+        let n = Arc::new(mcs::MCSLock::new(0));
+        let n0 = n.clone();
+        let r = std::thread::spawn(move || {
+            let mut node = mcs::MCSNode::new();
+            let mut guard = n0.lock(&mut node);
+            *guard += 1;
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(r.is_err());
+        assert!(n.is_poisoned());
+
+        // the lock is acquired even if it is poisoned
+        let mut node = mcs::MCSNode::new();
+        let Err(err) = n.lock_checked(&mut node) else {
+            panic!("the lock must be poisoned");
+        };
+        let guard = err.into_inner();
+        assert_eq!(*guard, 1);
+        drop(guard);
+
+        n.clear_poison();
+        assert!(n.lock_checked(&mut node).is_ok());
+
+        // only writers poison RwLock
+        let rw = Arc::new(rwlock::RwLock::new(0));
+        let rw0 = rw.clone();
+        let r = std::thread::spawn(move || {
+            let _guard = rw0.read();
+            panic!("readers do not poison the lock");
+        })
+        .join();
+        assert!(r.is_err());
+        assert!(!rw.is_poisoned());
+        assert!(rw.read_checked().is_ok());
+
+        let rw0 = rw.clone();
+        let r = std::thread::spawn(move || {
+            let _guard = rw0.write();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(r.is_err());
+        assert!(rw.is_poisoned());
+        assert!(rw.read_checked().is_err());
+        assert!(rw.write_checked().is_err());
+
+        rw.clear_poison();
+        assert!(rw.write_checked().is_ok());
+    }
+
+    #[test]
+    fn test_rwlock_state() {
+        let lock = Arc::new(rwlock::RwLock::new(0));
+
+        // no lock
+        let state = lock.state_debug();
+        assert_eq!(state.readers, 0);
+        assert!(!state.write_locked);
+        assert!(!state.writer_pending);
+
+        // N readers
+        let r1 = lock.read();
+        let r2 = lock.read();
+        assert_eq!(lock.reader_count(), 2);
+        assert!(!lock.is_write_locked());
+        assert!(!lock.writer_pending());
+
+        // writer waiting
+        let lock0 = lock.clone();
+        let t = std::thread::spawn(move || {
+            *lock0.write() += 1;
+        });
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        assert_eq!(lock.reader_count(), 2);
+        assert!(!lock.is_write_locked());
+
+        drop(r1);
+        drop(r2);
+        t.join().unwrap();
+
+        // writer held
+        let w = lock.write();
+        let state = lock.state_debug();
+        assert_eq!(state.readers, 0);
+        assert!(state.write_locked);
+        assert!(!state.writer_pending);
+        drop(w);
+
+        assert_eq!(lock.reader_count(), 0);
+        assert!(!lock.is_write_locked());
+    }
+
+    #[test]
+    fn test_rwlock_map() {
+        use rwlock::{MappedRwLockReadGuard, MappedRwLockWriteGuard};
+        use rwlock::{RwLockReadGuard, RwLockWriteGuard};
+
+        let lock = Arc::new(rwlock::RwLock::new(((0, 0), 0)));
+
+        // map of a mapped guard keeps the lock held
+        let a = RwLockWriteGuard::map(lock.write(), |v| &mut v.0);
+        let mut a1 = MappedRwLockWriteGuard::map(a, |a| &mut a.1);
+        *a1 += 1;
+        assert!(lock.is_write_locked());
+        drop(a1);
+        assert!(!lock.is_write_locked());
+
+        // mapped read guards are used concurrently with other readers
+        let a = RwLockReadGuard::map(lock.read(), |v| &v.0);
+        let a1 = MappedRwLockReadGuard::map(a, |a| &a.1);
+        let b = lock.read();
+        assert_eq!(lock.reader_count(), 2);
+        assert_eq!((*a1, b.1), (1, 0));
+        drop(a1);
+        assert_eq!(lock.reader_count(), 1);
+        drop(b);
+        assert_eq!(lock.reader_count(), 0);
+
+        // the lock is released exactly once
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i % 2 == 0 {
+                        let mut a = RwLockWriteGuard::map(lock0.write(), |v| &mut v.0);
+                        a.0 += 1;
+                        let mut a1 = MappedRwLockWriteGuard::map(a, |a| &mut a.1);
+                        *a1 += 1;
+                    } else {
+                        let a = RwLockReadGuard::map(lock0.read(), |v| &v.0);
+                        assert_eq!(a.0 + 1, a.1);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let state = lock.state_debug();
+        assert_eq!((state.readers, state.write_locked), (0, false));
+        assert_eq!(lock.read().0 .0, NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+    }
+
+    #[cfg(feature = "lock_api")]
+    #[test]
+    fn test_lock_api_rwlock() {
+        use lock_api::RwLockUpgradableReadGuard;
+
+        type RwLock<T> = lock_api::RwLock<rwlock::RawSyncRwLock, T>;
+
+        let lock = Arc::new(RwLock::new(0));
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    match i % 3 {
+                        0 => *lock0.write() += 1,
+                        1 => {
+                            let r = lock0.upgradable_read();
+                            *RwLockUpgradableReadGuard::upgrade(r) += 1;
+                        }
+                        _ => assert!(*lock0.read() <= NUM_SMALL_LOOP * NUM_SMALL_THREADS),
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let writers = (0..NUM_SMALL_THREADS).filter(|i| i % 3 != 2).count();
+        assert_eq!(*lock.read(), NUM_SMALL_LOOP * writers);
+
+        // an upgradable reader coexists with readers, but cannot upgrade while they exist
+        let r = lock.read();
+        let u = lock.upgradable_read();
+        assert!(lock.try_upgradable_read().is_none());
+        let u = RwLockUpgradableReadGuard::try_upgrade(u).unwrap_err();
+        drop(r);
+        let mut w = RwLockUpgradableReadGuard::try_upgrade(u).unwrap();
+        *w += 1;
+        assert!(lock.is_locked_exclusive());
+        drop(w);
+        assert!(!lock.is_locked());
+
+        // a writer waiting for the upgradable reader is woken up after it upgrades
+        let u = lock.upgradable_read();
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| *lock.write() += 1);
+
+            // let the writer set the writer waiting bit and wait for the reader
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            *RwLockUpgradableReadGuard::upgrade(u) += 1;
+            writer.join().unwrap();
+        });
+        assert_eq!(*lock.read(), NUM_SMALL_LOOP * writers + 3);
+    }
+
+    #[cfg(feature = "lock_api")]
+    #[test]
+    fn test_lock_api_rwlock_downgrade() {
+        use rwlock::{LockApiRwLock, LockApiRwLockReadGuard, LockApiRwLockWriteGuard};
+
+        let lock = Arc::new(LockApiRwLock::new((0, 0)));
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i % 2 == 0 {
+                        // no writer can modify the data between writing and reading
+                        let mut w = lock0.write();
+                        w.0 += 1;
+                        w.1 = w.0;
+                        let r = LockApiRwLockWriteGuard::downgrade(w);
+                        assert_eq!(r.0, r.1);
+                    } else {
+                        let r = LockApiRwLockReadGuard::map(lock0.read(), |v| &v.0);
+                        assert!(*r <= NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let writers = (0..NUM_SMALL_THREADS).filter(|i| i % 2 == 0).count();
+        assert_eq!(
+            *lock.read(),
+            (NUM_SMALL_LOOP * writers, NUM_SMALL_LOOP * writers)
+        );
+
+        // a downgraded guard coexists with readers, and blocks writers
+        let w = lock.write();
+        let r1 = LockApiRwLockWriteGuard::downgrade(w);
+        let r2 = lock.read();
+        assert!(lock.is_locked() && !lock.is_locked_exclusive());
+        assert!(lock.try_write().is_none());
+        drop((r1, r2));
+        assert!(lock.try_write().is_some());
+    }
+
+    #[test]
+    fn test_rwlock_try_upgrade() {
+        use rwlock::RwLockReadGuard;
+
+        let lock = rwlock::RwLock::new(0);
+
+        // the upgrade fails while a second reader exists
+        let r1 = lock.read();
+        let r2 = lock.read();
+        let r1 = RwLockReadGuard::try_upgrade(r1).err().unwrap();
+        assert_eq!(lock.reader_count(), 2);
+        assert_eq!((*r1, *r2), (0, 0));
+
+        // the only reader can upgrade
+        drop(r2);
+        let mut w = RwLockReadGuard::try_upgrade(r1).ok().unwrap();
+        *w += 1;
+        assert!(lock.is_write_locked());
+        assert_eq!(lock.reader_count(), 0);
+        drop(w);
+
+        let state = lock.state_debug();
+        assert_eq!((state.readers, state.write_locked), (0, false));
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_rwlock_map_unwind() {
+        use rwlock::{MappedRwLockReadGuard, MappedRwLockWriteGuard};
+        use rwlock::{RwLockReadGuard, RwLockWriteGuard};
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let lock = rwlock::RwLock::new((0, 0));
+
+        // the lock is released if `f` panics
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            RwLockWriteGuard::map(lock.write(), |_| -> &mut usize { panic!("map") })
+        }));
+        assert!(r.is_err());
+        assert!(!lock.is_write_locked());
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            RwLockReadGuard::map(lock.read(), |_| -> &usize { panic!("map") })
+        }));
+        assert!(r.is_err());
+        assert_eq!(lock.reader_count(), 0);
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            let a = RwLockWriteGuard::map(lock.write(), |v| &mut v.0);
+            MappedRwLockWriteGuard::map(a, |_| -> &mut usize { panic!("map") })
+        }));
+        assert!(r.is_err());
+        assert!(!lock.is_write_locked());
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            let a = RwLockReadGuard::map(lock.read(), |v| &v.0);
+            MappedRwLockReadGuard::map(a, |_| -> &usize { panic!("map") })
+        }));
+        assert!(r.is_err());
+        assert_eq!(lock.reader_count(), 0);
+
+        // the lock is acquired again
+        lock.write().0 += 1;
+        assert_eq!(*lock.read(), (1, 0));
+    }
+
+    #[test]
+    fn test_rwlock_try_map() {
+        use rwlock::{MappedRwLockReadGuard, MappedRwLockWriteGuard};
+        use rwlock::{RwLockReadGuard, RwLockWriteGuard};
+
+        let lock = Arc::new(rwlock::RwLock::new((None, 0)));
+
+        // the original guard is returned intact on failure
+        let r = RwLockReadGuard::try_map(lock.read(), |v| v.0.as_ref())
+            .err()
+            .unwrap();
+        assert_eq!(lock.reader_count(), 1);
+        assert_eq!(r.1, 0);
+        drop(r);
+        assert_eq!(lock.reader_count(), 0);
+
+        let mut w = RwLockWriteGuard::try_map(lock.write(), |v| v.0.as_mut())
+            .err()
+            .unwrap();
+        assert!(lock.is_write_locked());
+        w.0 = Some((0, 0));
+        let w = RwLockWriteGuard::try_map(w, |v| v.0.as_mut()).ok().unwrap();
+        let w = MappedRwLockWriteGuard::try_map(w, |_| None::<&mut u32>)
+            .err()
+            .unwrap();
+        assert!(lock.is_write_locked());
+        drop(w);
+        assert!(!lock.is_write_locked());
+
+        let r = RwLockReadGuard::try_map(lock.read(), |v| v.0.as_ref())
+            .ok()
+            .unwrap();
+        let r = MappedRwLockReadGuard::try_map(r, |_| None::<&u32>)
+            .err()
+            .unwrap();
+        let r = MappedRwLockReadGuard::try_map(r, |v| Some(&v.1))
+            .ok()
+            .unwrap();
+        assert_eq!(*r, 0);
+        assert_eq!(lock.reader_count(), 1);
+        drop(r);
+        assert_eq!(lock.reader_count(), 0);
+
+        // the lock is released exactly once in both paths
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    if i == 0 {
+                        match RwLockWriteGuard::try_map(lock0.write(), |v| v.0.as_mut()) {
+                            Ok(mut a) => {
+                                a.0 += 1;
+                                a.1 += 1;
+                            }
+                            Err(mut w) => w.1 += 1,
+                        }
+                    } else {
+                        let cond = j % 2 == 0;
+                        match RwLockReadGuard::try_map(lock0.read(), |v| {
+                            v.0.as_ref().filter(|_| cond)
+                        }) {
+                            Ok(a) => assert_eq!(a.0, a.1),
+                            Err(r) => assert!(r.1 <= NUM_SMALL_LOOP),
+                        }
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let state = lock.state_debug();
+        assert_eq!((state.readers, state.write_locked), (0, false));
+        assert_eq!(lock.read().0, Some((NUM_SMALL_LOOP, NUM_SMALL_LOOP)));
+    }
+
+    #[test]
+    fn test_rwlock_map_split() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use rwlock::{MappedRwLockWriteGuard, RwLockWriteGuard};
+
+        let lock = Arc::new(rwlock::RwLock::new([0; 8]));
+        let acquired = Arc::new(AtomicBool::new(false));
+
+        let (mut a, b) = RwLockWriteGuard::map_split(lock.write(), |v| v.split_at_mut(4));
+
+        // a reader is blocked until both halves are dropped
+        let lock0 = lock.clone();
+        let acquired0 = acquired.clone();
+        let t = std::thread::spawn(move || {
+            let r = lock0.read();
+            acquired0.store(true, Ordering::Relaxed);
+            assert_eq!(*r, [1, 1, 1, 1, 2, 2, 2, 2]);
+        });
+
+        a.fill(1);
+        let mut b = MappedRwLockWriteGuard::map(b, |b| &mut b[..]);
+        b.fill(2);
+        drop(a);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(lock.is_write_locked());
+        assert!(!acquired.load(Ordering::Relaxed));
+
+        drop(b);
+        t.join().unwrap();
+        assert!(acquired.load(Ordering::Relaxed));
+
+        // the lock is released exactly once, and is usable as usual after the split
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i % 2 == 0 {
+                        let (mut a, mut b) =
+                            RwLockWriteGuard::map_split(lock0.write(), |v| v.split_at_mut(4));
+                        a[0] += 1;
+                        b[0] += 1;
+                    } else {
+                        let mut w = lock0.write();
+                        w[1] += 1;
+                        w[5] += 1;
+                        drop(w);
+                        let r = lock0.read();
+                        assert_eq!(r[0] + 1, r[4]);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let state = lock.state_debug();
+        assert_eq!((state.readers, state.write_locked), (0, false));
+        let n = NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2;
+        let r = lock.read();
+        assert_eq!((r[0], r[1], r[4], r[5]), (n + 1, n + 1, n + 2, n + 2));
+    }
+
+    #[test]
+    fn test_rwlock_read_recursive() {
+        let lock = Arc::new(rwlock::RwLock::new(0));
+
+        // a recursive reader passes a waiting writer
+        let r1 = lock.read();
+        let lock0 = lock.clone();
+        let t = std::thread::spawn(move || *lock0.write() += 1);
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+
+        let r2 = lock.read_recursive();
+        assert_eq!(lock.reader_count(), 2);
+        assert!(lock.writer_pending());
+        assert_eq!((*r1, *r2), (0, 0));
+        drop(r1);
+        drop(r2);
+        t.join().unwrap();
+        assert_eq!(*lock.read_recursive(), 1);
+
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i % 2 == 0 {
+                        *lock0.write() += 1;
+                    } else {
+                        let r1 = lock0.read();
+                        let r2 = lock0.read_recursive();
+                        assert_eq!(*r1, *r2);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let state = lock.state_debug();
+        assert_eq!((state.readers, state.write_locked), (0, false));
+        assert_eq!(*lock.read(), NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2 + 1);
+    }
+
+    #[test]
+    fn test_rwlock_unlock_fair() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+
+        let lock = Arc::new(rwlock::RwLock::new(0));
+
+        // the writer cannot acquire the lock again while a reader is waiting,
+        // even if another writer is waiting too
+        for with_writer in [false, true] {
+            let acquired = Arc::new(AtomicBool::new(false));
+            let w = lock.write();
+
+            let lock0 = lock.clone();
+            let acquired0 = acquired.clone();
+            let reader = std::thread::spawn(move || {
+                let _r = lock0.read();
+                acquired0.store(true, Ordering::Relaxed);
+            });
+
+            let lock0 = lock.clone();
+            let writer = with_writer.then(|| std::thread::spawn(move || *lock0.write() += 1));
+
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(!acquired.load(Ordering::Relaxed));
+
+            w.unlock_fair();
+            *lock.write() += 1;
+            assert!(acquired.load(Ordering::Relaxed));
+
+            reader.join().unwrap();
+            if let Some(writer) = writer {
+                writer.join().unwrap();
+            }
+        }
+
+        // without waiting readers, this is the same as unlock
+        lock.write().unlock_fair();
+        assert!(!lock.is_write_locked());
+        assert_eq!(*lock.read(), 3);
+
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i % 2 == 0 {
+                        let mut w = lock0.write();
+                        *w += 1;
+                        w.unlock_fair();
+                    } else {
+                        let r1 = lock0.read();
+                        let r2 = lock0.read_recursive();
+                        assert_eq!(*r1, *r2);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let state = lock.state_debug();
+        assert_eq!((state.readers, state.write_locked), (0, false));
+        assert_eq!(*lock.read(), NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2 + 3);
+    }
+
+    #[test]
+    fn test_rwlock_unsized() {
+        use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+        let lock: Arc<RwLock<[usize]>> = Arc::new(RwLock::new([0; 16]));
+
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    if i == 0 {
+                        // the sum is kept even
+                        let mut w = lock0.write();
+                        let len = w.len();
+                        w[j % len] += 1;
+                        w[(j + 1) % len] += 1;
+                    } else {
+                        let sum: usize = lock0.read().iter().sum();
+                        assert_eq!(sum % 2, 0);
+                        assert!(sum <= NUM_SMALL_LOOP * 2);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(lock.read().iter().sum::<usize>(), NUM_SMALL_LOOP * 2);
+
+        // guards of unsized data can be mapped and split
+        let r = RwLockReadGuard::map(lock.read(), |v| &v[..2]);
+        assert_eq!(r.len(), 2);
+        drop(r);
+        let (a, b) = RwLockWriteGuard::map_split(lock.write(), |v| v.split_at_mut(8));
+        assert_eq!((a.len(), b.len()), (8, 8));
+        drop((a, b));
+
+        // trait objects
+        let lock: std::boxed::Box<RwLock<dyn core::fmt::Write + Send>> =
+            std::boxed::Box::new(RwLock::new(std::string::String::new()));
+        write!(lock.write(), "{}", 1).unwrap();
+        assert_eq!(lock.reader_count(), 0);
+    }
+
+    #[test]
+    fn test_rwlock_debug() {
+        use std::format;
+
+        #[derive(Debug)]
+        struct Outer {
+            lock: rwlock::RwLock<usize>,
+        }
+
+        let outer = Outer {
+            lock: rwlock::RwLock::new(1),
+        };
+
+        // unlocked
+        assert_eq!(format!("{:?}", outer), "Outer { lock: RwLock { data: 1 } }");
+        assert_eq!(
+            format!("{:#?}", outer.lock),
+            "RwLock {\n    readers: 0,\n    data: 1,\n}"
+        );
+
+        // read locked
+        let r = outer.lock.read();
+        assert_eq!(format!("{:?}", r), "1");
+        assert_eq!(format!("{}", r), "1");
+        assert_eq!(format!("{:?}", outer.lock), "RwLock { data: 1 }");
+        assert_eq!(
+            format!("{:#?}", outer.lock),
+            "RwLock {\n    readers: 1,\n    data: 1,\n}"
+        );
+        drop(r);
+
+        // write locked
+        let mut w = outer.lock.write();
+        *w = 2;
+        assert_eq!(format!("{:?}", w), "2");
+        assert_eq!(format!("{:>3}", w), "  2");
+        assert_eq!(
+            format!("{:?}", outer.lock),
+            "RwLock { data: <write locked> }"
+        );
+        assert_eq!(
+            format!("{:#?}", outer.lock),
+            "RwLock {\n    readers: 0,\n    data: <write locked>,\n}"
+        );
+        drop(w);
+
+        // formatting does not leave the reader lock held
+        assert_eq!(outer.lock.reader_count(), 0);
+        assert_eq!(format!("{:?}", outer.lock), "RwLock { data: 2 }");
+    }
+
+    #[test]
+    fn test_mcs_guard_fmt() {
+        use std::format;
+
+        let lock = mcs::MCSLock::new("a");
+        let mut node = mcs::MCSNode::new();
+        let guard = lock.lock(&mut node);
+
+        // the guards format the data as `T`
+        assert_eq!(format!("{:?}", guard), "\"a\"");
+        assert_eq!(format!("{}", guard), "a");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_mcs_lock_arc() {
+        let lock = Arc::new(mcs::MCSLock::new(0));
+
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    let mut guard = lock0.lock_arc();
+                    *guard += 1;
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        // the guard outlives the local handle of the lock
+        let guard = {
+            let lock0 = lock.clone();
+            lock0.lock_arc()
+        };
+        assert_eq!(*guard, NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+        assert_eq!(Arc::strong_count(&lock), 2);
+        assert!(Arc::ptr_eq(mcs::ArcMCSGuard::lock(&guard), &lock));
+
+        // the Arc is released after unlocking
+        drop(guard);
+        assert_eq!(Arc::strong_count(&lock), 1);
+        let mut node = mcs::MCSNode::new();
+        assert_eq!(*lock.lock(&mut node), NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+    }
+
+    /// counter generic over the locking strategy
+    struct Counter<L: crate::traits::Lock<Data = usize>> {
+        lock: L,
+    }
+
+    impl<L: crate::traits::Lock<Data = usize>> Counter<L> {
+        fn increment(&self) -> usize {
+            let mut guard = self.lock.lock();
+            *guard += 1;
+            *guard
+        }
+    }
+
+    fn count_concurrently<L>(counter: Counter<L>)
+    where
+        L: crate::traits::Lock<Data = usize> + Send + Sync + 'static,
+    {
+        let counter = Arc::new(counter);
+
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let counter0 = counter.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    counter0.increment();
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(counter.increment(), NUM_SMALL_LOOP * NUM_SMALL_THREADS + 1);
+    }
+
+    #[test]
+    fn test_lock_trait() {
+        count_concurrently(Counter {
+            lock: rwlock::RwLock::new(0),
+        });
+
+        #[cfg(feature = "alloc")]
+        count_concurrently(Counter {
+            lock: mcs::MCSMutex::new(0),
+        });
+    }
+
+    #[test]
+    fn test_rwlock_policy() {
+        use rwlock::{ReaderPreferred, RwLock};
+
+        // a reader arriving while a writer is waiting sees the data written by the writer
+        let lock = Arc::new(RwLock::new(0));
+        let r = lock.read();
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write() = 1);
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        let lock0 = lock.clone();
+        let reader = std::thread::spawn(move || *lock0.read());
+        drop(r);
+        writer.join().unwrap();
+        assert_eq!(reader.join().unwrap(), 1);
+
+        // readers keep acquiring the lock while a writer is waiting
+        let lock: Arc<RwLock<_, ReaderPreferred>> = Arc::new(RwLock::with_policy(0));
+        let mut r = lock.read();
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write() = 1);
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        for _ in 0..NUM_SMALL_LOOP {
+            let r0 = lock.read();
+            assert_eq!(*r0, 0);
+            r = r0;
+        }
+        assert!(lock.writer_pending());
+        drop(r);
+        writer.join().unwrap();
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn test_rwlock_max_readers() {
+        let lock = rwlock::RwLock::new(0);
+
+        // the last reader
+        lock.set_reader_count(rwlock::MAX_READERS - 1);
+        let r = lock.read();
+        assert_eq!(lock.reader_count(), rwlock::MAX_READERS);
+        drop(r);
+        assert_eq!(lock.reader_count(), rwlock::MAX_READERS - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many readers")]
+    fn test_rwlock_too_many_readers() {
+        let lock = rwlock::RwLock::new(0);
+        lock.set_reader_count(rwlock::MAX_READERS);
+        let _r = lock.read();
+    }
+
+    #[test]
+    fn test_rwlock_contended() {
+        // many threads acquire reader locks by CAS and writer locks at the same time
+        let lock = Arc::new(rwlock::RwLock::new((0, 0)));
+        let mut v = Vec::new();
+
+        for i in 0..NUM_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    if (i + j) % 4 == 0 {
+                        let mut w = lock0.write();
+                        w.0 += 1;
+                        w.1 += 1;
+                    } else if i % 2 == 0 {
+                        let r = lock0.read();
+                        assert_eq!(r.0, r.1);
+                    } else {
+                        let r = lock0.read_recursive();
+                        assert_eq!(r.0, r.1);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let writes = (0..NUM_THREADS)
+            .map(|i| (0..NUM_SMALL_LOOP).filter(|j| (i + j) % 4 == 0).count())
+            .sum::<usize>();
+        assert_eq!(*lock.read(), (writes, writes));
+        assert_eq!(lock.reader_count(), 0);
+    }
+
+    #[test]
+    fn test_rwlock_with() {
+        let lock = Arc::new(rwlock::RwLock::new(std::vec::Vec::new()));
+        let mut v = Vec::new();
+
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    if i & 1 == 0 {
+                        lock0.with_write(|v| v.push(j));
+                    } else {
+                        let len = lock0.with_read(|v| v.len());
+                        assert!(len <= NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        // the lock is released after the closure returns
+        let len = lock.with_write(|v| {
+            v.push(0);
+            v.len()
+        });
+        assert_eq!(len, NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2 + 1);
+        assert!(!lock.is_write_locked());
+        assert_eq!(lock.with_read(|v| v.len()), len);
+        assert_eq!(lock.reader_count(), 0);
+
+        // the same as above, and also available under loom
+        lock.with_mut(|v| v.clear());
+        assert!(lock.with(|v| v.is_empty()));
+        assert!(!lock.is_write_locked());
+        assert_eq!(lock.reader_count(), 0);
+    }
+
+    #[test]
+    fn test_rwlock_layout() {
+        use core::mem::{align_of, size_of};
+
+        let lock = rwlock::RwLock::new(0u64);
+        let base = &lock as *const _ as usize;
+        let data = &*lock.read() as *const u64 as usize;
+
+        #[cfg(not(feature = "compact"))]
+        {
+            let line = align_of::<crate::util::CachePadded<u8>>();
+            let stats = if cfg!(feature = "stats") { 1 } else { 0 };
+            assert_eq!(align_of::<rwlock::RwLock<u64>>(), line);
+            assert_eq!(size_of::<rwlock::RwLock<u64>>(), line * (5 + stats));
+
+            // the data starts on its own cache line after the state words
+            assert_eq!(data % line, 0);
+            assert_eq!(data - base, line * (4 + stats));
+        }
+
+        #[cfg(feature = "compact")]
+        {
+            assert_eq!(align_of::<rwlock::RwLock<u64>>(), align_of::<usize>());
+
+            // the queues of tasks and the statistics are not counted
+            #[cfg(not(any(feature = "async", feature = "stats")))]
+            assert!(size_of::<rwlock::RwLock<u64>>() <= 64);
+            assert!(data - base < size_of::<rwlock::RwLock<u64>>());
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_mcs_approx_waiters() {
+        let lock = Arc::new(mcs::MCSLock::new(0));
+        assert_eq!(lock.approx_waiters(), 0);
+
+        let mut node = mcs::MCSNode::new();
+        let guard = lock.lock(&mut node);
+        assert_eq!(lock.approx_waiters(), 0);
+
+        let v: Vec<_> = (0..NUM_SMALL_THREADS)
+            .map(|_| {
+                let lock = lock.clone();
+                std::thread::spawn(move || {
+                    let mut node = mcs::MCSNode::new();
+                    *lock.lock(&mut node) += 1;
+                })
+            })
+            .collect();
+
+        while lock.approx_waiters() < NUM_SMALL_THREADS {
+            std::thread::yield_now();
+        }
+        assert_eq!(lock.approx_waiters(), NUM_SMALL_THREADS);
+
+        drop(guard);
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(lock.approx_waiters(), 0);
+        assert_eq!(*lock.lock(&mut node), NUM_SMALL_THREADS);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_rwlock_async() {
+        let n = Arc::new(rwlock::RwLock::new(0));
+        let mut v = Vec::new();
+
+        // async and blocking readers and writers share the lock
+        for i in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                block_on(async {
+                    for j in 0..NUM_SMALL_LOOP {
+                        let blocking = j % 3 == 0;
+                        if i % 2 == 0 {
+                            if blocking {
+                                *n0.write() += 1;
+                            } else {
+                                *n0.write_async().await += 1;
+                            }
+                        } else {
+                            let r = if blocking {
+                                n0.read()
+                            } else {
+                                n0.read_async().await
+                            };
+                            assert!(*r <= NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+                        }
+                    }
+                })
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(
+            block_on(async { *n.read_async().await }),
+            NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2
+        );
+        assert_eq!(n.reader_count(), 0);
+        assert!(!n.writer_pending());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_rwlock_async_cancel() {
+        use core::future::Future;
+        use core::sync::atomic::Ordering;
+        use core::task::Context;
+
+        let lock = rwlock::RwLock::new(0);
+        let (count_r, waker_r) = counting_waker();
+        let (count_w1, waker_w1) = counting_waker();
+        let (count_w2, waker_w2) = counting_waker();
+        let mut cx_r = Context::from_waker(&waker_r);
+        let mut cx_w1 = Context::from_waker(&waker_w1);
+        let mut cx_w2 = Context::from_waker(&waker_w2);
+
+        // a waiting writer sets the writer waiting bit, and blocks new readers
+        let g = lock.read();
+        let mut w1 = std::boxed::Box::pin(lock.write_async());
+        let mut w2 = std::boxed::Box::pin(lock.write_async());
+        assert!(w1.as_mut().poll(&mut cx_w1).is_pending());
+        assert!(w2.as_mut().poll(&mut cx_w2).is_pending());
+        assert!(lock.writer_pending());
+        let mut r = std::boxed::Box::pin(lock.read_async());
+        assert!(r.as_mut().poll(&mut cx_r).is_pending());
+
+        // the last reader wakes only the first writer
+        drop(g);
+        assert_eq!(count_w1.load(Ordering::Relaxed), 1);
+        assert_eq!(count_w2.load(Ordering::Relaxed), 0);
+        assert_eq!(count_r.load(Ordering::Relaxed), 0);
+
+        // the woken writer is dropped, and passes the wake-up to the next writer
+        drop(w1);
+        assert_eq!(count_w2.load(Ordering::Relaxed), 1);
+        assert_eq!(count_r.load(Ordering::Relaxed), 0);
+
+        // the last writer is dropped, and unblocks the reader
+        drop(w2);
+        assert!(!lock.writer_pending());
+        assert_eq!(count_r.load(Ordering::Relaxed), 1);
+        let core::task::Poll::Ready(g) = r.as_mut().poll(&mut cx_r) else {
+            panic!("the reader must acquire the lock");
+        };
+
+        // a canceled reader leaves the queue, and is never woken
+        let w = lock.write_async();
+        drop(g);
+        let mut w = std::boxed::Box::pin(w);
+        let core::task::Poll::Ready(mut g) = w.as_mut().poll(&mut cx_w1) else {
+            panic!("the writer must acquire the lock");
+        };
+        let mut r = std::boxed::Box::pin(lock.read_async());
+        assert!(r.as_mut().poll(&mut cx_r).is_pending());
+        drop(r);
+        *g += 1;
+        drop(g);
+        assert_eq!(count_r.load(Ordering::Relaxed), 1);
+
+        // a blocking writer wakes async readers when it releases the lock
+        let g = lock.write();
+        let mut r = std::boxed::Box::pin(lock.read_async());
+        assert!(r.as_mut().poll(&mut cx_r).is_pending());
+        drop(g);
+        assert_eq!(count_r.load(Ordering::Relaxed), 2);
+        assert!(matches!(r.as_mut().poll(&mut cx_r), core::task::Poll::Ready(ref g) if **g == 1));
+    }
+
+    #[cfg(feature = "prio")]
+    #[test]
+    fn test_mcs_lock_with_prio() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static BOOSTED: AtomicUsize = AtomicUsize::new(0);
+        static RESTORED: AtomicUsize = AtomicUsize::new(0);
+
+        std::thread_local! {
+            static ID: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+        }
+
+        struct Hooks;
+
+        impl mcs::PriorityHooks for Hooks {
+            fn current() -> usize {
+                ID.with(|id| id.get())
+            }
+
+            fn boost(owner: usize) {
+                BOOSTED.store(owner, Ordering::Relaxed);
+            }
+
+            fn restore(owner: usize) {
+                if BOOSTED.load(Ordering::Relaxed) == owner {
+                    RESTORED.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        ID.with(|id| id.set(1));
+        let lock = Arc::new(mcs::MCSLock::new(0));
+        let mut node = mcs::MCSNode::new();
+        let guard = lock.lock_with_prio::<Hooks>(&mut node);
+
+        // the waiter boosts the holder
+        let lock0 = lock.clone();
+        let t = std::thread::spawn(move || {
+            ID.with(|id| id.set(2));
+            let mut node = mcs::MCSNode::new();
+            *lock0.lock_with_prio::<Hooks>(&mut node) += 1;
+        });
+
+        while BOOSTED.load(Ordering::Relaxed) != 1 {
+            std::thread::yield_now();
+        }
+        assert_eq!(RESTORED.load(Ordering::Relaxed), 0);
+
+        // the holder restores its priority after handing over the lock
+        drop(guard);
+        assert!(RESTORED.load(Ordering::Relaxed) >= 1);
+        t.join().unwrap();
+
+        // the holder acquired by `lock` is not boosted
+        let guard = lock.lock(&mut node);
+        let lock0 = lock.clone();
+        let t = std::thread::spawn(move || {
+            ID.with(|id| id.set(2));
+            let mut node = mcs::MCSNode::new();
+            *lock0.lock_with_prio::<Hooks>(&mut node) += 1;
+        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(BOOSTED.load(Ordering::Relaxed), 1);
+        drop(guard);
+        t.join().unwrap();
+
+        assert_eq!(*lock.lock(&mut node), 2);
+    }
+
+    #[test]
+    fn test_leak() {
+        let lock = Arc::new(mcs::MCSLock::new(0));
+        let mut node = mcs::MCSNode::new();
+        let data = mcs::MCSLockGuard::leak(lock.lock(&mut node));
+        *data += 1;
+
+        // the leaked lock is still held
+        let lock0 = lock.clone();
+        let t = std::thread::spawn(move || {
+            let mut node = mcs::MCSNode::new();
+            *lock0.lock(&mut node) += 1;
+        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(!t.is_finished());
+
+        unsafe { lock.force_unlock(&mut node) };
+        t.join().unwrap();
+        assert_eq!(*lock.lock(&mut node), 2);
+
+        let lock = rwlock::RwLock::new(0);
+        let data = rwlock::RwLockWriteGuard::leak(lock.write());
+        *data += 1;
+        assert!(lock.is_write_locked());
+
+        unsafe { lock.force_unlock() };
+        assert!(!lock.is_write_locked());
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_rwlock_arc_guard() {
+        let lock = Arc::new(rwlock::RwLock::new(0));
+        let guard = lock.read_arc();
+        assert_eq!(Arc::strong_count(&lock), 2);
+
+        // a writer waits for the owned reader guard
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || {
+            *lock0.write_arc() += 1;
+        });
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        assert_eq!(*guard, 0);
+
+        // the guard is dropped by another thread, which wakes the writer
+        std::thread::spawn(move || {
+            assert_eq!(*guard, 0);
+            drop(guard);
+        })
+        .join()
+        .unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(lock.reader_count(), 0);
+        assert!(!lock.is_write_locked());
+        assert_eq!(Arc::strong_count(&lock), 1);
+
+        let mut guard = lock.write_arc();
+        *guard += 1;
+        assert!(Arc::ptr_eq(
+            rwlock::ArcRwLockWriteGuard::rwlock(&guard),
+            &lock
+        ));
+        drop(guard);
+        assert_eq!(*lock.read_arc(), 2);
+    }
+
+    #[test]
+    fn test_rwlock_update() {
+        let lock = Arc::new(rwlock::RwLock::new(0usize));
+        let v: Vec<_> = (0..NUM_SMALL_THREADS)
+            .map(|_| {
+                let lock = lock.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..NUM_SMALL_LOOP {
+                        lock.update(|v| *v += 1);
+                        assert!(lock.fetch_update(|v| Some(v + 1)).is_ok());
+                    }
+                })
+            })
+            .collect();
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let n = NUM_SMALL_LOOP * NUM_SMALL_THREADS * 2;
+        assert_eq!(lock.fetch_update(|_| None), Err(n));
+        assert_eq!(lock.update(core::mem::take), n);
+        assert_eq!(*lock.read(), 0);
+        assert!(!lock.is_write_locked());
+    }
+
+    #[test]
+    fn test_rwlock_force_unlock_read() {
+        let lock = Arc::new(rwlock::RwLock::new([1u32, 2, 3]));
+
+        // a reader forgets its guard, and keeps only the pointer as C code does
+        let guard = lock.read();
+        let ptr = rwlock::RwLockReadGuard::as_ptr(&guard);
+        core::mem::forget(guard);
+        assert_eq!(ptr, lock.data_ptr() as *const _);
+        assert_eq!(lock.reader_count(), 1);
+
+        // other readers continue, and a writer waits for the forgotten reader
+        let r = lock.read_recursive();
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || {
+            let mut guard = lock0.write();
+            unsafe { *rwlock::RwLockWriteGuard::as_mut_ptr(&mut guard) = [4, 5, 6] };
+        });
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        assert_eq!(unsafe { (*ptr)[1] }, r[1]);
+        drop(r);
+        assert_eq!(lock.reader_count(), 1);
+        assert!(!writer.is_finished());
+
+        // releasing the forgotten reader wakes the writer
+        unsafe { lock.force_unlock_read() };
+        writer.join().unwrap();
+        assert_eq!(*lock.read(), [4, 5, 6]);
+
+        let guard = lock.write();
+        core::mem::forget(guard);
+        assert!(lock.is_write_locked());
+        unsafe { lock.force_unlock_write() };
+        assert!(!lock.is_write_locked());
+        assert_eq!(lock.reader_count(), 0);
+    }
+
+    #[test]
+    fn test_static_mcs_lock() {
+        static LOCK: mcs::StaticMCSLock<usize, NUM_SMALL_THREADS> = mcs::StaticMCSLock::new(0);
+
+        // more threads than nodes wait for a free node
+        let v: Vec<_> = (0..NUM_SMALL_THREADS * 2)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..NUM_SMALL_LOOP {
+                        *LOCK.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(*LOCK.lock(), NUM_SMALL_LOOP * NUM_SMALL_THREADS * 2);
+
+        // the pool is shared with the nodes of the caller
+        let mut node = mcs::MCSNode::new();
+        let guard = LOCK.lock();
+        drop(guard);
+        *LOCK.inner().lock(&mut node) += 1;
+        assert_eq!(*LOCK.lock(), NUM_SMALL_LOOP * NUM_SMALL_THREADS * 2 + 1);
+    }
+
+    #[test]
+    fn test_rwlock_default_from() {
+        #[derive(Default)]
+        struct Stats {
+            hits: rwlock::RwLock<usize>,
+            names: rwlock::RwLock<Vec<usize>, rwlock::ReaderPreferred>,
+        }
+
+        let stats = Stats::default();
+        assert_eq!(*stats.hits.read(), 0);
+        assert!(stats.names.read().is_empty());
+
+        let lock = rwlock::RwLock::<_>::from(10);
+        assert_eq!(*lock.read(), 10);
+
+        let lock: rwlock::RwLock<_, rwlock::ReaderPreferred> = vec![1, 2, 3].into();
+        assert_eq!(*lock.read(), [1, 2, 3]);
+    }
+
+    #[cfg(feature = "debug-detect")]
+    #[test]
+    #[should_panic(expected = "deadlock")]
+    fn test_rwlock_detect_read_write() {
+        use crate::detect::{set_owner_id, OwnerId};
+
+        struct Thread;
+
+        impl OwnerId for Thread {
+            fn current() -> usize {
+                std::thread_local!(static ID: u8 = const { 0 });
+                ID.with(|id| id as *const u8 as usize)
+            }
+        }
+
+        set_owner_id::<Thread>();
+
+        fn increment(lock: &rwlock::RwLock<usize>) {
+            *lock.write() += 1;
+        }
+
+        let lock = rwlock::RwLock::new(0);
+
+        // released readers, and readers of other threads, are not deadlocks
+        drop(lock.read());
+        let r = lock.read();
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(*lock.read(), 0));
+        });
+        drop(r);
+        let r = rwlock::RwLockReadGuard::map(lock.read(), |v| v);
+        drop(r);
+        increment(&lock);
+
+        // the writer lock is requested by the holder of the reader lock
+        let r = lock.read();
+        increment(&lock);
+        drop(r);
+    }
+
+    #[test]
+    fn test_try_new() {
+        let lock = rwlock::RwLock::try_new(|| Ok::<_, ()>(vec![1, 2])).unwrap();
+        assert_eq!(*lock.read(), [1, 2]);
+        let err = rwlock::RwLock::<usize>::try_new(|| Err("no config"));
+        assert_eq!(err.err(), Some("no config"));
+
+        let lock = mcs::MCSLock::try_new(|| "7".parse::<usize>()).unwrap();
+        let mut node = mcs::MCSNode::new();
+        assert_eq!(*lock.lock(&mut node), 7);
+        assert!(mcs::MCSLock::try_new(|| "x".parse::<usize>()).is_err());
+    }
+
+    #[test]
+    fn test_rwlock_read_recursive_regression() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+
+        let lock = Arc::new(rwlock::RwLock::new(0));
+
+        // outer reader lock of the call stack
+        let outer = lock.read();
+
+        // a writer arrives between the outer and the inner reader locks
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write() += 1);
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+
+        // `read` waits for the writer, which waits for the outer reader lock,
+        // so `read` on the thread holding `outer` would deadlock;
+        // it is observed by another thread instead
+        let acquired = Arc::new(AtomicBool::new(false));
+        let lock0 = lock.clone();
+        let acquired0 = acquired.clone();
+        let reader = std::thread::spawn(move || {
+            let r = lock0.read();
+            acquired0.store(true, Ordering::Relaxed);
+            *r
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!acquired.load(Ordering::Relaxed));
+
+        // `read_recursive` ignores the waiting writer
+        let inner = lock.read_recursive();
+        assert_eq!((*outer, *inner), (0, 0));
+        drop(inner);
+        drop(outer);
+
+        writer.join().unwrap();
+        assert_eq!(reader.join().unwrap(), 1);
+        assert!(acquired.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_rwlock_optimistic() {
+        let lock = Arc::new(rwlock::RwLock::new([0usize; 4]));
+
+        // a writer in between invalidates the token
+        let token = lock.read_optimistic();
+        assert!(lock.validate(token));
+        lock.write()[0] = 1;
+        assert!(!lock.validate(token));
+        assert!(lock.validate(lock.read_optimistic()));
+
+        // never valid while a writer holds the lock
+        let w = lock.write();
+        let token = lock.read_optimistic();
+        assert!(!lock.validate(token));
+        assert_eq!(lock.try_read_optimistic(|v| v[0]), None);
+        drop(w);
+        assert!(!lock.validate(token));
+        assert_eq!(lock.try_read_optimistic(|v| v[0]), Some(1));
+
+        // readers retry while writers keep every element equal
+        *lock.write() = [0; 4];
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                let mut retries = 0;
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i % 2 == 0 {
+                        let mut w = lock0.write();
+                        for x in w.iter_mut() {
+                            *x += 1;
+                        }
+                    } else {
+                        let data = loop {
+                            match lock0.try_read_optimistic(|v| *v) {
+                                Some(data) => break data,
+                                None => {
+                                    retries += 1;
+                                    std::thread::yield_now();
+                                }
+                            }
+                        };
+                        assert!(data.iter().all(|x| *x == data[0]));
+                    }
+                }
+                retries
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(*lock.read(), [NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2; 4]);
+    }
+
+    #[test]
+    fn test_rwlock_write_if() {
+        let lock = Arc::new(rwlock::RwLock::new(0));
+        assert!(lock.write_if(|v| *v > 0).is_none());
+        *lock.write_if(|v| *v == 0).unwrap() += 1;
+        assert_eq!(*lock.read(), 1);
+
+        // every thread increments the data while it is less than the limit,
+        // so the recheck under the writer lock keeps it from exceeding the limit
+        let limit = NUM_SMALL_LOOP;
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                let mut n = 0;
+                for _ in 0..NUM_SMALL_LOOP {
+                    if let Some(mut w) = lock0.write_if(|v| *v < limit) {
+                        assert!(*w < limit);
+                        *w += 1;
+                        n += 1;
+                    }
+                }
+                n
+            });
+            v.push(t);
+        }
+
+        let mut n = 0;
+        for t in v {
+            n += t.join().unwrap();
+        }
+        assert_eq!(n, limit - 1);
+        assert_eq!(*lock.read(), limit);
+    }
+
+    #[test]
+    fn test_rwlock_version() {
+        let mut lock = Arc::new(rwlock::RwLock::new(0));
+        assert_eq!(lock.version(), 0);
+
+        // readers do not change the version, but every writer does
+        drop(lock.read());
+        assert_eq!(lock.version(), 0);
+        drop(lock.write());
+        assert_eq!(lock.version(), 1);
+        *lock.try_write_for(0).unwrap() += 1;
+        assert_eq!(lock.version(), 2);
+
+        // the data is not shared
+        *Arc::get_mut(&mut lock).unwrap().get_mut() += 1;
+        assert_eq!(lock.version(), 2);
+
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    let seen = {
+                        let r = lock0.read();
+                        (*r, lock0.version())
+                    };
+                    *lock0.write() += 1;
+
+                    // the version read with the data changes only when the data changes
+                    let r = lock0.read();
+                    assert!(lock0.version() > seen.1);
+                    assert!(*r > seen.0);
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(
+            lock.version() as usize,
+            2 + NUM_SMALL_LOOP * NUM_SMALL_THREADS
+        );
+        assert_eq!(*lock.read(), 2 + NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+    }
+
+    #[test]
+    fn test_lock_clone() {
+        #[derive(Clone)]
+        struct Both {
+            rw: rwlock::RwLock<Vec<usize>, rwlock::ReaderPreferred>,
+            mcs: mcs::MCSLock<Vec<usize>>,
+        }
+
+        let a = Both {
+            rw: rwlock::RwLock::with_policy(vec![1]),
+            mcs: mcs::MCSLock::new(vec![2]),
+        };
+
+        // the source may be read locked by the current thread
+        let r = a.rw.read();
+        let b = a.clone();
+        drop(r);
+
+        let mut node = mcs::MCSNode::new();
+        b.rw.write().push(10);
+        b.mcs.lock(&mut node).push(20);
+        assert_eq!(*a.rw.read(), [1]);
+        assert_eq!(*a.mcs.lock(&mut node), [2]);
+        assert_eq!(*b.rw.read(), [1, 10]);
+        assert_eq!(*b.mcs.lock(&mut node), [2, 20]);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_rwlock_stats() {
+        use std::time::Duration;
+
+        let lock = Arc::new(rwlock::RwLock::new(0));
+        assert_eq!(lock.stats(), rwlock::RwLockStats::default());
+
+        // uncontended
+        *lock.write() += 1;
+        drop(lock.read());
+        drop(lock.read_recursive());
+        let stats = lock.stats();
+        assert_eq!((stats.reads, stats.writes), (2, 1));
+        assert_eq!((stats.contended_reads, stats.contended_writes), (0, 0));
+        assert_eq!(stats.writer_wait_spins, 0);
+
+        // a reader and a writer wait for a writer
+        let w = lock.write();
+        let lock0 = lock.clone();
+        let reader = std::thread::spawn(move || *lock0.read());
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write() += 1);
+        std::thread::sleep(Duration::from_millis(100));
+        drop(w);
+        reader.join().unwrap();
+        writer.join().unwrap();
+
+        let stats = lock.stats();
+        assert_eq!((stats.reads, stats.writes), (3, 3));
+        assert_eq!((stats.contended_reads, stats.contended_writes), (1, 1));
+        assert!(stats.writer_wait_spins > 0);
+
+        // a writer waits for a reader
+        lock.reset_stats();
+        let r = lock.read();
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write() += 1);
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        drop(r);
+        writer.join().unwrap();
+
+        let stats = lock.stats();
+        assert_eq!((stats.reads, stats.writes), (1, 1));
+        assert_eq!((stats.contended_reads, stats.contended_writes), (0, 1));
+
+        lock.reset_stats();
+        assert_eq!(lock.stats(), rwlock::RwLockStats::default());
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_rwlock_wait_loads() {
+        use std::time::Duration;
+
+        const HOLD: Duration = Duration::from_millis(50);
+
+        let lock = Arc::new(rwlock::RwLock::new(0));
+
+        // a writer waits for a reader, and then for a writer
+        for write_first in [false, true] {
+            lock.reset_stats();
+
+            let r = if write_first { None } else { Some(lock.read()) };
+            let w = if write_first {
+                Some(lock.write())
+            } else {
+                None
+            };
+
+            let lock0 = lock.clone();
+            let writer = std::thread::spawn(move || *lock0.write() += 1);
+
+            std::thread::sleep(HOLD);
+            drop(r);
+            drop(w);
+            writer.join().unwrap();
+
+            // the writer reloads the lock at most once per microsecond on average,
+            // while a loop of a load and a `pause` reloads it hundreds of times
+            let stats = lock.stats();
+            assert_eq!(stats.contended_writes, 1);
+            assert!(
+                stats.writer_wait_spins < HOLD.as_micros() as usize,
+                "{:?}",
+                stats
+            );
+        }
+    }
+
+    #[test]
+    fn test_guard_sync() {
+        let rwlock = rwlock::RwLock::new(vec![1, 2]);
+        let mcs = mcs::MCSLock::new(3);
+        let mut node = mcs::MCSNode::new();
+
+        // shared references to the guards are passed to other threads
+        let r = rwlock.read();
+        let m = mcs.lock(&mut node);
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| assert_eq!((r.len(), *m), (2, 3)));
+            }
+        });
+        drop(r);
+
+        let w = rwlock.write();
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(w[1], 2));
+        });
+    }
+
+    #[test]
+    fn test_rwlock_read_guard_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        fn assert_sync<T: Sync>(_: &T) {}
+
+        let lock = rwlock::RwLock::new(vec![1]);
+        let r = lock.read();
+        assert_send(&r);
+        assert_sync(&r);
+
+        // the reader lock is released by another thread
+        let lock_ref = &lock;
+        let len = std::thread::scope(|s| {
+            s.spawn(move || {
+                assert_eq!(lock_ref.reader_count(), 1);
+                r.len()
+            })
+            .join()
+            .unwrap()
+        });
+        assert_eq!(len, 1);
+        assert_eq!(lock.reader_count(), 0);
+        lock.write().push(2);
+
+        // a `Sync` but not `Send` value
+        let mutex = std::sync::Mutex::new(0);
+        let lock = rwlock::RwLock::<_, rwlock::ReaderPreferred>::with_policy(mutex.lock().unwrap());
+        assert_send(&lock.read());
+    }
+
+    #[test]
+    fn test_rwlock_read_leak() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+
+        let lock = Arc::new(rwlock::RwLock::new(1));
+        let data = rwlock::RwLockReadGuard::leak(lock.read());
+        assert_eq!(*data, 1);
+
+        // readers proceed
+        assert_eq!(*lock.read(), 1);
+        assert_eq!(lock.reader_count(), 1);
+
+        // a writer blocks
+        let acquired = Arc::new(AtomicBool::new(false));
+        let lock0 = lock.clone();
+        let acquired0 = acquired.clone();
+        let writer = std::thread::spawn(move || {
+            *lock0.write() += 1;
+            acquired0.store(true, Ordering::Relaxed);
+        });
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!acquired.load(Ordering::Relaxed));
+        assert_eq!(*lock.read_recursive(), 1);
+
+        // release the leaked reader lock, so that the writer can finish
+        unsafe { lock.force_unlock_read() };
+        writer.join().unwrap();
+        assert!(acquired.load(Ordering::Relaxed));
+        assert_eq!(*lock.read(), 2);
+    }
+
+    #[cfg(feature = "internals")]
+    #[test]
+    fn test_raw_internals() {
+        let lock = rwlock::RwLock::new(0);
+        assert_eq!(lock.raw_state(), 0);
+        {
+            let _r0 = lock.read();
+            let _r1 = lock.read();
+            assert_eq!(lock.raw_state(), 4);
+        }
+        {
+            let _w = lock.write();
+            assert_eq!(lock.raw_state(), usize::MAX);
+        }
+        assert_eq!(lock.raw_state(), 0);
+
+        let lock = mcs::MCSLock::new(0);
+        assert!(lock.raw_last().is_null());
+        let mut node = mcs::MCSNode::new();
+        {
+            let _g = lock.lock(&mut node);
+            assert!(!lock.raw_last().is_null());
+        }
+        assert!(lock.raw_last().is_null());
+    }
+
+    #[test]
+    fn test_phase_fair_rwlock() {
+        use std::{
+            sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+            time::Duration,
+        };
+
+        let lock = Arc::new(rwlock::PhaseFairRwLock::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let entered = Arc::new(AtomicUsize::new(0));
+
+        // a continuous stream of readers, which always overlap with the main thread
+        let guard = lock.read();
+        let mut readers = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let stop0 = stop.clone();
+            let entered0 = entered.clone();
+            let t = std::thread::spawn(move || {
+                while !stop0.load(Ordering::Relaxed) {
+                    let v = *lock0.read();
+                    entered0.fetch_add(1, Ordering::Relaxed);
+                    assert!(v == 0 || v == 1);
+                }
+            });
+            readers.push(t);
+        }
+        while entered.load(Ordering::Relaxed) < NUM_SMALL_LOOP {
+            std::thread::yield_now();
+        }
+
+        // the writer waits only for the readers which arrived before it
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || {
+            *lock0.write() += 1;
+        });
+        while !lock.writer_present() {
+            std::thread::yield_now();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(*guard, 0);
+        assert!(lock.try_read().is_none());
+
+        // readers arriving now enter after the writer, within one phase
+        let lock0 = lock.clone();
+        let late = std::thread::spawn(move || *lock0.read());
+        drop(guard);
+        writer.join().unwrap();
+        assert_eq!(late.join().unwrap(), 1);
+
+        stop.store(true, Ordering::Relaxed);
+        for t in readers {
+            t.join().unwrap();
+        }
+        assert_eq!(*lock.read(), 1);
+        assert_eq!(format!("{:?}", lock), "PhaseFairRwLock { data: 1 }");
+
+        // writers take turns
+        let lock = Arc::new(rwlock::PhaseFairRwLock::new(0));
+        let mut writers = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    lock0.with_mut(|v| *v += 1);
+                    assert!(lock0.with(|v| *v) > 0);
+                }
+            });
+            writers.push(t);
+        }
+        for t in writers {
+            t.join().unwrap();
+        }
+        assert_eq!(*lock.read(), NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+    }
+
+    /// checks that threads acquire the lock in the order they enqueued,
+    /// which is observed by the waiter counter of the `metrics` feature
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_mcs_fifo() {
+        for _ in 0..10 {
+            let lock = Arc::new(mcs::MCSLock::new(Vec::new()));
+            let mut node = mcs::MCSNode::new();
+            let guard = lock.lock(&mut node);
+
+            // enqueue the threads one by one behind the holder
+            let mut v = Vec::new();
+            for i in 0..NUM_SMALL_THREADS {
+                let lock0 = lock.clone();
+                let t = std::thread::spawn(move || {
+                    let mut node = mcs::MCSNode::new();
+                    lock0.lock(&mut node).push(i);
+                });
+                v.push(t);
+
+                while lock.approx_waiters() != i + 1 {
+                    std::thread::yield_now();
+                }
+            }
+
+            drop(guard);
+            for t in v {
+                t.join().unwrap();
+            }
+
+            let order = lock.lock(&mut node).clone();
+            assert_eq!(order, (0..NUM_SMALL_THREADS).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_queue_rwlock() {
+        let n = Arc::new(rwlock::QueueRwLock::new(0));
+        let mut v = Vec::new();
+
+        for _ in 0..(NUM_THREADS - 1) {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = rwlock::QueueRwNode::new();
+                for _ in 0..NUM_SMALL_LOOP {
+                    let r = n0.read(&mut node);
+                    assert_eq!(*r, 0);
+                }
+            });
+
+            v.push(t);
+        }
+
+        let n0 = n.clone();
+        let wr = std::thread::spawn(move || {
+            let mut node = rwlock::QueueRwNode::new();
+            for _ in 0..NUM_SMALL_LOOP {
+                let mut r = n0.write(&mut node);
+                *r += 1;
+                *r -= 1;
+            }
+        });
+
+        v.push(wr);
+
+        for t in v {
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_queue_rwlock_writers() {
+        let n = Arc::new(rwlock::QueueRwLock::new(0));
+        let mut v = Vec::new();
+
+        for i in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = rwlock::QueueRwNode::new();
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i & 1 == 0 {
+                        *n0.write(&mut node) += 1;
+                    } else {
+                        let r = n0.read(&mut node);
+                        assert!(*r <= NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let mut node = rwlock::QueueRwNode::new();
+        assert_eq!(*n.read(&mut node), NUM_SMALL_LOOP * NUM_SMALL_THREADS / 2);
+    }
+
+    #[test]
+    fn test_queue_rwlock_reader_batch() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let n = Arc::new(rwlock::QueueRwLock::new(0));
+        let entered = Arc::new(AtomicUsize::new(0));
+
+        let mut node = rwlock::QueueRwNode::new();
+        let mut guard = n.write(&mut node);
+
+        // the readers queued behind the writer hold the lock at the same time
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let n0 = n.clone();
+            let entered0 = entered.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = rwlock::QueueRwNode::new();
+                let r = n0.read(&mut node);
+                entered0.fetch_add(1, Ordering::Relaxed);
+                while entered0.load(Ordering::Relaxed) < NUM_SMALL_THREADS {
+                    std::thread::yield_now();
+                }
+                *r
+            });
+            v.push(t);
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(entered.load(Ordering::Relaxed), 0);
+        *guard += 1;
+        drop(guard);
+
+        for t in v {
+            assert_eq!(t.join().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_big_rwlock() {
+        const SLOTS: usize = 4;
+
+        let n = Arc::new(rwlock::BigRwLock::<_, SLOTS>::new(0));
+        let mut v = Vec::new();
+
+        // readers are spread across the slots, and some share a slot
+        for i in 0..(NUM_THREADS - 1) {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    let r = n0.read(i);
+                    assert_eq!(*r % 2, 0);
+                }
+            });
+
+            v.push(t);
+        }
+
+        let n0 = n.clone();
+        let wr = std::thread::spawn(move || {
+            for _ in 0..NUM_SMALL_LOOP {
+                let mut w = n0.write();
+                *w += 1;
+                *w += 1;
+            }
+        });
+
+        v.push(wr);
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        assert_eq!(*n.read(0), NUM_SMALL_LOOP * 2);
+        assert_eq!(n.with(SLOTS + 1, |v| *v), NUM_SMALL_LOOP * 2);
+    }
+
+    /// checks that readers on different slots count on different cache lines
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_big_rwlock_slots() {
+        const SLOTS: usize = 4;
+
+        #[cfg(not(feature = "compact"))]
+        {
+            use core::mem::{align_of, size_of};
+
+            // the writer flag, the slots, and the data are on their own cache lines
+            let line = align_of::<crate::util::CachePadded<u8>>();
+            assert_eq!(
+                size_of::<rwlock::BigRwLock<u64, SLOTS>>(),
+                line * (SLOTS + 2)
+            );
+        }
+
+        let n = Arc::new(rwlock::BigRwLock::<u64, SLOTS>::new(0));
+        let mut v = Vec::new();
+        for i in 0..SLOTS {
+            let n0 = n.clone();
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP * (i + 1) {
+                    assert_eq!(*n0.read(i), 0);
+                }
+            });
+            v.push(t);
+        }
+        for t in v {
+            t.join().unwrap();
+        }
+
+        // each reader touched only its own slot
+        for i in 0..SLOTS {
+            assert_eq!(n.slot_reads(i), NUM_SMALL_LOOP * (i + 1));
+        }
+
+        *n.write() += 1;
+        assert_eq!(n.slot_reads(SLOTS), NUM_SMALL_LOOP);
+    }
+
+    #[test]
+    fn test_try_lock_weak() {
+        // retry the weak CAS as its callers do
+        fn retry<G>(mut f: impl FnMut() -> Option<G>) -> G {
+            loop {
+                if let Some(g) = f() {
+                    return g;
+                }
+            }
+        }
+
+        let lock = rwlock::RwLock::new(0);
+        {
+            let r = retry(|| lock.try_read_weak());
+            let _r2 = retry(|| lock.try_read_weak());
+            assert_eq!(*r, 0);
+            assert!(lock.try_write_weak().is_none());
+        }
+        *retry(|| lock.try_write_weak()) += 1;
+        {
+            let _w = lock.write();
+            assert!(lock.try_read_weak().is_none());
+            assert!(lock.try_write_weak().is_none());
+        }
+
+        // a waiting writer blocks new readers only under WriterPreferred
+        let lock = rwlock::RwLock::new(0);
+        let lock_rp = rwlock::RwLock::<_, rwlock::ReaderPreferred>::with_policy(0);
+        std::thread::scope(|s| {
+            let r = lock.read();
+            let r_rp = lock_rp.read();
+            s.spawn(|| *lock.write() += 1);
+            s.spawn(|| *lock_rp.write() += 1);
+            while !lock.writer_pending() || !lock_rp.writer_pending() {
+                std::thread::yield_now();
+            }
+
+            assert!(lock.try_read_weak().is_none());
+            assert!(lock.try_write_weak().is_none());
+            assert_eq!(*retry(|| lock_rp.try_read_weak()), 0);
+            assert!(lock_rp.try_write_weak().is_none());
+            drop(r);
+            drop(r_rp);
+        });
+        assert_eq!(*lock.read(), 1);
+        assert_eq!(*lock_rp.read(), 1);
+
+        let lock = Arc::new(mcs::MCSLock::new(0));
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let t = std::thread::spawn(move || {
+                let mut node = mcs::MCSNode::new();
+                for _ in 0..NUM_SMALL_LOOP {
+                    loop {
+                        if let Some(mut guard) = lock0.try_lock_weak(&mut node) {
+                            *guard += 1;
+                            break;
+                        }
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            v.push(t);
+        }
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let mut node = mcs::MCSNode::new();
+        let guard = lock.lock(&mut node);
+        assert_eq!(*guard, NUM_SMALL_LOOP * NUM_SMALL_THREADS);
+        let mut node2 = mcs::MCSNode::new();
+        assert!(lock.try_lock_weak(&mut node2).is_none());
+    }
+
+    #[test]
+    fn test_rwlock_try_for() {
+        let lock = Arc::new(rwlock::RwLock::new(0));
+
+        // a writer blocks readers and writers
+        {
+            let _w = lock.write();
+            assert!(lock.try_read_for(10).is_none());
+            assert!(lock.try_write_for(10).is_none());
+        }
+
+        // a writer giving up clears the writer waiting bit, so readers are not blocked
+        {
+            let _r = lock.read();
+            assert!(lock.try_write_for(10).is_none());
+            assert!(!lock.writer_pending());
+            assert_eq!(*lock.try_read_for(0).unwrap(), 0);
+        }
+
+        // the predicate cancels the wait
+        {
+            let _r = lock.read();
+            let mut calls = 0;
+            let w = lock.try_write_until(|| {
+                calls += 1;
+                calls == 3
+            });
+            assert!(w.is_none());
+            assert_eq!(calls, 3);
+            assert!(!lock.writer_pending());
+        }
+        *lock.try_write_until(|| unreachable!()).unwrap() += 1;
+        assert_eq!(*lock.try_read_until(|| unreachable!()).unwrap(), 1);
+
+        // a writer waiting for the lock sets the bit again after another writer gave up
+        let r = lock.read();
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write() += 1);
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+        assert!(lock.try_write_for(10).is_none());
+        drop(r);
+        writer.join().unwrap();
+        assert_eq!(*lock.read(), 2);
+    }
+
+    #[test]
+    fn test_rwlock_park() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static PARKS: AtomicUsize = AtomicUsize::new(0);
+        static UNPARKS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Count;
+
+        impl crate::backoff::Park for Count {
+            const SPINS: usize = 0;
+
+            fn park() {
+                PARKS.fetch_add(1, Ordering::Relaxed);
+                std::thread::yield_now();
+            }
+
+            fn unpark() {
+                UNPARKS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let lock = Arc::new(rwlock::RwLock::new(0));
+
+        // no wait, no hooks
+        *lock.write_with_park::<Count>() += 1;
+        assert_eq!(*lock.read_with_park::<Count>(), 1);
+        assert_eq!(PARKS.load(Ordering::Relaxed), 0);
+
+        // a writer parks while a reader holds the lock, and unparks once
+        let r = lock.read();
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write_with_park::<Count>() += 1);
+        while PARKS.load(Ordering::Relaxed) == 0 {
+            std::thread::yield_now();
+        }
+        drop(r);
+        writer.join().unwrap();
+        assert_eq!(UNPARKS.load(Ordering::Relaxed), 1);
+
+        // a reader parks while a writer holds the lock
+        let w = lock.write();
+        let parks = PARKS.load(Ordering::Relaxed);
+        let lock0 = lock.clone();
+        let reader = std::thread::spawn(move || *lock0.read_with_park::<Count>());
+        while PARKS.load(Ordering::Relaxed) == parks {
+            std::thread::yield_now();
+        }
+        drop(w);
+        assert_eq!(reader.join().unwrap(), 2);
+        assert_eq!(UNPARKS.load(Ordering::Relaxed), 2);
+    }
+
+    #[cfg(feature = "epoch")]
+    #[test]
+    fn test_epoch() {
+        use crate::epoch;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use std::boxed::Box;
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        // a node deferred while a guard is pinned is not freed until the guard is dropped
+        let guard = epoch::pin();
+        unsafe { epoch::defer_destroy(Box::into_raw(Box::new(Counted))) };
+        for _ in 0..4 {
+            epoch::collect();
+        }
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+        drop(guard);
+        while DROPS.load(Ordering::Relaxed) == 0 {
+            epoch::collect();
+            std::thread::yield_now();
+        }
+
+        // every node is freed exactly once by concurrent collectors
+        let mut v = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let t = std::thread::spawn(move || {
+                for _ in 0..NUM_SMALL_LOOP {
+                    let _guard = epoch::pin();
+                    unsafe { epoch::defer_destroy(Box::into_raw(Box::new(Counted))) };
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        while DROPS.load(Ordering::Relaxed) < NUM_SMALL_THREADS * NUM_SMALL_LOOP + 1 {
+            epoch::collect();
+            std::thread::yield_now();
+        }
+        assert_eq!(
+            DROPS.load(Ordering::Relaxed),
+            NUM_SMALL_THREADS * NUM_SMALL_LOOP + 1
+        );
+    }
+
+    #[cfg(feature = "epoch")]
+    #[test]
+    fn test_lfstack_peek() {
+        use crate::{epoch, lfstack};
+        let stack = Arc::new(lfstack::LFStack::new());
+        {
+            let guard = epoch::pin();
+            assert_eq!(stack.peek(&guard), None);
+        }
+
+        stack.push(1);
+        stack.push(2);
+
+        let guard = epoch::pin();
+        let top = stack.peek(&guard).unwrap();
+
+        // the node popped by another thread is not freed while the guard is held
+        let stack0 = stack.clone();
+        let popped = std::thread::spawn(move || stack0.pop());
+        assert_eq!(popped.join().unwrap(), Some(2));
+        for _ in 0..4 {
+            epoch::collect();
+        }
+        assert_eq!(*top, 2);
+        assert_eq!(stack.peek(&guard), Some(&1));
+    }
+
+    #[cfg(feature = "epoch")]
+    #[test]
+    fn test_lfstack_peek_clone() {
+        use crate::lfstack;
+        let stack = Arc::new(lfstack::LFStack::<Vec<usize>>::new());
+        assert_eq!(stack.peek_clone(), None);
+
+        // the peeked values are cloned while other threads pop and drop them
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let stack0 = stack.clone();
+            let t = std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    if i == 0 {
+                        if let Some(top) = stack0.peek_clone() {
+                            assert!(top.iter().all(|x| *x == top[0]));
+                        }
+                    } else {
+                        stack0.push(vec![i * NUM_SMALL_LOOP + j; 4]);
+                        assert!(stack0.pop().is_some());
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        stack.push(vec![1]);
+        assert_eq!(stack.peek_clone(), Some(vec![1]));
+        assert_eq!(stack.pop(), Some(vec![1]));
+    }
+
+    #[cfg(any(target_arch = "aarch64", feature = "alloc", miri))]
+    #[test]
+    fn test_lfstack_recycle() {
+        use crate::lfstack;
+        let stack = Arc::new(lfstack::LFStack::new());
+        let counter = Arc::new(());
+        let mut v = Vec::new();
+
+        // every thread pushes and pops at once,
+        // so a popped node is freed and its memory is reused by the next push immediately
+        for i in 0..NUM_THREADS {
+            let stack0 = stack.clone();
+            let counter0 = counter.clone();
+            let t = std::thread::spawn(move || {
+                let mut sum = 0;
+                for j in 0..NUM_SMALL_LOOP * 100 {
+                    stack0.push((i * NUM_SMALL_LOOP * 100 + j, counter0.clone()));
+                    let (k, _) = stack0.pop_blocking();
+                    sum += k;
+                }
+                sum
+            });
+            v.push(t);
+        }
+
+        let mut sum = 0;
+        for t in v {
+            sum += t.join().unwrap();
+        }
+
+        // every value is popped exactly once, and dropped
+        let n = NUM_THREADS * NUM_SMALL_LOOP * 100;
+        assert_eq!(sum, n * (n - 1) / 2);
+        assert!(stack.pop().is_none());
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn test_rwlock_unlocked() {
+        let lock = Arc::new(rwlock::RwLock::new(0));
+        let mut w = lock.write();
+        *w = 1;
+
+        // nobody waits, so the lock is kept held
+        w.bump();
+        assert!(lock.try_read_for(0).is_none());
+
+        // a waiting reader observes the intermediate state while the lock is released
+        let lock0 = lock.clone();
+        let reader = std::thread::spawn(move || *lock0.read());
+        let v = w.unlocked(|| reader.join().unwrap());
+        assert_eq!(v, 1);
+        assert!(lock.try_read_for(0).is_none());
+        assert!(lock.try_write_for(0).is_none());
+
+        // `bump` lets a waiting reader and a waiting writer through
+        *w = 2;
+        let lock0 = lock.clone();
+        let reader = std::thread::spawn(move || *lock0.read());
+        while !reader.is_finished() {
+            w.bump();
+            std::thread::yield_now();
+        }
+        assert_eq!(reader.join().unwrap(), 2);
+
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || *lock0.write() += 10);
+        while !writer.is_finished() {
+            w.bump();
+            std::thread::yield_now();
+        }
+        writer.join().unwrap();
+        assert_eq!(*w, 12);
+        assert!(lock.try_read_for(0).is_none());
+
+        // the lock is acquired again even if the closure panics
+        let r = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+            w.unlocked(|| panic!("unlocked"))
+        }));
+        assert!(r.is_err());
+        assert!(lock.try_read_for(0).is_none());
+        *w += 1;
+        drop(w);
+        assert_eq!(*lock.read(), 13);
+    }
+
+    #[test]
+    fn test_rwlock_condvar() {
+        use std::collections::VecDeque;
+
+        const CAPACITY: usize = 4;
+
+        struct Buffer {
+            queue: rwlock::RwLock<VecDeque<usize>>,
+            not_empty: rwlock::RwCondVar,
+            not_full: rwlock::RwCondVar,
+        }
+
+        let buf = Arc::new(Buffer {
+            queue: rwlock::RwLock::new(VecDeque::new()),
+            not_empty: rwlock::RwCondVar::new(),
+            not_full: rwlock::RwCondVar::new(),
+        });
+
+        // producers block while the buffer is full, and consumers while it is empty
+        let mut producers = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let buf0 = buf.clone();
+            producers.push(std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    let mut queue = buf0
+                        .not_full
+                        .wait_while(buf0.queue.write(), |queue| queue.len() == CAPACITY);
+                    queue.push_back(i * NUM_SMALL_LOOP + j);
+                    assert!(queue.len() <= CAPACITY);
+                    drop(queue);
+                    buf0.not_empty.notify_one();
+                }
+            }));
+        }
+
+        let mut consumers = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let buf0 = buf.clone();
+            consumers.push(std::thread::spawn(move || {
+                let mut sum = 0;
+                for _ in 0..NUM_SMALL_LOOP {
+                    let mut queue = buf0
+                        .not_empty
+                        .wait_while(buf0.queue.write(), |queue| queue.is_empty());
+                    sum += queue.pop_front().unwrap();
+                    drop(queue);
+                    buf0.not_full.notify_one();
+                }
+                sum
+            }));
+        }
+
+        for t in producers {
+            t.join().unwrap();
+        }
+
+        let sum: usize = consumers.into_iter().map(|t| t.join().unwrap()).sum();
+        let n = NUM_SMALL_THREADS * NUM_SMALL_LOOP;
+        assert_eq!(sum, n * (n - 1) / 2);
+        assert!(buf.queue.read().is_empty());
+
+        // readers wait for a writer by `wait_read` and are woken by `notify_all`
+        let pair = Arc::new((rwlock::RwLock::new(0), rwlock::RwCondVar::new()));
+        let mut readers = Vec::new();
+        for _ in 0..NUM_SMALL_THREADS {
+            let pair0 = pair.clone();
+            readers.push(std::thread::spawn(move || {
+                let (lock, cond) = &*pair0;
+                let mut r = lock.read();
+                while *r == 0 {
+                    r = cond.wait_read(r);
+                }
+                *r
+            }));
+        }
+
+        let (lock, cond) = &*pair;
+        *lock.write() = 1;
+        cond.notify_all();
+        for t in readers {
+            assert_eq!(t.join().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_get_mut_data_ptr() {
+        let mut lock = rwlock::RwLock::new(0);
+        *lock.get_mut() += 1;
+
+        // the pointer stays the same, and the data is written through it while holding the lock
+        let ptr = lock.data_ptr();
+        {
+            let _w = lock.write();
+            unsafe { *ptr += 1 };
+        }
+        assert_eq!(lock.data_ptr(), ptr);
+        assert_eq!(*lock.read(), 2);
+
+        let mut lock = mcs::MCSLock::new(0);
+        *lock.get_mut() += 1;
+
+        let ptr = lock.data_ptr();
+        let mut node = mcs::MCSNode::new();
+        {
+            let _g = lock.lock(&mut node);
+            unsafe { *ptr += 1 };
+        }
+        assert_eq!(lock.data_ptr(), ptr);
+        assert_eq!(*lock.lock(&mut node), 2);
+    }
+
+    #[test]
+    fn test_rwlock_value_methods() {
+        let lock = Arc::new(rwlock::RwLock::new((0, 0)));
+
+        // every value stored by `replace` is returned exactly once by `replace` or `take`,
+        // or is left in the lock, if each call is a single critical section
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            v.push(std::thread::spawn(move || {
+                let mut sum = 0;
+                for j in 0..NUM_SMALL_LOOP {
+                    let k = i * NUM_SMALL_LOOP + j + 1;
+                    let old = if j % 4 == 0 {
+                        // put the taken value back, leaving the default in between
+                        let taken = lock0.take();
+                        sum += lock0.replace(taken).0;
+                        lock0.replace((k, k * 2))
+                    } else {
+                        lock0.replace((k, k * 2))
+                    };
+                    assert_eq!(old.1, old.0 * 2);
+                    sum += old.0;
+                }
+                sum
+            }));
+        }
+
+        let lock0 = lock.clone();
+        let reader = std::thread::spawn(move || {
+            for _ in 0..NUM_SMALL_LOOP {
+                let (a, b) = lock0.get_cloned();
+                assert_eq!(b, a * 2);
+            }
+        });
+
+        let sum: usize = v.into_iter().map(|t| t.join().unwrap()).sum();
+        reader.join().unwrap();
+
+        let n = NUM_SMALL_THREADS * NUM_SMALL_LOOP;
+        assert_eq!(sum + lock.get_cloned().0, n * (n + 1) / 2);
+
+        lock.set((1, 2));
+        assert_eq!(lock.take(), (1, 2));
+        assert_eq!(lock.get_cloned(), (0, 0));
+    }
+
+    #[test]
+    fn test_rwlock_load_store() {
+        // fits in a word, so it is read optimistically
+        #[derive(Clone, Copy)]
+        #[repr(C, align(8))]
+        struct Pair(u32, u32);
+
+        let lock = Arc::new(rwlock::RwLock::new(Pair(0, !0)));
+        let big = Arc::new(rwlock::RwLock::new([0usize; 4]));
+
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let lock0 = lock.clone();
+            let big0 = big.clone();
+            v.push(std::thread::spawn(move || {
+                for j in 0..NUM_SMALL_LOOP {
+                    if i & 1 == 0 {
+                        let k = (i * NUM_SMALL_LOOP + j) as u32;
+                        lock0.store(Pair(k, !k));
+                        big0.store([j; 4]);
+                    } else {
+                        // a load never observes a torn value
+                        let p = lock0.load();
+                        assert_eq!(p.1, !p.0);
+                        let a = big0.load();
+                        assert!(a.iter().all(|&x| x == a[0]));
+                    }
+                }
+            }));
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        lock.store(Pair(1, 2));
+        let p = lock.load();
+        assert_eq!((p.0, p.1), (1, 2));
+        assert_eq!(big.load(), [NUM_SMALL_LOOP - 1; 4]);
+    }
+
+    #[test]
+    fn test_rwlock_hook() {
+        use rwlock::LockKind::{Read, Write};
+        use std::sync::Mutex;
+
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            ReadContended,
+            WriteContended,
+            Acquired(rwlock::LockKind, bool),
+            Released(rwlock::LockKind),
+        }
+
+        static EVENTS: Mutex<Vec<(usize, Event)>> = Mutex::new(Vec::new());
+
+        std::thread_local! {
+            static ID: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+        }
+
+        fn record(e: Event) {
+            EVENTS.lock().unwrap().push((ID.with(|id| id.get()), e));
+        }
+
+        fn events_of(id: usize) -> Vec<Event> {
+            let mut events = EVENTS.lock().unwrap();
+            let (of, rest) = events.drain(..).partition(|(i, _)| *i == id);
+            *events = rest;
+            of.into_iter().map(|(_, e)| e).collect()
+        }
+
+        struct Recorder;
+
+        impl rwlock::RwHook for Recorder {
+            fn on_read_contended() {
+                record(Event::ReadContended);
+            }
+
+            fn on_write_contended() {
+                record(Event::WriteContended);
+            }
+
+            fn on_acquired(kind: rwlock::LockKind, waited: bool) {
+                record(Event::Acquired(kind, waited));
+            }
+
+            fn on_released(kind: rwlock::LockKind) {
+                record(Event::Released(kind));
+            }
+        }
+
+        let lock: Arc<rwlock::RwLock<usize, rwlock::WriterPreferred, Recorder>> =
+            Arc::new(rwlock::RwLock::with_policy(0));
+
+        // a writer blocked behind two readers
+        let r1 = lock.read();
+        let r2 = lock.read();
+        let lock0 = lock.clone();
+        let writer = std::thread::spawn(move || {
+            ID.with(|id| id.set(1));
+            *lock0.write() += 1;
+        });
+
+        while !lock.writer_pending() {
+            std::thread::yield_now();
+        }
+
+        // the writer reported the contention before the readers leave
+        assert_eq!(events_of(1), [Event::WriteContended]);
+        drop(r1);
+        drop(r2);
+        writer.join().unwrap();
+
+        assert_eq!(
+            events_of(0),
+            [
+                Event::Acquired(Read, false),
+                Event::Acquired(Read, false),
+                Event::Released(Read),
+                Event::Released(Read),
+            ]
+        );
+        assert_eq!(
+            events_of(1),
+            [Event::Acquired(Write, true), Event::Released(Write)]
+        );
+
+        // a bounded reader gives up after reporting the contention,
+        // and a mapped guard reports the release
+        let w = lock.write();
+        assert!(lock.try_read_for(0).is_none());
+        assert!(lock.try_read_for(1).is_none());
+        let w = rwlock::RwLockWriteGuard::map(w, |v| v);
+        drop(w);
+        assert_eq!(
+            events_of(0),
+            [
+                Event::Acquired(Write, false),
+                Event::ReadContended,
+                Event::Released(Write),
+            ]
+        );
+
+        // an upgrade releases the reader lock and acquires the writer lock
+        let r = lock.read();
+        let w = rwlock::RwLockReadGuard::try_upgrade(r).ok().unwrap();
+        drop(w);
+        assert_eq!(
+            events_of(0),
+            [
+                Event::Acquired(Read, false),
+                Event::Released(Read),
+                Event::Acquired(Write, false),
+                Event::Released(Write),
+            ]
+        );
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn test_raw_rwlock() {
+        use crate::rwlock::RawRwLock;
+        use core::ptr::addr_of_mut;
+
+        static LOCK: RawRwLock = RawRwLock::new();
+        static mut RESOURCE: usize = 0;
+
+        let mut v = Vec::new();
+        for i in 0..NUM_SMALL_THREADS {
+            let t = std::thread::spawn(move || {
+                let ptr = addr_of_mut!(RESOURCE);
+                for _ in 0..NUM_SMALL_LOOP {
+                    if i & 1 == 0 {
+                        // a reader never observes the odd value in between
+                        LOCK.lock_exclusive();
+                        unsafe {
+                            ptr.write_volatile(ptr.read_volatile() + 1);
+                            ptr.write_volatile(ptr.read_volatile() + 1);
+                            LOCK.unlock_exclusive();
+                        }
+                    } else {
+                        LOCK.lock_shared();
+                        unsafe {
+                            assert_eq!(ptr.read_volatile() % 2, 0);
+                            LOCK.unlock_shared();
+                        }
+                    }
+                }
+            });
+            v.push(t);
+        }
+
+        for t in v {
+            t.join().unwrap();
+        }
+
+        let writers = NUM_SMALL_THREADS.div_ceil(2);
+        LOCK.lock_shared();
+        assert!(LOCK.try_lock_shared());
+        assert!(!LOCK.try_lock_exclusive());
+        let n = unsafe { addr_of_mut!(RESOURCE).read_volatile() };
+        assert_eq!(n, 2 * NUM_SMALL_LOOP * writers);
+        unsafe {
+            LOCK.unlock_shared();
+            LOCK.unlock_shared();
+        }
+
+        assert!(LOCK.try_lock_exclusive());
+        assert!(!LOCK.try_lock_shared());
+        assert!(!LOCK.try_lock_exclusive());
+        unsafe { LOCK.unlock_exclusive() };
+        assert!(LOCK.try_lock_shared());
+        unsafe { LOCK.unlock_shared() };
+    }
 }
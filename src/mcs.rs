@@ -1,120 +1,480 @@
-use core::{marker::PhantomData, ptr::null_mut};
+use core::{marker::PhantomData, mem::ManuallyDrop, ptr::null_mut};
+
+use crate::backoff::Backoff;
+
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, sync::Arc};
 
 #[cfg(not(loom))]
 use core::{
     cell::UnsafeCell,
+    fmt,
     hint::spin_loop,
-    ops::{Deref, DerefMut},
-    sync::atomic::{fence, AtomicBool, AtomicPtr, Ordering},
+    ops::{Deref, DerefMut, Index, IndexMut},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
 };
 
 #[cfg(loom)]
 use loom::{
     cell::UnsafeCell,
     hint::spin_loop,
-    sync::atomic::{fence, AtomicBool, AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
 };
 
-pub struct MCSLock<T> {
-    last: AtomicPtr<MCSNode<T>>,
-    data: UnsafeCell<T>,
+#[cfg(all(any(feature = "metrics", feature = "prio"), not(loom)))]
+use core::sync::atomic::AtomicUsize;
+
+#[cfg(all(any(feature = "metrics", feature = "prio"), loom))]
+use loom::sync::atomic::AtomicUsize;
+
+/// MCS lock without data
+///
+/// This can be used to protect resources which are not Rust values,
+/// e.g. hardware devices.
+/// [`MCSLock`] is implemented by this and `UnsafeCell`.
+///
+/// ```rust
+/// use synctools::mcs::{RawMCSLock, RawMCSNode};
+///
+/// static UART_LOCK: RawMCSLock = RawMCSLock::new();
+///
+/// fn write_uart() {
+///     let mut node = RawMCSNode::new();
+///     let _guard = UART_LOCK.lock(&mut node);
+///
+///     // access the device exclusively
+/// }
+/// ```
+///
+/// # Fairness
+///
+/// Threads acquire the lock in strict FIFO order of their swap on the tail of the queue,
+/// because each thread is handed the lock only by its predecessor in the queue.
+/// The swap is the linearization point, so threads calling `lock` concurrently
+/// are ordered by whichever swap happens first, not by the call.
+/// The following do not follow the queue.
+///
+/// - With the `tme` feature, an elided critical section does not enqueue,
+///   so it can run before the threads waiting in the queue.
+/// - [`StaticMCSLock`] does not enqueue a thread until it takes a free node from the pool.
+///
+/// [`MCSLock`] and `MCSMutex` inherit this guarantee.
+pub struct RawMCSLock {
+    last: AtomicPtr<RawMCSNode>,
+    /// the number of threads enqueued behind the holder
+    #[cfg(feature = "metrics")]
+    waiters: AtomicUsize,
+    /// the identity of the holder which acquired the lock by `lock_with_prio`, or 0
+    #[cfg(feature = "prio")]
+    owner: AtomicUsize,
 }
 
-pub struct MCSNode<T> {
-    next: AtomicPtr<MCSNode<T>>,
+/// node of [`RawMCSLock`]
+pub struct RawMCSNode {
+    next: AtomicPtr<RawMCSNode>,
     locked: AtomicBool,
+    /// the identity of the holder and its hook to restore the priority,
+    /// which are written before the node is published
+    #[cfg(feature = "prio")]
+    prio: Option<(usize, fn(usize))>,
 }
 
-impl<T> Default for MCSNode<T> {
+impl Default for RawMCSNode {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T> MCSNode<T> {
-    pub fn new() -> MCSNode<T> {
-        MCSNode {
+impl RawMCSNode {
+    #[cfg(not(loom))]
+    pub const fn new() -> RawMCSNode {
+        RawMCSNode {
+            next: AtomicPtr::new(null_mut()),
+            locked: AtomicBool::new(false),
+            #[cfg(feature = "prio")]
+            prio: None,
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> RawMCSNode {
+        RawMCSNode {
             next: AtomicPtr::new(null_mut()),
             locked: AtomicBool::new(false),
+            #[cfg(feature = "prio")]
+            prio: None,
         }
     }
 }
 
-impl<T> MCSLock<T> {
-    pub fn new(v: T) -> MCSLock<T> {
-        MCSLock {
+impl Default for RawMCSLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// # Memory ordering
+//
+// The critical section of a holder must happen before
+// the critical section of the next holder.
+// There are two ways to pass the lock.
+//
+// 1. No waiter: the holder sets `last` to null by the `Release` CAS (R2),
+//    and the next thread reads the null by the `Acquire` part of the swap (A1).
+// 2. Handoff: the holder sets `locked` of the successor by the `Release` store (R3),
+//    and the successor reads it by the `Acquire` load (A3).
+//
+// In addition, a node is initialized by plain writes before it is published,
+// and the other threads write to it through the published pointer.
+// These writes must happen after the initialization,
+// otherwise the initialization can overwrite them.
+//
+// - The successor writes `next` of the predecessor (A2)
+//   after reading the predecessor by the `Acquire` part of the swap (A1),
+//   which synchronizes with the `Release` part of the predecessor's swap (A1)
+//   executed after the initialization.
+// - The predecessor writes `locked` of the successor (R3)
+//   after reading the successor by the `Acquire` load of `next` (R1),
+//   which synchronizes with the `Release` store of the successor (A2)
+//   executed after the initialization.
+//
+// `Relaxed` is used only for the failure of the CAS (R2),
+// because nothing is read through `last` in that case.
+//
+// With the `metrics` feature, `waiters` is incremented before enqueuing (A2)
+// and decremented after being woken up (A3), by `Relaxed` operations,
+// because it is only a statistic and nothing is read through it.
+//
+// These are checked by the loom models in `tests/test_mcslock.rs`,
+// which access the data through `loom::cell::UnsafeCell`.
+impl RawMCSLock {
+    #[cfg(not(loom))]
+    pub const fn new() -> RawMCSLock {
+        RawMCSLock {
             last: AtomicPtr::new(null_mut()),
-            data: UnsafeCell::new(v),
+            #[cfg(feature = "metrics")]
+            waiters: AtomicUsize::new(0),
+            #[cfg(feature = "prio")]
+            owner: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> RawMCSLock {
+        RawMCSLock {
+            last: AtomicPtr::new(null_mut()),
+            #[cfg(feature = "metrics")]
+            waiters: AtomicUsize::new(0),
+            #[cfg(feature = "prio")]
+            owner: AtomicUsize::new(0),
         }
     }
 
     /// acquire lock
-    pub fn lock<'a>(&'a self, node: &'a mut MCSNode<T>) -> MCSLockGuard<T> {
-        node.next = AtomicPtr::new(null_mut());
-        node.locked = AtomicBool::new(false);
+    ///
+    /// With the `tme` feature, this first tries to elide the lock
+    /// by a transaction of the Transactional Memory Extension of AArch64.
+    /// See [`RawMCSGuard::is_elided`].
+    pub fn lock<'a>(&'a self, node: &'a mut RawMCSNode) -> RawMCSGuard<'a> {
+        // other threads access the node through this pointer,
+        // so the node is accessed only through it until the guard is dropped
+        let node = node as *mut RawMCSNode;
+
+        #[cfg(feature = "tme")]
+        let elided = self.try_elide();
 
-        // set myself as the last node
-        let guard = MCSLockGuard {
+        #[cfg(feature = "tme")]
+        if !elided {
+            unsafe { self.acquire(node) };
+        }
+
+        #[cfg(not(feature = "tme"))]
+        unsafe {
+            self.acquire(node)
+        };
+
+        RawMCSGuard {
             node,
-            mcs_lock: self,
+            raw_lock: self,
+            #[cfg(feature = "tme")]
+            elided,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// try to acquire lock by a single `compare_exchange_weak` from the unlocked state
+    ///
+    /// This returns `None` if the lock is held.
+    /// It may also fail spuriously even if the lock is free,
+    /// because the weak CAS maps to a single LL/SC pair on AArch64 and RISC-V without a retry loop.
+    /// So this is for callers which already retry in a loop, e.g. with their own backoff.
+    /// The lock is never elided by this method.
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_lock_weak<'a>(&'a self, node: &'a mut RawMCSNode) -> Option<RawMCSGuard<'a>> {
+        let ptr = node as *mut RawMCSNode;
+        unsafe {
+            (*ptr).next = AtomicPtr::new(null_mut());
+            (*ptr).locked = AtomicBool::new(false);
+            #[cfg(feature = "prio")]
+            {
+                (*ptr).prio = None;
+            }
+        }
+
+        // `Acquire` synchronizes with the release (R2) as the swap (A1),
+        // and `Release` publishes the initialization of the node to the successor
+        self.last
+            .compare_exchange_weak(null_mut(), ptr, Ordering::AcqRel, Ordering::Relaxed)
+            .ok()?;
+
+        Some(RawMCSGuard {
+            node: ptr,
+            raw_lock: self,
+            #[cfg(feature = "tme")]
+            elided: false,
             _phantom: PhantomData,
+        })
+    }
+
+    /// acquire lock with priority inheritance
+    ///
+    /// While the current thread waits for the lock,
+    /// the holder is boosted by [`PriorityHooks::boost`] if it acquired the lock by this method,
+    /// and it is boosted again whenever the holder changes.
+    /// When the lock acquired by this method is released,
+    /// the holder restores its own priority by [`PriorityHooks::restore`]
+    /// after handing over the lock to the next thread.
+    /// Holders which acquired the lock by [`RawMCSLock::lock`] are not boosted.
+    /// The lock is never elided by this method.
+    ///
+    /// # Correctness
+    ///
+    /// This only calls the hooks, so priority inversion is avoided
+    /// only if the hooks implement priority inheritance correctly.
+    /// Because the hooks run without synchronizing with the holder,
+    /// `boost` may be called for a thread which has just released the lock,
+    /// in which case `restore` is called for it again.
+    /// Therefore, both hooks must be idempotent,
+    /// and `restore` must be callable from any thread.
+    ///
+    /// ```rust
+    /// use synctools::mcs::{MCSLock, MCSNode, PriorityHooks};
+    ///
+    /// struct Rtos;
+    ///
+    /// impl PriorityHooks for Rtos {
+    ///     fn current() -> usize {
+    ///         // e.g. the task ID of the RTOS, which must not be 0
+    ///         1
+    ///     }
+    ///
+    ///     fn boost(owner: usize) {
+    ///         // raise the priority of `owner` to the priority of the current task
+    ///     }
+    ///
+    ///     fn restore(owner: usize) {
+    ///         // restore the base priority of `owner`
+    ///     }
+    /// }
+    ///
+    /// let lock = MCSLock::new(0);
+    /// let mut node = MCSNode::new();
+    /// *lock.lock_with_prio::<Rtos>(&mut node) += 1;
+    /// ```
+    #[cfg(feature = "prio")]
+    pub fn lock_with_prio<'a, H: PriorityHooks>(
+        &'a self,
+        node: &'a mut RawMCSNode,
+    ) -> RawMCSGuard<'a> {
+        let node = node as *mut RawMCSNode;
+        let current = H::current();
+        debug_assert_ne!(current, 0, "PriorityHooks::current must not return 0");
+
+        let mut owner = 0;
+        unsafe {
+            self.acquire_with(node, Some((current, H::restore)), || {
+                let o = self.owner.load(Ordering::Relaxed);
+                if o != owner {
+                    owner = o;
+                    if o != 0 {
+                        H::boost(o);
+
+                        // the holder may have restored its priority before the boost
+                        if self.owner.load(Ordering::Relaxed) != o {
+                            H::restore(o);
+                        }
+                    }
+                }
+            })
         };
+        self.owner.store(current, Ordering::Relaxed);
+
+        RawMCSGuard {
+            node,
+            raw_lock: self,
+            #[cfg(feature = "tme")]
+            elided: false,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// start a transaction if the lock is not held
+    ///
+    /// `last` is added to the read set of the transaction,
+    /// so the transaction is aborted when another thread acquires the lock by the queue.
+    #[cfg(feature = "tme")]
+    fn try_elide(&self) -> bool {
+        #[cfg(all(target_arch = "aarch64", not(loom)))]
+        if crate::tme::is_available() {
+            for _ in 0..TME_RETRIES {
+                match unsafe { crate::tme::tstart() } {
+                    Ok(()) => {
+                        if self.last.load(Ordering::Relaxed).is_null() {
+                            return true;
+                        }
+
+                        // restart from tstart without the retry flag
+                        unsafe { crate::tme::tcancel::<{ crate::tme::REASON_LOCKED }>() };
+                    }
+                    Err(status) => {
+                        if status & crate::tme::STATUS_RTRY == 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    /// acquire the lock by `node`
+    ///
+    /// # Safety
+    ///
+    /// `node` must be valid, and must not be moved or accessed
+    /// except through `node` until the lock is released by `release`.
+    /// References to the node must not be made in between,
+    /// because they would invalidate the pointer held by the other threads.
+    unsafe fn acquire(&self, ptr: *mut RawMCSNode) {
+        self.acquire_with(
+            ptr,
+            #[cfg(feature = "prio")]
+            None,
+            || (),
+        );
+    }
+
+    /// acquire the lock by `node`, and call `wait` repeatedly while waiting
+    ///
+    /// With the `prio` feature, `prio` is stored to the node to restore the priority on release.
+    ///
+    /// # Safety
+    ///
+    /// See `acquire`.
+    unsafe fn acquire_with(
+        &self,
+        ptr: *mut RawMCSNode,
+        #[cfg(feature = "prio")] prio: Option<(usize, fn(usize))>,
+        mut wait: impl FnMut(),
+    ) {
+        (*ptr).next = AtomicPtr::new(null_mut());
+        (*ptr).locked = AtomicBool::new(false);
+        #[cfg(feature = "prio")]
+        {
+            (*ptr).prio = prio;
+        }
+
+        // the fields are atomic, so a shared reference does not conflict with the other threads
+        let node = &*ptr;
 
-        let ptr = guard.node as *mut MCSNode<T>;
+        // set myself as the last node (A1)
+        //
+        // without contention, this swap is the only atomic read-modify-write to acquire the lock;
+        // trying a CAS from null first is not faster, because a CAS costs as much as a swap,
+        // and it needs a load in advance to avoid a failing CAS under contention (see benches/mcs.rs)
         let prev = self.last.swap(ptr, Ordering::AcqRel);
 
         // if prev is null then nobody is trying to acquire lock
         if prev.is_null() {
-            return guard;
+            return;
         }
 
-        // enqueue myself
+        #[cfg(feature = "metrics")]
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+
+        // enqueue myself (A2)
         let prev = unsafe { &*prev };
         prev.next.store(ptr, Ordering::Release);
 
-        // spin until other thread sets locked true
-        while !guard.node.locked.load(Ordering::Relaxed) {
+        // spin until other thread sets locked true (A3)
+        while !node.locked.load(Ordering::Acquire) {
+            wait();
             spin_loop();
 
             #[cfg(loom)]
             loom::thread::yield_now();
         }
-        fence(Ordering::Acquire);
 
-        guard
+        #[cfg(feature = "metrics")]
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
     }
-}
 
-unsafe impl<T> Sync for MCSLock<T> {}
-unsafe impl<T> Send for MCSLock<T> {}
+    /// approximate number of threads waiting for the lock
+    ///
+    /// The holder of the lock is not counted.
+    /// The counter is updated without synchronizing with the queue,
+    /// so the value may be stale as soon as it is returned,
+    /// and it may briefly miss a thread which has just swapped itself into the queue.
+    /// Use it only as a statistic, e.g. to decide when to shed load.
+    #[cfg(feature = "metrics")]
+    pub fn approx_waiters(&self) -> usize {
+        self.waiters.load(Ordering::Relaxed)
+    }
 
-pub struct MCSLockGuard<'a, T> {
-    node: &'a mut MCSNode<T>,
-    mcs_lock: &'a MCSLock<T>,
-    _phantom: PhantomData<*mut ()>,
-}
+    /// release the lock acquired by `node`
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held by `node`, which is the pointer passed to `acquire`.
+    unsafe fn release(&self, ptr: *mut RawMCSNode) {
+        #[cfg(feature = "prio")]
+        let prio = (*ptr).prio;
 
-impl<'a, T> MCSLockGuard<'a, T> {
-    /// unlock MCS lock
-    pub fn unlock(self) {}
+        #[cfg(feature = "prio")]
+        if prio.is_some() {
+            self.owner.store(0, Ordering::Relaxed);
+        }
 
-    #[cfg(loom)]
-    pub fn with_mut<F, R>(&mut self, f: F) -> R
-    where
-        F: FnOnce(*mut T) -> R,
-    {
-        self.mcs_lock.data.with_mut(f)
+        self.hand_over(ptr);
+
+        // restore after the next thread is made executable,
+        // so that the holder is not preempted while it hands over the lock
+        #[cfg(feature = "prio")]
+        if let Some((current, restore)) = prio {
+            restore(current);
+        }
     }
-}
 
-impl<'a, T> Drop for MCSLockGuard<'a, T> {
-    fn drop(&mut self) {
+    /// release the lock acquired by `node`, and make the next thread executable if any
+    ///
+    /// # Safety
+    ///
+    /// See `release`.
+    unsafe fn hand_over(&self, ptr: *mut RawMCSNode) {
+        let node = &*ptr;
+
+        // (R1)
+        let mut next = node.next.load(Ordering::Acquire);
+
         // if next node is null and self is the last node
         // set the last node to null
-        if self.node.next.load(Ordering::Relaxed).is_null() {
-            let ptr = self.node as *mut MCSNode<T>;
+        //
+        // this is the fast path without contention, which takes a CAS only,
+        // and the CAS is skipped if a successor has already been enqueued
+        if next.is_null() {
+            // (R2)
             if self
-                .mcs_lock
                 .last
                 .compare_exchange(ptr, null_mut(), Ordering::Release, Ordering::Relaxed)
                 .is_ok()
@@ -122,8 +482,13 @@ impl<'a, T> Drop for MCSLockGuard<'a, T> {
                 return;
             }
 
-            // other thread is entering lock and wait the execution
-            while self.node.next.load(Ordering::Relaxed).is_null() {
+            // other thread is entering lock and wait the execution (R1)
+            loop {
+                next = node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+
                 spin_loop();
 
                 #[cfg(loom)]
@@ -131,24 +496,1527 @@ impl<'a, T> Drop for MCSLockGuard<'a, T> {
             }
         }
 
-        // make next thread executable
-        let next = unsafe { &mut *self.node.next.load(Ordering::Acquire) };
-        next.locked.store(true, Ordering::Release);
+        // make next thread executable (R3)
+        (*next).locked.store(true, Ordering::Release);
     }
 }
 
-#[cfg(not(loom))]
-impl<'a, T> Deref for MCSLockGuard<'a, T> {
-    type Target = T;
+/// hooks of the scheduler for [`RawMCSLock::lock_with_prio`] and [`MCSLock::lock_with_prio`]
+///
+/// The identity of a thread and the operations on its priority depend on the scheduler,
+/// so they are supplied by the user.
+#[cfg(feature = "prio")]
+pub trait PriorityHooks {
+    /// the identity of the current thread, which must not be 0
+    fn current() -> usize;
 
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.mcs_lock.data.get() }
+    /// raise the priority of `owner` to the priority of the current thread if it is lower
+    fn boost(owner: usize);
+
+    /// restore the base priority of `owner`
+    fn restore(owner: usize);
+}
+
+/// the number of transactions tried before falling back to the queue
+#[cfg(all(feature = "tme", target_arch = "aarch64", not(loom)))]
+const TME_RETRIES: usize = 3;
+
+/// guard of [`RawMCSLock`]
+///
+/// # Lock elision
+///
+/// With the `tme` feature, the guard may hold a transaction instead of the lock.
+/// The transaction is committed when the guard is dropped,
+/// and if another thread acquires the lock or accesses the same memory in between,
+/// the transaction is aborted and the critical section is restarted from `lock`.
+/// After the aborts, the lock is acquired by the queue as usual.
+///
+/// Therefore, irrevocable operations, e.g. system calls, I/O, and accesses to devices,
+/// must not be performed in critical sections which can be elided,
+/// because they may be executed more than once or abort the transaction every time.
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct RawMCSGuard<'a> {
+    node: *mut RawMCSNode,
+    raw_lock: &'a RawMCSLock,
+    #[cfg(feature = "tme")]
+    elided: bool,
+    _phantom: PhantomData<(&'a mut RawMCSNode, *mut ())>,
+}
+
+impl<'a> RawMCSGuard<'a> {
+    /// unlock MCS lock
+    pub fn unlock(self) {}
+
+    /// true if the lock is elided by a transaction
+    ///
+    /// This is always false if TME is not available.
+    #[cfg(feature = "tme")]
+    pub fn is_elided(&self) -> bool {
+        self.elided
+    }
+
+    /// abort the transaction if the lock is elided
+    ///
+    /// The critical section is restarted from `lock`,
+    /// and the lock is acquired by the queue this time.
+    #[cfg(feature = "tme")]
+    fn ensure_acquired(&self) {
+        #[cfg(all(target_arch = "aarch64", not(loom)))]
+        if self.elided {
+            unsafe { crate::tme::tcancel::<{ crate::tme::REASON_LOCKED }>() };
+        }
+    }
+}
+
+impl<'a> Drop for RawMCSGuard<'a> {
+    fn drop(&mut self) {
+        #[cfg(all(feature = "tme", target_arch = "aarch64", not(loom)))]
+        if self.elided {
+            unsafe { crate::tme::tcommit() };
+            return;
+        }
+
+        unsafe { self.raw_lock.release(self.node) };
+    }
+}
+
+pub struct MCSLock<T> {
+    raw: RawMCSLock,
+    #[cfg(feature = "poison")]
+    poison: crate::poison::Flag,
+    data: UnsafeCell<T>,
+}
+
+#[repr(transparent)]
+pub struct MCSNode<T> {
+    raw: RawMCSNode,
+    _phantom: PhantomData<fn(T) -> T>,
+}
+
+impl<T> Default for MCSNode<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MCSNode<T> {
+    pub fn new() -> MCSNode<T> {
+        MCSNode {
+            raw: RawMCSNode::new(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> MCSLock<T> {
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> MCSLock<T> {
+        MCSLock {
+            raw: RawMCSLock::new(),
+            #[cfg(feature = "poison")]
+            poison: crate::poison::Flag::new(),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(v: T) -> MCSLock<T> {
+        MCSLock {
+            raw: RawMCSLock::new(),
+            #[cfg(feature = "poison")]
+            poison: crate::poison::Flag::new(),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    /// create a lock of the value returned by `f`, or return the error of `f`
+    pub fn try_new<E, F>(f: F) -> Result<MCSLock<T>, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        f().map(MCSLock::new)
+    }
+
+    /// acquire lock
+    pub fn lock<'a>(&'a self, node: &'a mut MCSNode<T>) -> MCSLockGuard<'a, T> {
+        MCSLockGuard {
+            raw: self.raw.lock(&mut node.raw),
+            mcs_lock: self,
+            #[cfg(feature = "poison")]
+            panicking: self.poison.guard(),
+        }
+    }
+
+    /// try to acquire lock by a single `compare_exchange_weak` from the unlocked state
+    ///
+    /// See [`RawMCSLock::try_lock_weak`].
+    ///
+    /// ```rust
+    /// use synctools::mcs::{MCSLock, MCSNode};
+    ///
+    /// let lock = MCSLock::new(0);
+    /// let mut node = MCSNode::new();
+    /// let mut guard = loop {
+    ///     if let Some(guard) = lock.try_lock_weak(&mut node) {
+    ///         break guard;
+    ///     }
+    /// };
+    /// *guard += 1;
+    /// ```
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_lock_weak<'a>(&'a self, node: &'a mut MCSNode<T>) -> Option<MCSLockGuard<'a, T>> {
+        Some(MCSLockGuard {
+            raw: self.raw.try_lock_weak(&mut node.raw)?,
+            mcs_lock: self,
+            #[cfg(feature = "poison")]
+            panicking: self.poison.guard(),
+        })
+    }
+
+    /// acquire lock with priority inheritance
+    ///
+    /// See [`RawMCSLock::lock_with_prio`].
+    #[cfg(feature = "prio")]
+    pub fn lock_with_prio<'a, H: PriorityHooks>(
+        &'a self,
+        node: &'a mut MCSNode<T>,
+    ) -> MCSLockGuard<'a, T> {
+        MCSLockGuard {
+            raw: self.raw.lock_with_prio::<H>(&mut node.raw),
+            mcs_lock: self,
+            #[cfg(feature = "poison")]
+            panicking: self.poison.guard(),
+        }
+    }
+
+    /// acquire lock, and return `Err` if the lock is poisoned
+    ///
+    /// The lock is poisoned if a thread panicked while holding it.
+    /// Even then, the lock is acquired and the guard can be taken
+    /// by [`crate::poison::PoisonError::into_inner`].
+    #[cfg(feature = "poison")]
+    pub fn lock_checked<'a>(
+        &'a self,
+        node: &'a mut MCSNode<T>,
+    ) -> crate::poison::LockResult<MCSLockGuard<'a, T>> {
+        let guard = self.lock(node);
+        self.poison.map_result(guard)
+    }
+
+    /// true if a thread panicked while holding the lock
+    #[cfg(feature = "poison")]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// approximate number of threads waiting for the lock
+    ///
+    /// See [`RawMCSLock::approx_waiters`].
+    #[cfg(feature = "metrics")]
+    pub fn approx_waiters(&self) -> usize {
+        self.raw.approx_waiters()
+    }
+
+    /// snapshot the raw tail of the queue without taking the lock
+    ///
+    /// The pointer is null if the lock is free, and otherwise points to the node of the last thread in the queue.
+    ///
+    /// The snapshot is racy, and the node may be freed as soon as it is returned.
+    /// Use it only for diagnostics, e.g. to compare with `null`, and never dereference it.
+    #[cfg(feature = "internals")]
+    pub fn raw_last(&self) -> *const () {
+        self.raw.last.load(Ordering::Relaxed) as *const ()
+    }
+
+    /// clear the poisoned state
+    #[cfg(feature = "poison")]
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    /// replace the value by `value` and return the old value
+    pub fn replace(&self, node: &mut MCSNode<T>, value: T) -> T {
+        #[cfg(not(loom))]
+        {
+            let mut guard = self.lock(node);
+            core::mem::replace(&mut *guard, value)
+        }
+
+        #[cfg(loom)]
+        {
+            let mut guard = self.lock(node);
+            guard.with_mut(|data| unsafe { core::mem::replace(&mut *data, value) })
+        }
+    }
+
+    /// take the value and leave `T::default()`
+    pub fn take(&self, node: &mut MCSNode<T>) -> T
+    where
+        T: Default,
+    {
+        self.replace(node, T::default())
+    }
+
+    /// swap the value with `other`
+    pub fn swap(&self, node: &mut MCSNode<T>, other: &mut T) {
+        #[cfg(not(loom))]
+        {
+            let mut guard = self.lock(node);
+            core::mem::swap(&mut *guard, other);
+        }
+
+        #[cfg(loom)]
+        {
+            let mut guard = self.lock(node);
+            guard.with_mut(|data| unsafe { core::ptr::swap(data, other) });
+        }
+    }
+
+    /// release the lock held by `node` without a guard
+    ///
+    /// This pairs with [`MCSLockGuard::leak`], e.g. to release a lock
+    /// which was acquired before handing the data over an FFI boundary.
+    /// Unlike other locks, the node of the holder is needed to hand over the lock to the next thread,
+    /// so it is passed explicitly.
+    ///
+    /// ```rust
+    /// use synctools::mcs::{MCSLock, MCSLockGuard, MCSNode};
+    ///
+    /// let lock = MCSLock::new(0);
+    /// let mut node = MCSNode::new();
+    ///
+    /// let data = MCSLockGuard::leak(lock.lock(&mut node));
+    /// *data += 1;
+    ///
+    /// // the reference is not used any longer
+    /// unsafe { lock.force_unlock(&mut node) };
+    /// assert_eq!(*lock.lock(&mut node), 1);
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// This bypasses RAII, so the caller must guarantee all of the following.
+    ///
+    /// - The lock must be held by `node`, and its guard must be leaked by `MCSLockGuard::leak`
+    ///   or otherwise forgotten, so that it is never dropped.
+    /// - `node` must not be moved or accessed between `leak` and this call.
+    /// - The reference returned by `leak` must not be used after this call,
+    ///   because another thread may hold the lock then.
+    /// - The lock must be released only once.
+    pub unsafe fn force_unlock(&self, node: &mut MCSNode<T>) {
+        self.raw.release(&mut node.raw as *mut RawMCSNode);
+    }
+
+    /// get the data without locking, which is safe because of `&mut self`
+    ///
+    /// No guard can be alive while the lock is borrowed mutably, so this ignores poisoning.
+    #[cfg(not(loom))]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// raw pointer to the data
+    ///
+    /// This does not acquire the lock, e.g. to pass the data to C code
+    /// while the caller holds the lock.
+    /// Dereferencing the pointer is safe only while the lock is held,
+    /// or while the caller guarantees exclusive access otherwise, e.g. by `&mut MCSLock`.
+    ///
+    /// The pointer stays the same until the lock is moved or dropped.
+    /// A reference created from it must not outlive the lock held for it,
+    /// and must not overlap a reference from a guard or [`MCSLock::get_mut`].
+    #[cfg(not(loom))]
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+unsafe impl<T: Send> Sync for MCSLock<T> {}
+unsafe impl<T: Send> Send for MCSLock<T> {}
+
+/// serialize the data while holding the lock
+///
+/// The lock is acquired during serialization,
+/// so serializing a lock which is held by the current thread deadlocks.
+#[cfg(all(feature = "serde", not(loom)))]
+impl<T: serde::Serialize> serde::Serialize for MCSLock<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut node = MCSNode::new();
+        let guard = self.lock(&mut node);
+        (*guard).serialize(serializer)
+    }
+}
+
+/// deserialize the data and create a new lock
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for MCSLock<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(MCSLock::new)
     }
 }
 
+/// create a new lock of a clone of the data
+///
+/// The lock of `self` is acquired by a node on the stack while cloning the data,
+/// so cloning a lock held by the current thread deadlocks.
+/// The new lock is independent of `self`, and is not poisoned even if `self` is.
+///
+/// ```rust
+/// use synctools::mcs::{MCSLock, MCSNode};
+///
+/// #[derive(Clone)]
+/// struct Counters {
+///     hits: MCSLock<u64>,
+/// }
+///
+/// let a = Counters { hits: MCSLock::new(1) };
+/// let b = a.clone();
+///
+/// let mut node = MCSNode::new();
+/// *b.hits.lock(&mut node) += 1;
+/// assert_eq!(*a.hits.lock(&mut node), 1);
+/// assert_eq!(*b.hits.lock(&mut node), 2);
+/// ```
 #[cfg(not(loom))]
-impl<'a, T> DerefMut for MCSLockGuard<'a, T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.mcs_lock.data.get() }
+impl<T: Clone> Clone for MCSLock<T> {
+    fn clone(&self) -> Self {
+        let mut node = MCSNode::new();
+        let guard = self.lock(&mut node);
+        MCSLock::new((*guard).clone())
+    }
+}
+
+/// fold `items` into the data of `lock` by `f` from scoped threads
+///
+/// The items are split into as many chunks as [`std::thread::available_parallelism`],
+/// and each chunk is folded by a thread with its own node,
+/// which calls `f` with the data and an item while holding the lock.
+/// This returns after all threads finish.
+/// `f` is called in no particular order,
+/// so the result should not depend on the order, e.g. a sum.
+///
+/// ```rust
+/// use synctools::mcs::{parallel_fold, MCSLock, MCSNode};
+///
+/// let lock = MCSLock::new(0);
+/// parallel_fold(&lock, 1..=100, |sum, i| *sum += i);
+///
+/// let mut node = MCSNode::new();
+/// assert_eq!(*lock.lock(&mut node), 5050);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `f` panics in a thread.
+#[cfg(all(feature = "std", not(loom)))]
+pub fn parallel_fold<T, I, F>(lock: &MCSLock<T>, items: I, f: F)
+where
+    T: Send,
+    I: IntoIterator,
+    I::Item: Send,
+    F: Fn(&mut T, I::Item) + Sync,
+{
+    let mut items: std::vec::Vec<I::Item> = items.into_iter().collect();
+    if items.is_empty() {
+        return;
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = items.len().div_ceil(threads);
+    let f = &f;
+
+    std::thread::scope(|s| {
+        while !items.is_empty() {
+            let rest = items.split_off(chunk.min(items.len()));
+            let part = core::mem::replace(&mut items, rest);
+            s.spawn(move || {
+                let mut node = MCSNode::new();
+                for item in part {
+                    f(&mut lock.lock(&mut node), item);
+                }
+            });
+        }
+    });
+}
+
+/// guard of [`MCSLock`], which releases the lock when it is dropped
+///
+/// The guard is also dropped while unwinding from a panic of the critical section,
+/// so the lock is handed over to the next waiter as usual, even without `std`.
+/// The lock is poisoned then only with the `poison` feature.
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct MCSLockGuard<'a, T> {
+    /// not `Send`, because the node must not be released by another thread
+    raw: RawMCSGuard<'a>,
+    /// `MCSLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    mcs_lock: &'a MCSLock<T>,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+}
+
+// a shared reference to a guard gives only `&T`
+unsafe impl<'a, T: Sync> Sync for MCSLockGuard<'a, T> {}
+
+impl<'a, T> MCSLockGuard<'a, T> {
+    /// unlock MCS lock
+    pub fn unlock(self) {}
+
+    /// consume the guard without unlocking, and return the reference to the data
+    ///
+    /// The lock stays held until it is released by [`MCSLock::force_unlock`],
+    /// and it is never released if that is not called,
+    /// so every thread trying to acquire it spins forever.
+    /// This is an associated function, because a method would shadow the methods of `T`,
+    /// so call it as `MCSLockGuard::leak(guard)`.
+    ///
+    /// A transaction cannot outlive the guard,
+    /// so if the lock is elided, the critical section is restarted
+    /// and the lock is acquired by the queue.
+    #[cfg(not(loom))]
+    pub fn leak(s: Self) -> &'a mut T {
+        #[cfg(feature = "tme")]
+        s.raw.ensure_acquired();
+
+        let guard = ManuallyDrop::new(s);
+        unsafe { &mut *guard.mcs_lock.data.get() }
+    }
+
+    /// true if the lock is elided by a transaction
+    ///
+    /// See [`RawMCSGuard`] for lock elision.
+    #[cfg(feature = "tme")]
+    pub fn is_elided(&self) -> bool {
+        self.raw.is_elided()
+    }
+
+    /// decompose the guard into the raw pointers of the lock and the node without unlocking
+    ///
+    /// The lock is kept acquired until the guard is rebuilt by
+    /// [`MCSLockGuard::from_raw`] and dropped.
+    ///
+    /// A transaction cannot outlive the guard,
+    /// so if the lock is elided, the critical section is restarted
+    /// and the lock is acquired by the queue.
+    pub fn into_raw(self) -> (*const MCSLock<T>, *mut MCSNode<T>) {
+        #[cfg(feature = "tme")]
+        self.raw.ensure_acquired();
+
+        let guard = ManuallyDrop::new(self);
+        (
+            guard.mcs_lock as *const MCSLock<T>,
+            guard.raw.node as *mut MCSNode<T>,
+        )
+    }
+
+    /// rebuild a guard from the raw pointers returned by [`MCSLockGuard::into_raw`]
+    ///
+    /// Dropping the returned guard unlocks the lock exactly as the original guard does.
+    ///
+    /// # Safety
+    ///
+    /// - `lock` and `node` must be the pointers returned by a single call of `into_raw`,
+    ///   and the guard must be rebuilt only once.
+    /// - The lock must still be held, that is, it must not be unlocked by other means.
+    /// - The node must not be moved, dropped, or accessed until the rebuilt guard is dropped.
+    /// - Both the lock and the node must outlive the rebuilt guard,
+    ///   even though its lifetime is `'static`.
+    pub unsafe fn from_raw(
+        lock: *const MCSLock<T>,
+        node: *mut MCSNode<T>,
+    ) -> MCSLockGuard<'static, T> {
+        MCSLockGuard {
+            raw: RawMCSGuard {
+                node: node as *mut RawMCSNode,
+                raw_lock: &(*lock).raw,
+                #[cfg(feature = "tme")]
+                elided: false,
+                _phantom: PhantomData,
+            },
+            mcs_lock: &*lock,
+            #[cfg(feature = "poison")]
+            panicking: (*lock).poison.guard(),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(*mut T) -> R,
+    {
+        self.mcs_lock.data.with_mut(f)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MCSLock<T> {
+    /// acquire lock, and return a guard owning the node and a clone of the `Arc`
+    ///
+    /// The guard has no lifetime, so it can be stored in structures
+    /// which outlive the stack frame acquiring the lock.
+    /// The node is allocated on the heap so that it is not moved while it is in the queue.
+    /// The lock is released and then the `Arc` is dropped when the guard is dropped.
+    ///
+    /// The lock is never elided by TME, because a transaction cannot outlive the caller.
+    ///
+    /// ```rust
+    /// use synctools::mcs::{ArcMCSGuard, MCSLock};
+    /// use std::sync::Arc;
+    ///
+    /// struct Holder {
+    ///     guard: ArcMCSGuard<usize>,
+    /// }
+    ///
+    /// let lock = Arc::new(MCSLock::new(0));
+    /// let mut holder = Holder {
+    ///     guard: lock.lock_arc(),
+    /// };
+    /// *holder.guard += 1;
+    /// drop(holder);
+    ///
+    /// let mut node = synctools::mcs::MCSNode::new();
+    /// assert_eq!(*lock.lock(&mut node), 1);
+    /// ```
+    pub fn lock_arc(self: &Arc<Self>) -> ArcMCSGuard<T> {
+        let node = Box::into_raw(Box::new(RawMCSNode::new()));
+
+        // the lock is released and the node is freed by ArcMCSGuard::drop
+        unsafe { self.raw.acquire(node) };
+
+        ArcMCSGuard {
+            mcs_lock: self.clone(),
+            node,
+            #[cfg(feature = "poison")]
+            panicking: self.poison.guard(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// guard returned by [`MCSLock::lock_arc`]
+#[cfg(feature = "alloc")]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct ArcMCSGuard<T> {
+    mcs_lock: Arc<MCSLock<T>>,
+    /// allocated by `Box`, which is not kept
+    /// because moving it would invalidate the pointer held by the other threads
+    node: *mut RawMCSNode,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+    _phantom: PhantomData<*mut ()>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> ArcMCSGuard<T> {
+    /// unlock MCS lock
+    pub fn unlock(self) {}
+
+    /// the lock held by this guard
+    pub fn lock(s: &Self) -> &Arc<MCSLock<T>> {
+        &s.mcs_lock
+    }
+
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(*mut T) -> R,
+    {
+        self.mcs_lock.data.with_mut(f)
+    }
+}
+
+/// release the lock and poison it if the thread is panicking,
+/// and then the `Arc` and the node are dropped
+#[cfg(feature = "alloc")]
+impl<T> Drop for ArcMCSGuard<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "poison")]
+        self.mcs_lock.poison.done(self.panicking);
+
+        unsafe {
+            self.mcs_lock.raw.release(self.node);
+            drop(Box::from_raw(self.node));
+        }
+    }
+}
+
+#[cfg(all(feature = "alloc", not(loom)))]
+impl<T> Deref for ArcMCSGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mcs_lock.data.get() }
+    }
+}
+
+#[cfg(all(feature = "alloc", not(loom)))]
+impl<T> DerefMut for ArcMCSGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mcs_lock.data.get() }
+    }
+}
+
+/// [`MCSLock`] which can be acquired without a node
+///
+/// A node is allocated on the heap for each acquisition and owned by the guard,
+/// so this implements [`crate::traits::Lock`].
+/// Use [`MCSLock`] to avoid the allocation.
+///
+/// ```rust
+/// use synctools::mcs::MCSMutex;
+///
+/// let lock = MCSMutex::new(0);
+/// *lock.lock() += 1;
+/// assert_eq!(*lock.lock(), 1);
+/// ```
+#[cfg(feature = "alloc")]
+pub struct MCSMutex<T> {
+    mcs_lock: MCSLock<T>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> MCSMutex<T> {
+    pub fn new(v: T) -> MCSMutex<T> {
+        MCSMutex {
+            mcs_lock: MCSLock::new(v),
+        }
+    }
+
+    /// acquire lock
+    pub fn lock(&self) -> MCSMutexGuard<'_, T> {
+        let node = Box::into_raw(Box::new(RawMCSNode::new()));
+
+        // the lock is released and the node is freed by MCSMutexGuard::drop
+        unsafe { self.mcs_lock.raw.acquire(node) };
+
+        MCSMutexGuard {
+            mcs_lock: &self.mcs_lock,
+            node,
+            #[cfg(feature = "poison")]
+            panicking: self.mcs_lock.poison.guard(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// the underlying lock, which can be acquired by a node as usual
+    pub fn inner(&self) -> &MCSLock<T> {
+        &self.mcs_lock
+    }
+
+    pub fn into_inner(self) -> MCSLock<T> {
+        self.mcs_lock
+    }
+}
+
+/// guard of [`MCSMutex`]
+#[cfg(feature = "alloc")]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct MCSMutexGuard<'a, T> {
+    mcs_lock: &'a MCSLock<T>,
+    /// allocated by `Box`, which is not kept
+    /// because moving it would invalidate the pointer held by the other threads
+    node: *mut RawMCSNode,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+    _phantom: PhantomData<*mut ()>,
+}
+
+#[cfg(feature = "alloc")]
+unsafe impl<'a, T: Sync> Sync for MCSMutexGuard<'a, T> {}
+
+#[cfg(feature = "alloc")]
+impl<'a, T> MCSMutexGuard<'a, T> {
+    /// unlock MCS lock
+    pub fn unlock(self) {}
+
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(*mut T) -> R,
+    {
+        self.mcs_lock.data.with_mut(f)
+    }
+}
+
+/// release the lock and poison it if the thread is panicking
+#[cfg(feature = "alloc")]
+impl<'a, T> Drop for MCSMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "poison")]
+        self.mcs_lock.poison.done(self.panicking);
+
+        unsafe {
+            self.mcs_lock.raw.release(self.node);
+            drop(Box::from_raw(self.node));
+        }
+    }
+}
+
+#[cfg(all(feature = "alloc", not(loom)))]
+impl<'a, T> Deref for MCSMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mcs_lock.data.get() }
+    }
+}
+
+#[cfg(all(feature = "alloc", not(loom)))]
+impl<'a, T> DerefMut for MCSMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mcs_lock.data.get() }
+    }
+}
+
+/// [`MCSLock`] owning a pool of `N` nodes, which can be acquired without a node
+///
+/// `lock` takes a free node from the pool, so the caller does not place nodes on its stack,
+/// e.g. where an MPU forbids sharing stacks between threads.
+/// Unlike [`MCSMutex`], this allocates nothing and can be placed in a `static`.
+///
+/// # The number of nodes
+///
+/// `N` must be at least the maximum number of threads which hold or wait for the lock at the same time,
+/// e.g. the number of threads on a system with a fixed number of threads.
+/// A thread calling `lock` while all `N` nodes are in use spins until a node is returned to the pool,
+/// which is safe but not fair, because the thread is not queued until then.
+/// Each node takes a few words, so `N` should be as small as the system allows.
+///
+/// ```rust
+/// use synctools::mcs::StaticMCSLock;
+///
+/// // for 4 threads
+/// static COUNTER: StaticMCSLock<u64, 4> = StaticMCSLock::new(0);
+///
+/// *COUNTER.lock() += 1;
+/// assert_eq!(*COUNTER.lock(), 1);
+/// ```
+#[cfg(not(loom))]
+pub struct StaticMCSLock<T, const N: usize> {
+    mcs_lock: MCSLock<T>,
+    nodes: [UnsafeCell<RawMCSNode>; N],
+    /// true if the node of the same index is taken by a guard
+    used: [AtomicBool; N],
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> StaticMCSLock<T, N> {
+    /// create a lock with `N` nodes
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is 0, which fails compilation if this is evaluated in a constant context.
+    pub const fn new(v: T) -> StaticMCSLock<T, N> {
+        assert!(N > 0, "StaticMCSLock: N must not be 0");
+        StaticMCSLock {
+            mcs_lock: MCSLock::new(v),
+            nodes: [const { UnsafeCell::new(RawMCSNode::new()) }; N],
+            used: [const { AtomicBool::new(false) }; N],
+        }
+    }
+
+    /// acquire lock by a node taken from the pool
+    ///
+    /// This spins until a node is free if all `N` nodes are in use.
+    pub fn lock(&self) -> StaticMCSGuard<'_, T, N> {
+        let index = self.take_node();
+
+        // the lock is released and the node is returned by StaticMCSGuard::drop
+        unsafe { self.mcs_lock.raw.acquire(self.nodes[index].get()) };
+
+        StaticMCSGuard {
+            lock: self,
+            index,
+            #[cfg(feature = "poison")]
+            panicking: self.mcs_lock.poison.guard(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// the underlying lock, which can be acquired by a node as usual
+    pub fn inner(&self) -> &MCSLock<T> {
+        &self.mcs_lock
+    }
+
+    pub fn into_inner(self) -> MCSLock<T> {
+        self.mcs_lock
+    }
+
+    /// take a free node, and return its index
+    fn take_node(&self) -> usize {
+        let mut backoff = crate::backoff::Backoff::new();
+        loop {
+            for (i, used) in self.used.iter().enumerate() {
+                // `Acquire` so that the last use of the node happens before
+                if !used.load(Ordering::Relaxed)
+                    && used
+                        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    return i;
+                }
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+#[cfg(not(loom))]
+unsafe impl<T: Send, const N: usize> Sync for StaticMCSLock<T, N> {}
+#[cfg(not(loom))]
+unsafe impl<T: Send, const N: usize> Send for StaticMCSLock<T, N> {}
+#[cfg(not(loom))]
+unsafe impl<'a, T: Sync, const N: usize> Sync for StaticMCSGuard<'a, T, N> {}
+
+/// guard of [`StaticMCSLock`]
+#[cfg(not(loom))]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct StaticMCSGuard<'a, T, const N: usize> {
+    lock: &'a StaticMCSLock<T, N>,
+    /// the index of the node taken from the pool
+    index: usize,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+    _phantom: PhantomData<*mut ()>,
+}
+
+#[cfg(not(loom))]
+impl<'a, T, const N: usize> StaticMCSGuard<'a, T, N> {
+    /// unlock MCS lock
+    pub fn unlock(self) {}
+}
+
+/// release the lock and poison it if the thread is panicking,
+/// and then return the node to the pool
+#[cfg(not(loom))]
+impl<'a, T, const N: usize> Drop for StaticMCSGuard<'a, T, N> {
+    fn drop(&mut self) {
+        let mcs_lock = &self.lock.mcs_lock;
+
+        #[cfg(feature = "poison")]
+        mcs_lock.poison.done(self.panicking);
+
+        // no other thread accesses the node after `release` returns
+        unsafe { mcs_lock.raw.release(self.lock.nodes[self.index].get()) };
+        self.lock.used[self.index].store(false, Ordering::Release);
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T, const N: usize> Deref for StaticMCSGuard<'a, T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.mcs_lock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T, const N: usize> DerefMut for StaticMCSGuard<'a, T, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.mcs_lock.data.get() }
+    }
+}
+
+/// poison the lock if the thread is panicking,
+/// and the lock is released by `RawMCSGuard::drop`
+#[cfg(feature = "poison")]
+impl<'a, T> Drop for MCSLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mcs_lock.poison.done(self.panicking);
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T> Deref for MCSLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mcs_lock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T> DerefMut for MCSLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mcs_lock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: fmt::Debug> fmt::Debug for MCSLockGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: fmt::Display> fmt::Display for MCSLockGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// acquire two locks in a canonical order
+///
+/// The locks are acquired in the order of their addresses,
+/// so two threads calling `lock_both(a, b, ..)` and `lock_both(b, a, ..)`
+/// concurrently never deadlock.
+/// The guards are returned in the order of the arguments.
+///
+/// # Panics
+///
+/// Panics if `a` and `b` are the same lock.
+/// Use [`lock_both_or_one`] if they can alias.
+pub fn lock_both<'a, T, U>(
+    a: &'a MCSLock<T>,
+    b: &'a MCSLock<U>,
+    na: &'a mut MCSNode<T>,
+    nb: &'a mut MCSNode<U>,
+) -> (MCSLockGuard<'a, T>, MCSLockGuard<'a, U>) {
+    let addr_a = a as *const MCSLock<T> as usize;
+    let addr_b = b as *const MCSLock<U> as usize;
+    assert_ne!(addr_a, addr_b, "lock_both: a and b are the same lock");
+
+    if addr_a < addr_b {
+        let ga = a.lock(na);
+        let gb = b.lock(nb);
+        (ga, gb)
+    } else {
+        let gb = b.lock(nb);
+        let ga = a.lock(na);
+        (ga, gb)
+    }
+}
+
+/// guards returned by [`lock_both_or_one`]
+#[must_use = "the lock is released when the guard is dropped"]
+pub enum MCSLockBoth<'a, T> {
+    /// `a` and `b` were the same lock, so it was acquired only once
+    One(MCSLockGuard<'a, T>),
+
+    /// `a` and `b` were different locks, guards are in the order of the arguments
+    Two(MCSLockGuard<'a, T>, MCSLockGuard<'a, T>),
+}
+
+/// acquire two locks of the same type in a canonical order
+///
+/// This is the same as [`lock_both`] except that `a` and `b` may be the same lock.
+/// In that case, the lock is acquired only once by `na`
+/// and [`MCSLockBoth::One`] is returned.
+pub fn lock_both_or_one<'a, T>(
+    a: &'a MCSLock<T>,
+    b: &'a MCSLock<T>,
+    na: &'a mut MCSNode<T>,
+    nb: &'a mut MCSNode<T>,
+) -> MCSLockBoth<'a, T> {
+    if core::ptr::eq(a, b) {
+        MCSLockBoth::One(a.lock(na))
+    } else {
+        let (ga, gb) = lock_both(a, b, na, nb);
+        MCSLockBoth::Two(ga, gb)
+    }
+}
+
+/// acquire all locks of a slice in index order
+///
+/// `locks[i]` is acquired by `nodes[i]`.
+/// Since every caller acquires the locks in index order,
+/// calling `lock_all` concurrently on overlapping ranges of the same slice never deadlocks.
+/// The locks are released in reverse order when the returned [`LockedSlice`] is dropped.
+///
+/// # Panics
+///
+/// Panics if `nodes.len() < locks.len()`.
+pub fn lock_all<'a, T>(locks: &'a [MCSLock<T>], nodes: &'a mut [MCSNode<T>]) -> LockedSlice<'a, T> {
+    assert!(
+        nodes.len() >= locks.len(),
+        "lock_all: nodes.len() must be greater than or equal to locks.len()"
+    );
+
+    // the nodes are accessed only through this pointer until the locks are released
+    let nodes = nodes.as_mut_ptr();
+    for (i, lock) in locks.iter().enumerate() {
+        // the lock is released by LockedSlice::drop
+        unsafe { lock.raw.acquire(nodes.add(i) as *mut RawMCSNode) };
+    }
+
+    LockedSlice {
+        locks,
+        nodes,
+        #[cfg(feature = "poison")]
+        panicking: std::thread::panicking(),
+        _phantom: PhantomData,
+    }
+}
+
+/// guard of the locks acquired by [`lock_all`]
+#[must_use = "the locks are released when the guard is dropped"]
+pub struct LockedSlice<'a, T> {
+    locks: &'a [MCSLock<T>],
+    /// the first of `locks.len()` nodes
+    nodes: *mut MCSNode<T>,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+    _phantom: PhantomData<(&'a mut [MCSNode<T>], *mut ())>,
+}
+
+impl<'a, T> LockedSlice<'a, T> {
+    /// the number of acquired locks
+    pub fn len(&self) -> usize {
+        self.locks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locks.is_empty()
+    }
+
+    /// unlock all locks
+    pub fn unlock(self) {}
+
+    #[cfg(not(loom))]
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.locks.iter().map(|lock| unsafe { &*lock.data.get() })
+    }
+
+    #[cfg(not(loom))]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.locks
+            .iter()
+            .map(|lock| unsafe { &mut *lock.data.get() })
+    }
+}
+
+impl<'a, T> Drop for LockedSlice<'a, T> {
+    fn drop(&mut self) {
+        for (i, lock) in self.locks.iter().enumerate().rev() {
+            #[cfg(feature = "poison")]
+            lock.poison.done(self.panicking);
+
+            unsafe { lock.raw.release(self.nodes.add(i) as *mut RawMCSNode) };
+        }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T> Index<usize> for LockedSlice<'a, T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        unsafe { &*self.locks[index].data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T> IndexMut<usize> for LockedSlice<'a, T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        unsafe { &mut *self.locks[index].data.get() }
+    }
+}
+
+/// MCS lock whose waiters can give up, e.g. by a timeout
+///
+/// A waiter of [`MCSLock`] cannot leave the queue,
+/// because its predecessor cannot be found from a singly-linked node.
+/// [`MCSNodeCancelable`] is doubly-linked,
+/// so a waiter which gives up unlinks itself from its neighbors in O(1),
+/// and its node can be reused or dropped immediately after that.
+/// The threads behind it keep their order in the queue.
+///
+/// The cost is that handing over the lock and leaving the queue lock the links of the neighbors,
+/// so releasing the lock is slower than [`MCSLock`] even without contention.
+///
+/// ```rust
+/// use synctools::mcs::{MCSLockCancelable, MCSNodeCancelable};
+///
+/// let lock = MCSLockCancelable::new(0);
+/// let mut node0 = MCSNodeCancelable::new();
+/// let mut node1 = MCSNodeCancelable::new();
+///
+/// let mut guard = lock.lock(&mut node0);
+/// assert!(lock.try_lock_for(&mut node1, 10).is_none());
+/// *guard += 1;
+/// drop(guard);
+///
+/// assert_eq!(*lock.try_lock_for(&mut node1, 10).unwrap(), 1);
+/// ```
+pub struct MCSLockCancelable<T> {
+    last: AtomicPtr<MCSNodeCancelable<T>>,
+    data: UnsafeCell<T>,
+}
+
+/// node of [`MCSLockCancelable`]
+pub struct MCSNodeCancelable<T> {
+    prev: AtomicPtr<MCSNodeCancelable<T>>,
+    next: AtomicPtr<MCSNodeCancelable<T>>,
+    granted: AtomicBool,
+    /// spin lock of `prev` and `next`, and of `granted` while the node is waiting
+    links: AtomicBool,
+    _phantom: PhantomData<fn(T) -> T>,
+}
+
+impl<T> Default for MCSNodeCancelable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MCSNodeCancelable<T> {
+    #[cfg(not(loom))]
+    pub const fn new() -> MCSNodeCancelable<T> {
+        MCSNodeCancelable {
+            prev: AtomicPtr::new(null_mut()),
+            next: AtomicPtr::new(null_mut()),
+            granted: AtomicBool::new(false),
+            links: AtomicBool::new(false),
+            _phantom: PhantomData,
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> MCSNodeCancelable<T> {
+        MCSNodeCancelable {
+            prev: AtomicPtr::new(null_mut()),
+            next: AtomicPtr::new(null_mut()),
+            granted: AtomicBool::new(false),
+            links: AtomicBool::new(false),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// lock the links of the node
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must not be freed while this waits.
+    unsafe fn lock_links(ptr: *mut Self) {
+        let mut backoff = Backoff::new();
+        while !Self::try_lock_links(ptr) {
+            backoff.snooze();
+        }
+    }
+
+    /// # Safety
+    ///
+    /// See `lock_links`.
+    unsafe fn try_lock_links(ptr: *mut Self) -> bool {
+        (*ptr)
+            .links
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// # Safety
+    ///
+    /// The links must be locked by the current thread.
+    unsafe fn unlock_links(ptr: *mut Self) {
+        (*ptr).links.store(false, Ordering::Release);
+    }
+
+    /// wait until `next` is set by the successor which has swapped `last`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must not be freed while this waits.
+    unsafe fn wait_next(ptr: *mut Self) -> *mut Self {
+        let mut backoff = Backoff::new();
+        loop {
+            let next = (*ptr).next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+// # Links
+//
+// A waiting node has `prev`, and a node other than the last has `next`.
+// `prev` and `next` of a node are protected by `links` of the node,
+// except for the following, which need no lock.
+//
+// - A thread writes `prev` of its own node before it writes `next` of its predecessor (C2),
+//   and nobody reads `prev` of the node until then.
+// - The successor writes `next` of the predecessor obtained by the swap on `last` (C2),
+//   when `next` is still null.
+//   A node whose `next` is null and which is not `last` is waited for by `wait_next`
+//   before it is unlinked, so the write is never lost.
+//
+// The lock is handed over (R) or a waiter leaves the queue (L)
+// while the links of the nodes whose links change are locked.
+//
+// - (R) The holder locks its own links, then the links of the successor,
+//   and sets `granted` of the successor.
+// - (L) The waiter locks its own links, then tries to lock the links of the predecessor,
+//   and locks the links of the successor if any.
+//   Then, it links the predecessor and the successor, or sets `last` to the predecessor.
+//   If `granted` is set before it locks its own links, it has the lock and does not leave.
+//
+// Every thread locks its own links first, and then locks its neighbors.
+// A thread waits only for the links of its successor,
+// and releases its own links if the links of its predecessor are locked,
+// so they never deadlock.
+//
+// A node is not freed while the links of its neighbors are locked,
+// because a thread unlinks or hands over its node only after it locks the links of its neighbors.
+// So, holding its own links, a thread can access its predecessor and successor.
+//
+// # Memory ordering
+//
+// In addition to `RawMCSLock`, the links are published by `links`,
+// which is locked by `Acquire` and unlocked by `Release`.
+// The critical section of the holder happens before the critical section of the next holder
+// by the `Release` store of `granted` (R) and the `Acquire` load by the waiter,
+// or by the `Release` CAS on `last` and the `Acquire` part of the swap (C1).
+// The `Release` CAS on `last` by (L) publishes null `next` of the predecessor
+// to the thread which swaps `last` next.
+//
+// These are checked by the loom models in `tests/test_mcslock.rs`,
+// in which waiters leave while the lock is handed over.
+impl<T> MCSLockCancelable<T> {
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> MCSLockCancelable<T> {
+        MCSLockCancelable {
+            last: AtomicPtr::new(null_mut()),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(v: T) -> MCSLockCancelable<T> {
+        MCSLockCancelable {
+            last: AtomicPtr::new(null_mut()),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    /// acquire lock
+    pub fn lock<'a>(&'a self, node: &'a mut MCSNodeCancelable<T>) -> MCSLockCancelableGuard<'a, T> {
+        let ptr = unsafe { self.enqueue(node) };
+        let mut backoff = Backoff::new();
+        while !unsafe { (*ptr).granted.load(Ordering::Acquire) } {
+            backoff.snooze();
+        }
+
+        self.guard(ptr)
+    }
+
+    /// acquire lock, or leave the queue if it is still held after `max_spins` waits
+    ///
+    /// Each wait is a backoff step as [`crate::backoff::Backoff::snooze`],
+    /// so `max_spins` does not map to wall-clock time, as [`crate::rwlock::RwLock::try_read_for`].
+    /// The lock may still be acquired while leaving the queue,
+    /// in which case this returns the guard.
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_lock_for<'a>(
+        &'a self,
+        node: &'a mut MCSNodeCancelable<T>,
+        max_spins: usize,
+    ) -> Option<MCSLockCancelableGuard<'a, T>> {
+        let mut spins = 0;
+        self.try_lock_until(node, move || {
+            spins += 1;
+            spins > max_spins
+        })
+    }
+
+    /// acquire lock, or leave the queue when `cancel` returns true
+    ///
+    /// `cancel` is called before each wait, e.g. to read a clock or check for a pending interrupt.
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_lock_until<'a, F>(
+        &'a self,
+        node: &'a mut MCSNodeCancelable<T>,
+        mut cancel: F,
+    ) -> Option<MCSLockCancelableGuard<'a, T>>
+    where
+        F: FnMut() -> bool,
+    {
+        let ptr = unsafe { self.enqueue(node) };
+        let mut backoff = Backoff::new();
+        while !unsafe { (*ptr).granted.load(Ordering::Acquire) } {
+            if cancel() {
+                if unsafe { self.leave(ptr) } {
+                    return None;
+                }
+                break;
+            }
+            backoff.snooze();
+        }
+
+        Some(self.guard(ptr))
+    }
+
+    #[cfg(not(loom))]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    #[cfg(not(loom))]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    fn guard(&self, ptr: *mut MCSNodeCancelable<T>) -> MCSLockCancelableGuard<'_, T> {
+        MCSLockCancelableGuard {
+            node: ptr,
+            lock: self,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// enqueue `node`, and set `granted` if the lock is acquired without waiting
+    ///
+    /// # Safety
+    ///
+    /// The returned pointer must be released by `release` or `leave` before `node` is used again.
+    unsafe fn enqueue(&self, node: &mut MCSNodeCancelable<T>) -> *mut MCSNodeCancelable<T> {
+        *node = MCSNodeCancelable::new();
+        let ptr = node as *mut MCSNodeCancelable<T>;
+
+        // (C1)
+        let prev = self.last.swap(ptr, Ordering::AcqRel);
+        if prev.is_null() {
+            (*ptr).granted.store(true, Ordering::Relaxed);
+        } else {
+            // (C2)
+            (*ptr).prev.store(prev, Ordering::Relaxed);
+            (*prev).next.store(ptr, Ordering::Release);
+        }
+
+        ptr
+    }
+
+    /// hand over the lock to the successor, or unlock it if there is no successor
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held by `ptr`.
+    unsafe fn release(&self, ptr: *mut MCSNodeCancelable<T>) {
+        // (R)
+        MCSNodeCancelable::lock_links(ptr);
+
+        let mut next = (*ptr).next.load(Ordering::Acquire);
+        if next.is_null() {
+            if self
+                .last
+                .compare_exchange(ptr, null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                MCSNodeCancelable::unlock_links(ptr);
+                return;
+            }
+
+            next = MCSNodeCancelable::wait_next(ptr);
+        }
+
+        MCSNodeCancelable::lock_links(next);
+        (*next).prev.store(null_mut(), Ordering::Relaxed);
+        (*next).granted.store(true, Ordering::Release);
+        MCSNodeCancelable::unlock_links(next);
+
+        MCSNodeCancelable::unlock_links(ptr);
+    }
+
+    /// unlink `ptr` from the queue, or return false if the lock has been handed over to it
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be waiting in the queue.
+    unsafe fn leave(&self, ptr: *mut MCSNodeCancelable<T>) -> bool {
+        // (L)
+        let mut backoff = Backoff::new();
+        let prev = loop {
+            MCSNodeCancelable::lock_links(ptr);
+            if (*ptr).granted.load(Ordering::Acquire) {
+                MCSNodeCancelable::unlock_links(ptr);
+                return false;
+            }
+
+            let prev = (*ptr).prev.load(Ordering::Relaxed);
+            if MCSNodeCancelable::try_lock_links(prev) {
+                break prev;
+            }
+
+            // the predecessor is handing over the lock or leaving,
+            // and may wait for the links of this node
+            MCSNodeCancelable::unlock_links(ptr);
+            backoff.snooze();
+        };
+
+        let mut next = (*ptr).next.load(Ordering::Acquire);
+        if next.is_null() {
+            (*prev).next.store(null_mut(), Ordering::Relaxed);
+            if self
+                .last
+                .compare_exchange(ptr, prev, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                MCSNodeCancelable::unlock_links(prev);
+                MCSNodeCancelable::unlock_links(ptr);
+                return true;
+            }
+
+            // a successor has swapped `last`
+            (*prev).next.store(ptr, Ordering::Relaxed);
+            next = MCSNodeCancelable::wait_next(ptr);
+        }
+
+        MCSNodeCancelable::lock_links(next);
+        (*prev).next.store(next, Ordering::Relaxed);
+        (*next).prev.store(prev, Ordering::Relaxed);
+        MCSNodeCancelable::unlock_links(next);
+
+        MCSNodeCancelable::unlock_links(prev);
+        MCSNodeCancelable::unlock_links(ptr);
+        true
+    }
+}
+
+unsafe impl<T: Send> Sync for MCSLockCancelable<T> {}
+unsafe impl<T: Send> Send for MCSLockCancelable<T> {}
+
+/// guard of [`MCSLockCancelable`], which hands over the lock when dropped
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct MCSLockCancelableGuard<'a, T> {
+    node: *mut MCSNodeCancelable<T>,
+    lock: &'a MCSLockCancelable<T>,
+    _phantom: PhantomData<&'a mut MCSNodeCancelable<T>>,
+}
+
+unsafe impl<'a, T: Sync> Sync for MCSLockCancelableGuard<'a, T> {}
+
+impl<'a, T> MCSLockCancelableGuard<'a, T> {
+    /// unlock MCS lock
+    pub fn unlock(self) {}
+
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(*mut T) -> R,
+    {
+        self.lock.data.with_mut(f)
+    }
+}
+
+impl<'a, T> Drop for MCSLockCancelableGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe { self.lock.release(self.node) };
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T> Deref for MCSLockCancelableGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T> DerefMut for MCSLockCancelableGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
     }
 }
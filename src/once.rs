@@ -0,0 +1,140 @@
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// a cell which can be written only once
+///
+/// While a thread is initializing the cell,
+/// other threads calling `get_or_init` spin until the initialization completes.
+pub struct OnceCell<T> {
+    state: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> OnceCell<T> {
+        OnceCell {
+            state: AtomicU8::new(INCOMPLETE),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// get the reference to the value if it is initialized
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            Some(unsafe { self.get_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// set the value
+    ///
+    /// If the cell is already initialized or being initialized,
+    /// `v` is returned as `Err(v)`.
+    pub fn set(&self, v: T) -> Result<(), T> {
+        if self
+            .state
+            .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(v);
+        }
+
+        unsafe { (*self.data.get()).write(v) };
+        self.state.store(COMPLETE, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// get the reference to the value, initializing it by `f` if it is not initialized
+    ///
+    /// If `f` panics, the cell stays uninitialized
+    /// and another thread can initialize it.
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        let mut f = Some(f);
+        loop {
+            match self.state.compare_exchange_weak(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // reset the state if f panics
+                    let reset = ResetOnUnwind { state: &self.state };
+                    let v = (f.take().unwrap())();
+                    core::mem::forget(reset);
+
+                    unsafe { (*self.data.get()).write(v) };
+                    self.state.store(COMPLETE, Ordering::Release);
+                    break;
+                }
+                Err(COMPLETE) => break,
+                Err(_) => spin_loop(),
+            }
+        }
+
+        unsafe { self.get_unchecked() }
+    }
+
+    /// get the mutable reference to the value if it is initialized
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if *self.state.get_mut() == COMPLETE {
+            Some(unsafe { (*self.data.get()).assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// take the value out of the cell
+    pub fn into_inner(mut self) -> Option<T> {
+        if *self.state.get_mut() == COMPLETE {
+            *self.state.get_mut() = INCOMPLETE;
+            Some(unsafe { (*self.data.get()).assume_init_read() })
+        } else {
+            None
+        }
+    }
+
+    unsafe fn get_unchecked(&self) -> &T {
+        (*self.data.get()).assume_init_ref()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { (*self.data.get()).assume_init_drop() };
+        }
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+unsafe impl<T: Send> Send for OnceCell<T> {}
+
+struct ResetOnUnwind<'a> {
+    state: &'a AtomicU8,
+}
+
+impl<'a> Drop for ResetOnUnwind<'a> {
+    fn drop(&mut self) {
+        self.state.store(INCOMPLETE, Ordering::Release);
+    }
+}
@@ -0,0 +1,103 @@
+//! lock poisoning
+//!
+//! With the `poison` feature, a lock is poisoned when a thread panics while holding it,
+//! as `std::sync::Mutex`.
+//! The methods acquiring a lock, e.g. [`crate::mcs::MCSLock::lock`], do not change,
+//! because features must be additive.
+//! Use the `*_checked` methods, e.g. [`crate::mcs::MCSLock::lock_checked`],
+//! to detect poisoning.
+//!
+//! Poisoning requires `std::thread::panicking`, so this feature enables the `std` feature.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// result of the `*_checked` methods
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+/// error returned when a lock is poisoned
+///
+/// The lock is acquired even if it is poisoned,
+/// and the guard can be taken by [`PoisonError::into_inner`].
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+impl<G> PoisonError<G> {
+    pub fn new(guard: G) -> PoisonError<G> {
+        PoisonError { guard }
+    }
+
+    /// take the guard
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("poisoned lock: another thread panicked while holding it")
+    }
+}
+
+impl<G> std::error::Error for PoisonError<G> {}
+
+/// poison flag embedded in a lock
+pub(crate) struct Flag {
+    failed: AtomicBool,
+}
+
+impl Flag {
+    pub(crate) const fn new() -> Flag {
+        Flag {
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn get(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.failed.store(false, Ordering::Relaxed);
+    }
+
+    /// called when a guard is created,
+    /// and returns whether the thread is already panicking
+    pub(crate) fn guard(&self) -> bool {
+        std::thread::panicking()
+    }
+
+    /// called when a guard is dropped,
+    /// and poison the lock if the thread started panicking while holding it
+    pub(crate) fn done(&self, panicking: bool) {
+        if !panicking && std::thread::panicking() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// wrap `guard` by `Err` if poisoned
+    pub(crate) fn map_result<G>(&self, guard: G) -> LockResult<G> {
+        if self.get() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+}
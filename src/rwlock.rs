@@ -1,50 +1,537 @@
-use core::marker::PhantomData;
+use crate::backoff::{wait_until, wait_until_park, Backoff, Park, SpinPark};
+use core::{fmt, marker::PhantomData, mem::ManuallyDrop, ptr::null_mut};
+
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
 
 #[cfg(not(loom))]
 use core::{
     cell::UnsafeCell,
-    hint,
+    mem::MaybeUninit,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicUsize, Ordering},
+    ptr,
+    sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
 };
 
+#[cfg(all(target_has_atomic = "64", not(loom)))]
+use core::sync::atomic::AtomicU64;
+
 #[cfg(loom)]
 use loom::{
     cell::UnsafeCell,
-    hint,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
 };
 
-pub struct RwLock<T> {
-    state: AtomicUsize,
-    writer_wake_counter: AtomicUsize,
-    data: UnsafeCell<T>,
+#[cfg(all(feature = "async", not(loom)))]
+use crate::wait_queue::{SpinLocked, WaitList, Waiter, WaiterState};
+
+#[cfg(all(feature = "async", not(loom)))]
+use core::{
+    future::Future,
+    marker::PhantomPinned,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// readers writer lock
+///
+/// `T` can be a dynamically sized type such as a slice or a trait object.
+/// Such a lock is made from a sized one by unsized coercion.
+///
+/// ```rust
+/// use synctools::rwlock::RwLock;
+///
+/// static PAGES: RwLock<[u64; 4]> = RwLock::new([0; 4]);
+///
+/// let pages: &RwLock<[u64]> = &PAGES;
+/// pages.write()[1] = 1;
+/// assert_eq!(pages.read().iter().sum::<u64>(), 1);
+/// ```
+///
+/// # Policy
+///
+/// `P` decides whether new readers wait for a waiting writer.
+/// See [`WriterPreferred`] and [`ReaderPreferred`].
+/// It is chosen at the type level, so it costs nothing at runtime.
+///
+/// # Layout
+///
+/// The reader state, the writer wake-up counter, and the data are placed
+/// on their own cache lines, so that spinning threads do not bounce
+/// the line holding the data, and the data does not share a line with
+/// unrelated variables.
+/// Consequently, a lock takes 4 cache lines plus the data rounded up to a cache line,
+/// e.g. 640 bytes for `RwLock<u64>` on x86_64 and AArch64 and 320 bytes on others.
+/// The `stats` feature adds a cache line for the counters of [`RwLock::stats`].
+/// The `cache_line_64` feature assumes 64 bytes cache lines on every architecture,
+/// and the `compact` feature removes the padding to restore the old layout
+/// for memory-constrained users.
+///
+/// # Forgotten guards
+///
+/// A guard which is never dropped, e.g. by `core::mem::forget` or [`RwLockWriteGuard::leak`],
+/// keeps its lock held, which is safe because the lock is released only by dropping the guard
+/// and nothing else depends on the drop.
+///
+/// - A forgotten writer guard blocks every reader and writer forever.
+/// - A forgotten reader guard blocks every writer forever.
+///   With [`WriterPreferred`], readers also block once a writer starts waiting,
+///   except for [`RwLock::read_recursive`].
+///
+/// Such a lock can be released by [`RwLock::force_unlock_read`] or [`RwLock::force_unlock_write`].
+pub struct RwLock<T: ?Sized, P = WriterPreferred, H: RwHook = NoopHook> {
+    raw: RawRwLock,
+    /// the number of the guards made by [`RwLockWriteGuard::map_split`] which are alive,
+    /// or 0 if the writer lock is not split
+    #[cfg(not(loom))]
+    write_parts: AtomicUsize,
+    #[cfg(feature = "poison")]
+    poison: crate::poison::Flag,
+    /// the owners of the reader guards, see [`crate::detect`]
+    #[cfg(feature = "debug-detect")]
+    readers: crate::detect::Readers,
+    _policy: PhantomData<fn() -> (P, H)>,
+    data: Line<UnsafeCell<T>>,
 }
 
-impl<T> RwLock<T> {
+/// a value placed on its own cache line
+#[cfg(not(feature = "compact"))]
+type Line<T> = crate::util::CachePadded<T>;
+
+/// a value not padded, as the `compact` feature is enabled
+#[cfg(feature = "compact")]
+#[repr(transparent)]
+struct Line<T: ?Sized>(T);
+
+#[cfg(feature = "compact")]
+impl<T> Line<T> {
+    const fn new(value: T) -> Line<T> {
+        Line(value)
+    }
+}
+
+#[cfg(feature = "compact")]
+impl<T: ?Sized> core::ops::Deref for Line<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// policy of [`RwLock`] deciding whether new readers wait for a waiting writer
+pub trait Policy {
+    /// true if new readers acquire the lock even if a writer is waiting
+    const READER_PREFERRED: bool;
+}
+
+/// new readers wait while a writer is waiting, which is the default policy
+///
+/// A writer sets the writer waiting bit and waits for the readers holding the lock,
+/// and new readers wait until the writer releases the lock.
+/// Therefore, writers are never starved by readers.
+/// In exchange, a writer arriving frequently stalls every reader for a while,
+/// even if the lock is read-mostly.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WriterPreferred;
+
+impl Policy for WriterPreferred {
+    const READER_PREFERRED: bool = false;
+}
+
+/// new readers acquire the lock even if a writer is waiting
+///
+/// This is the same as acquiring every reader lock by [`RwLock::read_recursive`].
+/// Readers never wait while only readers hold the lock,
+/// so read-mostly workloads are not stalled by writers.
+/// In exchange, a writer waits until no reader holds the lock,
+/// so it is starved as long as readers keep overlapping.
+///
+/// ```rust
+/// use synctools::rwlock::{ReaderPreferred, RwLock};
+///
+/// let lock: RwLock<_, ReaderPreferred> = RwLock::with_policy(0);
+/// assert_eq!(*lock.read(), 0);
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReaderPreferred;
+
+impl Policy for ReaderPreferred {
+    const READER_PREFERRED: bool = true;
+}
+
+/// the kind of the lock passed to the hooks of [`RwHook`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockKind {
+    /// a reader lock
+    Read,
+    /// the writer lock
+    Write,
+}
+
+/// hooks called by [`RwLock`] when a thread starts waiting for, acquires, and releases the lock,
+/// e.g. to trace lock events or to drive a deterministic scheduler in tests
+///
+/// The hooks are functions without `self` as [`Park`], so the lock stores nothing for them,
+/// and the calls of [`NoopHook`], which is the default, are removed by the compiler.
+/// They are called by the thread acquiring or releasing the lock
+/// while it does not spin on the state, so they may block,
+/// but they must not acquire the same lock.
+///
+/// - `on_read_contended` and `on_write_contended` are called once per acquisition
+///   when the thread starts waiting, because the lock is not available.
+///   An acquisition with a bound, e.g. by [`RwLock::try_read_for`], may give up after this.
+/// - `on_acquired` is called after the lock is acquired,
+///   and `waited` is true if the contended hook was called for the acquisition.
+/// - `on_released` is called after the lock is released.
+///   The lock split by [`RwLockWriteGuard::map_split`] is released when its last part is dropped.
+///
+/// The optimistic reads, e.g. [`RwLock::load`], do not acquire the lock, so they call no hook.
+/// Upgrading a reader lock calls `on_released(LockKind::Read)` and then
+/// `on_acquired(LockKind::Write, false)`.
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use synctools::rwlock::{LockKind, RwHook, RwLock, WriterPreferred};
+///
+/// static WRITES: AtomicUsize = AtomicUsize::new(0);
+///
+/// struct CountWrites;
+///
+/// impl RwHook for CountWrites {
+///     fn on_acquired(kind: LockKind, _waited: bool) {
+///         if kind == LockKind::Write {
+///             WRITES.fetch_add(1, Ordering::Relaxed);
+///         }
+///     }
+/// }
+///
+/// let lock: RwLock<_, WriterPreferred, CountWrites> = RwLock::with_policy(0);
+/// *lock.write() += 1;
+/// assert_eq!(*lock.read(), 1);
+/// assert_eq!(WRITES.load(Ordering::Relaxed), 1);
+/// ```
+pub trait RwHook {
+    /// called when a reader starts waiting
+    fn on_read_contended() {}
+
+    /// called when a writer starts waiting
+    fn on_write_contended() {}
+
+    /// called when the lock of `kind` is acquired
+    fn on_acquired(_kind: LockKind, _waited: bool) {}
+
+    /// called when the lock of `kind` is released
+    fn on_released(_kind: LockKind) {}
+}
+
+/// the hooks doing nothing, which is the default of [`RwLock`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopHook;
+
+impl RwHook for NoopHook {}
+
+/// panic if a reader cannot be added to the state `s`, which is not `usize::MAX`
+#[inline(always)]
+pub(crate) fn check_readers(s: usize) {
+    if s >= MAX_READERS * 2 {
+        too_many_readers();
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn too_many_readers() -> ! {
+    panic!("RwLock: too many readers, the maximum is MAX_READERS");
+}
+
+/// the maximum number of readers holding a lock at the same time
+///
+/// Acquiring a reader lock panics if the number of readers is already this,
+/// e.g. because reader guards are leaked in a loop,
+/// instead of overflowing into the writer waiting bit.
+pub const MAX_READERS: usize = usize::MAX / 4;
+
+/// readers writer lock without data
+///
+/// This can be used to protect resources which are not Rust values,
+/// e.g. memory-mapped registers of a device.
+/// [`RwLock`] is implemented by this and `UnsafeCell`,
+/// and [`RawRwLock::lock_shared`] waits for a waiting writer as [`RwLock::read`] does.
+/// Unlike [`RwLock`], this has no guards,
+/// so the caller must release the lock it acquired by `unlock_shared` or `unlock_exclusive`.
+///
+/// ```rust
+/// use synctools::rwlock::RawRwLock;
+///
+/// static REGS_LOCK: RawRwLock = RawRwLock::new();
+///
+/// fn read_regs() {
+///     REGS_LOCK.lock_shared();
+///
+///     // read the registers, shared with other readers
+///
+///     // the reader lock is held by this thread
+///     unsafe { REGS_LOCK.unlock_shared() };
+/// }
+///
+/// fn write_regs() {
+///     REGS_LOCK.lock_exclusive();
+///
+///     // write the registers exclusively
+///
+///     // the writer lock is held by this thread
+///     unsafe { REGS_LOCK.unlock_exclusive() };
+/// }
+/// # read_regs();
+/// # write_regs();
+/// ```
+pub struct RawRwLock {
+    // readers spin on `state` while writers spin on `writer_wake_counter`,
+    // so they are placed on different cache lines
+    state: Line<AtomicUsize>,
+    writer_wake_counter: Line<AtomicUsize>,
+    waiting_readers: AtomicUsize,
+    /// the number of writers which waited for another writer and have not acquired the lock yet,
+    /// see `has_waiters`
+    waiting_writers: AtomicUsize,
+    handoff: AtomicBool,
+    /// odd while a writer holds the lock, see `begin_write`
     #[cfg(not(loom))]
-    pub const fn new(v: T) -> RwLock<T> {
-        RwLock {
-            state: AtomicUsize::new(0),
-            writer_wake_counter: AtomicUsize::new(0),
-            data: UnsafeCell::new(v),
+    version: AtomicUsize,
+    /// the number of releases of the writer lock, see `RwLock::version`
+    #[cfg(all(target_has_atomic = "64", not(loom)))]
+    generation: AtomicU64,
+    #[cfg(all(feature = "async", not(loom)))]
+    tasks: Tasks,
+    /// every thread updates the counters, so they do not share a line with the state
+    #[cfg(feature = "stats")]
+    stats: Line<Stats>,
+}
+
+/// the counters of [`RwLock::stats`]
+#[cfg(feature = "stats")]
+struct Stats {
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+    contended_reads: AtomicUsize,
+    contended_writes: AtomicUsize,
+    writer_wait_spins: AtomicUsize,
+}
+
+#[cfg(feature = "stats")]
+impl Stats {
+    #[cfg(not(loom))]
+    const fn new() -> Stats {
+        Stats {
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+            contended_reads: AtomicUsize::new(0),
+            contended_writes: AtomicUsize::new(0),
+            writer_wait_spins: AtomicUsize::new(0),
         }
     }
 
     #[cfg(loom)]
-    pub fn new(v: T) -> RwLock<T> {
-        RwLock {
-            state: AtomicUsize::new(0),
-            writer_wake_counter: AtomicUsize::new(0),
-            data: UnsafeCell::new(v),
+    fn new() -> Stats {
+        Stats {
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+            contended_reads: AtomicUsize::new(0),
+            contended_writes: AtomicUsize::new(0),
+            writer_wait_spins: AtomicUsize::new(0),
         }
     }
+}
 
-    /// acquire reader lock
-    pub fn read(&self) -> RwLockReadGuard<T> {
+/// statistics of the contention of a [`RwLock`] returned by [`RwLock::stats`]
+///
+/// The counters are updated by `Relaxed` operations without synchronizing with each other,
+/// so a snapshot may be slightly inconsistent, e.g. `contended_reads` may exceed `reads` for a moment.
+/// They wrap around on overflow.
+#[cfg(feature = "stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RwLockStats {
+    /// the number of reader locks acquired
+    pub reads: usize,
+    /// the number of writer locks acquired, including upgrades
+    pub writes: usize,
+    /// the number of reader locks which waited for a writer
+    pub contended_reads: usize,
+    /// the number of writer locks which waited for readers or another writer
+    pub contended_writes: usize,
+    /// the total number of times writers waited for a change of the lock before reloading it,
+    /// which is the number of loads while waiting, less one per wait
+    pub writer_wait_spins: usize,
+}
+
+/// the tasks waiting for [`RwLock::read_async`] and [`RwLock::write_async`]
+#[cfg(all(feature = "async", not(loom)))]
+struct Tasks {
+    /// true if a task is queued, which is read without the spin lock
+    queued: AtomicBool,
+    lists: SpinLocked<TaskLists>,
+}
+
+/// readers and writers are queued separately,
+/// so that waking a writer does not wake every reader
+#[cfg(all(feature = "async", not(loom)))]
+struct TaskLists {
+    readers: WaitList,
+    writers: WaitList,
+}
+
+impl Default for RawRwLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// # State
+//
+// `state` is `usize::MAX` if a writer holds the lock.
+// Otherwise, it is (the number of readers) * 2 + (1 if a writer is waiting).
+//
+// # Fair unlocking
+//
+// `waiting_readers` is the number of readers waiting for the lock.
+// If it is not 0, `unlock_exclusive_fair` hands over the lock to them
+// by storing 2 to `state`, that is, a reader held on behalf of the waiting readers,
+// and setting `handoff`.
+// While `handoff` is set, waiting readers acquire the lock even if a writer is waiting,
+// and the first of them clears `handoff` and releases the reader held on behalf of them.
+// Writers cannot acquire the lock until then, because `state` is not less than 2.
+//
+// # Backoff
+//
+// A failed CAS on `state` is retried after `Backoff::spin`,
+// and a thread waiting for another thread waits by `Backoff::snooze`,
+// so that contended threads do not saturate the interconnect by loads and CASes.
+// The backoff is reset after waiting, and the uncontended path never calls it.
+//
+// # Memory ordering
+//
+// The data is published only through `state`.
+//
+// - A reader acquires the lock by the `Acquire` CAS,
+//   which synchronizes with the `Release` store of the last writer
+//   (`unlock_exclusive`).
+//   CASes of other readers in between do not break this
+//   because RMW operations continue the release sequence.
+// - A writer acquires the lock by the `Acquire` CAS,
+//   which synchronizes with the `Release` store of the last writer
+//   and the `Release` `fetch_sub` of every reader (`unlock_shared`),
+//   which are in the same release sequence.
+//
+// The other loads of `state` are `Relaxed`,
+// because they are only hints to decide the next CAS and nothing is read after them.
+//
+// # Waking writers
+//
+// A writer waiting for another writer spins on `state`,
+// which is written only by the holder until it releases the lock,
+// so releasing the writer lock never touches `writer_wake_counter`.
+// A writer waiting for readers sets the writer waiting bit and spins on `writer_wake_counter`,
+// which is incremented only when `state` leaves (readers, waiting bit set),
+// that is, by the last reader (`unlock_shared`),
+// by the last reader upgrading to the writer lock (`RawSyncRwLock::try_upgrade_state`),
+// or by a writer task clearing the bit (`TaskLists::writer_gone`).
+// Therefore, a writer waits on the counter only if it observed such a state,
+// and readers spinning on `state` are not disturbed by the increments.
+//
+// `writer_wake_counter` does not publish the data, but it must not lose wake-ups.
+// A waiting writer loads the counter by `Acquire` before reloading `state`.
+// If it observes the increment by the last reader,
+// it also observes the `fetch_sub` of the reader preceding the increment,
+// so it does not wait for an increment which has already happened.
+//
+// These are checked by the loom models in `tests/test_rwlock.rs`,
+// which access the data through `loom::cell::UnsafeCell`.
+//
+// # Optimistic reads
+//
+// `version` is incremented when a writer acquires the lock and when it releases it,
+// so it is odd while a writer holds the lock, as a sequence lock.
+// Only the holder of the writer lock modifies it.
+//
+// - A writer increments it after acquiring the lock and issues a `Release` fence
+//   before writing the data, and increments it by a `Release` store before releasing the lock.
+// - An optimistic reader loads it by `Acquire`, reads the data,
+//   and loads it again after an `Acquire` fence.
+//   If the reader read a value written by a writer,
+//   the fences make the second load observe the first increment by the writer.
+//   Therefore, the read is not torn if both loads return the same even version.
+//
+// # Tasks
+//
+// With the `async` feature, tasks waiting by `read_async` and `write_async`
+// are parked in `tasks` instead of spinning, as `AsyncRwLock`.
+// A task sets `queued` after it is queued and retries the lock,
+// and a thread releasing the lock changes `state` and then loads `queued`.
+// Both are separated by `SeqCst` fences,
+// so either the retry observes the release, or the release observes the task.
+// Threads releasing the lock skip the spin lock while no task is queued.
+//
+// - A writer releasing the lock wakes all readers and one writer.
+// - The last reader wakes one writer if the writer waiting bit is set.
+// - A writer handing over the lock to readers or downgrading the lock wakes all readers.
+// - A writer which leaves the queue passes its wake-up to the next writer,
+//   or clears the writer waiting bit and wakes all readers if no writer is queued any longer.
+//   Threads waiting for the writer lock are woken to set the bit again.
+impl RawRwLock {
+    #[cfg(not(loom))]
+    pub const fn new() -> RawRwLock {
+        RawRwLock {
+            state: Line::new(AtomicUsize::new(0)),
+            writer_wake_counter: Line::new(AtomicUsize::new(0)),
+            waiting_readers: AtomicUsize::new(0),
+            waiting_writers: AtomicUsize::new(0),
+            handoff: AtomicBool::new(false),
+            version: AtomicUsize::new(0),
+            #[cfg(target_has_atomic = "64")]
+            generation: AtomicU64::new(0),
+            #[cfg(feature = "async")]
+            tasks: Tasks {
+                queued: AtomicBool::new(false),
+                lists: SpinLocked::new(TaskLists {
+                    readers: WaitList::new(),
+                    writers: WaitList::new(),
+                }),
+            },
+            #[cfg(feature = "stats")]
+            stats: Line::new(Stats::new()),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> RawRwLock {
+        RawRwLock {
+            state: Line::new(AtomicUsize::new(0)),
+            writer_wake_counter: Line::new(AtomicUsize::new(0)),
+            waiting_readers: AtomicUsize::new(0),
+            waiting_writers: AtomicUsize::new(0),
+            handoff: AtomicBool::new(false),
+            #[cfg(feature = "stats")]
+            stats: Line::new(Stats::new()),
+        }
+    }
+
+    /// acquire the reader lock
+    ///
+    /// This waits while a writer holds the lock or is waiting for it.
+    pub fn lock_shared(&self) {
+        self.lock_shared_park::<SpinPark, NoopHook>();
+    }
+
+    /// acquire the reader lock, and return true if this waited for a writer
+    fn lock_shared_park<K: Park, H: RwHook>(&self) -> bool {
         let mut s = self.state.load(Ordering::Relaxed);
+        let mut waiting = false;
+        let mut backoff = Backoff::new();
         loop {
-            if s & 1 == 0 {
+            // the writer waiting bit is ignored while the lock is handed over to readers
+            if s & 1 == 0 || (waiting && s != usize::MAX && self.handoff.load(Ordering::Relaxed)) {
+                check_readers(s);
                 match self.state.compare_exchange_weak(
                     s,
                     s + 2,
@@ -52,23 +539,77 @@ impl<T> RwLock<T> {
                     Ordering::Relaxed,
                 ) {
                     Ok(_) => {
-                        return RwLockReadGuard {
-                            rwlock: self,
-                            _phantom: Default::default(),
+                        self.count_read(waiting);
+                        if waiting {
+                            self.end_waiting();
                         }
+                        return waiting;
+                    }
+                    Err(e) => {
+                        // contended by other readers
+                        s = e;
+                        backoff.spin();
                     }
-                    Err(e) => s = e,
                 }
             }
 
             if s & 1 == 1 {
-                while self.state.load(Ordering::Relaxed) == s {
-                    hint::spin_loop();
+                if !waiting {
+                    H::on_read_contended();
+                    self.waiting_readers.fetch_add(1, Ordering::Relaxed);
+                    waiting = true;
+                }
+
+                // `handoff` is set before `state` is changed
+                let (v, _) = wait_until_park::<K>(&self.state, Ordering::Relaxed, |v| {
+                    v != s || self.handoff.load(Ordering::Relaxed)
+                });
+                s = v;
+                backoff.reset();
+            }
+
+            #[cfg(loom)]
+            loom::thread::yield_now();
+        }
+    }
 
-                    #[cfg(loom)]
-                    loom::thread::yield_now();
+    /// acquire the reader lock even if a writer is waiting
+    fn lock_shared_recursive_park<K: Park, H: RwHook>(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        let mut waiting = false;
+        let mut backoff = Backoff::new();
+        loop {
+            if s != usize::MAX {
+                check_readers(s);
+                match self.state.compare_exchange_weak(
+                    s,
+                    s + 2,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        self.count_read(waiting);
+                        if waiting {
+                            self.end_waiting();
+                        }
+                        return waiting;
+                    }
+                    Err(e) => {
+                        s = e;
+                        backoff.spin();
+                    }
                 }
-                s = self.state.load(Ordering::Relaxed);
+            } else {
+                if !waiting {
+                    H::on_read_contended();
+                    self.waiting_readers.fetch_add(1, Ordering::Relaxed);
+                    waiting = true;
+                }
+
+                let (v, _) =
+                    wait_until_park::<K>(&self.state, Ordering::Relaxed, |v| v != usize::MAX);
+                s = v;
+                backoff.reset();
             }
 
             #[cfg(loom)]
@@ -76,9 +617,254 @@ impl<T> RwLock<T> {
         }
     }
 
-    /// acquire writer lock
-    pub fn write(&self) -> RwLockWriteGuard<T> {
+    /// called by a reader which acquired the lock after waiting,
+    /// and release the reader held on behalf of the waiting readers if the lock is handed over
+    fn end_waiting(&self) {
+        if self.handoff.load(Ordering::Relaxed) && self.handoff.swap(false, Ordering::Relaxed) {
+            // the reader held on behalf of the waiting readers
+            unsafe { self.unlock_shared() };
+        }
+        self.waiting_readers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// acquire the reader lock without blocking, even if a writer is waiting
+    #[cfg(not(loom))]
+    fn try_lock_shared_recursive(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s != usize::MAX {
+            check_readers(s);
+            match self
+                .state
+                .compare_exchange_weak(s, s + 2, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    self.count_read(false);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    /// try to acquire the reader lock, and return true if it is acquired
+    ///
+    /// This fails only if a writer holds the lock,
+    /// so it can acquire the lock while a writer is waiting for it.
+    pub fn try_lock_shared(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s & 1 == 0 {
+            check_readers(s);
+            match self
+                .state
+                .compare_exchange_weak(s, s + 2, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    self.count_read(false);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+
+    /// acquire the reader lock by a single `compare_exchange_weak`, which may fail spuriously
+    ///
+    /// A waiting writer fails this unless `recursive`.
+    fn try_lock_shared_weak(&self, recursive: bool) -> bool {
+        let s = self.state.load(Ordering::Relaxed);
+        if s == usize::MAX || (!recursive && s & 1 == 1) {
+            return false;
+        }
+
+        check_readers(s);
+        let locked = self
+            .state
+            .compare_exchange_weak(s, s + 2, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked {
+            self.count_read(false);
+        }
+        locked
+    }
+
+    /// acquire the writer lock by a single `compare_exchange_weak`, which may fail spuriously
+    fn try_lock_exclusive_weak(&self) -> bool {
+        let s = self.state.load(Ordering::Relaxed);
+        if s > 1 {
+            return false;
+        }
+
+        let locked = self
+            .state
+            .compare_exchange_weak(s, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked {
+            self.begin_write();
+            self.count_write(false, 0);
+        }
+        locked
+    }
+
+    /// acquire the reader lock unless it is still blocked after `max_spins` waits
+    /// or `cancel` returns true
+    ///
+    /// A waiting writer blocks this unless `recursive`.
+    /// This returns whether this waited if the lock is acquired.
+    fn try_lock_shared_for<H: RwHook>(
+        &self,
+        recursive: bool,
+        max_spins: usize,
+        mut cancel: impl FnMut() -> bool,
+    ) -> Option<bool> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        let mut backoff = Backoff::new();
+        let mut spins = 0;
+        loop {
+            if s != usize::MAX && (recursive || s & 1 == 0) {
+                check_readers(s);
+                match self.state.compare_exchange_weak(
+                    s,
+                    s + 2,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        self.count_read(spins > 0);
+                        return Some(spins > 0);
+                    }
+                    Err(e) => {
+                        // contended by other readers
+                        s = e;
+                        backoff.spin();
+                        continue;
+                    }
+                }
+            }
+
+            if spins >= max_spins || cancel() {
+                return None;
+            }
+            if spins == 0 {
+                H::on_read_contended();
+            }
+            spins += 1;
+            backoff.snooze();
+            s = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    /// acquire the writer lock unless it is still blocked after `max_spins` waits
+    /// or `cancel` returns true
+    ///
+    /// This sets the writer waiting bit while readers hold the lock as `lock_exclusive`,
+    /// and clears it before giving up if this set it.
+    fn try_lock_exclusive_for<H: RwHook>(
+        &self,
+        max_spins: usize,
+        mut cancel: impl FnMut() -> bool,
+    ) -> Option<bool> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        let mut backoff = Backoff::new();
+        let mut spins = 0;
+        let mut intent = false;
+        loop {
+            if s <= 1 {
+                match self.state.compare_exchange_weak(
+                    s,
+                    usize::MAX,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        self.begin_write();
+                        self.count_write(spins > 0, spins);
+                        return Some(spins > 0);
+                    }
+                    Err(e) => {
+                        s = e;
+                        backoff.spin();
+                        continue;
+                    }
+                }
+            }
+
+            if s != usize::MAX && s & 1 == 0 {
+                match self.state.compare_exchange_weak(
+                    s,
+                    s + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => intent = true,
+                    Err(e) => {
+                        s = e;
+                        backoff.spin();
+                        continue;
+                    }
+                }
+            }
+
+            if spins >= max_spins || cancel() {
+                if intent {
+                    self.clear_writer_waiting();
+                }
+                return None;
+            }
+            if spins == 0 {
+                H::on_write_contended();
+            }
+            spins += 1;
+            backoff.snooze();
+            s = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    /// clear the writer waiting bit set by a writer which gave up,
+    /// so that readers are not blocked by a writer which no longer waits
+    ///
+    /// The bit may have been set by another writer, which sets it again
+    /// after it is woken up by `writer_wake_counter`, as `TaskLists::writer_gone`.
+    fn clear_writer_waiting(&self) {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s != usize::MAX && s & 1 == 1 {
+            match self
+                .state
+                .compare_exchange_weak(s, s - 1, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    self.writer_wake_counter.fetch_add(1, Ordering::Release);
+
+                    // parked tasks retry, and writer tasks set the bit again
+                    #[cfg(all(feature = "async", not(loom)))]
+                    self.wake_tasks(|lists| {
+                        lists.readers.notify_all();
+                        lists.writers.notify_one()
+                    });
+                    return;
+                }
+                Err(e) => s = e,
+            }
+        }
+    }
+
+    /// acquire the writer lock
+    pub fn lock_exclusive(&self) {
+        self.lock_exclusive_park::<SpinPark, NoopHook>();
+    }
+
+    /// acquire the writer lock, and return true if this waited for the lock
+    fn lock_exclusive_park<K: Park, H: RwHook>(&self) -> bool {
         let mut s = self.state.load(Ordering::Relaxed);
+        let mut backoff = Backoff::new();
+
+        // true if the lock is held by others, and the number of iterations waiting for it
+        let mut waited = false;
+        let mut spins = 0;
+
+        // true if counted by `waiting_writers`
+        let mut counted = false;
         loop {
             if s <= 1 {
                 match self.state.compare_exchange(
@@ -88,18 +874,41 @@ impl<T> RwLock<T> {
                     Ordering::Relaxed,
                 ) {
                     Ok(_) => {
-                        return RwLockWriteGuard {
-                            rwlock: self,
-                            _phantom: PhantomData,
+                        self.begin_write();
+                        self.count_write(waited, spins);
+                        if counted {
+                            self.waiting_writers.fetch_sub(1, Ordering::Relaxed);
                         }
+                        return waited;
                     }
                     Err(e) => {
                         s = e;
+                        backoff.spin();
                         continue;
                     }
                 }
             }
 
+            if !waited {
+                H::on_write_contended();
+                waited = true;
+            }
+
+            if s == usize::MAX {
+                // the writer releases the lock without waking writers,
+                // so wait for it on `state`, which nobody else writes until then
+                if !counted {
+                    self.waiting_writers.fetch_add(1, Ordering::Relaxed);
+                    counted = true;
+                }
+                let (v, waits) =
+                    wait_until_park::<K>(&self.state, Ordering::Relaxed, |v| v != usize::MAX);
+                s = v;
+                spins += waits;
+                backoff.reset();
+                continue;
+            }
+
             if s & 1 == 0 {
                 match self
                     .state
@@ -108,6 +917,7 @@ impl<T> RwLock<T> {
                     Ok(_) => (),
                     Err(e) => {
                         s = e;
+                        backoff.spin();
                         continue;
                     }
                 }
@@ -116,63 +926,3393 @@ impl<T> RwLock<T> {
             let w = self.writer_wake_counter.load(Ordering::Acquire);
             s = self.state.load(Ordering::Relaxed);
 
-            if s >= 2 {
-                while self.writer_wake_counter.load(Ordering::Acquire) == w {
-                    hint::spin_loop();
-
-                    #[cfg(loom)]
-                    loom::thread::yield_now();
-                }
+            // wait only while readers hold the lock and the writer waiting bit is set,
+            // because the counter is incremented only when they leave the state
+            if s != usize::MAX && s >= 3 && s & 1 == 1 {
+                let (_, waits) =
+                    wait_until_park::<K>(&self.writer_wake_counter, Ordering::Acquire, |v| v != w);
+                spins += waits;
                 s = self.state.load(Ordering::Relaxed);
+                backoff.reset();
             }
 
             #[cfg(loom)]
             loom::thread::yield_now();
         }
     }
-}
-
-pub struct RwLockReadGuard<'a, T> {
-    rwlock: &'a RwLock<T>,
-    _phantom: PhantomData<*mut ()>,
-}
 
-impl<'a, T> RwLockReadGuard<'a, T> {
-    /// unlock read lock
-    pub fn unlock(self) {}
+    /// try to acquire the writer lock, and return true if it is acquired
+    pub fn try_lock_exclusive(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s <= 1 {
+            match self
+                .state
+                .compare_exchange(s, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    self.begin_write();
+                    self.count_write(false, 0);
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
 
-    #[cfg(loom)]
-    pub fn with<F, R>(&self, f: F) -> R
-    where
-        F: FnOnce(*const T) -> R,
-    {
-        self.rwlock.data.with(f)
+    /// upgrade the reader lock to the writer lock
+    /// if the caller is the only reader and no writer is waiting
+    fn try_upgrade(&self) -> bool {
+        let upgraded = self
+            .state
+            .compare_exchange(2, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if upgraded {
+            self.begin_write();
+            self.count_write(false, 0);
+        }
+        upgraded
     }
-}
 
-pub struct RwLockWriteGuard<'a, T> {
-    rwlock: &'a RwLock<T>,
-    _phantom: PhantomData<*mut ()>,
-}
+    /// acquire the writer lock without blocking,
+    /// or set the writer waiting bit to block new readers
+    #[cfg(all(feature = "async", not(loom)))]
+    fn try_lock_exclusive_or_wait(&self) -> bool {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s <= 1 {
+                match self.state.compare_exchange(
+                    s,
+                    usize::MAX,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        self.begin_write();
+                        self.count_write(false, 0);
+                        return true;
+                    }
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
 
-impl<'a, T> RwLockWriteGuard<'a, T> {
-    /// unlock write lock
-    pub fn unlock(self) {}
+            if s & 1 == 1 {
+                return false;
+            }
 
-    #[cfg(loom)]
-    pub fn with_mut<F, R>(&mut self, f: F) -> R
-    where
-        F: FnOnce(*mut T) -> R,
-    {
-        self.rwlock.data.with_mut(f)
+            match self
+                .state
+                .compare_exchange(s, s + 1, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return false,
+                Err(e) => s = e,
+            }
+        }
     }
-}
 
-unsafe impl<T> Sync for RwLock<T> {}
-unsafe impl<T> Send for RwLock<T> {}
+    /// downgrade the writer lock to a reader lock
+    #[cfg(all(feature = "lock_api", not(loom)))]
+    fn downgrade(&self) {
+        self.end_write();
 
-#[cfg(not(loom))]
-impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+        // writers waiting for the writer lock observe this on `state`,
+        // and set the writer waiting bit to wait for the reader
+        self.state.store(2, Ordering::Release);
+
+        #[cfg(feature = "async")]
+        self.wake_tasks(|lists| {
+            lists.readers.notify_all();
+            None
+        });
+    }
+
+    /// release the reader lock
+    ///
+    /// # Safety
+    ///
+    /// The reader lock must be held by the caller,
+    /// e.g. acquired by `lock_shared` and not released yet.
+    pub unsafe fn unlock_shared(&self) {
+        if self.state.fetch_sub(2, Ordering::Release) == 3 {
+            self.writer_wake_counter.fetch_add(1, Ordering::Release);
+
+            #[cfg(all(feature = "async", not(loom)))]
+            self.wake_tasks(|lists| lists.writers.notify_one());
+        }
+    }
+
+    /// release the writer lock
+    ///
+    /// # Safety
+    ///
+    /// The writer lock must be held by the caller,
+    /// e.g. acquired by `lock_exclusive` and not released yet.
+    pub unsafe fn unlock_exclusive(&self) {
+        self.end_write();
+
+        // writers waiting for the writer lock observe this on `state`
+        self.state.store(0, Ordering::Release);
+
+        #[cfg(all(feature = "async", not(loom)))]
+        self.wake_tasks(|lists| {
+            lists.readers.notify_all();
+            lists.writers.notify_one()
+        });
+    }
+
+    /// count a reader lock acquired, which waited for a writer if `waited`
+    #[inline]
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    fn count_read(&self, waited: bool) {
+        #[cfg(feature = "stats")]
+        {
+            self.stats.reads.fetch_add(1, Ordering::Relaxed);
+            if waited {
+                self.stats.contended_reads.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// count a writer lock acquired, which waited for others if `waited`
+    /// and spun `spins` times
+    #[inline]
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    fn count_write(&self, waited: bool, spins: usize) {
+        #[cfg(feature = "stats")]
+        {
+            self.stats.writes.fetch_add(1, Ordering::Relaxed);
+            if waited {
+                self.stats.contended_writes.fetch_add(1, Ordering::Relaxed);
+            }
+            if spins > 0 {
+                self.stats
+                    .writer_wait_spins
+                    .fetch_add(spins, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// count a lock acquired by a task which waited for it
+    #[cfg(all(feature = "async", not(loom)))]
+    #[inline]
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    fn count_task_waited(&self, writer: bool) {
+        #[cfg(feature = "stats")]
+        if writer {
+            self.stats.contended_writes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.stats.contended_reads.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// make `version` odd, which is called after acquiring the writer lock
+    #[inline]
+    fn begin_write(&self) {
+        #[cfg(not(loom))]
+        {
+            let v = self.version.load(Ordering::Relaxed);
+            self.version.store(v.wrapping_add(1), Ordering::Relaxed);
+
+            // the increment is visible to optimistic readers before the data is written
+            core::sync::atomic::fence(Ordering::Release);
+        }
+    }
+
+    /// make `version` even, which is called before releasing the writer lock
+    #[inline]
+    fn end_write(&self) {
+        #[cfg(not(loom))]
+        {
+            let v = self.version.load(Ordering::Relaxed);
+            self.version.store(v.wrapping_add(1), Ordering::Release);
+
+            // only the holder of the writer lock modifies it, as `version`
+            #[cfg(target_has_atomic = "64")]
+            {
+                let g = self.generation.load(Ordering::Relaxed);
+                self.generation.store(g.wrapping_add(1), Ordering::Release);
+            }
+        }
+    }
+
+    /// true if a reader or a writer waits for the writer lock held by the caller
+    ///
+    /// This counts the readers and the writers blocked by `lock_shared` and `lock_exclusive`,
+    /// and the queued tasks, but not the threads trying to acquire the lock with a bound.
+    fn has_waiters(&self) -> bool {
+        let waiting = self.waiting_readers.load(Ordering::Relaxed) != 0
+            || self.waiting_writers.load(Ordering::Relaxed) != 0;
+
+        #[cfg(all(feature = "async", not(loom)))]
+        let waiting = waiting || self.tasks.queued.load(Ordering::Relaxed);
+
+        waiting
+    }
+
+    /// wait until a writer counted by `waiting_writers` acquires the lock,
+    /// which is called after releasing the writer lock
+    ///
+    /// A counted writer stays counted until it acquires the lock,
+    /// and releases it by writing `state` after it is uncounted,
+    /// so this returns when the lock is held by others or no writer is counted.
+    fn wait_for_writers(&self) {
+        wait_until(&self.state, Ordering::Relaxed, |v| {
+            v != 0 || self.waiting_writers.load(Ordering::Relaxed) == 0
+        });
+    }
+
+    /// hand over the lock to the waiting readers if any
+    fn unlock_exclusive_fair(&self) {
+        if self.waiting_readers.load(Ordering::Relaxed) == 0 {
+            // the writer lock is held by the caller
+            unsafe { self.unlock_exclusive() };
+            return;
+        }
+
+        self.end_write();
+
+        // `handoff` is published by the `Release` store to `state`
+        self.handoff.store(true, Ordering::Relaxed);
+        self.state.store(2, Ordering::Release);
+
+        #[cfg(all(feature = "async", not(loom)))]
+        self.wake_tasks(|lists| {
+            lists.readers.notify_all();
+            None
+        });
+    }
+
+    /// call `f` with the queues if a task is queued, and wake the returned waker
+    ///
+    /// This must be called after `state` is changed.
+    #[cfg(all(feature = "async", not(loom)))]
+    fn wake_tasks(&self, f: impl FnOnce(&mut TaskLists) -> Option<Waker>) {
+        fence(Ordering::SeqCst);
+        if !self.tasks.queued.load(Ordering::Relaxed) {
+            return;
+        }
+
+        if let Some(waker) = self.with_tasks(f) {
+            waker.wake();
+        }
+    }
+
+    /// modify the queues while holding the spin lock, and update `queued`
+    #[cfg(all(feature = "async", not(loom)))]
+    fn with_tasks<R>(&self, f: impl FnOnce(&mut TaskLists) -> R) -> R {
+        self.tasks.lists.with(|lists| {
+            let result = f(lists);
+            self.tasks.queued.store(
+                !lists.readers.is_empty() || !lists.writers.is_empty(),
+                Ordering::Relaxed,
+            );
+            result
+        })
+    }
+
+    /// queue the task of `waiter` to `list` unless it is already queued,
+    /// and retry the lock by `try_lock`
+    ///
+    /// The waiter leaves the queue if the retry succeeds.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must be valid and pinned,
+    /// and must be in the list selected by `list` if it is `Waiting`.
+    #[cfg(all(feature = "async", not(loom)))]
+    unsafe fn queue_and_retry(
+        &self,
+        waiter: *mut Waiter,
+        list: fn(&mut TaskLists) -> &mut WaitList,
+        waker: &Waker,
+        try_lock: impl FnOnce() -> bool,
+    ) -> bool {
+        self.tasks.lists.with(|lists| {
+            if (*waiter).state == WaiterState::Waiting {
+                (*waiter).register(waker);
+            } else {
+                list(lists).push_back(waiter, waker);
+            }
+
+            self.tasks.queued.store(true, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+
+            if !try_lock() {
+                return false;
+            }
+
+            list(lists).remove(waiter);
+            (*waiter).state = WaiterState::Done;
+            self.tasks.queued.store(
+                !lists.readers.is_empty() || !lists.writers.is_empty(),
+                Ordering::Relaxed,
+            );
+            true
+        })
+    }
+}
+
+#[cfg(all(feature = "async", not(loom)))]
+impl TaskLists {
+    fn readers(&mut self) -> &mut WaitList {
+        &mut self.readers
+    }
+
+    fn writers(&mut self) -> &mut WaitList {
+        &mut self.writers
+    }
+
+    /// A writer left the queue without acquiring the lock.
+    /// Pass the wake-up to the next writer if `notified`,
+    /// or unblock readers if there is no writer.
+    fn writer_gone(&mut self, raw: &RawRwLock, notified: bool) -> Option<Waker> {
+        if !self.writers.is_empty() {
+            return if notified {
+                self.writers.notify_one()
+            } else {
+                None
+            };
+        }
+
+        let mut s = raw.state.load(Ordering::Relaxed);
+        while s != usize::MAX && s & 1 == 1 {
+            match raw
+                .state
+                .compare_exchange_weak(s, s - 1, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    // threads waiting for the writer lock set the bit again
+                    raw.writer_wake_counter.fetch_add(1, Ordering::Release);
+                    break;
+                }
+                Err(e) => s = e,
+            }
+        }
+
+        self.readers.notify_all();
+        None
+    }
+}
+
+impl<T> RwLock<T> {
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> RwLock<T> {
+        RwLock::with_policy(v)
+    }
+
+    #[cfg(loom)]
+    pub fn new(v: T) -> RwLock<T> {
+        RwLock::with_policy(v)
+    }
+
+    /// create a lock of the value returned by `f`, or return the error of `f`
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::try_new(|| "10".parse::<u32>()).unwrap();
+    /// assert_eq!(*lock.read(), 10);
+    ///
+    /// assert!(RwLock::try_new(|| "x".parse::<u32>()).is_err());
+    /// ```
+    pub fn try_new<E, F>(f: F) -> Result<RwLock<T>, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        f().map(RwLock::new)
+    }
+}
+
+impl<T, P, H: RwHook> RwLock<T, P, H> {
+    /// create a lock of the policy `P`
+    #[cfg(not(loom))]
+    pub const fn with_policy(v: T) -> RwLock<T, P, H> {
+        RwLock {
+            raw: RawRwLock::new(),
+            write_parts: AtomicUsize::new(0),
+            #[cfg(feature = "poison")]
+            poison: crate::poison::Flag::new(),
+            #[cfg(feature = "debug-detect")]
+            readers: crate::detect::Readers::new(),
+            _policy: PhantomData,
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+
+    /// create a lock of the policy `P`
+    #[cfg(loom)]
+    pub fn with_policy(v: T) -> RwLock<T, P, H> {
+        RwLock {
+            raw: RawRwLock::new(),
+            #[cfg(feature = "poison")]
+            poison: crate::poison::Flag::new(),
+            #[cfg(feature = "debug-detect")]
+            readers: crate::detect::Readers::new(),
+            _policy: PhantomData,
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+}
+
+impl<T: ?Sized, P: Policy, H: RwHook> RwLock<T, P, H> {
+    /// acquire reader lock
+    ///
+    /// Whether this waits for a waiting writer depends on the policy `P`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`MAX_READERS`] readers already hold the lock.
+    pub fn read(&self) -> RwLockReadGuard<'_, T, P, H> {
+        self.read_with_park::<SpinPark>()
+    }
+
+    /// try to acquire reader lock by a single `compare_exchange_weak`
+    ///
+    /// This returns `None` if a writer holds the lock, or is waiting under [`WriterPreferred`].
+    /// It may also fail spuriously even if the lock is available,
+    /// because the weak CAS maps to a single LL/SC pair on AArch64 and RISC-V without a retry loop.
+    /// So this is for callers which already retry in a loop, e.g. with their own backoff.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(1);
+    /// let r = loop {
+    ///     if let Some(r) = lock.try_read_weak() {
+    ///         break r;
+    ///     }
+    /// };
+    /// assert_eq!(*r, 1);
+    /// assert!(lock.try_write_weak().is_none());
+    /// ```
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_read_weak(&self) -> Option<RwLockReadGuard<'_, T, P, H>> {
+        if self.raw.try_lock_shared_weak(P::READER_PREFERRED) {
+            Some(self.read_guard(false))
+        } else {
+            None
+        }
+    }
+
+    /// acquire reader lock, or give up after waiting `max_spins` times
+    ///
+    /// Each wait is a backoff step as [`crate::backoff::Backoff::snooze`],
+    /// so this never hangs, e.g. on a watchdog path, even if a writer never releases the lock.
+    /// Whether this waits for a waiting writer depends on the policy `P` as [`RwLock::read`].
+    ///
+    /// This needs no time source, but `max_spins` does not map to wall-clock time.
+    /// A step spins twice as long as the previous one up to a limit,
+    /// and the time of a spin depends on the CPU, its frequency, and preemption of the thread.
+    /// So choose `max_spins` by measurement on the target, and use a clock for real timeouts.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(0);
+    /// let w = lock.write();
+    /// assert!(lock.try_read_for(10).is_none());
+    /// drop(w);
+    /// assert_eq!(*lock.try_read_for(10).unwrap(), 0);
+    /// ```
+    #[doc(alias = "try_read_spins")]
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_read_for(&self, max_spins: usize) -> Option<RwLockReadGuard<'_, T, P, H>> {
+        self.try_read_for_or(max_spins, || false)
+    }
+
+    /// acquire reader lock, or give up when `cancel` returns true
+    ///
+    /// `cancel` is called before each wait, e.g. to check for a pending interrupt.
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_read_until<F>(&self, cancel: F) -> Option<RwLockReadGuard<'_, T, P, H>>
+    where
+        F: FnMut() -> bool,
+    {
+        self.try_read_for_or(usize::MAX, cancel)
+    }
+
+    fn try_read_for_or<F>(
+        &self,
+        max_spins: usize,
+        cancel: F,
+    ) -> Option<RwLockReadGuard<'_, T, P, H>>
+    where
+        F: FnMut() -> bool,
+    {
+        let waited = self
+            .raw
+            .try_lock_shared_for::<H>(P::READER_PREFERRED, max_spins, cancel)?;
+        Some(self.read_guard(waited))
+    }
+
+    /// acquire reader lock, and call the hooks of `K` during a long wait
+    ///
+    /// This is the same as [`RwLock::read`] except that,
+    /// after waiting `K::SPINS` times, it gives up the CPU by [`Park::park`]
+    /// instead of spinning.
+    /// See [`Park`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`MAX_READERS`] readers already hold the lock.
+    pub fn read_with_park<K: Park>(&self) -> RwLockReadGuard<'_, T, P, H> {
+        let waited = self.lock_read::<K>();
+        self.read_guard(waited)
+    }
+
+    /// acquire reader lock, and return `Err` if the lock is poisoned
+    ///
+    /// The lock is poisoned if a thread panicked while holding the writer lock.
+    /// Even then, the lock is acquired and the guard can be taken
+    /// by [`crate::poison::PoisonError::into_inner`].
+    #[cfg(feature = "poison")]
+    pub fn read_checked(&self) -> crate::poison::LockResult<RwLockReadGuard<'_, T, P, H>> {
+        let guard = self.read();
+        self.poison.map_result(guard)
+    }
+
+    /// acquire reader lock, call `f` with the data, and release the lock
+    ///
+    /// The reference cannot escape from `f`,
+    /// so the lock is never held longer than the closure.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new((1, 2));
+    /// assert_eq!(lock.with_read(|v| v.0 + v.1), 3);
+    /// ```
+    #[cfg(not(loom))]
+    pub fn with_read<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let guard = self.read();
+        f(&guard)
+    }
+
+    /// acquire reader lock, call `f` with the data, and release the lock
+    ///
+    /// This is the same as [`RwLock::with_read`], but is also available under loom,
+    /// where the access is tracked by loom,
+    /// so that code and tests can access the data in the same way in both configurations.
+    pub fn with<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            self.with_read(f)
+        }
+
+        #[cfg(loom)]
+        {
+            self.read().with(f)
+        }
+    }
+
+    /// acquire reader lock asynchronously
+    ///
+    /// The task is parked by `Poll::Pending` instead of spinning while the lock is not available,
+    /// and whether it waits for a waiting writer depends on the policy `P` as [`RwLock::read`].
+    /// The lock can be acquired by [`RwLock::read`] and this at the same time.
+    /// If the returned future is dropped before it is resolved, it leaves the queue.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// static CONFIG: RwLock<u64> = RwLock::new(0);
+    ///
+    /// async fn get() -> u64 {
+    ///     *CONFIG.read_async().await
+    /// }
+    /// ```
+    #[cfg(all(feature = "async", not(loom)))]
+    pub fn read_async(&self) -> RwLockReadFuture<'_, T, P, H> {
+        RwLockReadFuture {
+            rwlock: self,
+            waiter: UnsafeCell::new(Waiter::new()),
+            queued: false,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// acquire reader lock by the policy `P`, and return true if this waited
+    fn lock_read<K: Park>(&self) -> bool {
+        if P::READER_PREFERRED {
+            self.raw.lock_shared_recursive_park::<K, H>()
+        } else {
+            self.raw.lock_shared_park::<K, H>()
+        }
+    }
+
+    #[cfg(all(feature = "async", not(loom)))]
+    fn try_read_async(&self) -> bool {
+        if P::READER_PREFERRED {
+            self.raw.try_lock_shared_recursive()
+        } else {
+            self.raw.try_lock_shared()
+        }
+    }
+}
+
+impl<T, P: Policy, H: RwHook> RwLock<T, P, H> {
+    /// clone the data under the reader lock
+    ///
+    /// The lock is released before the clone is returned,
+    /// so no guard outlives the call.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(1);
+    /// lock.set(2);
+    /// assert_eq!(lock.replace(3), 2);
+    /// assert_eq!(lock.take(), 3);
+    /// assert_eq!(lock.get_cloned(), 0);
+    /// ```
+    pub fn get_cloned(&self) -> T
+    where
+        T: Clone,
+    {
+        self.with(|v| v.clone())
+    }
+
+    /// overwrite the data under the writer lock
+    ///
+    /// The old value is dropped after the lock is released,
+    /// so that its destructor does not lengthen the critical section.
+    pub fn set(&self, value: T) {
+        drop(self.replace(value));
+    }
+
+    /// replace the data by `value` under the writer lock, and return the old value
+    pub fn replace(&self, value: T) -> T {
+        self.with_mut(|v| core::mem::replace(v, value))
+    }
+
+    /// take the data under the writer lock, and leave `T::default()`
+    ///
+    /// The default value is created before acquiring the lock.
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+}
+
+#[cfg(not(loom))]
+impl<T: Copy, P, H: RwHook> RwLock<T, P, H> {
+    /// true if `T` is copied by a single load of at most a word,
+    /// so that [`RwLock::load`] reads it optimistically instead of acquiring the reader lock
+    ///
+    /// The copy of such a type is short, so a writer rarely intervenes,
+    /// while the copy of a larger type is retried more often and costs more for each retry.
+    const OPTIMISTIC_LOAD: bool =
+        size_of::<T>() <= size_of::<usize>() && align_of::<T>() == size_of::<T>();
+
+    /// copy the data out without a guard
+    ///
+    /// If `T` fits in a word, e.g. `u64` on 64-bit architectures, this reads the data
+    /// optimistically as [`RwLock::try_read_optimistic`], which never writes the lock,
+    /// so concurrent readers do not bounce its cache line, and retries a few times
+    /// if a writer intervenes.
+    /// Otherwise, or if every retry fails, this copies the data under the reader lock.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(1u32);
+    /// lock.store(2);
+    /// assert_eq!(lock.load(), 2);
+    /// ```
+    pub fn load(&self) -> T
+    where
+        P: Policy,
+    {
+        if Self::OPTIMISTIC_LOAD {
+            let mut backoff = Backoff::new();
+            for _ in 0..OPTIMISTIC_LOAD_RETRIES {
+                if let Some(v) = self.try_read_optimistic(|v| *v) {
+                    return v;
+                }
+                backoff.spin();
+            }
+        }
+
+        self.with_read(|v| *v)
+    }
+
+    /// copy `value` in under the writer lock
+    pub fn store(&self, value: T) {
+        self.with_write(|v| *v = value);
+    }
+
+    /// store the value returned by `f` under the writer lock if it is `Some`
+    ///
+    /// This has the same signature as `fetch_update` of atomics,
+    /// and returns `Ok` of the previous value if `f` returns `Some`, or `Err` of it otherwise.
+    /// Unlike atomics, `f` is called exactly once,
+    /// because no other thread can modify the data while the writer lock is held.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(7);
+    /// assert_eq!(lock.fetch_update(|v| (v < 10).then_some(v + 1)), Ok(7));
+    /// assert_eq!(lock.fetch_update(|v| (v > 10).then_some(v + 1)), Err(8));
+    /// assert_eq!(*lock.read(), 8);
+    /// ```
+    pub fn fetch_update<F>(&self, f: F) -> Result<T, T>
+    where
+        F: FnOnce(T) -> Option<T>,
+    {
+        self.with_write(|v| {
+            let prev = *v;
+            match f(prev) {
+                Some(next) => {
+                    *v = next;
+                    Ok(prev)
+                }
+                None => Err(prev),
+            }
+        })
+    }
+
+    /// call `f` with a copy of the data read optimistically without acquiring the lock
+    ///
+    /// This returns `None` without calling `f` if a writer held the lock during the read,
+    /// so the caller retries, or falls back to [`RwLock::read`].
+    /// See [`RwLock::read_optimistic`].
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new([1u32, 2, 3]);
+    ///
+    /// let sum = loop {
+    ///     if let Some(sum) = lock.try_read_optimistic(|v| v.iter().sum::<u32>()) {
+    ///         break sum;
+    ///     }
+    /// };
+    /// assert_eq!(sum, 6);
+    /// ```
+    pub fn try_read_optimistic<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let token = self.read_optimistic();
+        if token.version & 1 == 1 {
+            return None;
+        }
+
+        // the copy may be torn, so it is not assumed to be initialized until validated
+        let copy = unsafe { ptr::read_volatile(self.data.get() as *const MaybeUninit<T>) };
+        if !self.validate(token) {
+            return None;
+        }
+
+        Some(f(unsafe { copy.assume_init_ref() }))
+    }
+}
+
+/// the number of optimistic reads of [`RwLock::load`] before acquiring the reader lock
+#[cfg(not(loom))]
+const OPTIMISTIC_LOAD_RETRIES: usize = 4;
+
+/// token of an optimistic read returned by [`RwLock::read_optimistic`]
+#[cfg(not(loom))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptimisticReadToken {
+    version: usize,
+}
+
+impl<T: ?Sized, P, H: RwHook> RwLock<T, P, H> {
+    /// acquire reader lock, even if a writer is waiting
+    ///
+    /// [`RwLock::read`] of [`WriterPreferred`] waits while a writer is waiting,
+    /// so that writers are not starved.
+    /// Therefore, if a thread holding a reader lock calls `read` again,
+    /// it deadlocks when a writer starts waiting between the two calls,
+    /// because the writer waits for the first guard and the second call waits for the writer.
+    ///
+    /// This method waits only while a writer holds the lock,
+    /// so it can be called while the same thread holds a reader lock.
+    /// In exchange, a writer is starved as long as threads keep acquiring reader locks
+    /// by this method, so use `read` unless the thread may already hold a reader lock.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(0);
+    /// let r1 = lock.read();
+    /// // never deadlocks even if another thread calls `write` here
+    /// let r2 = lock.read_recursive();
+    /// assert_eq!(*r1, *r2);
+    /// ```
+    pub fn read_recursive(&self) -> RwLockReadGuard<'_, T, P, H> {
+        let waited = self.raw.lock_shared_recursive_park::<SpinPark, H>();
+        self.read_guard(waited)
+    }
+
+    /// acquire writer lock, or give up after waiting `max_spins` times
+    ///
+    /// While readers hold the lock, this sets the writer waiting bit to block new readers
+    /// as [`RwLock::write`], and clears it before giving up,
+    /// so that readers are not blocked by a writer which no longer waits.
+    /// As [`RwLock::try_read_for`], `max_spins` does not map to wall-clock time.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(0);
+    /// let r = lock.read();
+    /// assert!(lock.try_write_for(10).is_none());
+    ///
+    /// // the writer waiting bit is cleared
+    /// assert!(!lock.writer_pending());
+    /// drop(r);
+    /// *lock.try_write_for(10).unwrap() += 1;
+    /// ```
+    #[doc(alias = "try_write_spins")]
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_write_for(&self, max_spins: usize) -> Option<RwLockWriteGuard<'_, T, P, H>> {
+        self.try_write_for_or(max_spins, || false)
+    }
+
+    /// acquire writer lock, or give up when `cancel` returns true
+    ///
+    /// `cancel` is called before each wait, e.g. to check for a pending interrupt.
+    /// See [`RwLock::try_write_for`].
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_write_until<F>(&self, cancel: F) -> Option<RwLockWriteGuard<'_, T, P, H>>
+    where
+        F: FnMut() -> bool,
+    {
+        self.try_write_for_or(usize::MAX, cancel)
+    }
+
+    fn try_write_for_or<F>(
+        &self,
+        max_spins: usize,
+        cancel: F,
+    ) -> Option<RwLockWriteGuard<'_, T, P, H>>
+    where
+        F: FnMut() -> bool,
+    {
+        let waited = self.raw.try_lock_exclusive_for::<H>(max_spins, cancel)?;
+        Some(self.write_guard(waited))
+    }
+
+    /// try to acquire writer lock by a single `compare_exchange_weak`
+    ///
+    /// This returns `None` if a reader or a writer holds the lock,
+    /// and may also fail spuriously as [`RwLock::try_read_weak`].
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_write_weak(&self) -> Option<RwLockWriteGuard<'_, T, P, H>> {
+        if self.raw.try_lock_exclusive_weak() {
+            Some(self.write_guard(false))
+        } else {
+            None
+        }
+    }
+
+    /// acquire writer lock
+    pub fn write(&self) -> RwLockWriteGuard<'_, T, P, H> {
+        self.write_with_park::<SpinPark>()
+    }
+
+    /// acquire writer lock, and call the hooks of `K` during a long wait
+    ///
+    /// See [`RwLock::read_with_park`] and [`Park`].
+    pub fn write_with_park<K: Park>(&self) -> RwLockWriteGuard<'_, T, P, H> {
+        #[cfg(feature = "debug-detect")]
+        self.readers.check_write();
+
+        let waited = self.raw.lock_exclusive_park::<K, H>();
+        self.write_guard(waited)
+    }
+
+    /// acquire writer lock asynchronously
+    ///
+    /// A waiting task sets the writer waiting bit to block new readers as [`RwLock::write`].
+    /// If the returned future is dropped before it is resolved,
+    /// it leaves the queue and passes its wake-up to the next writer.
+    #[cfg(all(feature = "async", not(loom)))]
+    pub fn write_async(&self) -> RwLockWriteFuture<'_, T, P, H> {
+        RwLockWriteFuture {
+            rwlock: self,
+            waiter: UnsafeCell::new(Waiter::new()),
+            queued: false,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// acquire writer lock, call `f` with the data, and release the lock
+    ///
+    /// See [`RwLock::with_read`].
+    #[cfg(not(loom))]
+    pub fn with_write<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut guard = self.write();
+        f(&mut guard)
+    }
+
+    /// acquire writer lock, call `f` with the data, and release the lock
+    ///
+    /// This is the same as [`RwLock::with_write`], but is also available under loom.
+    /// See [`RwLock::with`].
+    pub fn with_mut<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            self.with_write(f)
+        }
+
+        #[cfg(loom)]
+        {
+            self.write().with_mut(f)
+        }
+    }
+
+    /// read, modify, and write the data by `f` under the writer lock, and return its result
+    ///
+    /// This is the same as [`RwLock::with_write`], but expresses the intent of
+    /// a read-modify-write operation as `fetch_*` methods of atomics.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(1);
+    /// let old = lock.update(|v| core::mem::replace(v, *v * 2));
+    /// assert_eq!((old, *lock.read()), (1, 2));
+    /// ```
+    #[cfg(not(loom))]
+    pub fn update<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.with_write(f)
+    }
+
+    /// acquire writer lock only if `pred` holds for the data
+    ///
+    /// `pred` is checked twice.
+    /// It is first checked under a reader lock, which is shared with other readers,
+    /// and this returns `None` without acquiring the writer lock if it does not hold.
+    /// Otherwise, the writer lock is acquired and `pred` is checked again,
+    /// because another writer may have changed the data between the two locks,
+    /// and the writer lock is released and `None` is returned if it no longer holds.
+    /// Therefore, the returned guard always holds the data satisfying `pred`.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(Vec::new());
+    ///
+    /// // initialize only once
+    /// if let Some(mut v) = lock.write_if(|v| v.is_empty()) {
+    ///     v.push(1);
+    /// }
+    /// assert!(lock.write_if(|v| v.is_empty()).is_none());
+    /// assert_eq!(*lock.read(), [1]);
+    /// ```
+    #[cfg(not(loom))]
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn write_if<F>(&self, pred: F) -> Option<RwLockWriteGuard<'_, T, P, H>>
+    where
+        P: Policy,
+        F: Fn(&T) -> bool,
+    {
+        if !pred(&self.read()) {
+            return None;
+        }
+
+        let guard = self.write();
+        if pred(&guard) {
+            Some(guard)
+        } else {
+            None
+        }
+    }
+
+    /// release the writer lock without a guard
+    ///
+    /// This pairs with [`RwLockWriteGuard::leak`], e.g. to release a lock
+    /// which was acquired before handing the data over an FFI boundary.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::{RwLock, RwLockWriteGuard};
+    ///
+    /// let lock = RwLock::new(0);
+    ///
+    /// let data = RwLockWriteGuard::leak(lock.write());
+    /// *data += 1;
+    ///
+    /// // the reference is not used any longer
+    /// unsafe { lock.force_unlock() };
+    /// assert_eq!(*lock.read(), 1);
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// This bypasses RAII, so the caller must guarantee all of the following.
+    ///
+    /// - The writer lock must be held, and its guard must be leaked by `RwLockWriteGuard::leak`
+    ///   or otherwise forgotten, so that it is never dropped.
+    /// - The reference returned by `leak` must not be used after this call,
+    ///   because other threads may hold the lock then.
+    /// - The lock must be released only once.
+    ///
+    /// The lock is never poisoned by this method.
+    /// This is the same as [`RwLock::force_unlock_write`].
+    pub unsafe fn force_unlock(&self) {
+        self.force_unlock_write();
+    }
+
+    /// release a reader lock without a guard
+    ///
+    /// This does the same as dropping a [`RwLockReadGuard`],
+    /// so it wakes a waiting writer if this is the last reader.
+    /// Other readers holding the lock are not affected.
+    ///
+    /// # Safety
+    ///
+    /// This bypasses RAII, so the caller must guarantee all of the following.
+    ///
+    /// - A reader lock must be held, and its guard must be forgotten by `core::mem::forget`
+    ///   or consumed otherwise, so that it is never dropped.
+    /// - References and pointers to the data obtained through the guard,
+    ///   e.g. by [`RwLockReadGuard::as_ptr`], must not be used after this call,
+    ///   because a writer may hold the lock then.
+    /// - Each forgotten guard must be released only once.
+    pub unsafe fn force_unlock_read(&self) {
+        self.unlocker().unlock_read(
+            #[cfg(feature = "debug-detect")]
+            crate::detect::current(),
+        );
+    }
+
+    /// release the writer lock without a guard
+    ///
+    /// This does the same as dropping a [`RwLockWriteGuard`], except that the lock is never poisoned.
+    ///
+    /// # Safety
+    ///
+    /// See [`RwLock::force_unlock`].
+    /// In addition, the guard must not have been split by [`RwLockWriteGuard::map_split`].
+    pub unsafe fn force_unlock_write(&self) {
+        self.raw.unlock_exclusive();
+        H::on_released(LockKind::Write);
+    }
+
+    /// start an optimistic read, which does not acquire the lock
+    ///
+    /// The data may be read through [`RwLock::data_ptr`] after this,
+    /// and the read is valid only if [`RwLock::validate`] returns true for the token afterwards.
+    /// Otherwise, a writer held the lock in between, so the read may be torn, and must be retried,
+    /// e.g. by [`RwLock::read`] after some failures.
+    /// This costs readers only two loads, and never blocks writers.
+    ///
+    /// The data may be modified concurrently, so it must be copied
+    /// by [`core::ptr::read_volatile`] into `MaybeUninit` before validation,
+    /// and the copy must not be used until then, because it may be invalid as `T`.
+    /// References and pointers in the data must not be dereferenced before validation either.
+    /// [`RwLock::try_read_optimistic`] does this safely for `T: Copy`.
+    ///
+    /// The token is never valid while a writer holds the lock,
+    /// including a leaked writer guard, and poisoning is ignored.
+    ///
+    /// ```rust
+    /// use core::mem::MaybeUninit;
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new((1u64, 2u64));
+    ///
+    /// let (a, b) = loop {
+    ///     let token = lock.read_optimistic();
+    ///     let copy = unsafe { lock.data_ptr().cast::<MaybeUninit<(u64, u64)>>().read_volatile() };
+    ///     if lock.validate(token) {
+    ///         break unsafe { copy.assume_init() };
+    ///     }
+    /// };
+    /// assert_eq!(a + b, 3);
+    /// ```
+    #[cfg(not(loom))]
+    pub fn read_optimistic(&self) -> OptimisticReadToken {
+        OptimisticReadToken {
+            version: self.raw.version.load(Ordering::Acquire),
+        }
+    }
+
+    /// true if no writer held the lock since `token` was returned by [`RwLock::read_optimistic`]
+    #[cfg(not(loom))]
+    pub fn validate(&self, token: OptimisticReadToken) -> bool {
+        // reads of the data are not reordered after the load of `version`
+        core::sync::atomic::fence(Ordering::Acquire);
+        token.version & 1 == 0 && self.raw.version.load(Ordering::Relaxed) == token.version
+    }
+
+    /// the number of times the writer lock has been released
+    ///
+    /// This is incremented when a write guard is dropped or downgraded,
+    /// even if the data is not modified, but not by [`RwLock::get_mut`],
+    /// so a reader can detect that the data may have changed since it last read it
+    /// by comparing the versions.
+    /// A version loaded while holding a reader lock is that of the data it reads.
+    ///
+    /// The version wraps around to 0 after `u64::MAX`,
+    /// which takes centuries even if the lock is released every nanosecond.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(0);
+    ///
+    /// let (cached, seen) = {
+    ///     let r = lock.read();
+    ///     (*r, lock.version())
+    /// };
+    ///
+    /// *lock.write() += 1;
+    ///
+    /// // the cache is stale
+    /// assert_ne!(lock.version(), seen);
+    /// assert_ne!(*lock.read(), cached);
+    /// ```
+    #[cfg(all(target_has_atomic = "64", not(loom)))]
+    pub fn version(&self) -> u64 {
+        self.raw.generation.load(Ordering::Acquire)
+    }
+
+    /// get the data without locking, which is safe because of `&mut self`
+    ///
+    /// No guard can be alive while the lock is borrowed mutably, so this ignores poisoning.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let mut lock = RwLock::new(0);
+    /// *lock.get_mut() += 1;
+    /// assert_eq!(*lock.read(), 1);
+    /// ```
+    #[cfg(not(loom))]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    /// raw pointer to the data
+    ///
+    /// This does not acquire the lock, e.g. to pass the data to C code
+    /// which reads it while the caller holds the lock.
+    /// Dereferencing the pointer is safe only while the lock is held accordingly,
+    /// that is, reading requires a reader or the writer lock,
+    /// and writing requires the writer lock,
+    /// or while the caller guarantees exclusive access otherwise, e.g. by `&mut RwLock`.
+    ///
+    /// The pointer stays the same until the lock is moved or dropped.
+    /// A reference created from it must not outlive the lock held for it,
+    /// and must not overlap a mutable reference from a guard or [`RwLock::get_mut`].
+    #[cfg(not(loom))]
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    /// acquire writer lock, and return `Err` if the lock is poisoned
+    ///
+    /// See [`RwLock::read_checked`].
+    #[cfg(feature = "poison")]
+    pub fn write_checked(&self) -> crate::poison::LockResult<RwLockWriteGuard<'_, T, P, H>> {
+        let guard = self.write();
+        self.poison.map_result(guard)
+    }
+
+    /// true if a thread panicked while holding the writer lock
+    #[cfg(feature = "poison")]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// clear the poisoned state
+    #[cfg(feature = "poison")]
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    /// the number of readers holding the lock
+    ///
+    /// This is a racy snapshot, which can be outdated as soon as it is returned,
+    /// so it must be used only for diagnostics and heuristics.
+    pub fn reader_count(&self) -> usize {
+        self.state_debug().readers
+    }
+
+    /// true if a writer holds the lock
+    ///
+    /// This is a racy snapshot as [`RwLock::reader_count`].
+    pub fn is_write_locked(&self) -> bool {
+        self.state_debug().write_locked
+    }
+
+    /// true if a writer is waiting for readers to release the lock
+    ///
+    /// New readers wait while this is true,
+    /// so readers holding the lock for a long time can check this to back off voluntarily.
+    /// This is false while a writer holds the lock, even if other writers are waiting.
+    /// This is a racy snapshot as [`RwLock::reader_count`].
+    pub fn writer_pending(&self) -> bool {
+        self.state_debug().writer_pending
+    }
+
+    /// statistics of the contention since the lock was created or [`RwLock::reset_stats`] was called
+    ///
+    /// This finds hot locks, e.g. by the ratio of `contended_reads` to `reads`.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(0);
+    /// *lock.write() += 1;
+    /// assert_eq!(*lock.read(), 1);
+    ///
+    /// let stats = lock.stats();
+    /// assert_eq!((stats.reads, stats.writes), (1, 1));
+    /// assert_eq!((stats.contended_reads, stats.contended_writes), (0, 0));
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> RwLockStats {
+        let stats = &self.raw.stats;
+        RwLockStats {
+            reads: stats.reads.load(Ordering::Relaxed),
+            writes: stats.writes.load(Ordering::Relaxed),
+            contended_reads: stats.contended_reads.load(Ordering::Relaxed),
+            contended_writes: stats.contended_writes.load(Ordering::Relaxed),
+            writer_wait_spins: stats.writer_wait_spins.load(Ordering::Relaxed),
+        }
+    }
+
+    /// reset the statistics to 0
+    ///
+    /// Locks acquired concurrently may be counted before or after resetting.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        let stats = &self.raw.stats;
+        stats.reads.store(0, Ordering::Relaxed);
+        stats.writes.store(0, Ordering::Relaxed);
+        stats.contended_reads.store(0, Ordering::Relaxed);
+        stats.contended_writes.store(0, Ordering::Relaxed);
+        stats.writer_wait_spins.store(0, Ordering::Relaxed);
+    }
+
+    /// snapshot the raw lock word without taking the lock
+    ///
+    /// `usize::MAX` means a writer holds the lock.
+    /// Otherwise, the value is the number of readers times 2, plus 1 if a writer is waiting.
+    ///
+    /// The snapshot is racy and may be stale as soon as it is returned.
+    /// Use it only for diagnostics, never for synchronization.
+    #[cfg(feature = "internals")]
+    pub fn raw_state(&self) -> usize {
+        self.raw.state.load(Ordering::Relaxed)
+    }
+
+    /// overwrite the state as if `readers` readers hold the lock
+    #[cfg(all(test, not(loom)))]
+    pub(crate) fn set_reader_count(&self, readers: usize) {
+        self.raw.state.store(readers * 2, Ordering::Relaxed);
+    }
+
+    /// decode the state of the lock for formatting
+    ///
+    /// All fields are decoded from the same snapshot.
+    pub fn state_debug(&self) -> RwLockState {
+        let s = self.raw.state.load(Ordering::Relaxed);
+        if s == usize::MAX {
+            RwLockState {
+                readers: 0,
+                write_locked: true,
+                writer_pending: false,
+            }
+        } else {
+            RwLockState {
+                readers: s >> 1,
+                write_locked: false,
+                writer_pending: s & 1 == 1,
+            }
+        }
+    }
+}
+
+/// snapshot of the state of [`RwLock`], returned by [`RwLock::state_debug`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RwLockState {
+    /// the number of readers holding the lock
+    pub readers: usize,
+
+    /// true if a writer holds the lock
+    pub write_locked: bool,
+
+    /// true if a writer is waiting for readers
+    pub writer_pending: bool,
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct RwLockReadGuard<'a, T: ?Sized, P = WriterPreferred, H: RwHook = NoopHook> {
+    /// `RwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    rwlock: &'a RwLock<T, P, H>,
+    /// the owner recorded in the table of readers, or 0
+    #[cfg(feature = "debug-detect")]
+    owner: usize,
+    /// `Send` and `Sync` are implemented manually
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T: ?Sized, P, H: RwHook> RwLockReadGuard<'a, T, P, H> {
+    /// unlock read lock
+    pub fn unlock(self) {}
+
+    /// upgrade to the writer lock without blocking
+    ///
+    /// This succeeds only if this is the only reader and no writer is waiting.
+    /// Otherwise, the reader guard is returned as is.
+    /// Unlike releasing the reader lock and acquiring the writer lock,
+    /// no other writer can modify the data in between.
+    /// This is an associated function as [`RwLockReadGuard::map`].
+    ///
+    /// ```rust
+    /// use synctools::rwlock::{RwLock, RwLockReadGuard};
+    ///
+    /// let lock = RwLock::new(0);
+    /// let guard = lock.read();
+    /// if *guard == 0 {
+    ///     if let Ok(mut guard) = RwLockReadGuard::try_upgrade(guard) {
+    ///         *guard = 1;
+    ///     }
+    /// }
+    /// ```
+    pub fn try_upgrade(s: Self) -> Result<RwLockWriteGuard<'a, T, P, H>, Self> {
+        let rwlock = s.rwlock;
+        if !rwlock.raw.try_upgrade() {
+            return Err(s);
+        }
+
+        // the reader lock is consumed by the CAS
+        #[cfg(feature = "debug-detect")]
+        rwlock.readers.remove(s.owner);
+        core::mem::forget(s);
+        H::on_released(LockKind::Read);
+        Ok(rwlock.write_guard(false))
+    }
+
+    /// consume the guard without unlocking, and return the reference to the data
+    ///
+    /// The reader lock stays held until it is released by [`RwLock::force_unlock_read`],
+    /// so writers wait forever if that is not called, while readers still acquire the lock.
+    /// This is an associated function as [`RwLockReadGuard::map`].
+    ///
+    /// ```rust
+    /// use synctools::rwlock::{RwLock, RwLockReadGuard};
+    ///
+    /// static CONFIG: RwLock<u32> = RwLock::new(0);
+    ///
+    /// *CONFIG.write() = 42;
+    ///
+    /// // freeze the value
+    /// let config: &'static u32 = RwLockReadGuard::leak(CONFIG.read());
+    /// assert_eq!(*config, 42);
+    /// assert_eq!(*CONFIG.read(), 42);
+    /// ```
+    #[cfg(not(loom))]
+    pub fn leak(s: Self) -> &'a T {
+        let s = ManuallyDrop::new(s);
+        unsafe { &*s.rwlock.data.get() }
+    }
+
+    /// raw pointer to the data, which can be read while the guard is alive
+    ///
+    /// This is an associated function as [`RwLockReadGuard::map`].
+    /// The pointer can be kept after the guard is forgotten by `core::mem::forget`
+    /// until the lock is released by [`RwLock::force_unlock_read`].
+    #[cfg(not(loom))]
+    pub fn as_ptr(s: &Self) -> *const T {
+        s.rwlock.data.get()
+    }
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.rwlock.data.with(|data| f(unsafe { &*data }))
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct RwLockWriteGuard<'a, T: ?Sized, P = WriterPreferred, H: RwHook = NoopHook> {
+    /// `RwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`,
+    /// which forbids writing a value of a shorter lifetime through the guard
+    rwlock: &'a RwLock<T, P, H>,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+    /// not `Send`, because the lock must be released by the thread which acquired it,
+    /// and `Sync` is implemented manually
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T: ?Sized, P, H: RwHook> RwLockWriteGuard<'a, T, P, H> {
+    /// unlock write lock
+    pub fn unlock(self) {}
+
+    /// consume the guard without unlocking, and return the reference to the data
+    ///
+    /// The writer lock stays held until it is released by [`RwLock::force_unlock`],
+    /// and it is never released if that is not called,
+    /// so every thread trying to acquire the lock waits forever.
+    /// This is an associated function as [`RwLockReadGuard::map`],
+    /// so call it as `RwLockWriteGuard::leak(guard)`.
+    #[cfg(not(loom))]
+    pub fn leak(s: Self) -> &'a mut T {
+        let s = ManuallyDrop::new(s);
+        unsafe { &mut *s.rwlock.data.get() }
+    }
+
+    /// raw pointer to the data, which can be written while the guard is alive
+    ///
+    /// This is an associated function as [`RwLockWriteGuard::leak`].
+    #[cfg(not(loom))]
+    pub fn as_mut_ptr(s: &mut Self) -> *mut T {
+        s.rwlock.data.get()
+    }
+
+    /// unlock write lock, and hand over the lock to the waiting readers
+    ///
+    /// [`RwLockWriteGuard::unlock`] releases the lock and lets every thread compete for it,
+    /// so a writer which releases the lock and acquires it again immediately
+    /// tends to win against readers spinning on the lock, and can starve them.
+    /// If readers are waiting for the lock, this method makes them acquire it
+    /// before any writer including the caller,
+    /// even if a writer is waiting when they try.
+    /// Otherwise, this is the same as `unlock`.
+    ///
+    /// The waiting readers and writers take turns by this,
+    /// but it costs throughput, because the lock stays cold in the cache of the caller
+    /// and a writer waits for the readers instead of continuing its work.
+    /// Readers which start waiting concurrently with this method may not be counted.
+    pub fn unlock_fair(self) {
+        let s = ManuallyDrop::new(self);
+
+        #[cfg(feature = "poison")]
+        s.rwlock.unlocker().unlock_write_fair(s.panicking);
+
+        #[cfg(not(feature = "poison"))]
+        s.rwlock.unlocker().unlock_write_fair();
+    }
+
+    /// release the writer lock, call `f`, and acquire the writer lock again
+    ///
+    /// The lock is handed over to the waiting readers as [`RwLockWriteGuard::unlock_fair`],
+    /// so that other threads observe the data written so far while `f` is running,
+    /// e.g. between the steps of a long rebuild.
+    /// The lock is acquired again as [`RwLock::write`], which sets the writer waiting bit
+    /// and waits for the readers and writers which acquired the lock meanwhile.
+    /// The lock is acquired again even if `f` panics.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::RwLock;
+    ///
+    /// let lock = RwLock::new(0);
+    /// let mut w = lock.write();
+    /// *w = 1;
+    /// w.unlocked(|| assert_eq!(*lock.read(), 1));
+    /// *w = 2;
+    /// ```
+    pub fn unlocked<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        /// acquire the lock again when `f` returns or panics
+        struct Relock<'b, 'a, T: ?Sized, P, H: RwHook>(&'b mut RwLockWriteGuard<'a, T, P, H>);
+
+        impl<'b, 'a, T: ?Sized, P, H: RwHook> Drop for Relock<'b, 'a, T, P, H> {
+            fn drop(&mut self) {
+                let rwlock = self.0.rwlock;
+                let waited = rwlock.raw.lock_exclusive_park::<SpinPark, H>();
+                H::on_acquired(LockKind::Write, waited);
+
+                #[cfg(feature = "poison")]
+                {
+                    self.0.panicking = rwlock.poison.guard();
+                }
+            }
+        }
+
+        #[cfg(feature = "poison")]
+        self.rwlock.unlocker().unlock_write_fair(self.panicking);
+
+        #[cfg(not(feature = "poison"))]
+        self.rwlock.unlocker().unlock_write_fair();
+
+        let _relock = Relock(self);
+        f()
+    }
+
+    /// release and acquire the writer lock again if a reader or a writer is waiting for it
+    ///
+    /// The lock is handed over to the waiting readers as [`RwLockWriteGuard::unlocked`],
+    /// or this waits until a waiting writer acquires and releases the lock.
+    /// This does nothing if no thread is waiting,
+    /// so it can be called often in a long write section.
+    /// Threads which wait with a bound, e.g. by [`RwLock::try_write_for`], are not counted.
+    pub fn bump(&mut self) {
+        let raw = &self.rwlock.raw;
+        if raw.has_waiters() {
+            // let a waiting writer acquire the lock before acquiring it again
+            self.unlocked(|| raw.wait_for_writers());
+        }
+    }
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.rwlock.data.with_mut(|data| f(unsafe { &mut *data }))
+    }
+}
+
+// readers share `&T` across threads, and a writer can move `T` by `&mut T`,
+// so the bounds are the same as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send + Sync, P, H: RwHook> Sync for RwLock<T, P, H> {}
+unsafe impl<T: ?Sized + Send, P, H: RwHook> Send for RwLock<T, P, H> {}
+
+// a shared reference to a guard gives only `&T`, as `std::sync::RwLock`
+unsafe impl<'a, T: ?Sized + Sync, P, H: RwHook> Sync for RwLockReadGuard<'a, T, P, H> {}
+
+// a reader lock is only a count, so it can be released by another thread,
+// and the thread gets only `&T`
+unsafe impl<'a, T: ?Sized + Sync, P, H: RwHook> Send for RwLockReadGuard<'a, T, P, H> {}
+unsafe impl<'a, T: ?Sized + Sync, P, H: RwHook> Sync for RwLockWriteGuard<'a, T, P, H> {}
+
+/// future returned by [`RwLock::read_async`]
+#[cfg(all(feature = "async", not(loom)))]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct RwLockReadFuture<'a, T: ?Sized, P = WriterPreferred, H: RwHook = NoopHook> {
+    rwlock: &'a RwLock<T, P, H>,
+    waiter: UnsafeCell<Waiter>,
+    queued: bool,
+    _pin: PhantomPinned,
+}
+
+#[cfg(all(feature = "async", not(loom)))]
+impl<'a, T: ?Sized, P: Policy, H: RwHook> Future for RwLockReadFuture<'a, T, P, H> {
+    type Output = RwLockReadGuard<'a, T, P, H>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // the waiter is not moved, because self is pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let rwlock = this.rwlock;
+
+        let first = !this.queued && rwlock.try_read_async();
+        if !first && !this.queued {
+            H::on_read_contended();
+        }
+        let acquired = first
+            || unsafe {
+                rwlock.raw.queue_and_retry(
+                    this.waiter.get(),
+                    TaskLists::readers,
+                    cx.waker(),
+                    || rwlock.try_read_async(),
+                )
+            };
+
+        if acquired {
+            if !first {
+                rwlock.raw.count_task_waited(false);
+            }
+
+            Poll::Ready(rwlock.read_guard(!first))
+        } else {
+            this.queued = true;
+            Poll::Pending
+        }
+    }
+}
+
+/// leave the queue
+#[cfg(all(feature = "async", not(loom)))]
+impl<'a, T: ?Sized, P, H: RwHook> Drop for RwLockReadFuture<'a, T, P, H> {
+    fn drop(&mut self) {
+        if !self.queued {
+            return;
+        }
+
+        let waiter = self.waiter.get();
+        self.rwlock.raw.with_tasks(|lists| unsafe {
+            if (*waiter).state == WaiterState::Waiting {
+                lists.readers.remove(waiter);
+            }
+        });
+    }
+}
+
+/// future returned by [`RwLock::write_async`]
+#[cfg(all(feature = "async", not(loom)))]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct RwLockWriteFuture<'a, T: ?Sized, P = WriterPreferred, H: RwHook = NoopHook> {
+    rwlock: &'a RwLock<T, P, H>,
+    waiter: UnsafeCell<Waiter>,
+    queued: bool,
+    _pin: PhantomPinned,
+}
+
+#[cfg(all(feature = "async", not(loom)))]
+impl<'a, T: ?Sized, P, H: RwHook> Future for RwLockWriteFuture<'a, T, P, H> {
+    type Output = RwLockWriteGuard<'a, T, P, H>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // the waiter is not moved, because self is pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let rwlock = this.rwlock;
+        let raw = &rwlock.raw;
+
+        let first = !this.queued && raw.try_lock_exclusive_or_wait();
+        if !first && !this.queued {
+            H::on_write_contended();
+        }
+        let acquired = first
+            || unsafe {
+                raw.queue_and_retry(this.waiter.get(), TaskLists::writers, cx.waker(), || {
+                    raw.try_lock_exclusive_or_wait()
+                })
+            };
+
+        if acquired {
+            if !first {
+                raw.count_task_waited(true);
+            }
+
+            Poll::Ready(rwlock.write_guard(!first))
+        } else {
+            this.queued = true;
+            Poll::Pending
+        }
+    }
+}
+
+/// leave the queue, and pass the wake-up to the next writer
+#[cfg(all(feature = "async", not(loom)))]
+impl<'a, T: ?Sized, P, H: RwHook> Drop for RwLockWriteFuture<'a, T, P, H> {
+    fn drop(&mut self) {
+        if !self.queued {
+            return;
+        }
+
+        let waiter = self.waiter.get();
+        let raw = &self.rwlock.raw;
+        let waker = raw.with_tasks(|lists| unsafe {
+            match (*waiter).state {
+                WaiterState::Waiting => {
+                    lists.writers.remove(waiter);
+                    lists.writer_gone(raw, false)
+                }
+                WaiterState::Notified => lists.writer_gone(raw, true),
+                WaiterState::Idle | WaiterState::Done => None,
+            }
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// the waiters are accessed only while the spin lock is held
+#[cfg(all(feature = "async", not(loom)))]
+unsafe impl<'a, T: ?Sized + Send + Sync, P, H: RwHook> Send for RwLockReadFuture<'a, T, P, H> {}
+#[cfg(all(feature = "async", not(loom)))]
+unsafe impl<'a, T: ?Sized + Send + Sync, P, H: RwHook> Sync for RwLockReadFuture<'a, T, P, H> {}
+#[cfg(all(feature = "async", not(loom)))]
+unsafe impl<'a, T: ?Sized + Send + Sync, P, H: RwHook> Send for RwLockWriteFuture<'a, T, P, H> {}
+#[cfg(all(feature = "async", not(loom)))]
+unsafe impl<'a, T: ?Sized + Send + Sync, P, H: RwHook> Sync for RwLockWriteFuture<'a, T, P, H> {}
+
+/// serialize the data while holding the reader lock
+///
+/// The reader lock is acquired during serialization,
+/// so serializing a lock whose writer lock is held by the current thread deadlocks.
+#[cfg(all(feature = "serde", not(loom)))]
+impl<T: ?Sized + serde::Serialize, P: Policy, H: RwHook> serde::Serialize for RwLock<T, P, H> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let guard = self.read();
+        (*guard).serialize(serializer)
+    }
+}
+
+/// deserialize the data and create a new lock
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>, P, H: RwHook> serde::Deserialize<'de> for RwLock<T, P, H> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(RwLock::with_policy)
+    }
+}
+
+/// format the data if the reader lock is available without blocking
+///
+/// `<write locked>` is printed instead while a writer holds the lock.
+/// The alternate form `{:#?}` also prints the number of readers,
+/// which is a racy snapshot taken before acquiring the reader lock.
+///
+/// ```rust
+/// use synctools::rwlock::RwLock;
+///
+/// let lock = RwLock::new(1);
+/// assert_eq!(format!("{:?}", lock), "RwLock { data: 1 }");
+///
+/// let _w = lock.write();
+/// assert_eq!(format!("{:?}", lock), "RwLock { data: <write locked> }");
+/// ```
+#[cfg(not(loom))]
+impl<T: ?Sized + fmt::Debug, P, H: RwHook> fmt::Debug for RwLock<T, P, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alternate = f.alternate();
+        let mut d = f.debug_struct("RwLock");
+        if alternate {
+            d.field("readers", &self.reader_count());
+        }
+
+        // a waiting writer is passed as `read_recursive`,
+        // so that formatting never blocks
+        if self.raw.try_lock_shared_recursive() {
+            let guard = self.read_guard(false);
+            d.field("data", &&*guard);
+        } else {
+            d.field("data", &format_args!("<write locked>"));
+        }
+        d.finish()
+    }
+}
+
+/// print only the type under loom,
+/// because accessing the data is an operation of the model
+#[cfg(loom)]
+impl<T: ?Sized, P, H: RwHook> fmt::Debug for RwLock<T, P, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RwLock").finish_non_exhaustive()
+    }
+}
+
+/// create a lock of the default value
+///
+/// ```rust
+/// use synctools::rwlock::RwLock;
+///
+/// #[derive(Default)]
+/// struct Counter {
+///     hits: RwLock<u64>,
+/// }
+///
+/// let c = Counter::default();
+/// assert_eq!(*c.hits.read(), 0);
+/// ```
+impl<T: Default, P, H: RwHook> Default for RwLock<T, P, H> {
+    fn default() -> Self {
+        RwLock::with_policy(T::default())
+    }
+}
+
+/// create a new lock of a clone of the data
+///
+/// The reader lock of `self` is acquired while cloning the data,
+/// so cloning a lock whose writer lock is held by the current thread deadlocks.
+/// The new lock is independent of `self`, and is not poisoned even if `self` is.
+///
+/// ```rust
+/// use synctools::rwlock::RwLock;
+///
+/// #[derive(Clone)]
+/// struct Index {
+///     keys: RwLock<Vec<u32>>,
+/// }
+///
+/// let a = Index { keys: RwLock::new(vec![1]) };
+/// let b = a.clone();
+/// b.keys.write().push(2);
+/// assert_eq!(*a.keys.read(), [1]);
+/// assert_eq!(*b.keys.read(), [1, 2]);
+/// ```
+impl<T: Clone, P: Policy, H: RwHook> Clone for RwLock<T, P, H> {
+    fn clone(&self) -> Self {
+        RwLock::with_policy(self.with(T::clone))
+    }
+}
+
+/// create a lock of the value, as [`RwLock::with_policy`]
+///
+/// ```rust
+/// use synctools::rwlock::RwLock;
+///
+/// let lock: RwLock<_> = RwLock::from(vec![1, 2]);
+/// assert_eq!(*lock.read(), [1, 2]);
+///
+/// let lock: RwLock<_> = 3.into();
+/// assert_eq!(*lock.read(), 3);
+/// ```
+impl<T, P, H: RwHook> From<T> for RwLock<T, P, H> {
+    fn from(v: T) -> Self {
+        RwLock::with_policy(v)
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized + fmt::Debug, P, H: RwHook> fmt::Debug for RwLockReadGuard<'a, T, P, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized + fmt::Debug, P, H: RwHook> fmt::Debug for RwLockWriteGuard<'a, T, P, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized + fmt::Display, P, H: RwHook> fmt::Display for RwLockReadGuard<'a, T, P, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized + fmt::Display, P, H: RwHook> fmt::Display for RwLockWriteGuard<'a, T, P, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, P, H: RwHook> Deref for RwLockReadGuard<'a, T, P, H> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, P, H: RwHook> Deref for RwLockWriteGuard<'a, T, P, H> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, P, H: RwHook> DerefMut for RwLockWriteGuard<'a, T, P, H> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+/// release read lock
+impl<'a, T: ?Sized, P, H: RwHook> Drop for RwLockReadGuard<'a, T, P, H> {
+    fn drop(&mut self) {
+        self.rwlock.unlocker().unlock_read(
+            #[cfg(feature = "debug-detect")]
+            self.owner,
+        );
+    }
+}
+
+/// release write lock, and poison the lock if the thread is panicking
+impl<'a, T: ?Sized, P, H: RwHook> Drop for RwLockWriteGuard<'a, T, P, H> {
+    fn drop(&mut self) {
+        #[cfg(feature = "poison")]
+        self.rwlock.unlocker().unlock_write(self.panicking);
+
+        #[cfg(not(feature = "poison"))]
+        self.rwlock.unlocker().unlock_write();
+    }
+}
+
+/// the fields of [`RwLock`] used to release it,
+/// which do not depend on the type of the data so that mapped guards can hold them
+#[derive(Clone, Copy)]
+struct Unlocker<'a> {
+    raw: &'a RawRwLock,
+    #[cfg(feature = "debug-detect")]
+    readers: &'a crate::detect::Readers,
+    /// `RwHook::on_released` of the lock
+    on_released: fn(LockKind),
+    #[cfg(not(loom))]
+    write_parts: &'a AtomicUsize,
+    #[cfg(feature = "poison")]
+    poison: &'a crate::poison::Flag,
+}
+
+impl<'a> Unlocker<'a> {
+    fn unlock_read(self, #[cfg(feature = "debug-detect")] owner: usize) {
+        #[cfg(feature = "debug-detect")]
+        self.readers.remove(owner);
+
+        // the reader lock is held by the guard
+        unsafe { self.raw.unlock_shared() };
+        (self.on_released)(LockKind::Read);
+    }
+
+    fn unlock_write(self, #[cfg(feature = "poison")] panicking: bool) {
+        #[cfg(feature = "poison")]
+        self.poison.done(panicking);
+
+        // the writer lock is released when the last part of the split is dropped
+        #[cfg(not(loom))]
+        if self.write_parts.load(Ordering::Relaxed) != 0
+            && self.write_parts.fetch_sub(1, Ordering::AcqRel) != 1
+        {
+            return;
+        }
+
+        // the writer lock is held by the guard
+        unsafe { self.raw.unlock_exclusive() };
+        (self.on_released)(LockKind::Write);
+    }
+
+    fn unlock_write_fair(self, #[cfg(feature = "poison")] panicking: bool) {
+        #[cfg(feature = "poison")]
+        self.poison.done(panicking);
+
+        self.raw.unlock_exclusive_fair();
+        (self.on_released)(LockKind::Write);
+    }
+}
+
+impl<T: ?Sized, P, H: RwHook> RwLock<T, P, H> {
+    /// make a reader guard of the lock acquired by the caller
+    fn read_guard(&self, waited: bool) -> RwLockReadGuard<'_, T, P, H> {
+        H::on_acquired(LockKind::Read, waited);
+        RwLockReadGuard {
+            rwlock: self,
+            #[cfg(feature = "debug-detect")]
+            owner: self.readers.insert(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// make a writer guard of the lock acquired by the caller
+    fn write_guard(&self, waited: bool) -> RwLockWriteGuard<'_, T, P, H> {
+        H::on_acquired(LockKind::Write, waited);
+        RwLockWriteGuard {
+            rwlock: self,
+            #[cfg(feature = "poison")]
+            panicking: self.poison.guard(),
+            _phantom: PhantomData,
+        }
+    }
+
+    fn unlocker(&self) -> Unlocker<'_> {
+        Unlocker {
+            raw: &self.raw,
+            #[cfg(feature = "debug-detect")]
+            readers: &self.readers,
+            on_released: H::on_released,
+            #[cfg(not(loom))]
+            write_parts: &self.write_parts,
+            #[cfg(feature = "poison")]
+            poison: &self.poison,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, P: Policy, H: RwHook> RwLock<T, P, H> {
+    /// acquire reader lock, and return a guard owning a clone of the `Arc`
+    ///
+    /// The guard has no lifetime, so it can be stored in structures
+    /// which outlive the stack frame acquiring the lock,
+    /// and it can be sent to and dropped by another thread.
+    /// The lock is released exactly as [`RwLockReadGuard`], and then the `Arc` is dropped.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::{ArcRwLockReadGuard, RwLock};
+    /// use std::sync::Arc;
+    ///
+    /// struct Request {
+    ///     config: ArcRwLockReadGuard<u64>,
+    /// }
+    ///
+    /// let lock = Arc::new(RwLock::new(1));
+    /// let request = Request {
+    ///     config: lock.read_arc(),
+    /// };
+    /// assert_eq!(*request.config, 1);
+    /// ```
+    pub fn read_arc(self: &Arc<Self>) -> ArcRwLockReadGuard<T, P, H> {
+        let waited = self.lock_read::<SpinPark>();
+        H::on_acquired(LockKind::Read, waited);
+        ArcRwLockReadGuard {
+            rwlock: self.clone(),
+            #[cfg(feature = "debug-detect")]
+            owner: self.readers.insert(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, P, H: RwHook> RwLock<T, P, H> {
+    /// acquire writer lock, and return a guard owning a clone of the `Arc`
+    ///
+    /// See [`RwLock::read_arc`].
+    pub fn write_arc(self: &Arc<Self>) -> ArcRwLockWriteGuard<T, P, H> {
+        #[cfg(feature = "debug-detect")]
+        self.readers.check_write();
+
+        let waited = self.raw.lock_exclusive_park::<SpinPark, H>();
+        H::on_acquired(LockKind::Write, waited);
+        ArcRwLockWriteGuard {
+            rwlock: self.clone(),
+            #[cfg(feature = "poison")]
+            panicking: self.poison.guard(),
+        }
+    }
+}
+
+/// guard returned by [`RwLock::read_arc`]
+#[cfg(feature = "alloc")]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct ArcRwLockReadGuard<T: ?Sized, P = WriterPreferred, H: RwHook = NoopHook> {
+    rwlock: Arc<RwLock<T, P, H>>,
+    /// the owner recorded in the table of readers, or 0
+    #[cfg(feature = "debug-detect")]
+    owner: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, P, H: RwHook> ArcRwLockReadGuard<T, P, H> {
+    /// unlock read lock
+    pub fn unlock(self) {}
+
+    /// the lock held by this guard
+    pub fn rwlock(s: &Self) -> &Arc<RwLock<T, P, H>> {
+        &s.rwlock
+    }
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.rwlock.data.with(|data| f(unsafe { &*data }))
+    }
+}
+
+/// guard returned by [`RwLock::write_arc`]
+#[cfg(feature = "alloc")]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct ArcRwLockWriteGuard<T: ?Sized, P = WriterPreferred, H: RwHook = NoopHook> {
+    rwlock: Arc<RwLock<T, P, H>>,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, P, H: RwHook> ArcRwLockWriteGuard<T, P, H> {
+    /// unlock write lock
+    pub fn unlock(self) {}
+
+    /// the lock held by this guard
+    pub fn rwlock(s: &Self) -> &Arc<RwLock<T, P, H>> {
+        &s.rwlock
+    }
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.rwlock.data.with_mut(|data| f(unsafe { &mut *data }))
+    }
+}
+
+/// release read lock, and then the `Arc` is dropped
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, P, H: RwHook> Drop for ArcRwLockReadGuard<T, P, H> {
+    fn drop(&mut self) {
+        self.rwlock.unlocker().unlock_read(
+            #[cfg(feature = "debug-detect")]
+            self.owner,
+        );
+    }
+}
+
+/// release write lock and poison the lock if the thread is panicking,
+/// and then the `Arc` is dropped
+#[cfg(feature = "alloc")]
+impl<T: ?Sized, P, H: RwHook> Drop for ArcRwLockWriteGuard<T, P, H> {
+    fn drop(&mut self) {
+        #[cfg(feature = "poison")]
+        self.rwlock.unlocker().unlock_write(self.panicking);
+
+        #[cfg(not(feature = "poison"))]
+        self.rwlock.unlocker().unlock_write();
+    }
+}
+
+#[cfg(all(feature = "alloc", not(loom)))]
+impl<T: ?Sized, P, H: RwHook> Deref for ArcRwLockReadGuard<T, P, H> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(all(feature = "alloc", not(loom)))]
+impl<T: ?Sized, P, H: RwHook> Deref for ArcRwLockWriteGuard<T, P, H> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(all(feature = "alloc", not(loom)))]
+impl<T: ?Sized, P, H: RwHook> DerefMut for ArcRwLockWriteGuard<T, P, H> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+// the lock is released by atomic operations, which do not depend on the thread
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized + Send + Sync, P, H: RwHook> Send for ArcRwLockReadGuard<T, P, H> {}
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized + Send + Sync, P, H: RwHook> Sync for ArcRwLockReadGuard<T, P, H> {}
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized + Send + Sync, P, H: RwHook> Send for ArcRwLockWriteGuard<T, P, H> {}
+#[cfg(feature = "alloc")]
+unsafe impl<T: ?Sized + Send + Sync, P, H: RwHook> Sync for ArcRwLockWriteGuard<T, P, H> {}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, P, H: RwHook> RwLockReadGuard<'a, T, P, H> {
+    /// make a guard for a part of the data
+    ///
+    /// The reader lock is kept held until the returned guard is dropped.
+    /// This is an associated function, because a method would shadow the methods of `T`,
+    /// so call it as `RwLockReadGuard::map(guard, f)`.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::{RwLock, RwLockReadGuard};
+    ///
+    /// let lock = RwLock::new((1, 2));
+    /// let second = RwLockReadGuard::map(lock.read(), |v| &v.1);
+    /// assert_eq!(*second, 2);
+    /// ```
+    pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedRwLockReadGuard<'a, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        // `s` is dropped and releases the lock if `f` panics
+        let rwlock = s.rwlock;
+        let data = f(unsafe { &*rwlock.data.get() });
+
+        let s = ManuallyDrop::new(s);
+        MappedRwLockReadGuard {
+            unlocker: s.rwlock.unlocker(),
+            #[cfg(feature = "debug-detect")]
+            owner: s.owner,
+            data,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// make a guard for a part of the data if `f` returns `Some`
+    ///
+    /// If `f` returns `None`, the original guard is returned by `Err`
+    /// and the reader lock is kept held.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::{RwLock, RwLockReadGuard};
+    ///
+    /// let lock = RwLock::new(Some(1));
+    /// let inner = RwLockReadGuard::try_map(lock.read(), |v| v.as_ref()).ok().unwrap();
+    /// assert_eq!(*inner, 1);
+    /// ```
+    pub fn try_map<U: ?Sized, F>(s: Self, f: F) -> Result<MappedRwLockReadGuard<'a, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        let rwlock = s.rwlock;
+        let Some(data) = f(unsafe { &*rwlock.data.get() }) else {
+            return Err(s);
+        };
+
+        // the reader lock is moved to the mapped guard
+        let s = ManuallyDrop::new(s);
+        Ok(MappedRwLockReadGuard {
+            unlocker: s.rwlock.unlocker(),
+            #[cfg(feature = "debug-detect")]
+            owner: s.owner,
+            data,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, P, H: RwHook> RwLockWriteGuard<'a, T, P, H> {
+    /// make a guard for a part of the data
+    ///
+    /// The writer lock is kept held until the returned guard is dropped.
+    /// See [`RwLockReadGuard::map`].
+    pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedRwLockWriteGuard<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        // `s` is dropped and releases the lock if `f` panics
+        let rwlock = s.rwlock;
+        let data = f(unsafe { &mut *rwlock.data.get() });
+
+        let s = ManuallyDrop::new(s);
+        MappedRwLockWriteGuard {
+            unlocker: s.rwlock.unlocker(),
+            #[cfg(feature = "poison")]
+            panicking: s.panicking,
+            data,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// make a guard for a part of the data if `f` returns `Some`
+    ///
+    /// If `f` returns `None`, the original guard is returned by `Err`
+    /// and the writer lock is kept held.
+    /// See [`RwLockReadGuard::try_map`].
+    pub fn try_map<U: ?Sized, F>(s: Self, f: F) -> Result<MappedRwLockWriteGuard<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        let rwlock = s.rwlock;
+        let Some(data) = f(unsafe { &mut *rwlock.data.get() }) else {
+            return Err(s);
+        };
+
+        // the writer lock is moved to the mapped guard
+        let s = ManuallyDrop::new(s);
+        Ok(MappedRwLockWriteGuard {
+            unlocker: s.rwlock.unlocker(),
+            #[cfg(feature = "poison")]
+            panicking: s.panicking,
+            data,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// split the guard into guards for two disjoint parts of the data
+    ///
+    /// The writer lock is kept held until both of the returned guards are dropped.
+    /// They cannot be sent to other threads.
+    ///
+    /// ```rust
+    /// use synctools::rwlock::{RwLock, RwLockWriteGuard};
+    ///
+    /// let lock = RwLock::new([0; 4]);
+    /// let (mut a, mut b) = RwLockWriteGuard::map_split(lock.write(), |v| v.split_at_mut(2));
+    /// a[0] = 1;
+    /// b[0] = 2;
+    /// drop(a);
+    /// assert!(lock.is_write_locked());
+    /// drop(b);
+    /// assert_eq!(*lock.read(), [1, 0, 2, 0]);
+    /// ```
+    pub fn map_split<A: ?Sized, B: ?Sized, F>(
+        s: Self,
+        f: F,
+    ) -> (MappedRwLockWriteGuard<'a, A>, MappedRwLockWriteGuard<'a, B>)
+    where
+        F: FnOnce(&mut T) -> (&mut A, &mut B),
+    {
+        let rwlock = s.rwlock;
+        let (a, b) = f(unsafe { &mut *rwlock.data.get() });
+
+        // the writer lock is shared by the two mapped guards
+        let s = ManuallyDrop::new(s);
+        s.rwlock.write_parts.store(2, Ordering::Relaxed);
+        (
+            MappedRwLockWriteGuard {
+                unlocker: rwlock.unlocker(),
+                #[cfg(feature = "poison")]
+                panicking: s.panicking,
+                data: a,
+                _phantom: PhantomData,
+            },
+            MappedRwLockWriteGuard {
+                unlocker: rwlock.unlocker(),
+                #[cfg(feature = "poison")]
+                panicking: s.panicking,
+                data: b,
+                _phantom: PhantomData,
+            },
+        )
+    }
+}
+
+/// reader guard for a part of the data, returned by [`RwLockReadGuard::map`]
+#[cfg(not(loom))]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct MappedRwLockReadGuard<'a, U: ?Sized> {
+    unlocker: Unlocker<'a>,
+    /// the owner recorded in the table of readers, or 0
+    #[cfg(feature = "debug-detect")]
+    owner: usize,
+    data: *const U,
+    _phantom: PhantomData<&'a U>,
+}
+
+#[cfg(not(loom))]
+impl<'a, U: ?Sized> MappedRwLockReadGuard<'a, U> {
+    /// unlock read lock
+    pub fn unlock(self) {}
+
+    /// make a guard for a part of the data
+    ///
+    /// See [`RwLockReadGuard::map`].
+    pub fn map<V: ?Sized, F>(s: Self, f: F) -> MappedRwLockReadGuard<'a, V>
+    where
+        F: FnOnce(&U) -> &V,
+    {
+        // `s` is dropped and releases the lock if `f` panics
+        let data = f(unsafe { &*s.data });
+
+        let s = ManuallyDrop::new(s);
+        MappedRwLockReadGuard {
+            unlocker: s.unlocker,
+            #[cfg(feature = "debug-detect")]
+            owner: s.owner,
+            data,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// make a guard for a part of the data if `f` returns `Some`
+    ///
+    /// See [`RwLockReadGuard::try_map`].
+    pub fn try_map<V: ?Sized, F>(s: Self, f: F) -> Result<MappedRwLockReadGuard<'a, V>, Self>
+    where
+        F: FnOnce(&U) -> Option<&V>,
+    {
+        let Some(data) = f(unsafe { &*s.data }) else {
+            return Err(s);
+        };
+
+        let s = ManuallyDrop::new(s);
+        Ok(MappedRwLockReadGuard {
+            unlocker: s.unlocker,
+            #[cfg(feature = "debug-detect")]
+            owner: s.owner,
+            data,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, U: ?Sized> Deref for MappedRwLockReadGuard<'a, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.data }
+    }
+}
+
+/// release read lock
+#[cfg(not(loom))]
+impl<'a, U: ?Sized> Drop for MappedRwLockReadGuard<'a, U> {
+    fn drop(&mut self) {
+        self.unlocker.unlock_read(
+            #[cfg(feature = "debug-detect")]
+            self.owner,
+        );
+    }
+}
+
+/// writer guard for a part of the data, returned by [`RwLockWriteGuard::map`]
+#[cfg(not(loom))]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct MappedRwLockWriteGuard<'a, U: ?Sized> {
+    unlocker: Unlocker<'a>,
+    #[cfg(feature = "poison")]
+    panicking: bool,
+    data: *mut U,
+    _phantom: PhantomData<&'a mut U>,
+}
+
+#[cfg(not(loom))]
+impl<'a, U: ?Sized> MappedRwLockWriteGuard<'a, U> {
+    /// unlock write lock
+    pub fn unlock(self) {}
+
+    /// make a guard for a part of the data
+    ///
+    /// See [`RwLockReadGuard::map`].
+    pub fn map<V: ?Sized, F>(s: Self, f: F) -> MappedRwLockWriteGuard<'a, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        // `s` is dropped and releases the lock if `f` panics
+        let data = f(unsafe { &mut *s.data });
+
+        let s = ManuallyDrop::new(s);
+        MappedRwLockWriteGuard {
+            unlocker: s.unlocker,
+            #[cfg(feature = "poison")]
+            panicking: s.panicking,
+            data,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// make a guard for a part of the data if `f` returns `Some`
+    ///
+    /// See [`RwLockReadGuard::try_map`].
+    pub fn try_map<V: ?Sized, F>(s: Self, f: F) -> Result<MappedRwLockWriteGuard<'a, V>, Self>
+    where
+        F: FnOnce(&mut U) -> Option<&mut V>,
+    {
+        let Some(data) = f(unsafe { &mut *s.data }) else {
+            return Err(s);
+        };
+
+        let s = ManuallyDrop::new(s);
+        Ok(MappedRwLockWriteGuard {
+            unlocker: s.unlocker,
+            #[cfg(feature = "poison")]
+            panicking: s.panicking,
+            data,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, U: ?Sized> Deref for MappedRwLockWriteGuard<'a, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.data }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, U: ?Sized> DerefMut for MappedRwLockWriteGuard<'a, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.data }
+    }
+}
+
+/// release write lock, and poison the lock if the thread is panicking
+#[cfg(not(loom))]
+impl<'a, U: ?Sized> Drop for MappedRwLockWriteGuard<'a, U> {
+    fn drop(&mut self) {
+        #[cfg(feature = "poison")]
+        self.unlocker.unlock_write(self.panicking);
+
+        #[cfg(not(feature = "poison"))]
+        self.unlocker.unlock_write();
+    }
+}
+
+/// condition variable for [`RwLock`], which spins until notified
+///
+/// A thread holding a guard of a [`RwLock`] waits by [`RwCondVar::wait_write`]
+/// or [`RwCondVar::wait_read`], which release the lock, wait for a notification,
+/// and acquire the lock again.
+/// The condition must be changed while holding the writer lock of the same [`RwLock`],
+/// and then notified with or without the lock,
+/// so that a notification between releasing the lock and waiting is not lost.
+///
+/// ```rust
+/// use synctools::rwlock::{RwCondVar, RwLock};
+/// use std::sync::Arc;
+///
+/// let pair = Arc::new((RwLock::new(false), RwCondVar::new()));
+/// let pair0 = pair.clone();
+/// std::thread::spawn(move || {
+///     let (lock, cond) = &*pair0;
+///     *lock.write() = true;
+///     cond.notify_one();
+/// });
+///
+/// let (lock, cond) = &*pair;
+/// let ready = cond.wait_while(lock.write(), |ready| !*ready);
+/// assert!(*ready);
+/// ```
+///
+/// # Algorithm
+///
+/// A waiting thread takes a ticket from `waiters` before releasing the lock,
+/// and waits until `notified` passes the ticket.
+/// `notify_one` advances `notified` by one, and `notify_all` catches it up with `waiters`,
+/// so the waiting threads are woken in FIFO order,
+/// and a notification is a load of each counter if no thread is waiting.
+/// A ticket is taken while holding the lock, before the condition is changed by a notifier,
+/// so the notifier observes the ticket, which is why no notification is lost.
+/// Both counters may wrap around, because they are only compared by their difference.
+pub struct RwCondVar {
+    waiters: AtomicUsize,
+    notified: AtomicUsize,
+}
+
+impl RwCondVar {
+    #[cfg(not(loom))]
+    pub const fn new() -> RwCondVar {
+        RwCondVar {
+            waiters: AtomicUsize::new(0),
+            notified: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> RwCondVar {
+        RwCondVar {
+            waiters: AtomicUsize::new(0),
+            notified: AtomicUsize::new(0),
+        }
+    }
+
+    /// release the writer lock, wait for a notification, and acquire the writer lock again
+    ///
+    /// This may return spuriously, e.g. if the notification is for a condition
+    /// of another waiting thread, so check the condition again after this returns,
+    /// or use [`RwCondVar::wait_while`].
+    pub fn wait_write<'a, T: ?Sized, P, H: RwHook>(
+        &self,
+        guard: RwLockWriteGuard<'a, T, P, H>,
+    ) -> RwLockWriteGuard<'a, T, P, H> {
+        let rwlock = guard.rwlock;
+        let ticket = self.waiters.fetch_add(1, Ordering::Relaxed);
+        drop(guard);
+
+        self.wait_ticket(ticket);
+        rwlock.write()
+    }
+
+    /// release the reader lock, wait for a notification, and acquire the reader lock again
+    ///
+    /// See [`RwCondVar::wait_write`].
+    pub fn wait_read<'a, T: ?Sized, P: Policy, H: RwHook>(
+        &self,
+        guard: RwLockReadGuard<'a, T, P, H>,
+    ) -> RwLockReadGuard<'a, T, P, H> {
+        let rwlock = guard.rwlock;
+        let ticket = self.waiters.fetch_add(1, Ordering::Relaxed);
+        drop(guard);
+
+        self.wait_ticket(ticket);
+        rwlock.read()
+    }
+
+    /// wait by [`RwCondVar::wait_write`] while `condition` returns true for the data
+    ///
+    /// `condition` is called with the writer lock held,
+    /// and the guard is returned when it returns false.
+    #[cfg(not(loom))]
+    pub fn wait_while<'a, T: ?Sized, P, H: RwHook, F>(
+        &self,
+        mut guard: RwLockWriteGuard<'a, T, P, H>,
+        mut condition: F,
+    ) -> RwLockWriteGuard<'a, T, P, H>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait_write(guard);
+        }
+        guard
+    }
+
+    /// wake the thread which has waited for the longest, if any
+    pub fn notify_one(&self) {
+        let _ = self
+            .notified
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                let w = self.waiters.load(Ordering::Relaxed);
+                (w != n).then(|| n.wrapping_add(1))
+            });
+    }
+
+    /// wake every waiting thread
+    pub fn notify_all(&self) {
+        let _ = self
+            .notified
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                let w = self.waiters.load(Ordering::Relaxed);
+                (w != n).then_some(w)
+            });
+    }
+
+    /// wait until the thread of `ticket` is notified
+    fn wait_ticket(&self, ticket: usize) {
+        // `notified` is ahead of `ticket` if the ticket was notified
+        wait_until(&self.notified, Ordering::Relaxed, |n| {
+            (n.wrapping_sub(ticket) as isize) > 0
+        });
+    }
+}
+
+impl Default for RwCondVar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// phase-fair readers writer lock
+///
+/// Readers and writers alternate phases, as the ticket-based phase-fair lock
+/// of Brandenburg and Anderson.
+/// A writer waits only for the readers which arrived before it and for the writers
+/// which took earlier tickets, and a reader waits for at most one writer critical section,
+/// so neither side starves.
+/// This suits real-time workloads which need bounded waiting, while [`RwLock`]
+/// lets a writer wait for a stream of readers under [`ReaderPreferred`],
+/// and readers wait for a stream of writers under [`WriterPreferred`].
+///
+/// ```rust
+/// use synctools::rwlock::PhaseFairRwLock;
+///
+/// let lock = PhaseFairRwLock::new(0);
+/// *lock.write() += 1;
+/// assert_eq!(*lock.read(), 1);
+/// ```
+///
+/// # Algorithm
+///
+/// `rin` counts the readers which entered by `PF_RINC` in the upper bits,
+/// and holds the writer present bit and the phase bit of the writer in the lower bits.
+/// `rout` counts the readers which left in the same way.
+/// `win` and `wout` are the ticket lock of writers.
+///
+/// A reader adds `PF_RINC` to `rin`, and if a writer is present,
+/// waits until the writer bits of `rin` change.
+/// Consecutive writers use alternate phase bits, so a reader never misses the end of a phase.
+/// A writer takes a ticket, waits for its turn, sets the writer bits of `rin`
+/// to block new readers, and waits until `rout` catches up with the readers counted by `rin`.
+/// Every counter may wrap around, because they are only compared for equality.
+pub struct PhaseFairRwLock<T: ?Sized> {
+    rin: Line<AtomicUsize>,
+    rout: Line<AtomicUsize>,
+    win: Line<AtomicUsize>,
+    wout: Line<AtomicUsize>,
+    data: Line<UnsafeCell<T>>,
+}
+
+/// the increment of a reader in `rin` and `rout` of [`PhaseFairRwLock`]
+const PF_RINC: usize = 0x100;
+
+/// the writer bits in `rin`
+const PF_WBITS: usize = 0x3;
+
+/// a writer is present
+const PF_PRES: usize = 0x2;
+
+/// the phase of the present writer
+const PF_PHID: usize = 0x1;
+
+impl<T> PhaseFairRwLock<T> {
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> PhaseFairRwLock<T> {
+        PhaseFairRwLock {
+            rin: Line::new(AtomicUsize::new(0)),
+            rout: Line::new(AtomicUsize::new(0)),
+            win: Line::new(AtomicUsize::new(0)),
+            wout: Line::new(AtomicUsize::new(0)),
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(v: T) -> PhaseFairRwLock<T> {
+        PhaseFairRwLock {
+            rin: Line::new(AtomicUsize::new(0)),
+            rout: Line::new(AtomicUsize::new(0)),
+            win: Line::new(AtomicUsize::new(0)),
+            wout: Line::new(AtomicUsize::new(0)),
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+}
+
+impl<T: ?Sized> PhaseFairRwLock<T> {
+    /// acquire reader lock
+    ///
+    /// This waits for at most the writer which is present, if any.
+    pub fn read(&self) -> PhaseFairReadGuard<'_, T> {
+        // `Acquire` synchronizes with the `Release` of the previous writer on `rin`
+        let w = self.rin.fetch_add(PF_RINC, Ordering::Acquire) & PF_WBITS;
+        if w != 0 {
+            wait_until(&self.rin, Ordering::Acquire, |v| v & PF_WBITS != w);
+        }
+
+        PhaseFairReadGuard {
+            rwlock: self,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// acquire reader lock without blocking
+    ///
+    /// This fails if a writer holds the lock or is waiting for readers.
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_read(&self) -> Option<PhaseFairReadGuard<'_, T>> {
+        let mut v = self.rin.load(Ordering::Relaxed);
+        while v & PF_WBITS == 0 {
+            match self.rin.compare_exchange_weak(
+                v,
+                v.wrapping_add(PF_RINC),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(PhaseFairReadGuard {
+                        rwlock: self,
+                        _phantom: PhantomData,
+                    })
+                }
+                Err(x) => v = x,
+            }
+        }
+
+        None
+    }
+
+    /// acquire writer lock
+    ///
+    /// Writers enter in the order of arrival,
+    /// and wait only for the readers which arrived before them.
+    pub fn write(&self) -> PhaseFairWriteGuard<'_, T> {
+        let ticket = self.win.fetch_add(1, Ordering::Relaxed);
+        // `Acquire` synchronizes with the release of the previous writer
+        wait_until(&self.wout, Ordering::Acquire, |v| v == ticket);
+
+        // block new readers, and count the readers which already entered
+        let w = PF_PRES | (ticket & PF_PHID);
+        let readers = self.rin.fetch_add(w, Ordering::Relaxed);
+
+        // `Acquire` synchronizes with the release of the readers
+        wait_until(&self.rout, Ordering::Acquire, |v| v == readers);
+
+        PhaseFairWriteGuard {
+            rwlock: self,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// acquire reader lock, call `f` with the data, and release the lock
+    ///
+    /// This is also available under loom as [`RwLock::with`].
+    pub fn with<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            f(&self.read())
+        }
+
+        #[cfg(loom)]
+        {
+            self.read().with(f)
+        }
+    }
+
+    /// acquire writer lock, call `f` with the data, and release the lock
+    ///
+    /// This is also available under loom as [`RwLock::with_mut`].
+    pub fn with_mut<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            f(&mut self.write())
+        }
+
+        #[cfg(loom)]
+        {
+            self.write().with_mut(f)
+        }
+    }
+
+    /// true if a writer holds the lock or is waiting for readers to release the lock
+    ///
+    /// New readers wait while this is true.
+    /// This is a racy snapshot as [`RwLock::reader_count`].
+    pub fn writer_present(&self) -> bool {
+        self.rin.load(Ordering::Relaxed) & PF_PRES != 0
+    }
+
+    fn unlock_read(&self) {
+        self.rout.fetch_add(PF_RINC, Ordering::Release);
+    }
+
+    fn unlock_write(&self) {
+        // start the reader phase, and then pass the ticket to the next writer
+        self.rin.fetch_and(!PF_WBITS, Ordering::Release);
+        self.wout.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct PhaseFairReadGuard<'a, T: ?Sized> {
+    /// `PhaseFairRwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    rwlock: &'a PhaseFairRwLock<T>,
+    /// `Send` and `Sync` are implemented manually
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T: ?Sized> PhaseFairReadGuard<'a, T> {
+    /// unlock read lock
+    pub fn unlock(self) {}
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.rwlock.data.with(|data| f(unsafe { &*data }))
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct PhaseFairWriteGuard<'a, T: ?Sized> {
+    /// `PhaseFairRwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    rwlock: &'a PhaseFairRwLock<T>,
+    /// `Sync` is implemented manually
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T: ?Sized> PhaseFairWriteGuard<'a, T> {
+    /// unlock write lock
+    pub fn unlock(self) {}
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.rwlock.data.with_mut(|data| f(unsafe { &mut *data }))
+    }
+}
+
+// the same bounds as `RwLock`
+unsafe impl<T: ?Sized + Send + Sync> Sync for PhaseFairRwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for PhaseFairRwLock<T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for PhaseFairReadGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Sync> Send for PhaseFairReadGuard<'a, T> {}
+// the writer guard is not `Send` as `RwLockWriteGuard`
+unsafe impl<'a, T: ?Sized + Sync> Sync for PhaseFairWriteGuard<'a, T> {}
+
+#[cfg(not(loom))]
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PhaseFairRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("PhaseFairRwLock");
+
+        // formatting never waits for a writer
+        if let Some(guard) = self.try_read() {
+            d.field("data", &&*guard);
+        } else {
+            d.field("data", &format_args!("<write locked>"));
+        }
+        d.finish()
+    }
+}
+
+impl<T: Default> Default for PhaseFairRwLock<T> {
+    fn default() -> Self {
+        PhaseFairRwLock::new(T::default())
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized> Deref for PhaseFairReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized> Deref for PhaseFairWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized> DerefMut for PhaseFairWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+/// release read lock
+impl<'a, T: ?Sized> Drop for PhaseFairReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_read();
+    }
+}
+
+/// release write lock
+impl<'a, T: ?Sized> Drop for PhaseFairWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.rwlock.unlock_write();
+    }
+}
+
+/// fair readers writer lock whose waiters queue and spin locally as [`crate::mcs::MCSLock`]
+///
+/// This is the fair readers writer queue lock of Mellor-Crummey and Scott.
+/// Each thread passes its own [`QueueRwNode`], and waits by spinning on it,
+/// so waiters do not bounce a shared cache line as [`RwLock`].
+/// Readers and writers acquire the lock in FIFO order,
+/// and adjacent readers in the queue enter together,
+/// that is, a reader which is woken up wakes the reader behind it.
+///
+/// ```rust
+/// use synctools::rwlock::{QueueRwLock, QueueRwNode};
+///
+/// let lock = QueueRwLock::new(0);
+/// let mut node = QueueRwNode::new();
+/// *lock.write(&mut node) += 1;
+/// assert_eq!(*lock.read(&mut node), 1);
+/// ```
+///
+/// # Algorithm
+///
+/// `tail` is the last node of the queue, as [`crate::mcs::RawMCSLock`].
+/// `reader_count` is the number of readers holding the lock,
+/// and `next_writer` is the writer waiting for them to release the lock.
+///
+/// - A reader behind a writer or a waiting reader marks the predecessor,
+///   and is woken up by it, which increments `reader_count` on its behalf.
+///   A reader behind a reader holding the lock, or with no predecessor,
+///   increments `reader_count` and enters immediately.
+/// - A writer behind a node waits until it is woken up.
+///   A writer behind a reader is woken up by the last reader leaving,
+///   because the reader passes the writer by `next_writer` when it leaves.
+///   A writer with no predecessor sets `next_writer` and enters
+///   if no reader holds the lock, or waits for the last reader to wake it up.
+///
+/// The state of a node holds whether it is blocked and the class of its successor,
+/// so that a reader can atomically check that its predecessor is still waiting
+/// when it asks the predecessor to wake it up.
+/// A node leaving the queue waits until its successor, if any, links itself,
+/// so no thread accesses a node after its guard is dropped.
+///
+/// A writer with no predecessor stores `next_writer` and then loads `reader_count`,
+/// while the last reader decrements `reader_count` and then loads `next_writer`.
+/// Both are separated by `SeqCst` fences, so at least one of them sees the other,
+/// and the writer is never left waiting.
+#[doc(alias = "MCSRwLock")]
+pub struct QueueRwLock<T: ?Sized> {
+    tail: Line<AtomicPtr<QueueRwNode>>,
+    reader_count: Line<AtomicUsize>,
+    next_writer: Line<AtomicPtr<QueueRwNode>>,
+    data: Line<UnsafeCell<T>>,
+}
+
+/// node of [`QueueRwLock`]
+///
+/// A node can be reused after the guard is dropped.
+pub struct QueueRwNode {
+    next: AtomicPtr<QueueRwNode>,
+    state: AtomicUsize,
+    writer: AtomicBool,
+}
+
+/// the node waits for the lock
+const QRW_BLOCKED: usize = 1;
+
+/// the successor is a reader, which is woken up by the node
+const QRW_SUCC_READER: usize = 2;
+
+/// the successor is a writer
+const QRW_SUCC_WRITER: usize = 4;
+
+impl Default for QueueRwNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QueueRwNode {
+    #[cfg(not(loom))]
+    pub const fn new() -> QueueRwNode {
+        QueueRwNode {
+            next: AtomicPtr::new(null_mut()),
+            state: AtomicUsize::new(0),
+            writer: AtomicBool::new(false),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> QueueRwNode {
+        QueueRwNode {
+            next: AtomicPtr::new(null_mut()),
+            state: AtomicUsize::new(0),
+            writer: AtomicBool::new(false),
+        }
+    }
+
+    /// initialize the node before it is published by the swap of `tail`
+    fn init(&self, writer: bool) {
+        self.writer.store(writer, Ordering::Relaxed);
+        self.next.store(null_mut(), Ordering::Relaxed);
+        self.state.store(QRW_BLOCKED, Ordering::Relaxed);
+    }
+
+    /// wait until the successor links itself
+    fn wait_next(&self) -> *mut QueueRwNode {
+        let mut backoff = Backoff::new();
+        loop {
+            let next = self.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// wake up the node
+    fn unblock(&self) {
+        // `Release` passes the critical sections to the node
+        self.state.fetch_and(!QRW_BLOCKED, Ordering::Release);
+    }
+
+    /// wait until the node is woken up, and return the state
+    fn wait_unblocked(&self) -> usize {
+        // `Acquire` synchronizes with `unblock`
+        wait_until(&self.state, Ordering::Acquire, |s| s & QRW_BLOCKED == 0).0
+    }
+}
+
+impl<T> QueueRwLock<T> {
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> QueueRwLock<T> {
+        QueueRwLock {
+            tail: Line::new(AtomicPtr::new(null_mut())),
+            reader_count: Line::new(AtomicUsize::new(0)),
+            next_writer: Line::new(AtomicPtr::new(null_mut())),
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new(v: T) -> QueueRwLock<T> {
+        QueueRwLock {
+            tail: Line::new(AtomicPtr::new(null_mut())),
+            reader_count: Line::new(AtomicUsize::new(0)),
+            next_writer: Line::new(AtomicPtr::new(null_mut())),
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+}
+
+impl<T: ?Sized> QueueRwLock<T> {
+    /// acquire reader lock
+    ///
+    /// The reader enters together with the readers adjacent to it in the queue.
+    pub fn read<'a>(&'a self, node: &'a mut QueueRwNode) -> QueueRwReadGuard<'a, T> {
+        let ptr = node as *mut QueueRwNode;
+        unsafe { self.lock_shared(ptr) };
+
+        QueueRwReadGuard {
+            rwlock: self,
+            node: ptr,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// acquire writer lock
+    pub fn write<'a>(&'a self, node: &'a mut QueueRwNode) -> QueueRwWriteGuard<'a, T> {
+        let ptr = node as *mut QueueRwNode;
+        unsafe { self.lock_exclusive(ptr) };
+
+        QueueRwWriteGuard {
+            rwlock: self,
+            node: ptr,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// acquire reader lock, call `f` with the data, and release the lock
+    ///
+    /// This is also available under loom as [`RwLock::with`].
+    pub fn with<R, F>(&self, node: &mut QueueRwNode, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            f(&self.read(node))
+        }
+
+        #[cfg(loom)]
+        {
+            self.read(node).with(f)
+        }
+    }
+
+    /// acquire writer lock, call `f` with the data, and release the lock
+    ///
+    /// This is also available under loom as [`RwLock::with_mut`].
+    pub fn with_mut<R, F>(&self, node: &mut QueueRwNode, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            f(&mut self.write(node))
+        }
+
+        #[cfg(loom)]
+        {
+            self.write(node).with_mut(f)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid and must not be moved until `unlock_shared` returns.
+    unsafe fn lock_shared(&self, ptr: *mut QueueRwNode) {
+        let node = &*ptr;
+        node.init(false);
+
+        // `Release` publishes the initialization,
+        // and `Acquire` synchronizes with the predecessor
+        let pred = self.tail.swap(ptr, Ordering::AcqRel);
+        if pred.is_null() {
+            self.reader_count.fetch_add(1, Ordering::Relaxed);
+            node.unblock();
+        } else {
+            let pred = &*pred;
+
+            // ask the predecessor to wake me up, only if it still waits
+            if pred.writer.load(Ordering::Relaxed)
+                || pred
+                    .state
+                    .compare_exchange(
+                        QRW_BLOCKED,
+                        QRW_BLOCKED | QRW_SUCC_READER,
+                        Ordering::Relaxed,
+                        // synchronizes with `unblock` of the predecessor
+                        Ordering::Acquire,
+                    )
+                    .is_ok()
+            {
+                pred.next.store(ptr, Ordering::Release);
+                node.wait_unblocked();
+            } else {
+                // the predecessor is a reader holding the lock, so enter together
+                self.reader_count.fetch_add(1, Ordering::Relaxed);
+                pred.next.store(ptr, Ordering::Release);
+                node.unblock();
+            }
+        }
+
+        // wake up the reader behind me, which may have been enqueued while I was waiting
+        if node.state.load(Ordering::Acquire) & QRW_SUCC_READER != 0 {
+            let next = node.wait_next();
+            self.reader_count.fetch_add(1, Ordering::Relaxed);
+            (*next).unblock();
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be the node which acquired the reader lock.
+    unsafe fn unlock_shared(&self, ptr: *mut QueueRwNode) {
+        let node = &*ptr;
+
+        if !node.next.load(Ordering::Acquire).is_null()
+            || self
+                .tail
+                .compare_exchange(ptr, null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_err()
+        {
+            // the successor linked itself after setting its class
+            let next = node.wait_next();
+            if node.state.load(Ordering::Relaxed) & QRW_SUCC_WRITER != 0 {
+                // another reader leaving may wake the writer up by this,
+                // so `Release` passes the initialization of the node acquired by `wait_next`
+                self.next_writer.store(next, Ordering::Release);
+            }
+        }
+
+        // the last reader wakes up the waiting writer,
+        // and `Release` passes the critical sections of the readers to it
+        if self.reader_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            fence(Ordering::SeqCst);
+            let w = self.next_writer.load(Ordering::Acquire);
+            if !w.is_null()
+                && self.reader_count.load(Ordering::Relaxed) == 0
+                && self
+                    .next_writer
+                    .compare_exchange(w, null_mut(), Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                (*w).unblock();
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid and must not be moved until `unlock_exclusive` returns.
+    unsafe fn lock_exclusive(&self, ptr: *mut QueueRwNode) {
+        let node = &*ptr;
+        node.init(true);
+
+        let pred = self.tail.swap(ptr, Ordering::AcqRel);
+        if pred.is_null() {
+            // readers which left the queue may still hold the lock
+            // `Release` publishes the initialization to the reader waking me up
+            self.next_writer.store(ptr, Ordering::Release);
+            fence(Ordering::SeqCst);
+
+            // `Acquire` synchronizes with the last reader
+            if self.reader_count.load(Ordering::Acquire) == 0
+                && self.next_writer.swap(null_mut(), Ordering::Relaxed) == ptr
+            {
+                return;
+            }
+        } else {
+            let pred = &*pred;
+
+            // the class must be set before linking, because the predecessor reads it after `next`
+            pred.state.fetch_or(QRW_SUCC_WRITER, Ordering::Relaxed);
+            pred.next.store(ptr, Ordering::Release);
+        }
+
+        node.wait_unblocked();
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be the node which acquired the writer lock.
+    unsafe fn unlock_exclusive(&self, ptr: *mut QueueRwNode) {
+        let node = &*ptr;
+
+        if node.next.load(Ordering::Acquire).is_null()
+            && self
+                .tail
+                .compare_exchange(ptr, null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+        {
+            return;
+        }
+
+        let next = &*node.wait_next();
+        if !next.writer.load(Ordering::Relaxed) {
+            self.reader_count.fetch_add(1, Ordering::Relaxed);
+        }
+        next.unblock();
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct QueueRwReadGuard<'a, T: ?Sized> {
+    /// `QueueRwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    rwlock: &'a QueueRwLock<T>,
+    node: *mut QueueRwNode,
+    /// the node is borrowed until the guard is dropped
+    _phantom: PhantomData<&'a mut QueueRwNode>,
+}
+
+impl<'a, T: ?Sized> QueueRwReadGuard<'a, T> {
+    /// unlock read lock
+    pub fn unlock(self) {}
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.rwlock.data.with(|data| f(unsafe { &*data }))
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct QueueRwWriteGuard<'a, T: ?Sized> {
+    /// `QueueRwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    rwlock: &'a QueueRwLock<T>,
+    node: *mut QueueRwNode,
+    /// the node is borrowed until the guard is dropped
+    _phantom: PhantomData<&'a mut QueueRwNode>,
+}
+
+impl<'a, T: ?Sized> QueueRwWriteGuard<'a, T> {
+    /// unlock write lock
+    pub fn unlock(self) {}
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.rwlock.data.with_mut(|data| f(unsafe { &mut *data }))
+    }
+}
+
+// the same bounds as `RwLock`, and the guards are neither `Send` nor `Sync`
+// without these because of the pointer to the node
+unsafe impl<T: ?Sized + Send + Sync> Sync for QueueRwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for QueueRwLock<T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for QueueRwReadGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for QueueRwWriteGuard<'a, T> {}
+
+impl<T: Default> Default for QueueRwLock<T> {
+    fn default() -> Self {
+        QueueRwLock::new(T::default())
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized> Deref for QueueRwReadGuard<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -181,7 +4321,7 @@ impl<'a, T> Deref for RwLockReadGuard<'a, T> {
 }
 
 #[cfg(not(loom))]
-impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+impl<'a, T: ?Sized> Deref for QueueRwWriteGuard<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -190,29 +4330,531 @@ impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
 }
 
 #[cfg(not(loom))]
-impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+impl<'a, T: ?Sized> DerefMut for QueueRwWriteGuard<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { &mut *self.rwlock.data.get() }
     }
 }
 
 /// release read lock
-impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+impl<'a, T: ?Sized> Drop for QueueRwReadGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe { self.rwlock.unlock_shared(self.node) };
+    }
+}
+
+/// release write lock
+impl<'a, T: ?Sized> Drop for QueueRwWriteGuard<'a, T> {
     fn drop(&mut self) {
-        if self.rwlock.state.fetch_sub(2, Ordering::Release) == 3 {
-            self.rwlock
-                .writer_wake_counter
-                .fetch_add(1, Ordering::Release);
+        unsafe { self.rwlock.unlock_exclusive(self.node) };
+    }
+}
+
+/// big-reader lock, whose readers are counted in `N` slots on separate cache lines
+///
+/// A reader passes a slot, e.g. the ID of the CPU or a hash of the thread,
+/// and touches only the cache line of the slot, so readers on different slots
+/// do not bounce a shared counter as [`RwLock`].
+/// Instead, a writer sets the writer flag and waits until every slot drains,
+/// so writing costs `N` cache lines.
+/// This suits data which is read far more often than it is written.
+///
+/// ```rust
+/// use synctools::rwlock::BigRwLock;
+///
+/// // for 4 CPUs
+/// static CONFIG: BigRwLock<u64, 4> = BigRwLock::new(0);
+///
+/// *CONFIG.write() += 1;
+/// assert_eq!(*CONFIG.read(2), 1);
+/// ```
+///
+/// # Algorithm
+///
+/// A reader increments its slot and then checks the writer flag.
+/// If the flag is set, it decrements the slot, waits until the flag is cleared, and retries.
+/// A writer sets the flag and then waits until every slot is 0.
+/// Both are separated by `SeqCst` fences,
+/// so either the reader sees the flag or the writer sees the reader,
+/// and readers back off while a writer is waiting, which never starves writers.
+///
+/// The `compact` feature removes the padding of the slots as the other locks.
+pub struct BigRwLock<T: ?Sized, const N: usize> {
+    writer: Line<AtomicUsize>,
+    slots: [Line<BigSlot>; N],
+    data: Line<UnsafeCell<T>>,
+}
+
+/// a slot of [`BigRwLock`]
+struct BigSlot {
+    /// the number of readers holding the lock through this slot
+    readers: AtomicUsize,
+    /// the number of readers which acquired the lock through this slot,
+    /// which is on the same cache line so that counting does not share a line either
+    #[cfg(feature = "stats")]
+    reads: AtomicUsize,
+}
+
+impl BigSlot {
+    #[cfg(not(loom))]
+    const fn new() -> BigSlot {
+        BigSlot {
+            readers: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            reads: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(loom)]
+    fn new() -> BigSlot {
+        BigSlot {
+            readers: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            reads: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<T, const N: usize> BigRwLock<T, N> {
+    /// create a lock with `N` slots
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is 0, which fails compilation if this is evaluated in a constant context.
+    #[cfg(not(loom))]
+    pub const fn new(v: T) -> BigRwLock<T, N> {
+        assert!(N > 0, "BigRwLock: N must not be 0");
+        BigRwLock {
+            writer: Line::new(AtomicUsize::new(0)),
+            slots: [const { Line::new(BigSlot::new()) }; N],
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+
+    /// create a lock with `N` slots
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is 0.
+    #[cfg(loom)]
+    pub fn new(v: T) -> BigRwLock<T, N> {
+        assert!(N > 0, "BigRwLock: N must not be 0");
+        BigRwLock {
+            writer: Line::new(AtomicUsize::new(0)),
+            slots: core::array::from_fn(|_| Line::new(BigSlot::new())),
+            data: Line::new(UnsafeCell::new(v)),
+        }
+    }
+}
+
+impl<T: ?Sized, const N: usize> BigRwLock<T, N> {
+    /// acquire reader lock through the slot `slot % N`
+    ///
+    /// Any slot is correct, but readers on the same slot share its cache line,
+    /// so each CPU or thread should use its own slot.
+    pub fn read(&self, slot: usize) -> BigRwLockReadGuard<'_, T, N> {
+        let slot = slot % N;
+        let s = &self.slots[slot];
+
+        loop {
+            s.readers.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+
+            // `Acquire` synchronizes with the release of the writer
+            if self.writer.load(Ordering::Acquire) == 0 {
+                break;
+            }
+
+            // let the writer go first
+            s.readers.fetch_sub(1, Ordering::Relaxed);
+            wait_until(&self.writer, Ordering::Relaxed, |w| w == 0);
+        }
+
+        #[cfg(feature = "stats")]
+        s.reads.fetch_add(1, Ordering::Relaxed);
+
+        BigRwLockReadGuard {
+            rwlock: self,
+            slot,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// acquire writer lock
+    ///
+    /// This waits until the readers of every slot release the lock.
+    pub fn write(&self) -> BigRwLockWriteGuard<'_, T, N> {
+        let mut backoff = Backoff::new();
+        while self
+            .writer
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            wait_until(&self.writer, Ordering::Relaxed, |w| w == 0);
+            backoff.spin();
+        }
+
+        fence(Ordering::SeqCst);
+
+        // `Acquire` synchronizes with the release of the readers
+        for s in self.slots.iter() {
+            wait_until(&s.readers, Ordering::Acquire, |r| r == 0);
+        }
+
+        BigRwLockWriteGuard {
+            rwlock: self,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// acquire reader lock, call `f` with the data, and release the lock
+    ///
+    /// This is also available under loom as [`RwLock::with`].
+    pub fn with<R, F>(&self, slot: usize, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            f(&self.read(slot))
         }
+
+        #[cfg(loom)]
+        {
+            self.read(slot).with(f)
+        }
+    }
+
+    /// acquire writer lock, call `f` with the data, and release the lock
+    ///
+    /// This is also available under loom as [`RwLock::with_mut`].
+    pub fn with_mut<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        #[cfg(not(loom))]
+        {
+            f(&mut self.write())
+        }
+
+        #[cfg(loom)]
+        {
+            self.write().with_mut(f)
+        }
+    }
+
+    /// the number of readers which acquired the lock through the slot `slot % N`
+    /// since the lock was created
+    ///
+    /// Each slot counts on its own cache line, so this shows how readers are spread over the slots.
+    #[cfg(feature = "stats")]
+    pub fn slot_reads(&self, slot: usize) -> usize {
+        self.slots[slot % N].reads.load(Ordering::Relaxed)
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct BigRwLockReadGuard<'a, T: ?Sized, const N: usize> {
+    /// `BigRwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    rwlock: &'a BigRwLock<T, N>,
+    slot: usize,
+    /// `Send` and `Sync` are implemented manually
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T: ?Sized, const N: usize> BigRwLockReadGuard<'a, T, N> {
+    /// unlock read lock
+    pub fn unlock(self) {}
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.rwlock.data.with(|data| f(unsafe { &*data }))
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct BigRwLockWriteGuard<'a, T: ?Sized, const N: usize> {
+    /// `BigRwLock` contains `UnsafeCell<T>`, so the guard is invariant over `T`
+    rwlock: &'a BigRwLock<T, N>,
+    /// `Sync` is implemented manually
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T: ?Sized, const N: usize> BigRwLockWriteGuard<'a, T, N> {
+    /// unlock write lock
+    pub fn unlock(self) {}
+
+    /// call `f` with the data
+    ///
+    /// loom tracks the access to the data while `f` is running.
+    #[cfg(loom)]
+    pub fn with_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.rwlock.data.with_mut(|data| f(unsafe { &mut *data }))
+    }
+}
+
+// the same bounds as `RwLock`
+unsafe impl<T: ?Sized + Send + Sync, const N: usize> Sync for BigRwLock<T, N> {}
+unsafe impl<T: ?Sized + Send, const N: usize> Send for BigRwLock<T, N> {}
+unsafe impl<'a, T: ?Sized + Sync, const N: usize> Sync for BigRwLockReadGuard<'a, T, N> {}
+unsafe impl<'a, T: ?Sized + Sync, const N: usize> Send for BigRwLockReadGuard<'a, T, N> {}
+unsafe impl<'a, T: ?Sized + Sync, const N: usize> Sync for BigRwLockWriteGuard<'a, T, N> {}
+
+impl<T: Default, const N: usize> Default for BigRwLock<T, N> {
+    fn default() -> Self {
+        BigRwLock::new(T::default())
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, const N: usize> Deref for BigRwLockReadGuard<'a, T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, const N: usize> Deref for BigRwLockWriteGuard<'a, T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(not(loom))]
+impl<'a, T: ?Sized, const N: usize> DerefMut for BigRwLockWriteGuard<'a, T, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+/// release read lock
+impl<'a, T: ?Sized, const N: usize> Drop for BigRwLockReadGuard<'a, T, N> {
+    fn drop(&mut self) {
+        // `Release` passes the critical section to the writer
+        self.rwlock.slots[self.slot]
+            .readers
+            .fetch_sub(1, Ordering::Release);
     }
 }
 
 /// release write lock
-impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+impl<'a, T: ?Sized, const N: usize> Drop for BigRwLockWriteGuard<'a, T, N> {
     fn drop(&mut self) {
-        self.rwlock.state.store(0, Ordering::Release);
-        self.rwlock
-            .writer_wake_counter
-            .fetch_add(1, Ordering::Release);
+        self.rwlock.writer.store(0, Ordering::Release);
+    }
+}
+
+/// raw readers writer lock for the `lock_api` crate
+///
+/// This implements the same algorithm as [`RwLock`],
+/// so `lock_api::RwLock<RawSyncRwLock, T>`, that is, [`LockApiRwLock`],
+/// provides the guards of `lock_api`,
+/// e.g. mapped, upgradable, downgradable, and `Arc` guards.
+///
+/// ```rust
+/// use synctools::rwlock::LockApiRwLock as RwLock;
+///
+/// let lock = RwLock::new(0);
+/// {
+///     let r = lock.upgradable_read();
+///     let mut w = lock_api::RwLockUpgradableReadGuard::upgrade(r);
+///     *w += 1;
+/// }
+/// assert_eq!(*lock.read(), 1);
+/// ```
+///
+/// # Memory ordering
+///
+/// `lock_api` requires that acquiring the lock synchronizes with the previous release,
+/// so that the data written while holding the lock is visible to the next holder.
+/// This is met because every successful acquisition, including upgrading,
+/// is an `Acquire` CAS on `state`, and every release is a `Release` store or `fetch_sub`
+/// on `state`, as [`RwLock`].
+///
+/// An upgradable reader is a reader which also holds `upgradable`,
+/// which is acquired by `Acquire` and released by `Release`.
+/// Upgrading sets the writer waiting bit to block new readers,
+/// and waits until the upgradable reader is the only reader.
+#[cfg(all(feature = "lock_api", not(loom)))]
+pub struct RawSyncRwLock {
+    raw: RawRwLock,
+    upgradable: AtomicBool,
+}
+
+#[cfg(all(feature = "lock_api", not(loom)))]
+impl RawSyncRwLock {
+    fn lock_upgradable_flag(&self) {
+        let mut backoff = Backoff::new();
+        while !self.try_lock_upgradable_flag() {
+            backoff.snooze();
+        }
+    }
+
+    fn try_lock_upgradable_flag(&self) -> bool {
+        self.upgradable
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock_upgradable_flag(&self) {
+        self.upgradable.store(false, Ordering::Release);
+    }
+
+    /// CAS `state` to `usize::MAX` if the caller is the only reader
+    fn try_upgrade_state(&self) -> bool {
+        let mut s = self.raw.state.load(Ordering::Relaxed);
+        while s == 2 || s == 3 {
+            match self.raw.state.compare_exchange(
+                s,
+                usize::MAX,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    // the last reader leaves the state with the writer waiting bit set,
+                    // so wake the writers waiting on the counter up to wait on `state`
+                    if s == 3 {
+                        self.raw.writer_wake_counter.fetch_add(1, Ordering::Release);
+                    }
+                    return true;
+                }
+                Err(e) => s = e,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(all(feature = "lock_api", not(loom)))]
+unsafe impl lock_api::RawRwLock for RawSyncRwLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: RawSyncRwLock = RawSyncRwLock {
+        raw: RawRwLock::new(),
+        upgradable: AtomicBool::new(false),
+    };
+
+    // the lock can be released by any thread
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock_shared(&self) {
+        self.raw.lock_shared();
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.raw.try_lock_shared()
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.raw.unlock_shared();
+    }
+
+    fn lock_exclusive(&self) {
+        self.raw.lock_exclusive();
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.raw.try_lock_exclusive()
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.raw.unlock_exclusive();
+    }
+
+    fn is_locked(&self) -> bool {
+        self.raw.state.load(Ordering::Relaxed) >= 2
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.raw.state.load(Ordering::Relaxed) == usize::MAX
+    }
+}
+
+/// downgrading does not block
+///
+/// Writers waiting for the writer lock keep waiting for the downgraded reader lock,
+/// and readers waiting for the writer lock can acquire the reader lock
+/// unless a writer starts waiting in between.
+#[cfg(all(feature = "lock_api", not(loom)))]
+unsafe impl lock_api::RawRwLockDowngrade for RawSyncRwLock {
+    unsafe fn downgrade(&self) {
+        self.raw.downgrade();
+    }
+}
+
+#[cfg(all(feature = "lock_api", not(loom)))]
+unsafe impl lock_api::RawRwLockUpgrade for RawSyncRwLock {
+    fn lock_upgradable(&self) {
+        self.lock_upgradable_flag();
+        self.raw.lock_shared();
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        if !self.try_lock_upgradable_flag() {
+            return false;
+        }
+
+        if self.raw.try_lock_shared() {
+            true
+        } else {
+            self.unlock_upgradable_flag();
+            false
+        }
+    }
+
+    unsafe fn unlock_upgradable(&self) {
+        self.raw.unlock_shared();
+        self.unlock_upgradable_flag();
+    }
+
+    unsafe fn upgrade(&self) {
+        let mut backoff = Backoff::new();
+        while !self.try_upgrade_state() {
+            // block new readers, and wait for the other readers
+            let s = self.raw.state.load(Ordering::Relaxed);
+            if s & 1 == 0 {
+                let _ =
+                    self.raw
+                        .state
+                        .compare_exchange(s, s + 1, Ordering::Relaxed, Ordering::Relaxed);
+            }
+            backoff.snooze();
+        }
+
+        // other upgradable readers wait for the writer lock
+        self.unlock_upgradable_flag();
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        if self.try_upgrade_state() {
+            self.unlock_upgradable_flag();
+            true
+        } else {
+            false
+        }
     }
 }
+
+/// readers writer lock of the `lock_api` crate using [`RawSyncRwLock`]
+#[cfg(all(feature = "lock_api", not(loom)))]
+pub type LockApiRwLock<T> = lock_api::RwLock<RawSyncRwLock, T>;
+
+/// reader guard of [`LockApiRwLock`]
+#[cfg(all(feature = "lock_api", not(loom)))]
+pub type LockApiRwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawSyncRwLock, T>;
+
+/// writer guard of [`LockApiRwLock`]
+#[cfg(all(feature = "lock_api", not(loom)))]
+pub type LockApiRwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawSyncRwLock, T>;
@@ -0,0 +1,512 @@
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicIsize, AtomicU32, AtomicUsize, Ordering},
+};
+
+/// MCS lock which can be placed in memory shared between processes,
+/// or between Rust and C
+///
+/// A shared memory region can be mapped at different addresses in each process,
+/// so the queue is linked by offsets from the lock instead of pointers.
+/// Therefore, nodes must be placed in the same shared memory region as the lock,
+/// and `T` must not contain pointers.
+///
+/// # Layout
+///
+/// `ShmMCSLock<T>`
+///
+/// | offset                          | C type     | description                                         |
+/// |---------------------------------|------------|-----------------------------------------------------|
+/// | 0                               | `intptr_t` | offset of the last node from the lock, 0 if unlocked |
+/// | `ShmMCSLock::<T>::DATA_OFFSET`  | `T`        | data                                                |
+///
+/// [`ShmMCSNode`]
+///
+/// | offset               | C type     | description                                     |
+/// |----------------------|------------|-------------------------------------------------|
+/// | 0                    | `intptr_t` | offset of the next node from the lock, 0 if none |
+/// | `sizeof(intptr_t)`   | `uint32_t` | set to 1 when the lock is handed over            |
+///
+/// The protocol is the same as [`crate::mcs::RawMCSLock`].
+/// The offsets are accessed by atomic instructions,
+/// the last node is swapped by `AcqRel`, and the others are `Acquire`/`Release`.
+///
+/// # Example
+///
+/// The first process initializes the lock by [`ShmMCSLock::init_in_place`],
+/// and the others use it by [`ShmMCSLock::from_raw`].
+///
+/// ```rust
+/// use synctools::shm::{ShmMCSLock, ShmMCSNode};
+///
+/// // shared memory region
+/// let mut region = vec![0u64; 16];
+/// let base = region.as_mut_ptr() as *mut u8;
+///
+/// let lock_ptr = base as *mut ShmMCSLock<u64>;
+/// let node_ptr = unsafe { base.add(64) } as *mut ShmMCSNode;
+///
+/// // first process
+/// let lock = unsafe { ShmMCSLock::init_in_place(lock_ptr, 0) };
+/// let node = unsafe { ShmMCSNode::init_in_place(node_ptr) };
+/// *lock.lock(node) += 1;
+///
+/// // second process
+/// let lock = unsafe { ShmMCSLock::<u64>::from_raw(lock_ptr) };
+/// let node = unsafe { ShmMCSNode::init_in_place(node_ptr) };
+/// assert_eq!(*lock.lock(node), 1);
+/// ```
+#[repr(C)]
+pub struct ShmMCSLock<T> {
+    last: AtomicIsize,
+    data: UnsafeCell<T>,
+}
+
+/// node of [`ShmMCSLock`], which must be in the same shared memory region as the lock
+#[repr(C)]
+pub struct ShmMCSNode {
+    next: AtomicIsize,
+    locked: AtomicU32,
+}
+
+impl ShmMCSNode {
+    pub const fn new() -> ShmMCSNode {
+        ShmMCSNode {
+            next: AtomicIsize::new(0),
+            locked: AtomicU32::new(0),
+        }
+    }
+
+    /// initialize a node at `ptr`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes and properly aligned.
+    pub unsafe fn init_in_place<'a>(ptr: *mut ShmMCSNode) -> &'a mut ShmMCSNode {
+        ptr.write(Self::new());
+        &mut *ptr
+    }
+}
+
+impl Default for ShmMCSNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ShmMCSLock<T> {
+    /// offset of the data from the head of the lock
+    pub const DATA_OFFSET: usize = core::mem::offset_of!(ShmMCSLock<T>, data);
+
+    pub const fn new(v: T) -> ShmMCSLock<T> {
+        ShmMCSLock {
+            last: AtomicIsize::new(0),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    /// initialize a lock at `ptr`
+    ///
+    /// This must be called only by the first process mapping the region,
+    /// before the other processes use the lock.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads and writes, properly aligned,
+    /// and must outlive `'a`.
+    pub unsafe fn init_in_place<'a>(ptr: *mut ShmMCSLock<T>, v: T) -> &'a ShmMCSLock<T> {
+        ptr.write(Self::new(v));
+        &*ptr
+    }
+
+    /// use a lock initialized by [`ShmMCSLock::init_in_place`] without initialization
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an initialized lock, properly aligned,
+    /// and must outlive `'a`.
+    pub unsafe fn from_raw<'a>(ptr: *mut ShmMCSLock<T>) -> &'a ShmMCSLock<T> {
+        &*ptr
+    }
+
+    /// acquire lock
+    ///
+    /// `node` must be in the same shared memory region as the lock.
+    pub fn lock<'a>(&'a self, node: &'a mut ShmMCSNode) -> ShmMCSLockGuard<'a, T> {
+        node.next = AtomicIsize::new(0);
+        node.locked = AtomicU32::new(0);
+
+        let guard = ShmMCSLockGuard {
+            node,
+            shm_lock: self,
+            _phantom: PhantomData,
+        };
+
+        // set myself as the last node
+        let offset = self.offset_of(guard.node);
+        let prev = self.last.swap(offset, Ordering::AcqRel);
+
+        // if prev is 0 then nobody is trying to acquire lock
+        if prev == 0 {
+            return guard;
+        }
+
+        // enqueue myself
+        let prev = unsafe { self.node_at(prev) };
+        prev.next.store(offset, Ordering::Release);
+
+        // spin until other process sets locked 1
+        while guard.node.locked.load(Ordering::Acquire) == 0 {
+            spin_loop();
+        }
+
+        guard
+    }
+
+    fn offset_of(&self, node: &ShmMCSNode) -> isize {
+        (node as *const ShmMCSNode as isize).wrapping_sub(self as *const Self as isize)
+    }
+
+    unsafe fn node_at(&self, offset: isize) -> &ShmMCSNode {
+        let addr = (self as *const Self as isize).wrapping_add(offset);
+        &*(addr as *const ShmMCSNode)
+    }
+}
+
+unsafe impl<T: Send> Sync for ShmMCSLock<T> {}
+unsafe impl<T: Send> Send for ShmMCSLock<T> {}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct ShmMCSLockGuard<'a, T> {
+    node: &'a mut ShmMCSNode,
+    shm_lock: &'a ShmMCSLock<T>,
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T> ShmMCSLockGuard<'a, T> {
+    /// unlock
+    pub fn unlock(self) {}
+}
+
+impl<'a, T> Drop for ShmMCSLockGuard<'a, T> {
+    fn drop(&mut self) {
+        let mut next = self.node.next.load(Ordering::Acquire);
+
+        // if next node is 0 and self is the last node
+        // set the last node to 0
+        if next == 0 {
+            let offset = self.shm_lock.offset_of(self.node);
+            if self
+                .shm_lock
+                .last
+                .compare_exchange(offset, 0, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+
+            // other process is entering lock and wait the execution
+            loop {
+                next = self.node.next.load(Ordering::Acquire);
+                if next != 0 {
+                    break;
+                }
+                spin_loop();
+            }
+        }
+
+        // make next process executable
+        let next = unsafe { self.shm_lock.node_at(next) };
+        next.locked.store(1, Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for ShmMCSLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.shm_lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for ShmMCSLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.shm_lock.data.get() }
+    }
+}
+
+/// readers writer lock which can be placed in memory shared between processes,
+/// or between Rust and C
+///
+/// Unlike [`ShmMCSLock`], the lock consists only of counters, without pointers nor offsets,
+/// so it works as it is even if the region is mapped at different addresses in each process.
+/// `T` must not contain pointers.
+///
+/// # Layout
+///
+/// `ShmRwLock<T>`
+///
+/// | offset                                           | C type      | description                                |
+/// |--------------------------------------------------|-------------|--------------------------------------------|
+/// | `ShmRwLock::<T>::STATE_OFFSET`, that is, 0       | `uintptr_t` | state of the lock, see below               |
+/// | `ShmRwLock::<T>::WRITER_WAKE_COUNTER_OFFSET`     | `uintptr_t` | incremented when the last reader leaves    |
+/// | `ShmRwLock::<T>::DATA_OFFSET`                    | `T`         | data                                       |
+///
+/// `WRITER_WAKE_COUNTER_OFFSET` is `sizeof(uintptr_t)`.
+///
+/// # Protocol
+///
+/// The protocol is the same as [`crate::rwlock::RwLock`] without fair unlocking,
+/// so that the C side can implement it by C11 atomics.
+/// `state` is `UINTPTR_MAX` if a writer holds the lock.
+/// Otherwise, it is (the number of readers) * 2 + (1 if a writer is waiting).
+///
+/// - A reader waits while the lowest bit of `state` is 1,
+///   that is, a writer holds the lock or is waiting for it,
+///   and adds 2 to `state` by an `Acquire` CAS.
+/// - A reader releases the lock by subtracting 2 from `state` by a `Release` `fetch_sub`.
+///   If the old value is 3, that is, it is the last reader and a writer is waiting,
+///   it increments `writer_wake_counter` by a `Release` `fetch_add`.
+/// - A writer sets `state` from 0 or 1 to `UINTPTR_MAX` by an `Acquire` CAS.
+///   While readers hold the lock, it sets the lowest bit by a `Relaxed` CAS to block new readers,
+///   loads `writer_wake_counter` by `Acquire`, loads `state` again,
+///   and waits until `writer_wake_counter` changes if readers still hold the lock with the bit set.
+///   While another writer holds the lock, it waits until `state` changes.
+/// - A writer releases the lock by storing 0 to `state` by `Release`.
+///
+/// The readers acquiring the lock more than [`crate::rwlock::MAX_READERS`] times panic.
+///
+/// # Example
+///
+/// The first process initializes the lock by [`ShmRwLock::init_in_place`],
+/// and the others use it by [`ShmRwLock::from_raw`].
+///
+/// ```rust
+/// use synctools::shm::ShmRwLock;
+///
+/// // shared memory region
+/// let mut region = vec![0u64; 4];
+/// let lock_ptr = region.as_mut_ptr() as *mut ShmRwLock<u64>;
+///
+/// // first process
+/// let lock = unsafe { ShmRwLock::init_in_place(lock_ptr, 0) };
+/// *lock.write() += 1;
+///
+/// // second process
+/// let lock = unsafe { ShmRwLock::<u64>::from_raw(lock_ptr) };
+/// assert_eq!(*lock.read(), 1);
+/// ```
+#[repr(C)]
+pub struct ShmRwLock<T> {
+    state: AtomicUsize,
+    writer_wake_counter: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+impl<T> ShmRwLock<T> {
+    /// offset of `state` from the head of the lock
+    pub const STATE_OFFSET: usize = core::mem::offset_of!(ShmRwLock<T>, state);
+
+    /// offset of `writer_wake_counter` from the head of the lock
+    pub const WRITER_WAKE_COUNTER_OFFSET: usize =
+        core::mem::offset_of!(ShmRwLock<T>, writer_wake_counter);
+
+    /// offset of the data from the head of the lock
+    pub const DATA_OFFSET: usize = core::mem::offset_of!(ShmRwLock<T>, data);
+
+    pub const fn new(v: T) -> ShmRwLock<T> {
+        ShmRwLock {
+            state: AtomicUsize::new(0),
+            writer_wake_counter: AtomicUsize::new(0),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    /// initialize a lock at `ptr`
+    ///
+    /// This must be called only by the first process mapping the region,
+    /// before the other processes use the lock.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads and writes, properly aligned,
+    /// and must outlive `'a`.
+    pub unsafe fn init_in_place<'a>(ptr: *mut ShmRwLock<T>, v: T) -> &'a ShmRwLock<T> {
+        ptr.write(Self::new(v));
+        &*ptr
+    }
+
+    /// use a lock initialized by [`ShmRwLock::init_in_place`] without initialization
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an initialized lock, properly aligned,
+    /// and must outlive `'a`.
+    pub unsafe fn from_raw<'a>(ptr: *mut ShmRwLock<T>) -> &'a ShmRwLock<T> {
+        &*ptr
+    }
+
+    /// acquire reader lock
+    pub fn read(&self) -> ShmRwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+
+            // wait while a writer holds the lock or is waiting for it
+            while self.state.load(Ordering::Relaxed) & 1 == 1 {
+                spin_loop();
+            }
+        }
+    }
+
+    /// try to acquire reader lock
+    ///
+    /// This fails if a writer holds the lock or is waiting for it.
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_read(&self) -> Option<ShmRwLockReadGuard<'_, T>> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s & 1 == 0 {
+            crate::rwlock::check_readers(s);
+            match self
+                .state
+                .compare_exchange_weak(s, s + 2, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    return Some(ShmRwLockReadGuard {
+                        shm_lock: self,
+                        _phantom: PhantomData,
+                    })
+                }
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    /// acquire writer lock
+    pub fn write(&self) -> ShmRwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+
+            let s = self.state.load(Ordering::Relaxed);
+            if s == usize::MAX {
+                // wait for the other writer
+                while self.state.load(Ordering::Relaxed) == usize::MAX {
+                    spin_loop();
+                }
+                continue;
+            }
+
+            // block new readers
+            if s & 1 == 0
+                && self
+                    .state
+                    .compare_exchange(s, s + 1, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_err()
+            {
+                continue;
+            }
+
+            // if the last reader leaves after this load while the bit is set,
+            // it increments the counter
+            let counter = self.writer_wake_counter.load(Ordering::Acquire);
+            let s = self.state.load(Ordering::Relaxed);
+            if s > 1 && s & 1 == 1 && s != usize::MAX {
+                while self.writer_wake_counter.load(Ordering::Acquire) == counter {
+                    spin_loop();
+                }
+            }
+        }
+    }
+
+    /// try to acquire writer lock
+    #[must_use = "the lock is released when the guard is dropped"]
+    pub fn try_write(&self) -> Option<ShmRwLockWriteGuard<'_, T>> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s <= 1 {
+            match self
+                .state
+                .compare_exchange(s, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    return Some(ShmRwLockWriteGuard {
+                        shm_lock: self,
+                        _phantom: PhantomData,
+                    })
+                }
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for ShmRwLock<T> {}
+unsafe impl<T: Send> Send for ShmRwLock<T> {}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct ShmRwLockReadGuard<'a, T> {
+    shm_lock: &'a ShmRwLock<T>,
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T> ShmRwLockReadGuard<'a, T> {
+    /// unlock
+    pub fn unlock(self) {}
+}
+
+impl<'a, T> Drop for ShmRwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        // the last reader wakes up the waiting writer
+        if self.shm_lock.state.fetch_sub(2, Ordering::Release) == 3 {
+            self.shm_lock
+                .writer_wake_counter
+                .fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+impl<'a, T> Deref for ShmRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.shm_lock.data.get() }
+    }
+}
+
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct ShmRwLockWriteGuard<'a, T> {
+    shm_lock: &'a ShmRwLock<T>,
+    _phantom: PhantomData<*mut ()>,
+}
+
+impl<'a, T> ShmRwLockWriteGuard<'a, T> {
+    /// unlock
+    pub fn unlock(self) {}
+}
+
+impl<'a, T> Drop for ShmRwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.shm_lock.state.store(0, Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for ShmRwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.shm_lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for ShmRwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.shm_lock.data.get() }
+    }
+}
@@ -0,0 +1,164 @@
+//! stack with the same API on every architecture
+//!
+//! [`Stack`] is lock-free where the LL/SC version of [`crate::lfstack::LFStack`] is available,
+//! and is a `Vec` protected by [`crate::mcs::MCSLock`] otherwise,
+//! so portable code can use a single type without `cfg`.
+//!
+//! | backend   | targets                                            | progress                                            |
+//! |-----------|----------------------------------------------------|-----------------------------------------------------|
+//! | `LFStack` | AArch64, except under Miri or `force-locked-stack` | lock-free, some thread always completes its operation |
+//! | `MCSLock` | the others                                         | blocking, a preempted holder blocks the others, but waiters are served in FIFO order |
+//!
+//! [`BACKEND`] tells which backend a build uses.
+//!
+//! ```rust
+//! use synctools::stack::Stack;
+//! use std::sync::Arc;
+//!
+//! let stack = Arc::new(Stack::new());
+//!
+//! let stack0 = stack.clone();
+//! std::thread::spawn(move || {
+//!     for i in 0..3 {
+//!         stack0.push(i);
+//!     }
+//! })
+//! .join()
+//! .unwrap();
+//!
+//! assert_eq!(stack.len(), 3);
+//! assert_eq!(stack.pop(), Some(2));
+//! ```
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(miri, feature = "force-locked-stack"))
+))]
+use crate::lfstack::LFStack;
+
+#[cfg(not(all(
+    target_arch = "aarch64",
+    not(any(miri, feature = "force-locked-stack"))
+)))]
+use {
+    crate::mcs::{MCSLock, MCSNode},
+    alloc::vec::Vec,
+};
+
+/// the backend of [`Stack`] in this build, `"lfstack"` or `"mcs"`
+#[cfg(all(
+    target_arch = "aarch64",
+    not(any(miri, feature = "force-locked-stack"))
+))]
+pub const BACKEND: &str = "lfstack";
+
+/// the backend of [`Stack`] in this build, `"lfstack"` or `"mcs"`
+#[cfg(not(all(
+    target_arch = "aarch64",
+    not(any(miri, feature = "force-locked-stack"))
+)))]
+pub const BACKEND: &str = "mcs";
+
+/// stack which is lock-free on AArch64, and protected by `MCSLock` on the others
+///
+/// This is `Send` and `Sync` only if `T: Send`, whichever the backend is.
+pub struct Stack<T> {
+    #[cfg(all(
+        target_arch = "aarch64",
+        not(any(miri, feature = "force-locked-stack"))
+    ))]
+    inner: LFStack<T>,
+    #[cfg(not(all(
+        target_arch = "aarch64",
+        not(any(miri, feature = "force-locked-stack"))
+    )))]
+    inner: MCSLock<Vec<T>>,
+    /// incremented before a push and decremented after a pop, so it never underflows
+    len: AtomicUsize,
+}
+
+impl<T> Stack<T> {
+    pub const fn new() -> Stack<T> {
+        Stack {
+            #[cfg(all(
+                target_arch = "aarch64",
+                not(any(miri, feature = "force-locked-stack"))
+            ))]
+            inner: LFStack::new(),
+            #[cfg(not(all(
+                target_arch = "aarch64",
+                not(any(miri, feature = "force-locked-stack"))
+            )))]
+            inner: MCSLock::new(Vec::new()),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn push(&self, v: T) {
+        self.len.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(all(
+            target_arch = "aarch64",
+            not(any(miri, feature = "force-locked-stack"))
+        ))]
+        self.inner.push(v);
+
+        #[cfg(not(all(
+            target_arch = "aarch64",
+            not(any(miri, feature = "force-locked-stack"))
+        )))]
+        {
+            let mut node = MCSNode::new();
+            self.inner.lock(&mut node).push(v);
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        #[cfg(all(
+            target_arch = "aarch64",
+            not(any(miri, feature = "force-locked-stack"))
+        ))]
+        let v = self.inner.pop();
+
+        #[cfg(not(all(
+            target_arch = "aarch64",
+            not(any(miri, feature = "force-locked-stack"))
+        )))]
+        let v = {
+            let mut node = MCSNode::new();
+            let v = self.inner.lock(&mut node).pop();
+            v
+        };
+
+        if v.is_some() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        v
+    }
+
+    /// the number of values
+    ///
+    /// While other threads push or pop, this is only a hint,
+    /// which may include the values being pushed.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// true if [`Stack::len`] is 0
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// both backends are `Send` and `Sync` if `T: Send`,
+// and the bounds are written here so that they do not depend on the backend
+unsafe impl<T: Send> Sync for Stack<T> {}
+unsafe impl<T: Send> Send for Stack<T> {}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
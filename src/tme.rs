@@ -0,0 +1,101 @@
+//! Transactional Memory Extension (TME) of AArch64
+//!
+//! This is used by [`crate::mcs::RawMCSLock`] to elide locks.
+//! The instructions are emitted by their encodings,
+//! because the assembler of LLVM does not support TME.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(target_arch = "aarch64")]
+use core::arch::asm;
+
+const UNKNOWN: u8 = 0;
+const UNAVAILABLE: u8 = 1;
+const AVAILABLE: u8 = 2;
+
+static TME: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// true if the CPU supports TME
+///
+/// This reads the TME field of `ID_AA64ISAR0_EL1` at the first call.
+/// At EL0, the OS must emulate the access to the ID registers, as Linux does.
+/// This always returns false on other architectures.
+pub fn is_available() -> bool {
+    match TME.load(Ordering::Relaxed) {
+        UNKNOWN => {
+            let available = detect();
+            let state = if available { AVAILABLE } else { UNAVAILABLE };
+            TME.store(state, Ordering::Relaxed);
+            available
+        }
+        state => state == AVAILABLE,
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect() -> bool {
+    let isar0: u64;
+    unsafe { asm!("mrs {}, ID_AA64ISAR0_EL1", out(reg) isar0, options(nomem, nostack)) };
+    (isar0 >> 24) & 0xf != 0
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn detect() -> bool {
+    false
+}
+
+/// the transaction may succeed if retried
+#[cfg(target_arch = "aarch64")]
+pub(crate) const STATUS_RTRY: u64 = 1 << 15;
+
+/// reason of `tcancel` when the lock is held by another thread,
+/// retrying is useless until the holder releases it
+#[cfg(target_arch = "aarch64")]
+pub(crate) const REASON_LOCKED: u16 = 1;
+
+/// start a transaction (`tstart x0`)
+///
+/// Returns `Ok(())` in the transaction.
+/// If the transaction is aborted, the execution is restarted from here
+/// and `Err(status)` is returned.
+///
+/// # Safety
+///
+/// TME must be available.
+/// The transaction must be committed by [`tcommit`] or canceled by [`tcancel`].
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) unsafe fn tstart() -> Result<(), u64> {
+    let status: u64;
+    asm!(".inst 0xd5233060", out("x0") status, options(nostack));
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(status)
+    }
+}
+
+/// commit the transaction (`tcommit`)
+///
+/// # Safety
+///
+/// This must be called in a transaction.
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) unsafe fn tcommit() {
+    asm!(".inst 0xd503307f", options(nostack));
+}
+
+/// cancel the transaction (`tcancel #reason`)
+///
+/// The execution is restarted from [`tstart`],
+/// which returns the status containing `REASON`.
+///
+/// # Safety
+///
+/// This must be called in a transaction.
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) unsafe fn tcancel<const REASON: u16>() -> ! {
+    asm!(".inst 0xd4600000 | ({reason} << 5)", reason = const REASON, options(noreturn, nostack));
+}
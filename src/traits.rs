@@ -0,0 +1,59 @@
+use core::ops::DerefMut;
+
+/// exclusive lock which can be acquired without arguments
+///
+/// This abstracts the locks of this crate,
+/// so that data structures can be generic over the locking strategy.
+///
+/// - [`crate::rwlock::RwLock`] is acquired by the writer lock.
+/// - [`crate::mcs::MCSMutex`] allocates the node of [`crate::mcs::MCSLock`]
+///   in the guard, which needs the `alloc` feature.
+///
+/// ```rust
+/// use synctools::{rwlock::RwLock, traits::Lock};
+///
+/// fn increment<L: Lock<Data = usize>>(lock: &L) {
+///     *lock.lock() += 1;
+/// }
+///
+/// let lock = RwLock::new(0);
+/// increment(&lock);
+/// assert_eq!(*lock.read(), 1);
+/// ```
+pub trait Lock {
+    /// the type of the protected data
+    type Data: ?Sized;
+
+    /// the guard releasing the lock when it is dropped
+    type Guard<'a>: DerefMut<Target = Self::Data>
+    where
+        Self: 'a;
+
+    /// acquire the lock exclusively
+    fn lock(&self) -> Self::Guard<'_>;
+}
+
+impl<T: ?Sized, P, H: crate::rwlock::RwHook> Lock for crate::rwlock::RwLock<T, P, H> {
+    type Data = T;
+    type Guard<'a>
+        = crate::rwlock::RwLockWriteGuard<'a, T, P, H>
+    where
+        Self: 'a;
+
+    fn lock(&self) -> Self::Guard<'_> {
+        self.write()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Lock for crate::mcs::MCSMutex<T> {
+    type Data = T;
+    type Guard<'a>
+        = crate::mcs::MCSMutexGuard<'a, T>
+    where
+        T: 'a;
+
+    fn lock(&self) -> Self::Guard<'_> {
+        crate::mcs::MCSMutex::lock(self)
+    }
+}
@@ -0,0 +1,141 @@
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+/// pad and align a value to the length of a cache line
+///
+/// This prevents false sharing between values which are accessed by different cores,
+/// e.g. locks in an array.
+/// The alignment is 128 bytes on x86_64 and AArch64,
+/// because their prefetchers fetch a pair of 64 bytes cache lines,
+/// and 64 bytes on other architectures.
+/// The `cache_line_64` feature makes it 64 bytes on every architecture,
+/// which saves memory on cores whose prefetchers fetch a single line.
+///
+/// `T` can be a dynamically sized type, which is made by unsized coercion.
+///
+/// ```rust
+/// use synctools::{mcs::MCSLock, util::CachePadded};
+///
+/// // each lock is placed on its own cache line
+/// let locks = [CachePadded::new(MCSLock::new(0)), CachePadded::new(MCSLock::new(0))];
+/// ```
+#[cfg_attr(
+    all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        not(feature = "cache_line_64")
+    ),
+    repr(align(128))
+)]
+#[cfg_attr(
+    not(all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        not(feature = "cache_line_64")
+    )),
+    repr(align(64))
+)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CachePadded<T: ?Sized> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> CachePadded<T> {
+        CachePadded { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: ?Sized> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T: ?Sized> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+/// error returned by [`check_platform`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlatformError {
+    missing: &'static str,
+}
+
+impl PlatformError {
+    /// the atomic type which the target does not support with compare-and-swap
+    pub fn missing(&self) -> &'static str {
+        self.missing
+    }
+}
+
+impl fmt::Display for PlatformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unsupported platform: {} is not a lock-free atomic with compare-and-swap",
+            self.missing
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PlatformError {}
+
+/// check that the target provides the lock-free atomics which this crate requires
+///
+/// The locks of this crate use `AtomicBool`, `AtomicU8`, `AtomicU32`, `AtomicUsize`,
+/// `AtomicIsize`, and `AtomicPtr` with compare-and-swap.
+/// The atomic types of `core` are always lock-free.
+/// If the target cannot operate on them atomically, they are not provided
+/// instead of falling back to a hidden lock, and this crate fails to build.
+/// So a build of this crate always passes this check,
+/// which confirms the requirement explicitly,
+/// e.g. before deploying the locks to a new target.
+///
+/// This is a `const fn`, so it can be checked at compile time.
+///
+/// ```rust
+/// use synctools::util::check_platform;
+///
+/// const _: () = assert!(check_platform().is_ok());
+///
+/// if let Err(e) = check_platform() {
+///     panic!("{}", e);
+/// }
+/// ```
+pub const fn check_platform() -> Result<(), PlatformError> {
+    if !cfg!(target_has_atomic = "8") {
+        return Err(PlatformError {
+            missing: "AtomicU8",
+        });
+    }
+
+    if !cfg!(target_has_atomic = "32") {
+        return Err(PlatformError {
+            missing: "AtomicU32",
+        });
+    }
+
+    if !cfg!(target_has_atomic = "ptr") {
+        return Err(PlatformError {
+            missing: "AtomicUsize",
+        });
+    }
+
+    Ok(())
+}
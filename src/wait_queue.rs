@@ -0,0 +1,180 @@
+//! intrusive queue of tasks for the asynchronous locks
+
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+};
+
+/// spin lock held only while a queue is modified
+pub(crate) struct SpinLocked<T> {
+    lock: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+impl<T> SpinLocked<T> {
+    pub(crate) const fn new(v: T) -> SpinLocked<T> {
+        SpinLocked {
+            lock: AtomicBool::new(false),
+            data: UnsafeCell::new(v),
+        }
+    }
+
+    /// modify the data exclusively
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.lock.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+
+        let result = f(unsafe { &mut *self.data.get() });
+        self.lock.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl<T: Send> Sync for SpinLocked<T> {}
+unsafe impl<T: Send> Send for SpinLocked<T> {}
+
+/// node of [`WaitList`], which is embedded in a pinned future
+pub(crate) struct Waiter {
+    next: *mut Waiter,
+    prev: *mut Waiter,
+    pub(crate) waker: Option<Waker>,
+    pub(crate) state: WaiterState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WaiterState {
+    /// not queued
+    Idle,
+
+    /// queued and waiting for a wake-up
+    Waiting,
+
+    /// removed from the queue and woken, but the future is not polled yet
+    Notified,
+
+    /// the future is resolved
+    Done,
+}
+
+impl Waiter {
+    pub(crate) const fn new() -> Waiter {
+        Waiter {
+            next: null_mut(),
+            prev: null_mut(),
+            waker: None,
+            state: WaiterState::Idle,
+        }
+    }
+
+    /// store `waker` unless the same task is already stored
+    pub(crate) fn register(&mut self, waker: &Waker) {
+        match &self.waker {
+            Some(w) if w.will_wake(waker) => (),
+            _ => self.waker = Some(waker.clone()),
+        }
+    }
+}
+
+/// doubly linked list of waiters in FIFO order
+///
+/// The waiters must not be moved or dropped while they are in the list.
+pub(crate) struct WaitList {
+    head: *mut Waiter,
+    tail: *mut Waiter,
+}
+
+// the waiters are accessed only while the spin lock is held
+unsafe impl Send for WaitList {}
+
+impl WaitList {
+    pub(crate) const fn new() -> WaitList {
+        WaitList {
+            head: null_mut(),
+            tail: null_mut(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// append `waiter` with the waker of the task, and set its state to `Waiting`
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must be valid and not in any list.
+    pub(crate) unsafe fn push_back(&mut self, waiter: *mut Waiter, waker: &Waker) {
+        (*waiter).register(waker);
+        (*waiter).next = null_mut();
+        (*waiter).prev = self.tail;
+        (*waiter).state = WaiterState::Waiting;
+
+        if self.tail.is_null() {
+            self.head = waiter;
+        } else {
+            (*self.tail).next = waiter;
+        }
+        self.tail = waiter;
+    }
+
+    /// remove `waiter` and set its state to `Idle`
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must be in this list.
+    pub(crate) unsafe fn remove(&mut self, waiter: *mut Waiter) {
+        let next = (*waiter).next;
+        let prev = (*waiter).prev;
+
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+
+        if next.is_null() {
+            self.tail = prev;
+        } else {
+            (*next).prev = prev;
+        }
+
+        (*waiter).state = WaiterState::Idle;
+    }
+
+    /// remove the first waiter, set its state to `Notified`, and return its waker
+    ///
+    /// The waker should be woken after the spin lock is released.
+    /// Returns `None` if the list is empty.
+    pub(crate) fn notify_one(&mut self) -> Option<Waker> {
+        let head = self.head;
+        if head.is_null() {
+            return None;
+        }
+
+        unsafe {
+            self.remove(head);
+            (*head).state = WaiterState::Notified;
+            (*head).waker.take()
+        }
+    }
+
+    /// notify all waiters
+    ///
+    /// The wakers are woken while the spin lock is held,
+    /// because there is no room to keep them without an allocator.
+    pub(crate) fn notify_all(&mut self) {
+        while let Some(waker) = self.notify_one() {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// a counter to wait for a set of workers to finish
+///
+/// `wait` spins until the counter reaches zero.
+pub struct WaitGroup {
+    count: AtomicUsize,
+}
+
+impl WaitGroup {
+    pub const fn new() -> WaitGroup {
+        WaitGroup {
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// add `n` to the counter
+    pub fn add(&self, n: usize) {
+        self.count.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// decrement the counter
+    ///
+    /// # Panics
+    ///
+    /// Panics if the counter is already zero.
+    /// The counter is not changed in that case,
+    /// so `wait` still returns if the panic is caught.
+    pub fn done(&self) {
+        let r = self
+            .count
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |n| n.checked_sub(1));
+        assert!(r.is_ok(), "WaitGroup::done: the counter is already zero");
+    }
+
+    /// spin until the counter reaches zero
+    pub fn wait(&self) {
+        while self.count.load(Ordering::Acquire) != 0 {
+            spin_loop();
+        }
+    }
+
+    /// the current value of the counter
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// increment the counter and return a handle which decrements it when dropped
+    pub fn worker(&self) -> Worker<'_> {
+        self.add(1);
+        Worker { wg: self }
+    }
+}
+
+impl Default for WaitGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// a handle of [`WaitGroup`], which calls `done` when dropped
+///
+/// Cloning a worker increments the counter.
+#[must_use = "the worker is done when it is dropped"]
+pub struct Worker<'a> {
+    wg: &'a WaitGroup,
+}
+
+impl<'a> Worker<'a> {
+    /// finish the work
+    pub fn done(self) {}
+}
+
+impl<'a> Clone for Worker<'a> {
+    fn clone(&self) -> Self {
+        self.wg.worker()
+    }
+}
+
+impl<'a> Drop for Worker<'a> {
+    fn drop(&mut self) {
+        self.wg.done();
+    }
+}
@@ -0,0 +1,14 @@
+/// check that misuses of the locks are rejected at compile time
+///
+/// The expected errors are in `tests/ui/*.stderr`,
+/// which are updated by `TRYBUILD=overwrite cargo test --test compile_fail`.
+/// The types in `tests/ui/alloc` are checked with the `alloc` feature.
+#[cfg(not(loom))]
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+
+    #[cfg(feature = "alloc")]
+    t.compile_fail("tests/ui/alloc/*.rs");
+}
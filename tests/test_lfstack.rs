@@ -0,0 +1,137 @@
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_lfstack --release`
+#[cfg(loom)]
+#[test]
+fn model_check_lfstack() {
+    loom::model(|| {
+        use loom::thread;
+        use std::sync::Arc;
+        use synctools::lfstack::LFStack;
+
+        let stack = Arc::new(LFStack::<usize>::new());
+        let num_producers = 2;
+
+        let producers: Vec<_> = (0..num_producers)
+            .map(|i| {
+                let stack = stack.clone();
+                thread::spawn(move || stack.push(i))
+            })
+            .collect();
+
+        let consumer = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                let mut popped = Vec::new();
+                for _ in 0..num_producers {
+                    if let Some(v) = stack.pop() {
+                        popped.push(v);
+                    }
+                }
+                popped
+            })
+        };
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut popped = consumer.join().unwrap();
+
+        while let Some(v) = stack.pop() {
+            popped.push(v);
+        }
+
+        // every node is popped exactly once
+        popped.sort();
+        assert_eq!(popped, (0..num_producers).collect::<Vec<_>>());
+    });
+}
+
+/// concurrent consumers, whose `pop` protects the head by a hazard pointer
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_lfstack --release`
+#[cfg(loom)]
+#[test]
+fn model_check_lfstack_consumers() {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        use loom::thread;
+        use std::sync::Arc;
+        use synctools::lfstack::LFStack;
+
+        let stack = Arc::new(LFStack::<usize>::new());
+        stack.push(0);
+        stack.push(1);
+
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let stack = stack.clone();
+                thread::spawn(move || stack.pop())
+            })
+            .collect();
+
+        // a producer recycles a popped node
+        {
+            let stack = stack.clone();
+            stack.push(2);
+        }
+
+        let mut popped: Vec<_> = consumers
+            .into_iter()
+            .filter_map(|c| c.join().unwrap())
+            .collect();
+
+        while let Some(v) = stack.pop() {
+            popped.push(v);
+        }
+
+        // every node is popped exactly once
+        popped.sort();
+        assert_eq!(popped, vec![0, 1, 2]);
+    });
+}
+
+/// a consumer drains the stack while a consumer pops and a producer pushes
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_lfstack --release`
+#[cfg(loom)]
+#[test]
+fn model_check_lfstack_pop_all() {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        use loom::thread;
+        use std::sync::Arc;
+        use synctools::lfstack::LFStack;
+
+        let stack = Arc::new(LFStack::<usize>::new());
+        stack.push(0);
+
+        let drainer = {
+            let stack = stack.clone();
+            thread::spawn(move || stack.pop_all().collect::<Vec<_>>())
+        };
+
+        let consumer = {
+            let stack = stack.clone();
+            thread::spawn(move || stack.pop())
+        };
+
+        stack.push(1);
+
+        let mut popped = drainer.join().unwrap();
+        popped.extend(consumer.join().unwrap());
+        popped.extend(stack.pop_all());
+
+        // every node is popped exactly once
+        popped.sort();
+        assert_eq!(popped, vec![0, 1]);
+    });
+}
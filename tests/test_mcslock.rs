@@ -40,3 +40,142 @@ fn model_check_mcslock() {
         assert_eq!(num_threads * num_iterations, data);
     });
 }
+
+/// Checks that the critical section of a holder happens before
+/// the critical section of the waiter which the lock is handed over to.
+///
+/// The waiter spins on `locked` by `Acquire` loads (A3 in `src/mcs.rs`).
+/// This fails if they are weakened to `Relaxed` without an `Acquire` fence after the spin,
+/// because the read of the data races with the write by the previous holder.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_mcslock --release`
+#[cfg(loom)]
+#[test]
+fn model_check_mcslock_handoff() {
+    use loom::sync::Arc;
+    use synctools::mcs::{MCSLock, MCSNode};
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let lock = Arc::new(MCSLock::new(0));
+
+        let lock0 = lock.clone();
+        let writer = loom::thread::spawn(move || {
+            let mut node = MCSNode::new();
+            let mut guard = lock0.lock(&mut node);
+            guard.with_mut(|data| unsafe { *data = 1 });
+        });
+
+        // the lock may be handed over from the writer, or the other way around
+        let mut node = MCSNode::new();
+        let data = lock.lock(&mut node).with_mut(|data| unsafe { *data });
+        assert!(data == 0 || data == 1);
+
+        writer.join().unwrap();
+    });
+}
+
+/// Checks that a waiter of `MCSLockCancelable` which leaves the queue
+/// while the lock is handed over either gets the lock or unlinks itself,
+/// and the waiter behind it still gets the lock.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_mcslock --release`
+#[cfg(loom)]
+#[test]
+fn model_check_mcslock_cancelable_handoff() {
+    use loom::sync::Arc;
+    use synctools::mcs::{MCSLockCancelable, MCSNodeCancelable};
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(2);
+
+    builder.check(|| {
+        let lock = Arc::new(MCSLockCancelable::new(0));
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let lock = lock.clone();
+                loom::thread::spawn(move || {
+                    let mut node = MCSNodeCancelable::new();
+                    let mut guard = lock.lock(&mut node);
+                    guard.with_mut(|data| unsafe { *data += 1 });
+                })
+            })
+            .collect();
+
+        // leave at the first wait
+        let mut node = MCSNodeCancelable::new();
+        let acquired = match lock.try_lock_for(&mut node, 0) {
+            Some(mut guard) => {
+                guard.with_mut(|data| unsafe { *data += 1 });
+                1
+            }
+            None => 0,
+        };
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut node = MCSNodeCancelable::new();
+        let data = lock.lock(&mut node).with_mut(|data| unsafe { *data });
+        assert_eq!(data, 2 + acquired);
+    });
+}
+
+/// Checks that adjacent waiters of `MCSLockCancelable` can leave the queue at the same time
+/// while the holder hands over the lock.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_mcslock --release`
+#[cfg(loom)]
+#[test]
+fn model_check_mcslock_cancelable_adjacent() {
+    use loom::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use synctools::mcs::{MCSLockCancelable, MCSNodeCancelable};
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(2);
+
+    builder.check(|| {
+        let lock = Arc::new(MCSLockCancelable::new(0));
+        let acquired = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let lock = lock.clone();
+                let acquired = acquired.clone();
+                loom::thread::spawn(move || {
+                    let mut node = MCSNodeCancelable::new();
+                    if let Some(mut guard) = lock.try_lock_for(&mut node, 0) {
+                        guard.with_mut(|data| unsafe { *data += 1 });
+                        acquired.fetch_add(1, Ordering::Relaxed);
+                    };
+                })
+            })
+            .collect();
+
+        let mut node = MCSNodeCancelable::new();
+        let mut guard = lock.lock(&mut node);
+        guard.with_mut(|data| unsafe { *data += 1 });
+        drop(guard);
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut node = MCSNodeCancelable::new();
+        let data = lock.lock(&mut node).with_mut(|data| unsafe { *data });
+        assert_eq!(data, 1 + acquired.load(Ordering::Relaxed));
+    });
+}
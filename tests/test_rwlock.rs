@@ -20,8 +20,7 @@ fn test_rwlock() {
             let n0 = n.clone();
             let t = loom::thread::spawn(move || {
                 for _ in 0..num_iterations {
-                    let r = n0.read();
-                    let data = r.with(|data| unsafe { *data });
+                    let data = n0.with(|data| *data);
                     assert_eq!(data, 0);
                 }
             });
@@ -33,8 +32,7 @@ fn test_rwlock() {
             let n0 = n.clone();
             let t = loom::thread::spawn(move || {
                 for _ in 0..num_iterations {
-                    let mut r = n0.write();
-                    r.with_mut(|data| unsafe {
+                    n0.with_mut(|data| {
                         *data += 1;
                         *data -= 1;
                     });
@@ -53,3 +51,444 @@ fn test_rwlock() {
         }
     });
 }
+
+/// Checks that a reader and a writer see each other's accesses to the data.
+/// The data is accessed through `loom::cell::UnsafeCell`,
+/// so loom reports an error if a critical section does not happen before the next one.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_data() {
+    use loom::sync::Arc;
+    use synctools::rwlock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(rwlock::RwLock::new(0));
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            n0.with_mut(|data| *data = 1);
+        });
+
+        let data = n.with(|data| *data);
+        assert!(data == 0 || data == 1);
+
+        writer.join().unwrap();
+
+        assert_eq!(n.with(|data| *data), 1);
+    });
+}
+
+/// Checks that a reader which acquires the lock handed over by `unlock_fair`
+/// sees the data written by the writer,
+/// and that the writer acquires the lock again after the reader.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_unlock_fair() {
+    use loom::sync::Arc;
+    use synctools::rwlock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(rwlock::RwLock::new(0));
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            for _ in 0..2 {
+                let mut w = n0.write();
+                w.with_mut(|data| *data += 1);
+                w.unlock_fair();
+            }
+        });
+
+        let data = n.with(|data| *data);
+        assert!(data <= 2);
+
+        writer.join().unwrap();
+
+        assert_eq!(n.with(|data| *data), 2);
+        assert_eq!(n.reader_count(), 0);
+    });
+}
+
+/// Checks that no writer misses its wake-up while it waits for another writer,
+/// which releases the lock without incrementing `writer_wake_counter`.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_writer_wake() {
+    use loom::sync::Arc;
+    use synctools::rwlock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(rwlock::RwLock::new(0));
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            for _ in 0..2 {
+                n0.with_mut(|data| *data += 1);
+            }
+        });
+
+        n.with_mut(|data| *data += 1);
+        let data = n.with(|data| *data);
+        assert!((1..=3).contains(&data));
+
+        writer.join().unwrap();
+
+        assert_eq!(n.with(|data| *data), 3);
+    });
+}
+
+/// Checks that a writer waiting for readers is not stalled on `writer_wake_counter`
+/// while readers keep entering and leaving the lock.
+/// With [`ReaderPreferred`](synctools::rwlock::ReaderPreferred),
+/// the readers enter even while the writer waiting bit is set,
+/// so they race with the writer between its load of the counter and its reload of `state`.
+/// A lost wake-up makes the writer spin forever, which loom reports.
+///
+/// The readers only try the lock, because loom may keep scheduling
+/// two threads spinning on the writer instead of the writer itself.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_writer_churning_readers() {
+    use loom::sync::Arc;
+    use synctools::rwlock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(rwlock::RwLock::<_, rwlock::ReaderPreferred>::with_policy(0));
+
+        let mut readers = Vec::new();
+        for _ in 0..2 {
+            let n0 = n.clone();
+            readers.push(loom::thread::spawn(move || {
+                for _ in 0..2 {
+                    if let Some(guard) = n0.try_read_for(0) {
+                        let data = guard.with(|data| *data);
+                        assert!(data == 0 || data == 1);
+                    }
+                }
+            }));
+        }
+
+        n.with_mut(|data| *data += 1);
+
+        for r in readers {
+            r.join().unwrap();
+        }
+
+        assert_eq!(n.with(|data| *data), 1);
+    });
+}
+
+/// Checks that a reader and writers of the phase-fair lock exclude each other,
+/// and that the phase bit lets the reader pass between two writer phases.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_phase_fair() {
+    use loom::sync::Arc;
+    use synctools::rwlock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(rwlock::PhaseFairRwLock::new(0));
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            for _ in 0..2 {
+                n0.with_mut(|data| {
+                    *data += 1;
+                    *data += 1;
+                });
+            }
+        });
+
+        let data = n.with(|data| *data);
+        assert!(data == 0 || data == 2 || data == 4);
+
+        writer.join().unwrap();
+
+        assert_eq!(n.with(|data| *data), 4);
+    });
+}
+
+/// Checks that a reader and a writer of the queue lock exclude each other,
+/// and that a writer behind a reader is woken up by the reader.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_queue() {
+    use loom::sync::Arc;
+    use synctools::rwlock::{QueueRwLock, QueueRwNode};
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(QueueRwLock::new(0));
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            let mut node = QueueRwNode::new();
+            for _ in 0..2 {
+                n0.with_mut(&mut node, |data| {
+                    *data += 1;
+                    *data += 1;
+                });
+            }
+        });
+
+        let mut node = QueueRwNode::new();
+        let data = n.with(&mut node, |data| *data);
+        assert!(data == 0 || data == 2 || data == 4);
+
+        writer.join().unwrap();
+
+        assert_eq!(n.with(&mut node, |data| *data), 4);
+    });
+}
+
+/// Checks that readers queued behind a writer enter together,
+/// that is, the reader woken up by the writer wakes up the reader behind it,
+/// so that both hold the lock at the same time.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_queue_reader_batch() {
+    use loom::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use synctools::rwlock::{QueueRwLock, QueueRwNode};
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(2);
+
+    builder.check(|| {
+        let n = Arc::new(QueueRwLock::new(0));
+        let entered = Arc::new(AtomicUsize::new(0));
+
+        let mut node = QueueRwNode::new();
+        let mut guard = n.write(&mut node);
+
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let n0 = n.clone();
+                let entered0 = entered.clone();
+                loom::thread::spawn(move || {
+                    let mut node = QueueRwNode::new();
+                    let r = n0.read(&mut node);
+                    entered0.fetch_add(1, Ordering::Relaxed);
+
+                    // wait for the other reader, which holds the lock at the same time
+                    while entered0.load(Ordering::Relaxed) < 2 {
+                        loom::thread::yield_now();
+                    }
+                    r.with(|data| *data)
+                })
+            })
+            .collect();
+
+        guard.with_mut(|data| *data += 1);
+        drop(guard);
+
+        for r in readers {
+            assert_eq!(r.join().unwrap(), 1);
+        }
+
+        n.with_mut(&mut node, |data| *data += 1);
+        assert_eq!(n.with(&mut node, |data| *data), 2);
+    });
+}
+
+/// Checks that readers and a writer mixed in the queue all acquire the lock,
+/// that is, a writer queued behind a reader holding the lock is not starved by readers,
+/// and a reader queued behind the writer is woken up after it.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_queue_mix() {
+    use loom::sync::Arc;
+    use synctools::rwlock::{QueueRwLock, QueueRwNode};
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(2);
+
+    builder.check(|| {
+        let n = Arc::new(QueueRwLock::new(0));
+
+        let mut node = QueueRwNode::new();
+        let r = n.read(&mut node);
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            let mut node = QueueRwNode::new();
+            n0.with_mut(&mut node, |data| *data += 2);
+        });
+
+        let n0 = n.clone();
+        let reader = loom::thread::spawn(move || {
+            let mut node = QueueRwNode::new();
+            n0.with(&mut node, |data| *data)
+        });
+
+        assert_eq!(r.with(|data| *data), 0);
+        drop(r);
+
+        let data = reader.join().unwrap();
+        assert!(data == 0 || data == 2);
+        writer.join().unwrap();
+
+        assert_eq!(n.with(&mut node, |data| *data), 2);
+    });
+}
+
+/// Checks that a reader and a writer of the big-reader lock exclude each other,
+/// which depends on the `SeqCst` fences between the slot and the writer flag.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_big() {
+    use loom::sync::Arc;
+    use synctools::rwlock::BigRwLock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(BigRwLock::<_, 2>::new(0));
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            for _ in 0..2 {
+                n0.with_mut(|data| {
+                    *data += 1;
+                    *data += 1;
+                });
+            }
+        });
+
+        let data = n.with(1, |data| *data);
+        assert!(data == 0 || data == 2 || data == 4);
+
+        writer.join().unwrap();
+
+        assert_eq!(n.with(0, |data| *data), 4);
+    });
+}
+
+/// Checks that a writer giving up clears the writer waiting bit,
+/// racing with the reader holding the lock, so that later readers and writers are not blocked.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_try_write_for() {
+    use loom::sync::Arc;
+    use synctools::rwlock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(rwlock::RwLock::new(0));
+        let r = n.read();
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            if let Some(mut w) = n0.try_write_for(0) {
+                w.with_mut(|data| *data += 1);
+                true
+            } else {
+                false
+            }
+        });
+
+        drop(r);
+        let data = n.with(|data| *data);
+
+        let written = writer.join().unwrap();
+        assert!(data == 0 || (written && data == 1));
+
+        n.with_mut(|data| *data += 1);
+        assert_eq!(n.with(|data| *data), if written { 2 } else { 1 });
+    });
+}
+
+/// Checks that a writer waiting for a reader is not stalled
+/// when another writer gives up and clears the writer waiting bit set by the first one.
+///
+/// # How to test
+///
+/// `RUST_BACKTRACE=1 RUSTFLAGS="--cfg loom"  cargo test --test test_rwlock --release`
+#[cfg(loom)]
+#[test]
+fn test_rwlock_try_write_for_waiting_writer() {
+    use loom::sync::Arc;
+    use synctools::rwlock;
+
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+
+    builder.check(|| {
+        let n = Arc::new(rwlock::RwLock::new(0));
+        let r = n.read();
+
+        let n0 = n.clone();
+        let writer = loom::thread::spawn(move || {
+            n0.with_mut(|data| *data += 1);
+        });
+
+        // the reader holds the lock, so this gives up
+        assert!(n.try_write_for(0).is_none());
+        drop(r);
+
+        writer.join().unwrap();
+        assert_eq!(n.with(|data| *data), 1);
+    });
+}
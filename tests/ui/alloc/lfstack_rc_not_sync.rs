@@ -0,0 +1,13 @@
+// `Rc` is not `Send`, so a stack of it must not be shared across threads
+use std::rc::Rc;
+use std::sync::Arc;
+use synctools::lfstack::LFStack;
+
+fn main() {
+    let stack = Arc::new(LFStack::new());
+    stack.push(Rc::new(0u8));
+    let stack0 = stack.clone();
+    std::thread::spawn(move || {
+        let _ = stack0.pop();
+    });
+}
@@ -0,0 +1,10 @@
+// `Rc` is not `Send`, so a stack of it must not be shared across threads
+use std::rc::Rc;
+use synctools::stack::Stack;
+
+fn main() {
+    let stack = Stack::new();
+    std::thread::scope(|s| {
+        s.spawn(|| stack.push(Rc::new(0u8)));
+    });
+}
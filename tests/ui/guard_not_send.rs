@@ -0,0 +1,14 @@
+// writer guards must be released by the thread which acquired the lock
+use synctools::mcs::{MCSLock, MCSNode};
+use synctools::rwlock::RwLock;
+
+fn assert_send<T: Send>(_: &T) {}
+
+fn main() {
+    let rwlock = RwLock::new(0u32);
+    assert_send(&rwlock.write());
+
+    let mcs = MCSLock::new(0u32);
+    let mut node = MCSNode::new();
+    assert_send(&mcs.lock(&mut node));
+}
@@ -0,0 +1,16 @@
+// a shared reference to a guard gives `&T`, so the guard is `Sync` only if `T` is
+use std::cell::Cell;
+use synctools::mcs::{MCSLock, MCSNode};
+use synctools::rwlock::RwLock;
+
+fn assert_sync<T: Sync>(_: &T) {}
+
+fn main() {
+    let rwlock = RwLock::new(Cell::new(0u32));
+    assert_sync(&rwlock.read());
+    assert_sync(&rwlock.write());
+
+    let mcs = MCSLock::new(Cell::new(0u32));
+    let mut node = MCSNode::new();
+    assert_sync(&mcs.lock(&mut node));
+}
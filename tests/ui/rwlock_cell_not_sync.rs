@@ -0,0 +1,11 @@
+// `Cell` is not `Sync`, so readers in two threads must not get `&Cell` at the same time
+use std::cell::Cell;
+use synctools::rwlock::RwLock;
+
+fn main() {
+    let lock = RwLock::new(Cell::new(0u8));
+    std::thread::scope(|s| {
+        s.spawn(|| lock.read().set(1));
+        s.spawn(|| lock.read().set(2));
+    });
+}
@@ -0,0 +1,12 @@
+// `Rc` is not `Send`, so a lock of it must not be shared across threads
+use std::rc::Rc;
+use std::sync::Arc;
+use synctools::rwlock::RwLock;
+
+fn main() {
+    let lock = Arc::new(RwLock::new(Rc::new(0u8)));
+    let lock0 = lock.clone();
+    std::thread::spawn(move || {
+        let _ = lock0.read().clone();
+    });
+}
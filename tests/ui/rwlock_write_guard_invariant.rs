@@ -0,0 +1,17 @@
+// a writer guard is invariant over `T`,
+// otherwise a short-lived reference could be written into a lock of `&'static str`
+use synctools::rwlock::{RwLock, RwLockWriteGuard};
+
+fn shorten<'a, 'b>(guard: RwLockWriteGuard<'a, &'static str>) -> RwLockWriteGuard<'a, &'b str> {
+    guard
+}
+
+fn main() {
+    let lock = RwLock::new("static");
+    {
+        let local = String::from("local");
+        let mut guard = shorten(lock.write());
+        *guard = &local;
+    }
+    println!("{}", *lock.read());
+}